@@ -7,7 +7,7 @@ extern crate mrusty;
 
 use test::Bencher;
 
-use mrusty::{Mruby, MrubyImpl};
+use mrusty::{Mruby, MrubyImpl, MrubyPool, MrubyType};
 
 #[bench]
 fn fib_rust(b: &mut Bencher) {
@@ -67,3 +67,175 @@ fn fib_ruby(b: &mut Bencher) {
         mruby.run("fib 20").unwrap()
     });
 }
+
+#[bench]
+fn call_str(b: &mut Bencher) {
+    let mruby = Mruby::new();
+
+    let one = mruby.fixnum(1);
+
+    b.iter(|| {
+        for _ in 0..1_000 {
+            one.call("+", vec![one.clone()]).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn call_funcall_argv(b: &mut Bencher) {
+    let mruby = Mruby::new();
+
+    let one = mruby.fixnum(1);
+    let plus = mruby.intern("+");
+
+    b.iter(|| {
+        for _ in 0..1_000 {
+            mruby.funcall_argv(&one, plus, &[one.clone()]).unwrap();
+        }
+    });
+}
+
+fn dispatch_loop(mruby: &mrusty::MrubyType) -> mrusty::Value {
+    mruby.run("
+      def dispatch(cont)
+        i = 0
+
+        while i < 1000
+          cont.value
+          i += 1
+        end
+      end
+
+      Container.new
+    ").unwrap()
+}
+
+#[bench]
+fn dispatch_closure(b: &mut Bencher) {
+    struct Cont;
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Cont)
+    }));
+    mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, _slf: (&Cont)| {
+        mruby.fixnum(1)
+    }));
+
+    let cont = dispatch_loop(&mruby);
+    let top = mruby.run("self").unwrap();
+
+    b.iter(|| {
+        top.call1("dispatch", cont.clone()).unwrap()
+    });
+}
+
+#[bench]
+fn dispatch_fn(b: &mut Bencher) {
+    struct Cont;
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_fn::<Cont>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Cont)
+    }));
+    mruby.def_method_fn::<Cont>("value", mrfn!(|mruby, _slf: (&Cont)| {
+        mruby.fixnum(1)
+    }));
+
+    let cont = dispatch_loop(&mruby);
+    let top = mruby.run("self").unwrap();
+
+    b.iter(|| {
+        top.call1("dispatch", cont.clone()).unwrap()
+    });
+}
+
+macro_rules! startup_methods {
+    ( $define:ident ) => {
+        $define!("m0"); $define!("m1"); $define!("m2"); $define!("m3"); $define!("m4");
+        $define!("m5"); $define!("m6"); $define!("m7"); $define!("m8"); $define!("m9");
+        $define!("n0"); $define!("n1"); $define!("n2"); $define!("n3"); $define!("n4");
+        $define!("n5"); $define!("n6"); $define!("n7"); $define!("n8"); $define!("n9");
+    };
+}
+
+#[bench]
+fn startup_def_method_for(b: &mut Bencher) {
+    struct Cont;
+
+    let mruby = Mruby::new();
+
+    b.iter(|| {
+        mruby.def_class_for::<Cont>("Container");
+
+        macro_rules! define {
+            ( $name:expr ) => {
+                mruby.def_method_for::<Cont, _>($name, mrfn!(|mruby, _slf: (&Cont)| {
+                    mruby.nil()
+                }));
+            };
+        }
+
+        startup_methods!(define);
+    });
+}
+
+#[bench]
+fn startup_class_builder(b: &mut Bencher) {
+    struct Cont;
+
+    let mruby = Mruby::new();
+
+    b.iter(|| {
+        let mut builder = mruby.class_builder::<Cont>("Container");
+
+        macro_rules! define {
+            ( $name:expr ) => {
+                builder = builder.method($name, mrfn!(|mruby, _slf: (&Cont)| {
+                    mruby.nil()
+                }));
+            };
+        }
+
+        startup_methods!(define);
+
+        builder.define()
+    });
+}
+
+fn pool_setup(mruby: &MrubyType) {
+    mruby.run("
+      50.times do |i|
+        Object.const_set(\"Model#{i}\", Class.new do
+          10.times { |j| define_method(\"field#{j}\") { i * j } }
+        end)
+      end
+    ").unwrap();
+}
+
+const POOL_SCRIPT: &'static str =
+    "(0...50).map { |i| Object.const_get(\"Model#{i}\").new.field9 }.reduce(:+)";
+
+#[bench]
+fn pool_pooled(b: &mut Bencher) {
+    let pool = MrubyPool::new(pool_setup);
+
+    b.iter(|| {
+        pool.with(|mruby| mruby.run(POOL_SCRIPT).unwrap().to_i32().unwrap())
+    });
+}
+
+#[bench]
+fn pool_fresh(b: &mut Bencher) {
+    b.iter(|| {
+        let mruby = Mruby::new();
+
+        pool_setup(&mruby);
+
+        mruby.run(POOL_SCRIPT).unwrap().to_i32().unwrap()
+    });
+}
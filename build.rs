@@ -9,11 +9,17 @@ extern crate gcc;
 extern crate tar;
 extern crate walkdir;
 
+use std::env;
 use std::fs::File;
+use std::io::{Read, Write};
 
 use tar::Archive;
 use walkdir::{DirEntry, WalkDir, WalkDirIterator};
 
+// Shared with the library, which exports it for other crates' build scripts.
+#[path = "src/gems.rs"]
+mod gems;
+
 fn is_c(entry: &DirEntry) -> bool {
     match entry.path().extension() {
         Some(ext) => "c" == ext,
@@ -21,10 +27,57 @@ fn is_c(entry: &DirEntry) -> bool {
     }
 }
 
+// mruby's data types have no mark function, so the GC is made to call back into mrb_ext.c to trace
+// the Values held by Rust objects.
+fn patch_gc(path: &str) {
+    let mut gc = String::new();
+
+    File::open(path).unwrap().read_to_string(&mut gc).unwrap();
+
+    let marks = "  case MRB_TT_OBJECT:\n  case MRB_TT_DATA:\n  case MRB_TT_EXCEPTION:\n    \
+                 mrb_gc_mark_iv(mrb, (struct RObject*)obj);\n    break;\n";
+    let hooked = "  case MRB_TT_OBJECT:\n  case MRB_TT_EXCEPTION:\n    \
+                  mrb_gc_mark_iv(mrb, (struct RObject*)obj);\n    break;\n\n  \
+                  case MRB_TT_DATA:\n    mrb_ext_data_mark(mrb, (struct RData*)obj);\n    \
+                  mrb_gc_mark_iv(mrb, (struct RObject*)obj);\n    break;\n";
+    let children = "static void\ngc_mark_children(";
+
+    assert!(gc.matches(marks).count() == 1, "unexpected gc.c");
+
+    // Once Rust objects are marked, the GC has to stay in the mode set up by mrb_ext.c, so mruby
+    // code can no longer change it and mrb_ext.c gets to leave generational mode.
+    let ratio = "  mrb->gc.step_ratio = ratio;\n";
+    let locked_ratio = "  if (!mrb_ext_gc_locked(mrb))\n    mrb->gc.step_ratio = ratio;\n";
+    let mode = "  if (mrb->gc.generational != enable)\n";
+    let locked_mode = "  if (mrb->gc.generational != enable && !mrb_ext_gc_locked(mrb))\n";
+    let setter = "static mrb_value\ngc_generational_mode_set(";
+
+    assert!(gc.matches(ratio).count() == 1, "unexpected gc.c");
+    assert!(gc.matches(mode).count() == 1, "unexpected gc.c");
+
+    let gc = gc.replace(marks, hooked).replace(children, &format!(
+        "void mrb_ext_data_mark(mrb_state *mrb, struct RData *data);\n\
+         mrb_bool mrb_ext_gc_locked(mrb_state *mrb);\n\n{}", children
+    )).replace(ratio, locked_ratio).replace(mode, locked_mode).replace(setter, &format!(
+        "void\nmrb_ext_gc_incremental_mode(mrb_state *mrb)\n{{\n  if (mrb->gc.generational)\n    \
+         change_gen_gc_mode(mrb, &mrb->gc, FALSE);\n}}\n\n{}", setter
+    ));
+
+    File::create(path).unwrap().write_all(gc.as_bytes()).unwrap();
+}
+
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/gems.rs");
+    println!("cargo:rerun-if-changed=src/mrb_ext.c");
+    println!("cargo:rerun-if-changed=src/mruby/mruby-out.tar");
+    println!("cargo:rerun-if-env-changed=MRUSTY_GEMS");
+
     let mut archive = Archive::new(File::open("src/mruby/mruby-out.tar").unwrap());
     archive.unpack("target").unwrap();
 
+    patch_gc("target/mruby-out/src/gc.c");
+
     let mut config = gcc::Config::new();
 
     for entry in WalkDir::new("target/mruby-out/src").into_iter().filter_entry(|e| e.file_type().is_dir() || is_c(e)) {
@@ -33,9 +86,27 @@ fn main() {
         if is_c(&entry) { config.file(entry.path()); }
     }
 
+    // The instruction limit relies on the VM's code fetch hook, which costs a check on every
+    // instruction. Every unit sharing mrb_state needs the define so that they agree on its layout.
+    let debug_hook = env::var("CARGO_FEATURE_INSTRUCTION_LIMIT").is_ok();
+
+    if debug_hook {
+        config.define("MRB_ENABLE_DEBUG_HOOK", None);
+    }
+
     config.include("target/mruby-out/include").compile("libmruby.a");
 
     let mut config = gcc::Config::new();
 
+    if debug_hook {
+        config.define("MRB_ENABLE_DEBUG_HOOK", None);
+    }
+
     config.file("src/mrb_ext.c").include("target/mruby-out/include").compile("libmrbe.a");
+
+    if let Ok(gems) = env::var("MRUSTY_GEMS") {
+        let gems: Vec<&str> = gems.split(':').filter(|gem| !gem.is_empty()).collect();
+
+        gems::compile_gems(&gems);
+    }
 }
@@ -0,0 +1,154 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use super::*;
+
+// mruby 1.2 only switches to a Fiber when resume is called by the VM, so calls from Rust go
+// through these Ruby methods.
+const HELPERS: &'static str = "
+  class Fiber
+    def self.__mrusty_new(block)
+      new(&block)
+    end
+
+    def __mrusty_resume(args)
+      resume(*args)
+    end
+  end
+";
+
+/// An `enum` returned by `Fiber::resume`, telling whether the `Fiber` stopped at a `Fiber.yield`
+/// or ran to the end of its block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FiberResult {
+    /// value passed to `Fiber.yield`
+    Yielded(Value),
+    /// value returned by the block
+    Finished(Value)
+}
+
+/// A `struct` that wraps around an mruby `Fiber`, which can be resumed from Rust, e.g. once per
+/// frame of a game loop. Created with `MrubyImpl::fiber`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::FiberResult;
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// let mruby = Mruby::new();
+///
+/// let script = mruby.run("proc { |x| Fiber.yield(x + 1); :done }").unwrap();
+/// let fiber = mruby.fiber(script);
+///
+/// assert_eq!(fiber.resume(vec![mruby.fixnum(1)]).unwrap(),
+///            FiberResult::Yielded(mruby.fixnum(2)));
+/// assert_eq!(fiber.resume(vec![]).unwrap(), FiberResult::Finished(mruby.symbol("done")));
+/// ```
+pub struct Fiber {
+    mruby: MrubyType,
+    fiber: Value
+}
+
+impl Fiber {
+    #[doc(hidden)]
+    pub fn new(mruby: &MrubyType, block: Value) -> Fiber {
+        let class = mruby.get_class("Fiber").unwrap().to_value();
+        let defined = class.call("respond_to?", vec![mruby.symbol("__mrusty_new")]).unwrap();
+
+        if !defined.to_bool().unwrap() {
+            mruby.run(HELPERS).unwrap();
+        }
+
+        match class.call("__mrusty_new", vec![block]) {
+            Ok(fiber) => Fiber { mruby: mruby.clone(), fiber: fiber },
+            Err(err)  => panic!("fiber expects a Proc defined in mruby: {}", err)
+        }
+    }
+
+    /// Resumes the `Fiber` with `args`, which are passed to its block the first time and are
+    /// returned by `Fiber.yield` afterwards. Returns an `Err` if the `Fiber` is not `alive` or
+    /// if its block raises, which ends the `Fiber`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::FiberResult;
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let script = mruby.run("proc { |a| b = Fiber.yield(a * 2); a + b }").unwrap();
+    /// let fiber = mruby.fiber(script);
+    ///
+    /// assert_eq!(fiber.resume(vec![mruby.fixnum(3)]).unwrap(),
+    ///            FiberResult::Yielded(mruby.fixnum(6)));
+    /// assert_eq!(fiber.resume(vec![mruby.fixnum(4)]).unwrap(),
+    ///            FiberResult::Finished(mruby.fixnum(7)));
+    /// assert!(fiber.resume(vec![]).is_err());
+    /// ```
+    pub fn resume(&self, args: Vec<Value>) -> Result<FiberResult, MrubyError> {
+        let value = match self.fiber.call("__mrusty_resume", vec![self.mruby.array(args)]) {
+            Ok(value) => value,
+            Err(err)  => {
+                let mrb = self.mruby.borrow().mrb;
+
+                unsafe {
+                    mrb_ext_fiber_terminate_raised(mrb, self.fiber.value);
+                }
+
+                return Err(err);
+            }
+        };
+
+        if self.alive() {
+            Ok(FiberResult::Yielded(value))
+        } else {
+            Ok(FiberResult::Finished(value))
+        }
+    }
+
+    /// Returns whether the `Fiber` can still be resumed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let fiber = mruby.fiber(mruby.run("proc { Fiber.yield }").unwrap());
+    ///
+    /// fiber.resume(vec![]).unwrap();
+    /// assert!(fiber.alive());
+    ///
+    /// fiber.resume(vec![]).unwrap();
+    /// assert!(!fiber.alive());
+    /// ```
+    pub fn alive(&self) -> bool {
+        self.fiber.call("alive?", vec![]).unwrap().to_bool().unwrap()
+    }
+
+    /// Casts `Fiber` to `Value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let fiber = mruby.fiber(mruby.run("proc {}").unwrap());
+    /// let value = fiber.to_value();
+    ///
+    /// assert_eq!(value.class().to_str(), "Fiber");
+    /// ```
+    #[inline]
+    pub fn to_value(&self) -> Value {
+        self.fiber.clone()
+    }
+}
@@ -0,0 +1,57 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// A function meant to be called from a build script, with mrusty as a build-dependency. It
+/// compiles every .rb file in `gems` to bytecode with `mrbc` (or `$MRBC`), writing `name.mrb` to
+/// `OUT_DIR` so that it can be baked in with `include_gem!`. Cargo is told to re-run the build
+/// script when any of the files or `$MRBC` change.
+///
+/// # Panics
+///
+/// Panics if `mrbc` cannot be run or fails to compile a gem.
+///
+/// # Examples
+///
+/// ```no_run
+/// // build.rs
+/// extern crate mrusty;
+///
+/// fn main() {
+///     mrusty::compile_gems(&["gems/hello.rb"]);
+/// }
+/// ```
+///
+/// ```ignore
+/// // src/main.rs
+/// let mruby = Mruby::new();
+/// mruby.load_gem("hello", include_gem!(concat!(env!("OUT_DIR"), "/hello.mrb"))).unwrap();
+/// ```
+pub fn compile_gems<P: AsRef<Path>>(gems: &[P]) {
+    let mrbc = env::var("MRBC").unwrap_or("mrbc".to_owned());
+    let out_dir = env::var("OUT_DIR").expect("compile_gems must be called from a build script");
+
+    println!("cargo:rerun-if-env-changed=MRBC");
+
+    for gem in gems {
+        let gem = gem.as_ref();
+        let name = gem.file_stem().expect("gem path has no file name");
+        let out = Path::new(&out_dir).join(name).with_extension("mrb");
+
+        println!("cargo:rerun-if-changed={}", gem.display());
+
+        let status = Command::new(&mrbc).arg("-o").arg(&out).arg(gem).status()
+            .expect("could not run mrbc");
+
+        if !status.success() {
+            panic!("mrbc failed to compile {}", gem.display());
+        }
+    }
+}
@@ -0,0 +1,363 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use super::*;
+
+/// Deepest nesting of Arrays and Hashes accepted both ways. Guards against cyclic values.
+const MAX_NESTING: usize = 100;
+
+fn error<T>(message: String) -> Result<T, MrubyError> {
+    Err(MrubyError::Json(message))
+}
+
+pub fn to_json(value: &Value) -> Result<String, MrubyError> {
+    let mut json = String::new();
+
+    write_value(&mut json, value, 0)?;
+
+    Ok(json)
+}
+
+fn write_value(json: &mut String, value: &Value, depth: usize) -> Result<(), MrubyError> {
+    if depth > MAX_NESTING {
+        return error(format!("nesting of {} is too deep", depth));
+    }
+
+    match value.value.typ {
+        MrType::MRB_TT_FALSE if value.value.value as u32 == 0 => json.push_str("null"),
+        MrType::MRB_TT_FALSE  => json.push_str("false"),
+        MrType::MRB_TT_TRUE   => json.push_str("true"),
+        MrType::MRB_TT_FIXNUM => write!(json, "{}", value.to_i32()?).unwrap(),
+        MrType::MRB_TT_FLOAT  => {
+            let float = value.to_f64()?;
+
+            if !float.is_finite() {
+                return error(format!("{} cannot be converted to JSON", float));
+            }
+
+            write!(json, "{:?}", float).unwrap();
+        },
+        MrType::MRB_TT_STRING |
+        MrType::MRB_TT_SYMBOL => write_str(json, value.to_str()?),
+        MrType::MRB_TT_ARRAY  => {
+            json.push('[');
+
+            for (i, element) in value.to_vec()?.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+
+                write_value(json, element, depth + 1)?;
+            }
+
+            json.push(']');
+        },
+        MrType::MRB_TT_HASH   => {
+            let mrb = value.mruby.borrow().mrb;
+            let keys = unsafe { Value::new(value.mruby.clone(), mrb_hash_keys(mrb, value.value)) };
+
+            json.push('{');
+
+            for (i, key) in keys.to_vec()?.iter().enumerate() {
+                match key.value.typ {
+                    MrType::MRB_TT_STRING | MrType::MRB_TT_SYMBOL => (),
+                    _ => {
+                        return error(format!("{} keys cannot be converted to JSON",
+                                             key.class().to_str()));
+                    }
+                }
+
+                if i > 0 {
+                    json.push(',');
+                }
+
+                write_str(json, key.to_str()?);
+                json.push(':');
+
+                let element = unsafe {
+                    Value::new(value.mruby.clone(), mrb_hash_get(mrb, value.value, key.value))
+                };
+
+                write_value(json, &element, depth + 1)?;
+            }
+
+            json.push('}');
+        },
+        _ => return error(format!("{} cannot be converted to JSON", value.class().to_str()))
+    }
+
+    Ok(())
+}
+
+fn write_str(json: &mut String, string: &str) {
+    json.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"'  => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c    => json.push(c)
+        }
+    }
+
+    json.push('"');
+}
+
+pub fn parse(mruby: &MrubyType, json: &str) -> Result<Value, MrubyError> {
+    let mut parser = Parser {
+        mruby,
+        json,
+        chars: json.char_indices().peekable()
+    };
+
+    let value = parser.value(0)?;
+
+    parser.whitespace();
+
+    match parser.chars.peek() {
+        Some(&(i, c)) => parser.unexpected(i, c),
+        None          => Ok(value)
+    }
+}
+
+struct Parser<'a> {
+    mruby: &'a MrubyType,
+    json:  &'a str,
+    chars: Peekable<CharIndices<'a>>
+}
+
+impl<'a> Parser<'a> {
+    fn unexpected<T>(&self, offset: usize, c: char) -> Result<T, MrubyError> {
+        error(format!("unexpected {:?} at offset {}", c, offset))
+    }
+
+    fn next(&mut self) -> Result<(usize, char), MrubyError> {
+        match self.chars.next() {
+            Some(next) => Ok(next),
+            None       => error("unexpected end of input".to_owned())
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), MrubyError> {
+        match self.next()? {
+            (_, c) if c == expected => Ok(()),
+            (i, c)                  => self.unexpected(i, c)
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(&(_, ' ')) | Some(&(_, '\t')) | Some(&(_, '\n')) | Some(&(_, '\r')) =
+            self.chars.peek() {
+
+            self.chars.next();
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, MrubyError> {
+        if depth > MAX_NESTING {
+            return error(format!("nesting of {} is too deep", depth));
+        }
+
+        self.whitespace();
+
+        let (i, c) = match self.chars.peek() {
+            Some(&next) => next,
+            None        => return error("unexpected end of input".to_owned())
+        };
+
+        match c {
+            'n' => self.literal("null", self.mruby.nil()),
+            't' => self.literal("true", self.mruby.bool(true)),
+            'f' => self.literal("false", self.mruby.bool(false)),
+            '"' => {
+                let string = self.string()?;
+
+                Ok(self.mruby.string(&string))
+            },
+            '-' | '0'..='9' => self.number(),
+            '[' => self.array(depth),
+            '{' => self.object(depth),
+            c   => self.unexpected(i, c)
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, MrubyError> {
+        for expected in literal.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, MrubyError> {
+        let start = self.chars.peek().unwrap().0;
+        let mut end = self.json.len();
+        let mut float = false;
+
+        while let Some(&(i, c)) = self.chars.peek() {
+            match c {
+                '0'..='9' | '-' | '+' => (),
+                '.' | 'e' | 'E'       => float = true,
+                _                     => {
+                    end = i;
+
+                    break;
+                }
+            }
+
+            self.chars.next();
+        }
+
+        let number = &self.json[start..end];
+
+        if !float {
+            if let Ok(fixnum) = number.parse::<i32>() {
+                return Ok(self.mruby.fixnum(fixnum));
+            }
+        }
+
+        match number.parse::<f64>() {
+            Ok(float) => Ok(self.mruby.float(float)),
+            Err(_)    => error(format!("invalid number {} at offset {}", number, start))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, MrubyError> {
+        let mut string = String::new();
+
+        self.expect('"')?;
+
+        loop {
+            match self.next()? {
+                (_, '"')  => return Ok(string),
+                (_, '\\') => {
+                    match self.next()? {
+                        (_, '"')  => string.push('"'),
+                        (_, '\\') => string.push('\\'),
+                        (_, '/')  => string.push('/'),
+                        (_, 'b')  => string.push('\u{8}'),
+                        (_, 'f')  => string.push('\u{c}'),
+                        (_, 'n')  => string.push('\n'),
+                        (_, 'r')  => string.push('\r'),
+                        (_, 't')  => string.push('\t'),
+                        (i, 'u')  => string.push(self.unicode(i)?),
+                        (i, c)    => return self.unexpected(i, c)
+                    }
+                },
+                (i, c) if (c as u32) < 0x20 => return self.unexpected(i, c),
+                (_, c)    => string.push(c)
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, MrubyError> {
+        let mut code = 0;
+
+        for _ in 0..4 {
+            match self.next()? {
+                (_, c) if c.is_ascii_hexdigit() => code = code * 16 + c.to_digit(16).unwrap(),
+                (i, c)                          => return self.unexpected(i, c)
+            }
+        }
+
+        Ok(code)
+    }
+
+    fn unicode(&mut self, offset: usize) -> Result<char, MrubyError> {
+        let mut code = self.hex()?;
+
+        // Characters outside the Basic Multilingual Plane come as UTF-16 surrogate pairs.
+        if (0xd800..0xdc00).contains(&code) {
+            self.expect('\\')?;
+            self.expect('u')?;
+
+            let low = self.hex()?;
+
+            if !(0xdc00..0xe000).contains(&low) {
+                return error(format!("invalid surrogate pair at offset {}", offset));
+            }
+
+            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+        }
+
+        match ::std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None    => error(format!("invalid unicode escape at offset {}", offset))
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, MrubyError> {
+        let mut values = vec![];
+
+        self.expect('[')?;
+        self.whitespace();
+
+        if let Some(&(_, ']')) = self.chars.peek() {
+            self.chars.next();
+
+            return Ok(self.mruby.array(values));
+        }
+
+        loop {
+            values.push(self.value(depth + 1)?);
+
+            self.whitespace();
+
+            match self.next()? {
+                (_, ',') => (),
+                (_, ']') => return Ok(self.mruby.array(values)),
+                (i, c)   => return self.unexpected(i, c)
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, MrubyError> {
+        let mrb = self.mruby.borrow().mrb;
+        let hash = unsafe { Value::new(self.mruby.clone(), mrb_hash_new(mrb)) };
+
+        self.expect('{')?;
+        self.whitespace();
+
+        if let Some(&(_, '}')) = self.chars.peek() {
+            self.chars.next();
+
+            return Ok(hash);
+        }
+
+        loop {
+            self.whitespace();
+
+            let key = self.string()?;
+            let key = self.mruby.string(&key);
+
+            self.whitespace();
+            self.expect(':')?;
+
+            let value = self.value(depth + 1)?;
+
+            unsafe {
+                mrb_hash_set(mrb, hash.value, key.value, value.value);
+            }
+
+            self.whitespace();
+
+            match self.next()? {
+                (_, ',') => (),
+                (_, '}') => return Ok(hash),
+                (i, c)   => return self.unexpected(i, c)
+            }
+        }
+    }
+}
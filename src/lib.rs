@@ -17,10 +17,15 @@
 
 #[cfg(feature = "gnu-readline")]
 extern crate rl_sys;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
+mod gems;
 mod macros;
 mod mruby;
 mod mruby_ffi;
+mod pool;
 mod read_line;
 mod repl;
 mod spec;
@@ -31,16 +36,69 @@ pub use mruby_ffi::MrValue;
 /// Not meant to be called directly.
 #[doc(hidden)]
 pub use mruby_ffi::mrb_get_args;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::sym_name;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::SlfTypeError;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::BorrowConflict;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::protect_args;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::valid_sig;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::ArgConversionError;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::def_enum;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::enum_index;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::last_arg;
+/// Not meant to be called directly.
+#[doc(hidden)]
+pub use mruby::MrfnReturn;
+
+pub use gems::compile_gems;
 
+pub use mruby::BacktraceEntry;
 pub use mruby::Class;
+pub use mruby::ClassBuilder;
 pub use mruby::ClassLike;
+pub use mruby::CloseError;
+pub use mruby::Downcast;
+pub use mruby::Fiber;
+pub use mruby::FiberResult;
+pub use mruby::FromValue;
+pub use mruby::Marker;
 pub use mruby::Module;
+pub use mruby::MrInt;
 pub use mruby::Mruby;
+pub use mruby::MrubyAny;
 pub use mruby::MrubyError;
 pub use mruby::MrubyFile;
 pub use mruby::MrubyImpl;
+pub use mruby::MrubyMark;
+pub use mruby::MrubySymbolId;
 pub use mruby::MrubyType;
+pub use mruby::OutputCapture;
+pub use mruby::SandboxConfig;
+pub use mruby::SyntaxResult;
+pub use mruby::ToValue;
 pub use mruby::Value;
+pub use mruby::ValueEql;
+pub use mruby::ValueSnapshot;
+pub use mruby::Visibility;
+pub use pool::MrubyPool;
+pub use pool::PoolReset;
 pub use read_line::ReadLine;
 pub use repl::Repl;
 pub use spec::Spec;
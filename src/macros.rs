@@ -12,17 +12,29 @@
 /// * `bool`
 /// * `i32`
 /// * `f64`
+/// * `f32` (read as `f64` and narrowed)
 /// * `(&str)` (`&str`; macro limtation)
+/// * `(Symbol)` (`&str`, the name of a `Symbol`; a `String` is interned, anything else raises a
+///   `TypeError`)
 /// * `(Vec<Value>)` (`Vec<Value>`; macro limtation)
+/// * `(Vec<i32>)`, `(Vec<f64>)`, `(Vec<String>)` (panic if any element has the wrong type)
+/// * `(Vec<u8>)` (the bytes of a `String`, which may contain NUL; return with `mruby.bytes`)
+/// * `Range` (`(Value, Value, bool)` like `Value::to_range`; panic if not a `Range`)
+/// * `Hash` (`HashMap<String, Value>`; panic if not a `Hash` with `String` or `Symbol` keys)
 /// * `(&T)` (defined with `def_class`; macro limtation)
+/// * `(&dyn Trait)` (`Box<Trait>` defined with `def_class`; macro limtation)
 /// * `Value`
+/// * any other single-identifier type implementing `FromValue`, e.g. enums reflected with
+///   `mruby_enum!` (raise an `ArgumentError` if the conversion fails)
 ///
 /// Any `panic!` call within the closure will get rescued in a `RustPanic` mruby `Exception`.
+/// Borrowing a `(&T)` or `(&mut T)` `slf` or argument that is already mutably borrowed, e.g. by a
+/// method that called back into the same object, raises a `ReentrantBorrowError` instead.
 ///
 /// # Examples
 ///
-/// `mrfn!` uses the usual Rust closure syntax. `mruby` does not need type information.
-/// `slf` can be either `Value` or `T`.
+/// `mrfn!` uses the usual Rust closure syntax. `mruby` does not need type information and can be
+/// left out when it is not used. `slf` can be either `Value` or `T`.
 ///
 /// ```
 /// # #[macro_use] extern crate mrusty;
@@ -154,19 +166,80 @@
 /// assert_eq!(result.to_i32().unwrap(), 3);
 /// # }
 /// ```
+/// <br/>
+///
+/// Methods may call back into the interpreter, e.g. with `run` or `call`, or define more methods,
+/// since `mruby` is not borrowed while they run.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Cont;
+///
+/// mruby.def_class_for::<Cont>("Container");
+/// mruby.def_method_for::<Cont, _>("twice", mrfn!(|mruby, _slf: Value, a: i32| {
+///     mruby.run(&format!("Container.new.plus({}, {})", a, a)).unwrap()
+/// }));
+/// mruby.def_method_for::<Cont, _>("plus", mrfn!(|mruby, _slf: Value, a: i32, b: i32| {
+///     mruby.fixnum(a + b)
+/// }));
+///
+/// let result = mruby.run("Container.new.twice(3)").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 6);
+/// # }
+/// ```
+/// <br/>
+///
+/// Declaring a tuple return type with `-> (Value, Value)` or `-> (Value, Value, Value)` returns
+/// multiple values packed into an mruby `Array`.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl, Value};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Cont;
+///
+/// mruby.def_class_for::<Cont>("Container");
+/// mruby.def_method_for::<Cont, _>("divmod", mrfn!(|mruby, _slf: Value, a: i32, b: i32|
+///                                                 -> (Value, Value) {
+///     (mruby.fixnum(a / b), mruby.fixnum(a % b))
+/// }));
+///
+/// let result = mruby.run("q, r = Container.new.divmod(7, 2); q * 10 + r").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 31);
+/// # }
+/// ```
 #[macro_export]
 macro_rules! mrfn {
     // init
     ( @init ) => ();
-    ( @init $name:ident, bool )          => (let $name = ::std::mem::uninitialized::<bool>(););
-    ( @init $name:ident, i32 )           => (let $name = ::std::mem::uninitialized::<i32>(););
-    ( @init $name:ident, f64 )           => (let $name = ::std::mem::uninitialized::<f64>(););
-    ( @init $name:ident, (&str) )        => (let $name = ::std::mem::uninitialized::<*const ::std::os::raw::c_char>(););
-    ( @init $name:ident, (Vec<Value>) )  => (let $name = ::std::mem::uninitialized::<$crate::MrValue>(););
-    ( @init $name:ident, Class )         => (let $name = ::std::mem::uninitialized::<$crate::MrValue>(););
-    ( @init $name:ident, Value )         => (let $name = ::std::mem::uninitialized::<$crate::MrValue>(););
-    ( @init $name:ident, (&mut $_t:ty) ) => (let $name = ::std::mem::uninitialized::<$crate::MrValue>(););
-    ( @init $name:ident, (&$_t:ty) )     => (let $name = ::std::mem::uninitialized::<$crate::MrValue>(););
+    ( @init $name:ident, bool )          => (let mut $name = false;);
+    ( @init $name:ident, i32 )           => (let mut $name = 0i32;);
+    ( @init $name:ident, f64 )           => (let mut $name = 0f64;);
+    ( @init $name:ident, f32 )           => (let mut $name = 0f64;);
+    ( @init $name:ident, (&str) )        => (let mut $name = ::std::ptr::null::<::std::os::raw::c_char>(););
+    ( @init $name:ident, (Symbol) )      => (let mut $name = 0u32;);
+    ( @init $name:ident, (Vec<Value>) )  => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (Vec<i32>) )    => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (Vec<f64>) )    => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (Vec<String>) ) => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (Vec<u8>) )     => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, Class )         => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, Range )         => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, Hash )          => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, Value )         => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (&mut $_t:ty) ) => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, (&$_t:ty) )     => (let mut $name = $crate::MrValue::nil(););
+    ( @init $name:ident, $_t:ident )     => (let mut $name = $crate::MrValue::nil(););
     ( @init $name:ident : $t:tt )        => (mrfn!(@init $name, $t));
     ( @init $name:ident : $t:tt, $($names:ident : $ts:tt),+ ) => {
         mrfn!(@init $name, $t);
@@ -178,31 +251,72 @@ macro_rules! mrfn {
     ( @sig bool )          => ("b");
     ( @sig i32 )           => ("i");
     ( @sig f64 )           => ("f");
+    ( @sig f32 )           => ("f");
     ( @sig (&str) )        => ("z");
+    ( @sig (Symbol) )      => ("n");
     ( @sig (Vec<Value>) )  => ("A");
+    ( @sig (Vec<i32>) )    => ("A");
+    ( @sig (Vec<f64>) )    => ("A");
+    ( @sig (Vec<String>) ) => ("A");
+    ( @sig (Vec<u8>) )     => ("S");
     ( @sig Class )         => ("C");
+    ( @sig Range )         => ("o");
+    ( @sig Hash )          => ("o");
     ( @sig Value )         => ("o");
     ( @sig (&mut $_t:ty) ) => ("o");
     ( @sig (&$_t:ty) )     => ("o");
+    ( @sig $_t:ident )     => ("o");
     ( @sig $t:tt, $( $ts:tt ),+ ) => (concat!(mrfn!(@sig $t), mrfn!(@sig $( $ts ),*)));
 
+    // sig_str
+    ( @sig_str $sig:expr ) => {
+        {
+            const SIG: &'static str = concat!($sig, "\0");
+            const _: () = assert!($crate::valid_sig(SIG), "signature contains an interior NUL");
+
+            SIG.as_ptr() as *const ::std::os::raw::c_char
+        }
+    };
+
     // args
     ( @args )                            => ();
-    ( @args $name:ident, bool )          => (&$name as *const bool);
-    ( @args $name:ident, i32 )           => (&$name as *const i32);
-    ( @args $name:ident, f64 )           => (&$name as *const f64);
-    ( @args $name:ident, (&str) )        => (&$name as *const *const ::std::os::raw::c_char);
-    ( @args $name:ident, (Vec<Value>) )  => (&$name as *const $crate::MrValue);
-    ( @args $name:ident, Class )         => (&$name as *const $crate::MrValue);
-    ( @args $name:ident, Value )         => (&$name as *const $crate::MrValue);
-    ( @args $name:ident, (&mut $_t:ty) ) => (&$name as *const $crate::MrValue);
-    ( @args $name:ident, (&$_t:ty) )     => (&$name as *const $crate::MrValue);
+    ( @args $name:ident, bool )          => (&mut $name as *mut bool);
+    ( @args $name:ident, i32 )           => (&mut $name as *mut i32);
+    ( @args $name:ident, f64 )           => (&mut $name as *mut f64);
+    ( @args $name:ident, f32 )           => (&mut $name as *mut f64);
+    ( @args $name:ident, (&str) )        => (&mut $name as *mut *const ::std::os::raw::c_char);
+    ( @args $name:ident, (Symbol) )      => (&mut $name as *mut u32);
+    ( @args $name:ident, (Vec<Value>) )  => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (Vec<i32>) )    => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (Vec<f64>) )    => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (Vec<String>) ) => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (Vec<u8>) )     => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, Class )         => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, Range )         => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, Hash )          => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, Value )         => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (&mut $_t:ty) ) => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, (&$_t:ty) )     => (&mut $name as *mut $crate::MrValue);
+    ( @args $name:ident, $_t:ident )     => (&mut $name as *mut $crate::MrValue);
     ( @args $name:ident : $t:tt )        => (mrfn!(@args $name, $t));
     ( @args $mrb:expr, $sig:expr, $name:ident : $t:tt) => {
-        $crate::mrb_get_args($mrb, $sig, mrfn!(@args $name, $t));
+        $crate::protect_args($mrb, || {
+            $crate::mrb_get_args($mrb, $sig, mrfn!(@args $name, $t));
+        });
     };
     ( @args $mrb:expr, $sig:expr, $name:ident : $t:tt, $($names:ident : $ts:tt),+ ) => {
-        $crate::mrb_get_args($mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),*);
+        $crate::protect_args($mrb, || {
+            $crate::mrb_get_args($mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),*);
+        });
+    };
+
+    // slice; mrb_get_args leaves argv null when there are no rest arguments
+    ( @slice $args:ident, $count:ident ) => {
+        if $count > 0 {
+            ::std::slice::from_raw_parts($args, $count as usize)
+        } else {
+            &[]
+        }
     };
 
     // args_rest
@@ -210,13 +324,15 @@ macro_rules! mrfn {
         {
             let mrb = $mruby.borrow().mrb;
 
-            let args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-            let count = ::std::mem::uninitialized::<i32>();
+            let mut args = ::std::ptr::null_mut::<$crate::MrValue>();
+            let mut count = 0i32;
 
-            $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), &args as *const *mut $crate::MrValue,
-                         &count as *const i32);
+            $crate::protect_args(mrb, || {
+                $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), &mut args as *mut *mut $crate::MrValue,
+                             &mut count as *mut i32);
+            });
 
-            let args = ::std::slice::from_raw_parts(args, count as usize);
+            let args = mrfn!(@slice args, count);
             args.iter().map(|arg| { $crate::Value::new($mruby.clone(), arg.clone()) }).collect::<Vec<_>>()
          }
     };
@@ -224,13 +340,15 @@ macro_rules! mrfn {
         {
             let mrb = $mruby.borrow().mrb;
 
-            let args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-            let count = ::std::mem::uninitialized::<i32>();
+            let mut args = ::std::ptr::null_mut::<$crate::MrValue>();
+            let mut count = 0i32;
 
-            $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),* ,
-                         &args as *const *mut $crate::MrValue, &count as *const i32);
+            $crate::protect_args(mrb, || {
+                $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),* ,
+                             &mut args as *mut *mut $crate::MrValue, &mut count as *mut i32);
+            });
 
-            let args = ::std::slice::from_raw_parts(args, count as usize);
+            let args = mrfn!(@slice args, count);
             args.iter().map(|arg| { $crate::Value::new($mruby.clone(), arg.clone()) }).collect::<Vec<_>>()
          }
     };
@@ -240,14 +358,16 @@ macro_rules! mrfn {
         {
             let mrb = $mruby.borrow().mrb;
 
-            let args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-            let count = ::std::mem::uninitialized::<i32>();
-            let blk = ::std::mem::uninitialized::<$crate::MrValue>();
+            let mut args = ::std::ptr::null_mut::<$crate::MrValue>();
+            let mut count = 0i32;
+            let mut blk = $crate::MrValue::nil();
 
-            $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), &args as *const *mut $crate::MrValue,
-                         &count as *const i32, &blk as *const $crate::MrValue);
+            $crate::protect_args(mrb, || {
+                $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), &mut args as *mut *mut $crate::MrValue,
+                             &mut count as *mut i32, &mut blk as *mut $crate::MrValue);
+            });
 
-            let args = ::std::slice::from_raw_parts(args, count as usize);
+            let args = mrfn!(@slice args, count);
             let args = args.iter().map(|arg| { $crate::Value::new($mruby.clone(), arg.clone()) }).collect::<Vec<_>>();
             let blk = $crate::Value::new($mruby.clone(), blk);
 
@@ -258,14 +378,16 @@ macro_rules! mrfn {
         {
             let mrb = $mruby.borrow().mrb;
 
-            let args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-            let count = ::std::mem::uninitialized::<i32>();
-            let blk = ::std::mem::uninitialized::<$crate::MrValue>();
+            let mut args = ::std::ptr::null_mut::<$crate::MrValue>();
+            let mut count = 0i32;
+            let mut blk = $crate::MrValue::nil();
 
-            $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),* ,
-                         &args as *const *mut $crate::MrValue, &count as *const i32, &blk as *const $crate::MrValue);
+            $crate::protect_args(mrb, || {
+                $crate::mrb_get_args(mrb, $sig, mrfn!(@args $name, $t), $( mrfn!(@args $names : $ts) ),* ,
+                             &mut args as *mut *mut $crate::MrValue, &mut count as *mut i32, &mut blk as *mut $crate::MrValue);
+            });
 
-            let args = ::std::slice::from_raw_parts(args, count as usize);
+            let args = mrfn!(@slice args, count);
             let args = args.iter().map(|arg| { $crate::Value::new($mruby.clone(), arg.clone()) }).collect::<Vec<_>>();
             let blk = $crate::Value::new($mruby.clone(), blk);
 
@@ -278,25 +400,88 @@ macro_rules! mrfn {
     ( @conv $mruby:expr, $name:ident, bool )        => ();
     ( @conv $mruby:expr, $name:ident, i32 )         => ();
     ( @conv $mruby:expr, $name:ident, f64 )         => ();
+    ( @conv $mruby:expr, $name:ident, f32 )         => (let $name = $name as f32;);
     ( @conv $mruby:expr, $name:ident, (&str) )      => {
         let $name = ::std::ffi::CStr::from_ptr($name).to_str().unwrap();
     };
+    ( @conv $mruby:expr, $name:ident, (Symbol) )    => {
+        let $name = $crate::sym_name($mruby.borrow().mrb, $name);
+    };
     ( @conv $mruby:expr, $name:ident, (Vec<Value>) ) => {
         let $name = $crate::Value::new($mruby.clone(), $name).to_vec().unwrap();
     };
+    ( @conv $mruby:expr, $name:ident, (Vec<i32>) ) => {
+        let $name = mrfn!(@conv_vec $mruby, $name, to_i32, "expected Array<Fixnum>");
+    };
+    ( @conv $mruby:expr, $name:ident, (Vec<f64>) ) => {
+        let $name = mrfn!(@conv_vec $mruby, $name, to_f64, "expected Array<Float>");
+    };
+    ( @conv $mruby:expr, $name:ident, (Vec<String>) ) => {
+        let $name = mrfn!(@conv_vec $mruby, $name, to_str, "expected Array<String>")
+            .into_iter().map(|value| value.to_owned()).collect::<Vec<String>>();
+    };
+    ( @conv $mruby:expr, $name:ident, (Vec<u8>) ) => {
+        let $name = $crate::Value::new($mruby.clone(), $name).to_bytes().unwrap().to_vec();
+    };
     ( @conv $mruby:expr, $name:ident, Class )        => {
         let $name = $crate::Value::new($mruby.clone(), $name).to_class().unwrap();
     };
+    ( @conv $mruby:expr, $name:ident, Range )        => {
+        let $name = match $crate::Value::new($mruby.clone(), $name).to_range() {
+            Ok(range) => range,
+            Err(_)    => panic!("expected Range")
+        };
+    };
+    ( @conv $mruby:expr, $name:ident, Hash )         => {
+        let $name = $crate::Value::new($mruby.clone(), $name);
+
+        if !$name.is_a_named("Hash") {
+            panic!("expected Hash");
+        }
+
+        let $name = $crate::MrubyImpl::hash_keys(&$mruby, &$name).unwrap().into_iter().map(|key| {
+            let value = $crate::MrubyImpl::hash_get(&$mruby, &$name, &key);
+
+            match key.to_str() {
+                Ok(key) => (key.to_owned(), value),
+                Err(_)  => panic!("expected Hash with String or Symbol keys")
+            }
+        }).collect::<::std::collections::HashMap<String, $crate::Value>>();
+    };
     ( @conv $mruby:expr, $name:ident, Value )        => {
         let $name = $crate::Value::new($mruby.clone(), $name);
     };
+    ( @conv $mruby:expr, $name:ident, (&mut dyn $tr:path) ) => {
+        let $name = $crate::Value::new($mruby.clone(), $name).to_obj::<Box<dyn $tr>>().unwrap();
+        let mut $name = mrfn!(@borrow_mut $name, dyn $tr);
+        let $name: &mut dyn $tr = &mut **$name;
+    };
+    ( @conv $mruby:expr, $name:ident, (&dyn $tr:path) ) => {
+        let $name = $crate::Value::new($mruby.clone(), $name).to_obj::<Box<dyn $tr>>().unwrap();
+        let $name = mrfn!(@borrow $name, dyn $tr);
+        let $name: &dyn $tr = &**$name;
+    };
     ( @conv $mruby:expr, $name:ident, (&mut $t:ty) ) => {
         let $name = $crate::Value::new($mruby.clone(), $name).to_obj::<$t>().unwrap();
-        let mut $name = $name.borrow_mut();
+        let mut $name = mrfn!(@borrow_mut $name, $t);
     };
     ( @conv $mruby:expr, $name:ident, (&$t:ty) )     => {
         let $name = $crate::Value::new($mruby.clone(), $name).to_obj::<$t>().unwrap();
-        let $name = $name.borrow();
+        let $name = mrfn!(@borrow $name, $t);
+    };
+    ( @conv $mruby:expr, $name:ident, $t:ident )     => {
+        let $name = $crate::Value::new($mruby.clone(), $name);
+        let $name = match <$t as $crate::FromValue>::from_value(&$name) {
+            Ok(value) => value,
+            Err(err)  => {
+                let message = match err {
+                    $crate::MrubyError::Cast(expected) => format!("expected {}", expected),
+                    err                                => err.to_string()
+                };
+
+                ::std::panic::resume_unwind(Box::new($crate::ArgConversionError(message)))
+            }
+        };
     };
     ( @conv $mruby:expr, $name:ident : $t:tt )       => (mrfn!(@conv $mruby, $name, $t));
     ( @conv $mruby:expr, $name:ident : $t:tt, $($names:ident : $ts:tt),+ ) => {
@@ -304,32 +489,92 @@ macro_rules! mrfn {
         mrfn!(@conv $mruby, $( $names : $ts ),*);
     };
 
+    ( @conv_vec $mruby:expr, $name:ident, $to:ident, $message:expr ) => {
+        $crate::Value::new($mruby.clone(), $name).to_vec().unwrap().iter().map(|value| {
+            match value.$to() {
+                Ok(value) => value,
+                Err(_)    => panic!($message)
+            }
+        }).collect::<Vec<_>>()
+    };
+
+    ( @borrow $obj:ident, $t:ty ) => {
+        match $obj.try_borrow() {
+            Ok(obj) => obj,
+            Err(_)  => ::std::panic::resume_unwind(Box::new($crate::BorrowConflict(stringify!($t))))
+        }
+    };
+    ( @borrow_mut $obj:ident, $t:ty ) => {
+        match $obj.try_borrow_mut() {
+            Ok(obj) => obj,
+            Err(_)  => ::std::panic::resume_unwind(Box::new($crate::BorrowConflict(stringify!($t))))
+        }
+    };
+
     // slf
-    ( @slf $slf:ident, bool )         => (let $slf = $slf.to_bool().unwrap(););
-    ( @slf $slf:ident, i32 )          => (let $slf = $slf.to_i32().unwrap(););
-    ( @slf $slf:ident, f64 )          => (let $slf = $slf.to_f64().unwrap(););
-    ( @slf $slf:ident, (&str) )       => (let $slf = $slf.to_str().unwrap(););
-    ( @slf $slf:ident, (Vec<Value>) ) => (let $slf = $slf.to_vec().unwrap(););
-    ( @slf $slf:ident, Class )        => (let $slf = $slf.to_class().unwrap(););
+    ( @slf_try $conv:expr, $t:expr ) => {
+        match $conv {
+            Ok(slf) => slf,
+            Err(_)  => ::std::panic::resume_unwind(Box::new($crate::SlfTypeError($t)))
+        }
+    };
+    ( @slf $slf:ident, bool )         => (let $slf = mrfn!(@slf_try $slf.to_bool(), "bool"););
+    ( @slf $slf:ident, i32 )          => (let $slf = mrfn!(@slf_try $slf.to_i32(), "i32"););
+    ( @slf $slf:ident, f64 )          => (let $slf = mrfn!(@slf_try $slf.to_f64(), "f64"););
+    ( @slf $slf:ident, f32 )          => {
+        let $slf = mrfn!(@slf_try $slf.to_f64(), "f32") as f32;
+    };
+    ( @slf $slf:ident, (&str) )       => (let $slf = mrfn!(@slf_try $slf.to_str(), "&str"););
+    ( @slf $slf:ident, (Vec<Value>) ) => {
+        let $slf = mrfn!(@slf_try $slf.to_vec(), "Vec<Value>");
+    };
+    ( @slf $slf:ident, Class )        => (let $slf = mrfn!(@slf_try $slf.to_class(), "Class"););
+    ( @slf $slf:ident, Range )        => (let $slf = mrfn!(@slf_try $slf.to_range(), "Range"););
     ( @slf $slf:ident, Value )        => ();
+    ( @slf $slf:ident, (&mut dyn $tr:path) ) => {
+        let $slf = mrfn!(@slf_try $slf.to_obj::<Box<dyn $tr>>(), concat!("&mut dyn ", stringify!($tr)));
+        let mut $slf = mrfn!(@borrow_mut $slf, dyn $tr);
+        let $slf: &mut dyn $tr = &mut **$slf;
+    };
+    ( @slf $slf:ident, (&dyn $tr:path) ) => {
+        let $slf = mrfn!(@slf_try $slf.to_obj::<Box<dyn $tr>>(), concat!("&dyn ", stringify!($tr)));
+        let $slf = mrfn!(@borrow $slf, dyn $tr);
+        let $slf: &dyn $tr = &**$slf;
+    };
     ( @slf $slf:ident, (&mut $t:ty) ) => {
-        let $slf = $slf.to_obj::<$t>().unwrap();
-        let mut $slf = $slf.borrow_mut();
+        let $slf = mrfn!(@slf_try $slf.to_obj::<$t>(), concat!("&mut ", stringify!($t)));
+        let mut $slf = mrfn!(@borrow_mut $slf, $t);
     };
     ( @slf $slf:ident, (&$t:ty) )     => {
-        let $slf = $slf.to_obj::<$t>().unwrap();
-        let $slf = $slf.borrow();
+        let $slf = mrfn!(@slf_try $slf.to_obj::<$t>(), concat!("&", stringify!($t)));
+        let $slf = mrfn!(@borrow $slf, $t);
     };
 
     // mrfn
-    ( |$mruby:ident, $slf:ident : $st:tt| $block:expr ) => {
+    ( | $( $rest:tt )* ) => {
+        mrfn!(@closure [] $( $rest )*)
+    };
+    ( @closure [ $( $args:tt )* ] | -> $ret:ty $block:block ) => {
+        mrfn!(@fn | $( $args )* | {
+            let ret: $ret = $block;
+
+            $crate::MrfnReturn::into_value(ret)
+        })
+    };
+    ( @closure [ $( $args:tt )* ] | $block:expr ) => {
+        mrfn!(@fn | $( $args )* | $block)
+    };
+    ( @closure [ $( $args:tt )* ] $t:tt $( $rest:tt )* ) => {
+        mrfn!(@closure [ $( $args )* $t ] $( $rest )*)
+    };
+    ( @fn |$mruby:ident, $slf:ident : $st:tt| $block:expr ) => {
         |$mruby, $slf| {
             mrfn!(@slf $slf, $st);
 
             $block
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt; &$blk:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt; &$blk:ident| $block:expr ) => {
         |$mruby, $slf| {
             mrfn!(@slf $slf, $st);
 
@@ -338,31 +583,33 @@ macro_rules! mrfn {
 
                 mrfn!(@init $blk : Value);
 
-                let sig_str = ::std::ffi::CString::new("&").unwrap();
+                let sig_str = mrfn!(@sig_str "&");
 
-                mrfn!(@args mrb, sig_str.as_ptr(), $blk : Value);
+                mrfn!(@args mrb, sig_str, $blk : Value);
                 mrfn!(@conv $mruby, $blk : Value);
 
                 $block
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt; $args:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt; $args:ident| $block:expr ) => {
         |$mruby, $slf| {
             mrfn!(@slf $slf, $st);
 
             unsafe {
                 let mrb = $mruby.borrow().mrb;
 
-                let $args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-                let count = ::std::mem::uninitialized::<i32>();
+                let mut $args = ::std::ptr::null_mut::<$crate::MrValue>();
+                let mut count = 0i32;
 
-                let sig_str = ::std::ffi::CString::new("*").unwrap();
+                let sig_str = mrfn!(@sig_str "*");
 
-                $crate::mrb_get_args(mrb, sig_str.as_ptr(), &$args as *const *mut $crate::MrValue,
-                             &count as *const i32);
+                $crate::protect_args(mrb, || {
+                    $crate::mrb_get_args(mrb, sig_str, &mut $args as *mut *mut $crate::MrValue,
+                                 &mut count as *mut i32);
+                });
 
-                let $args = ::std::slice::from_raw_parts($args, count as usize);
+                let $args = mrfn!(@slice $args, count);
                 let $args = $args.iter().map(|arg| {
                     $crate::Value::new($mruby.clone(), arg.clone())
                 }).collect::<Vec<_>>();
@@ -371,24 +618,26 @@ macro_rules! mrfn {
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident| $block:expr ) => {
         |$mruby, $slf| {
             mrfn!(@slf $slf, $st);
 
             unsafe {
                 let mrb = $mruby.borrow().mrb;
 
-                let $args = ::std::mem::uninitialized::<*mut $crate::MrValue>();
-                let count = ::std::mem::uninitialized::<i32>();
-                let $blk = ::std::mem::uninitialized::<$crate::MrValue>();
+                let mut $args = ::std::ptr::null_mut::<$crate::MrValue>();
+                let mut count = 0i32;
+                let mut $blk = $crate::MrValue::nil();
 
-                let sig_str = ::std::ffi::CString::new("*&").unwrap();
+                let sig_str = mrfn!(@sig_str "*&");
 
-                $crate::mrb_get_args(mrb, sig_str.as_ptr(),
-                             &$args as *const *mut $crate::MrValue, &count as *const i32,
-                             &$blk as *const $crate::MrValue);
+                $crate::protect_args(mrb, || {
+                    $crate::mrb_get_args(mrb, sig_str,
+                                 &mut $args as *mut *mut $crate::MrValue, &mut count as *mut i32,
+                                 &mut $blk as *mut $crate::MrValue);
+                });
 
-                let $args = ::std::slice::from_raw_parts($args, count as usize);
+                let $args = mrfn!(@slice $args, count);
                 let $args = $args.iter().map(|arg| {
                     $crate::Value::new($mruby.clone(), arg.clone())
                 }).collect::<Vec<_>>();
@@ -398,7 +647,7 @@ macro_rules! mrfn {
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),*| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),*| $block:expr ) => {
         |$mruby, $slf| {
             unsafe {
                 mrfn!(@slf $slf, $st);
@@ -406,16 +655,16 @@ macro_rules! mrfn {
                 mrfn!(@init $( $name : $t ),*);
 
                 let mrb = $mruby.borrow().mrb;
-                let sig_str = ::std::ffi::CString::new(mrfn!(@sig $( $t ),*)).unwrap();
+                let sig_str = mrfn!(@sig_str mrfn!(@sig $( $t ),*));
 
-                mrfn!(@args mrb, sig_str.as_ptr(), $( $name : $t ),*);
+                mrfn!(@args mrb, sig_str, $( $name : $t ),*);
                 mrfn!(@conv $mruby, $( $name : $t ),*);
 
                 $block
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; &$blk:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; &$blk:ident| $block:expr ) => {
         |$mruby, $slf| {
             unsafe {
                 mrfn!(@slf $slf, $st);
@@ -423,47 +672,52 @@ macro_rules! mrfn {
                 mrfn!(@init $( $name : $t ),*, $blk : Value);
 
                 let mrb = $mruby.borrow().mrb;
-                let sig_str = ::std::ffi::CString::new(concat!(mrfn!(@sig $( $t ),*), "&")).unwrap();
+                let sig_str = mrfn!(@sig_str concat!(mrfn!(@sig $( $t ),*), "&"));
 
-                mrfn!(@args mrb, sig_str.as_ptr(), $( $name : $t ),*, $blk : Value);
+                mrfn!(@args mrb, sig_str, $( $name : $t ),*, $blk : Value);
                 mrfn!(@conv $mruby, $( $name : $t ),*, $blk : Value);
 
                 $block
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; $args:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; $args:ident| $block:expr ) => {
         |$mruby, $slf| {
             unsafe {
                 mrfn!(@slf $slf, $st);
 
                 mrfn!(@init $( $name : $t ),*);
 
-                let sig_str = ::std::ffi::CString::new(concat!(mrfn!(@sig $( $t ),*), "*")).unwrap();
+                let sig_str = mrfn!(@sig_str concat!(mrfn!(@sig $( $t ),*), "*"));
 
-                let $args = mrfn!(@args_rest $mruby, sig_str.as_ptr(), $( $name : $t ),*);
+                let $args = mrfn!(@args_rest $mruby, sig_str, $( $name : $t ),*);
                 mrfn!(@conv $mruby, $( $name : $t ),*);
 
                 $block
             }
         }
     };
-    ( |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; $args:ident, &$blk:ident| $block:expr ) => {
+    ( @fn |$mruby:ident, $slf:ident : $st:tt, $( $name:ident : $t:tt ),* ; $args:ident, &$blk:ident| $block:expr ) => {
         |$mruby, $slf| {
             unsafe {
                 mrfn!(@slf $slf, $st);
 
                 mrfn!(@init $( $name : $t ),*);
 
-                let sig_str = ::std::ffi::CString::new(concat!(mrfn!(@sig $( $t ),*), "*&")).unwrap();
+                let sig_str = mrfn!(@sig_str concat!(mrfn!(@sig $( $t ),*), "*&"));
 
-                let ($args, $blk) = mrfn!(@args_rest_blk $mruby, sig_str.as_ptr(), $( $name : $t ),*);
+                let ($args, $blk) = mrfn!(@args_rest_blk $mruby, sig_str, $( $name : $t ),*);
                 mrfn!(@conv $mruby, $( $name : $t ),*);
 
                 $block
             }
         }
     };
+
+    // mruby omitted
+    ( @fn |$slf:ident : $( $rest:tt )*) => {
+        mrfn!(@fn |_mruby, $slf : $( $rest )*)
+    };
 }
 
 /// Not meant to be called directly.
@@ -471,345 +725,493 @@ macro_rules! mrfn {
 #[macro_export]
 macro_rules! defines {
     // end recursion
-    ( $mruby:expr, $name:ty, ) => ();
+    ( $builder:ident, ) => ();
+
+    // directives
+    ( $builder:ident, comparable!(); $( $rest:tt )* ) => {
+        let $builder = $builder.comparable();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, equatable!(); $( $rest:tt )* ) => {
+        let $builder = $builder.equatable();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, private!(); $( $rest:tt )* ) => {
+        let $builder = $builder.private();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, protected!(); $( $rest:tt )* ) => {
+        let $builder = $builder.protected();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, public!(); $( $rest:tt )* ) => {
+        let $builder = $builder.public();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, display!(); $( $rest:tt )* ) => {
+        let $builder = $builder.display();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, display!(to_s); $( $rest:tt )* ) => {
+        let $builder = $builder.display().to_s();
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, include!($module:expr); $( $rest:tt )* ) => {
+        let $builder = $builder.include($module);
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, requires!($t:ty); $( $rest:tt )* ) => {
+        let $builder = $builder.requires::<$t>();
+
+        defines!($builder, $( $rest )*);
+    };
+
+    // constants & class variables
+    ( $builder:ident, const!($name:expr, | $mrb:ident | $value:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.constant($name, |$mrb| $value);
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, const!($name:expr, $value:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.constant($name, |mruby| $crate::ToValue::to_value(&$value, mruby));
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, cvar!($name:expr, | $mrb:ident | $value:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_var($name, |$mrb| $value);
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, cvar!($name:expr, $value:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_var($name, |mruby| $crate::ToValue::to_value(&$value, mruby));
+
+        defines!($builder, $( $rest )*);
+    };
+
+    // struct initialize
+    ( @field $field:ident, $value:expr ) => {
+        match $crate::FromValue::from_value($value) {
+            Ok(value) => value,
+            Err(err)  => {
+                let message = match err {
+                    $crate::MrubyError::Cast(expected) => {
+                        format!("expected {} for {}", expected, stringify!($field))
+                    },
+                    err                                => err.to_string()
+                };
+
+                ::std::panic::resume_unwind(Box::new($crate::ArgConversionError(message)))
+            }
+        }
+    };
+    ( $builder:ident, def_struct!( $( $field:ident ),+ ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value; args| {
+            let fields = [$( stringify!($field) ),+].len();
+
+            if args.len() != fields {
+                let message = format!("wrong number of arguments ({} for {})", args.len(), fields);
+
+                ::std::panic::resume_unwind(Box::new($crate::ArgConversionError(message)))
+            }
+
+            let mut args = args.iter();
+
+            slf.init(Self { $( $field: defines!(@field $field, args.next().unwrap()) ),+ })
+        }));
+
+        defines!($builder, $( $rest )*);
+    };
 
     // initialize
-    ( $mruby:expr, $name:ty, def!("initialize", || $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|_mruby, slf: Value| {
+    ( $builder:ident, def!("initialize", || $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*| {
+    ( $builder:ident, def!("initialize", | $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value| {
+    ( $builder:ident, def!("initialize", | $mrb:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),*| {
+    ( $builder:ident, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),*| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
+    };
+
+    // indexed assignment; returns the assigned value like Ruby's []=
+    ( $builder:ident, def!("[]=", | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("[]=", mrfn!(|mruby, $slf: $st, $( $n : $t ),*| {
+            let assigned = $crate::last_arg(&mruby);
+
+            $block;
+
+            assigned
+        }));
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, def!("[]=", | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("[]=", mrfn!(|$mrb, $slf: $st, $( $n : $t ),*| {
+            let assigned = $crate::last_arg(&$mrb);
+
+            $block;
+
+            assigned
+        }));
+
+        defines!($builder, $( $rest )*);
     };
 
     // instance methods
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),*| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),*| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),*| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),*| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // class methods
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),*| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),*| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),*| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),*| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
     
     // initialize block
-    ( $mruby:expr, $name:ty, def!("initialize", | $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; &$blk| {
+    ( $builder:ident, def!("initialize", | ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value; &$blk| {
+            slf.init($block)
+        }));
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, def!("initialize", | $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value; &$blk| {
+    ( $builder:ident, def!("initialize", | $mrb:ident; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value; &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; &$blk| {
+    ( $builder:ident, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // instance methods block
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; &$blk| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; &$blk| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; &$blk| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; &$blk| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // class methods block
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // initialize args
-    ( $mruby:expr, $name:ty, def!("initialize", | $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; $args| {
+    ( $builder:ident, def!("initialize", | ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value; $args| {
+            slf.init($block)
+        }));
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, def!("initialize", | $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; $args| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value; $args| {
+    ( $builder:ident, def!("initialize", | $mrb:ident; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value; $args| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; $args| {
+    ( $builder:ident, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; $args| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // instance methods args
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; $args| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; $args| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // class methods args
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; $args| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; $args| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // initialize args & block
-    ( $mruby:expr, $name:ty, def!("initialize", | $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; $args, &$blk| {
+    ( $builder:ident, def!("initialize", | ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value; $args, &$blk| {
+            slf.init($block)
+        }));
+
+        defines!($builder, $( $rest )*);
+    };
+    ( $builder:ident, def!("initialize", | $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|_mruby, slf: Value, $( $n : $t ),*; $args, &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value; $args, &$blk| {
+    ( $builder:ident, def!("initialize", | $mrb:ident; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value; $args, &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, "initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; $args, &$blk| {
+    ( $builder:ident, def!("initialize", | $mrb:ident, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn("initialize", mrfn!(|$mrb, slf: Value, $( $n : $t ),* ; $args, &$blk| {
             slf.init($block)
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // instance methods args & block
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; $args, &$blk| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
+    ( $builder:ident, def!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; $args, &$blk| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
+    ( $builder:ident, def!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.method_fn($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 
     // class methods args & block
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st; $args, &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|_mruby, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st; $args, &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
-    ( $mruby:expr, $name:ty, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
-        $crate::MrubyImpl::def_class_method_for::<$name, _>(&$mruby, $method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
+    ( $builder:ident, def_self!($method:expr, | $mrb:ident, $slf:ident : $st:tt, $( $n:ident : $t:tt ),* ; $args:ident, &$blk:ident | $block:expr ); $( $rest:tt )* ) => {
+        let $builder = $builder.class_method($method, mrfn!(|$mrb, $slf: $st, $( $n : $t ),* ; $args, &$blk| {
             $block
         }));
 
-        defines!($mruby, $name, $( $rest )*);
+        defines!($builder, $( $rest )*);
     };
 }
 
@@ -817,9 +1219,78 @@ macro_rules! defines {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! mruby_defines {
+    // visibility; needs to come first since @ cannot be parsed as an expression
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, ) => ();
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, private!(); $( $rest:tt )* ) => {
+        mruby_defines!(@visibility Private, $mruby, $class, $( $rest )*);
+    };
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, protected!(); $( $rest:tt )* ) => {
+        mruby_defines!(@visibility Protected, $mruby, $class, $( $rest )*);
+    };
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, public!(); $( $rest:tt )* ) => {
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, def!($method:tt, $( $def:tt )*); $( $rest:tt )* ) => {
+        mruby_defines!($mruby, $class, def!($method, $( $def )*););
+
+        $class.set_visibility($method, $crate::Visibility::$visibility);
+
+        mruby_defines!(@visibility $visibility, $mruby, $class, $( $rest )*);
+    };
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, def_self!($( $def:tt )*); $( $rest:tt )* ) => {
+        mruby_defines!($mruby, $class, def_self!($( $def )*););
+
+        mruby_defines!(@visibility $visibility, $mruby, $class, $( $rest )*);
+    };
+    ( @visibility $visibility:ident, $mruby:expr, $class:expr, $directive:ident!($( $args:tt )*); $( $rest:tt )* ) => {
+        mruby_defines!($mruby, $class, $directive!($( $args )*););
+
+        mruby_defines!(@visibility $visibility, $mruby, $class, $( $rest )*);
+    };
+
     // end recursion
     ( $mruby:expr, $class:expr, ) => ();
 
+    // visibility
+    ( $mruby:expr, $class:expr, private!(); $( $rest:tt )* ) => {
+        mruby_defines!(@visibility Private, $mruby, $class, $( $rest )*);
+    };
+    ( $mruby:expr, $class:expr, protected!(); $( $rest:tt )* ) => {
+        mruby_defines!(@visibility Protected, $mruby, $class, $( $rest )*);
+    };
+    ( $mruby:expr, $class:expr, public!(); $( $rest:tt )* ) => {
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+
+    // modules
+    ( $mruby:expr, $class:expr, include!($module:expr); $( $rest:tt )* ) => {
+        $class.include($crate::MrubyImpl::get_module(&$mruby, $module).unwrap());
+
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+
+    // constants & class variables
+    ( $mruby:expr, $class:expr, const!($name:expr, | $mrb:ident | $value:expr ); $( $rest:tt )* ) => {
+        $class.def_const($name, (|$mrb: &$crate::MrubyType| $value)(&$mruby));
+
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+    ( $mruby:expr, $class:expr, const!($name:expr, $value:expr ); $( $rest:tt )* ) => {
+        $class.def_const($name, $crate::ToValue::to_value(&$value, &$mruby));
+
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+    ( $mruby:expr, $class:expr, cvar!($name:expr, | $mrb:ident | $value:expr ); $( $rest:tt )* ) => {
+        $class.set_class_var($name, (|$mrb: &$crate::MrubyType| $value)(&$mruby));
+
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+    ( $mruby:expr, $class:expr, cvar!($name:expr, $value:expr ); $( $rest:tt )* ) => {
+        $class.set_class_var($name, $crate::ToValue::to_value(&$value, &$mruby));
+
+        mruby_defines!($mruby, $class, $( $rest )*);
+    };
+
     // instance methods
     ( $mruby:expr, $class:expr, def!($method:expr, | $slf:ident : $st:tt | $block:expr ); $( $rest:tt )* ) => {
         $crate::MrubyImpl::def_method(&$mruby, $class.clone(), $method, mrfn!(|_mruby, $slf: $st| {
@@ -1139,91 +1610,653 @@ macro_rules! mruby_defines {
 /// assert_eq!(result.to_str().unwrap(), "hi");
 /// # }
 /// ```
-#[macro_export]
-macro_rules! mrusty_class {
-    ( $name:ty ) => {
-        impl $crate::MrubyFile for $name {
-            fn require(mruby: $crate::MrubyType) {
-                $crate::MrubyImpl::def_class_for::<$name>(&mruby, stringify!($name));
-            }
-        }
-    };
-    ( $name:ty, { $( $rest:tt )* } ) => {
-        impl $crate::MrubyFile for $name {
-            fn require(mruby: $crate::MrubyType) {
-                $crate::MrubyImpl::def_class_for::<$name>(&mruby, stringify!($name));
-
-                defines!(mruby, $name, $( $rest )*);
-            }
-        }
-    };
-    ( $name:ty, $mrname:expr ) => {
-        impl $crate::MrubyFile for $name {
-            fn require(mruby: $crate::MrubyType) {
-                $crate::MrubyImpl::def_class_for::<$name>(&mruby, $mrname);
-            }
-        }
-    };
-    ( $name:ty, $mrname:expr, { $( $rest:tt )* } ) => {
-        impl $crate::MrubyFile for $name {
-            fn require(mruby: $crate::MrubyType) {
-                $crate::MrubyImpl::def_class_for::<$name>(&mruby, $mrname);
-
-                defines!(mruby, $name, $( $rest )*);
-            }
-        }
-    };
-}
-
-/// A `macro` that comes in handy when defining a pure mruby `Class`. It lets you define and
-/// control pure mruby types and returns the newly defined `Class`, unlike `mrusty_class!` which
-/// also handles Rust types.
-///
-/// The macro takes an mruby `MrubyType`, an mruby `Class` name, and a block as arguments. Inside
-/// of the block you can define mruby methods with the `def!` and `def_self!` helpers which are
-/// not visible outside of this macro.
-///
-/// `def!` and `def_self!` are analogous to `mrfn!` which has more usage examples.
-///
-/// # Examples
-///
-/// Use `def!` to define mruby instance methods.
+/// <br/>
 ///
-/// *Note:* `mruby` argument is optional.
+/// Use `comparable!();` to derive `<=>` and `Comparable` from `Ord`, like `def_comparable_for`,
+/// and `equatable!();` to derive `==`, `eql?` and `hash` from `PartialEq` and `Hash`, like
+/// `def_eq_for`. `display!();` defines `inspect` from `Debug`, like `def_display_for`, and
+/// `display!(to_s);` also defines `to_s` from `Display`, like `def_to_s_for`.
 ///
 /// ```
 /// # #[macro_use] extern crate mrusty;
-/// use mrusty::{Mruby, MrubyImpl};
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
 ///
 /// # fn main() {
 /// let mruby = Mruby::new();
 ///
-/// mruby_class!(mruby, "Container", {
-///     def!("initialize", |mruby, slf: Value, v: i32| {
-///         slf.set_var("value", mruby.fixnum(v));
+/// #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// struct Cont {
+///     value: i32
+/// };
 ///
-///         slf
+/// mrusty_class!(Cont, "Container", {
+///     def!("initialize", |v: i32| {
+///         Cont { value: v }
 ///     });
 ///
-///     def!("value", |mruby, slf: Value| {
-///         slf.get_var("value").unwrap()
-///     });
+///     comparable!();
+///     equatable!();
 /// });
 ///
-/// let result = mruby.run("Container.new(3).value").unwrap();
+/// Cont::require(mruby.clone()); // needs to be required manually
 ///
-/// assert_eq!(result.to_i32().unwrap(), 3);
+/// let result = mruby.run("Container.new(2) > Container.new(1)").unwrap();
+///
+/// assert_eq!(result.to_bool().unwrap(), true);
 /// # }
 /// ```
 /// <br/>
 ///
-/// Use `def_self!` to define mruby class methods.
-///
-/// *Note:* `mruby` argument is optional.
+/// `private!();` and `protected!();` make the `def!`s after them private or protected, like
+/// `def_private_method_for` and `def_protected_method_for`, until `public!();`.
 ///
 /// ```
 /// # #[macro_use] extern crate mrusty;
-/// use mrusty::{Mruby, MrubyImpl};
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Cont;
+///
+/// mrusty_class!(Cont, "Container", {
+///     def!("initialize", || {
+///         Cont
+///     });
+///
+///     private!();
+///
+///     def!("secret", |mruby, _slf: Value| {
+///         mruby.fixnum(3)
+///     });
+/// });
+///
+/// Cont::require(mruby.clone()); // needs to be required manually
+///
+/// assert!(mruby.run("Container.new.secret").is_err());
+/// assert_eq!(mruby.run("Container.new.send(:secret)").unwrap().to_i32().unwrap(), 3);
+/// # }
+/// ```
+/// <br/>
+///
+/// `include!` mixes an already defined mruby `Module` into the `Class`.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Cont;
+///
+/// mrusty_class!(Cont, "Container", {
+///     def!("initialize", || {
+///         Cont
+///     });
+///
+///     def!("each", |mruby, slf: Value; &blk| {
+///         for i in 1..4 {
+///             blk.call("call", vec![mruby.fixnum(i)]).unwrap();
+///         }
+///
+///         slf
+///     });
+///
+///     include!("Enumerable");
+/// });
+///
+/// Cont::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("Container.new.map { |i| i * 2 }.last").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 6);
+/// # }
+/// ```
+/// <br/>
+///
+/// `"Name" < "Superclass"` makes the `Class` inherit from an already defined mruby `Class`, like
+/// `def_subclass_for`. `requires!` requires another `MrubyFile` first unless its `Class` is
+/// already defined.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Character;
+/// struct Enemy;
+///
+/// mrusty_class!(Character, "Character", {
+///     def!("initialize", || {
+///         Character
+///     });
+/// });
+///
+/// mrusty_class!(Enemy, "Enemy" < "Character", {
+///     requires!(Character);
+///
+///     def!("initialize", || {
+///         Enemy
+///     });
+/// });
+///
+/// Enemy::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("Enemy.new.is_a?(Character)").unwrap();
+///
+/// assert_eq!(result.to_bool().unwrap(), true);
+/// # }
+/// ```
+/// <br/>
+///
+/// `const!` and `cvar!` define constants and class variables on the `Class` from a value
+/// implementing `ToValue` or a closure taking `mruby`.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Cont;
+///
+/// mrusty_class!(Cont, "Container", {
+///     const!("MAX_SPEED", 300);
+///     const!("NAMES", |mruby| mruby.array(vec![mruby.string("cont")]));
+///     cvar!("@@instances", 0);
+/// });
+///
+/// Cont::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("Container::MAX_SPEED").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 300);
+/// # }
+/// ```
+/// <br/>
+///
+/// `def_struct!` generates an `initialize` that takes one argument per listed field, in order,
+/// converting each with `FromValue`. Wrong argument counts or types raise an `ArgumentError`.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Player {
+///     name: String,
+///     level: i32
+/// };
+///
+/// mrusty_class!(Player, {
+///     def_struct!(name, level);
+///
+///     def!("level", |mruby, slf: (&Player)| {
+///         mruby.fixnum(slf.level)
+///     });
+/// });
+///
+/// Player::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("Player.new('hero', 3).level").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 3);
+/// # }
+/// ```
+/// <br/>
+///
+/// Operators are defined by name, unary ones like `-@` taking only `slf`. `"[]="` returns the
+/// assigned value regardless of its block, and defining `"coerce"` makes `Fixnum` and `Float`
+/// arithmetic with the type, like `2 * v`, work.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// #[derive(PartialEq)]
+/// struct Vector2 {
+///     x: f64,
+///     y: f64
+/// };
+///
+/// mrusty_class!(Vector2, {
+///     def!("initialize", |x: f64, y: f64| {
+///         Vector2 { x: x, y: y }
+///     });
+///
+///     def!("+", |mruby, slf: (&Vector2), other: (&Vector2)| {
+///         mruby.obj(Vector2 { x: slf.x + other.x, y: slf.y + other.y })
+///     });
+///
+///     def!("*", |mruby, slf: (&Vector2), k: f64| {
+///         mruby.obj(Vector2 { x: slf.x * k, y: slf.y * k })
+///     });
+///
+///     def!("==", |mruby, slf: (&Vector2), other: (&Vector2)| {
+///         mruby.bool(*slf == *other)
+///     });
+///
+///     def!("[]", |mruby, slf: (&Vector2), i: i32| {
+///         mruby.float(if i == 0 { slf.x } else { slf.y })
+///     });
+///
+///     def!("[]=", |slf: (&mut Vector2), i: i32, v: f64| {
+///         if i == 0 { slf.x = v } else { slf.y = v }
+///     });
+///
+///     def!("coerce", |mruby, slf: Value, k: Value| {
+///         mruby.array(vec![slf, k])
+///     });
+/// });
+///
+/// Vector2::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("
+///   v = Vector2.new(1.0, 2.0) + Vector2.new(1.0, 1.0)
+///   v[1] = 4.0
+///
+///   2 * v == Vector2.new(4.0, 8.0)
+/// ").unwrap();
+///
+/// assert_eq!(result.to_bool().unwrap(), true);
+/// # }
+/// ```
+/// <br/>
+///
+/// Instantiations of generic types, like `Wrapper<i32>`, are classes of their own and need an
+/// explicit name. Prefixing the type with its generic parameters instead implements `MrubyFile`
+/// for every instantiation at once, naming each through an expression that can use the
+/// parameters. Their bounds cannot contain angle brackets; use a helper trait instead.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{FromValue, Mruby, MrubyFile, MrubyImpl, ToValue};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// trait Named {
+///     const NAME: &'static str;
+/// }
+///
+/// impl Named for i32 {
+///     const NAME: &'static str = "Int";
+/// }
+///
+/// impl Named for f64 {
+///     const NAME: &'static str = "Float";
+/// }
+///
+/// struct Pair<T> {
+///     first: T,
+///     second: T
+/// }
+///
+/// mrusty_class!(<T: Named + FromValue + ToValue + Clone + 'static> Pair<T>,
+///               format!("{}Pair", T::NAME), {
+///     def!("initialize", |first: T, second: T| {
+///         Pair { first: first, second: second }
+///     });
+///
+///     def!("first", |mruby, slf: (&Pair<T>)| {
+///         mruby.value(slf.first.clone())
+///     });
+/// });
+///
+/// Pair::<i32>::require(mruby.clone());
+/// Pair::<f64>::require(mruby.clone());
+///
+/// let result = mruby.run("[IntPair.new(1, 2).first, FloatPair.new(0.5, 1.0).first]").unwrap();
+///
+/// assert_eq!(result.to_vec().unwrap()[0].to_i32().unwrap(), 1);
+/// assert_eq!(result.to_vec().unwrap()[1].to_f64().unwrap(), 0.5);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! mrusty_class {
+    // generics are collected up to the closing >, so bounds cannot contain angle brackets
+    ( @generics [ $( $gen:tt )* ] > $name:ty, $mrname:expr ) => {
+        impl< $( $gen )* > $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let name = $mrname;
+
+                $crate::MrubyImpl::def_class_for::<$name>(&mruby,
+                                                          ::std::convert::AsRef::<str>::as_ref(&name));
+            }
+        }
+    };
+    ( @generics [ $( $gen:tt )* ] > $name:ty, $mrname:expr, { $( $rest:tt )* } ) => {
+        impl< $( $gen )* > $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let name = $mrname;
+                let class = $crate::MrubyImpl::class_builder::<$name>(
+                    &mruby, ::std::convert::AsRef::<str>::as_ref(&name)
+                );
+
+                defines!(class, $( $rest )*);
+
+                class.define();
+            }
+        }
+    };
+    ( @generics [ $( $gen:tt )* ] $next:tt $( $rest:tt )* ) => {
+        mrusty_class!(@generics [ $( $gen )* $next ] $( $rest )*);
+    };
+    ( < $( $rest:tt )* ) => {
+        mrusty_class!(@generics [] $( $rest )*);
+    };
+    ( $name:ty ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                $crate::MrubyImpl::def_class_for::<$name>(&mruby, stringify!($name));
+            }
+        }
+    };
+    ( $name:ty, { $( $rest:tt )* } ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let class = $crate::MrubyImpl::class_builder::<$name>(&mruby, stringify!($name));
+
+                defines!(class, $( $rest )*);
+
+                class.define();
+            }
+        }
+    };
+    ( $name:ty, $mrname:tt < $superclass:tt, { $( $rest:tt )* } ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let class = $crate::MrubyImpl::class_builder::<$name>(&mruby, $mrname)
+                    .superclass($superclass);
+
+                defines!(class, $( $rest )*);
+
+                class.define();
+            }
+        }
+    };
+    ( $name:ty, $mrname:expr ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                $crate::MrubyImpl::def_class_for::<$name>(&mruby, $mrname);
+            }
+        }
+    };
+    ( $name:ty, $mrname:expr, { $( $rest:tt )* } ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let class = $crate::MrubyImpl::class_builder::<$name>(&mruby, $mrname);
+
+                defines!(class, $( $rest )*);
+
+                class.define();
+            }
+        }
+    };
+}
+
+/// A `macro` that reflects a plain-old-data Rust `struct` as an mruby `Class`. Like
+/// `mrusty_class!`, it implements `MrubyFile` for the type, but it also generates `initialize`
+/// taking all fields in order, readers and writers for every field, `==`, `to_h` (with `Symbol`
+/// keys) and `inspect`.
+///
+/// Fields can be `bool`, `i32`, `f32`, `f64` or `String`. An optional block can define further
+/// methods with `def!` and `def_self!`, just like in `mrusty_class!`.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Point {
+///     x: f64,
+///     y: f64
+/// };
+///
+/// def_data_class!(Point { x: f64, y: f64 }, "Point");
+///
+/// Point::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("
+///   point = Point.new(1.0, 2.0)
+///   point.y = 3.0
+///
+///   point.to_h[:y]
+/// ").unwrap();
+///
+/// assert_eq!(result.to_f64().unwrap(), 3.0);
+/// # }
+/// ```
+///
+/// `String` fields are read and written as mruby `String`s.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// struct Label {
+///     text: String,
+///     size: i32
+/// };
+///
+/// def_data_class!(Label { text: String, size: i32 }, "Label");
+///
+/// Label::require(mruby.clone());
+///
+/// let result = mruby.run("
+///   label = Label.new('hi', 12)
+///   label.text = label.text + '!'
+///
+///   label.inspect
+/// ").unwrap();
+///
+/// assert_eq!(result.to_str().unwrap(), "#<Label text=\"hi!\", size=12>");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! def_data_class {
+    ( @value $mruby:expr, bool, $value:expr )   => ($mruby.bool($value));
+    ( @value $mruby:expr, i32, $value:expr )    => ($mruby.fixnum($value));
+    ( @value $mruby:expr, f64, $value:expr )    => ($mruby.float($value));
+    ( @value $mruby:expr, f32, $value:expr )    => ($mruby.float32($value));
+    ( @value $mruby:expr, String, $value:expr ) => ($mruby.string(&$value));
+
+    ( $name:ident { $( $field:ident : $t:tt ),+ }, $mrname:expr ) => {
+        def_data_class!($name { $( $field : $t ),+ }, $mrname, {});
+    };
+    ( $name:ident { $( $field:ident : $t:tt ),+ }, $mrname:expr, { $( $rest:tt )* } ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                let class = $crate::MrubyImpl::class_builder::<$name>(&mruby, $mrname);
+
+                let class = class.method_fn("initialize", mrfn!(|_mruby, slf: Value, $( $field : $t ),+| {
+                    slf.init($name { $( $field: $field ),+ })
+                }));
+
+                $(
+                    let class = class.method_fn(stringify!($field), mrfn!(|mruby, slf: (&$name)| {
+                        def_data_class!(@value mruby, $t, slf.$field)
+                    }));
+
+                    let class = class.method_fn(concat!(stringify!($field), "="), mrfn!(|mruby, slf: (&mut $name), value: $t| {
+                        slf.$field = value;
+
+                        def_data_class!(@value mruby, $t, slf.$field)
+                    }));
+                )+
+
+                let class = class.method_fn("==", mrfn!(|mruby, slf: (&$name), other: Value| {
+                    match other.to_obj::<$name>() {
+                        Ok(other) => {
+                            let other = other.borrow();
+
+                            mruby.bool(true $( && slf.$field == other.$field )+)
+                        },
+                        Err(_)    => mruby.bool(false)
+                    }
+                }));
+
+                let class = class.method_fn("to_h", mrfn!(|mruby, slf: (&$name)| {
+                    let hash = $crate::MrubyImpl::get_class(&mruby, "Hash").unwrap().to_value();
+                    let hash = hash.call("new", vec![]).unwrap();
+
+                    $(
+                        hash.call("[]=", vec![mruby.symbol(stringify!($field)),
+                                              def_data_class!(@value mruby, $t, slf.$field)]).unwrap();
+                    )+
+
+                    hash
+                }));
+
+                let class = class.method_fn("inspect", mrfn!(|mruby, slf: (&$name)| {
+                    let fields = vec![$( format!("{}={:?}", stringify!($field), slf.$field) ),+];
+
+                    mruby.string(&format!("#<{} {}>", $mrname, fields.join(", ")))
+                }));
+
+                defines!(class, $( $rest )*);
+
+                class.define();
+            }
+        }
+    };
+}
+
+/// A `macro` that reflects a Rust `enum` of unit variants as an mruby `Module`. Like
+/// `mrusty_class!`, it implements `MrubyFile` for the type, which defines the `Module` with a
+/// `Fixnum` constant for every variant, in order, and a `from_sym` method which converts the
+/// variant's `Symbol` to its constant. Constants are upper snake case and `Symbol`s are snake
+/// case, e.g. `VeryHard` becomes `VERY_HARD` and `:very_hard`.
+///
+/// It also implements `ToValue`, which returns the constant, and `FromValue`, which accepts both
+/// the constant and the `Symbol`, so the `enum` can be used as an `mrfn!` argument type. Invalid
+/// arguments raise an `ArgumentError` listing the valid `Symbol`s.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyFile, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Difficulty {
+///     Easy,
+///     Normal,
+///     Hard
+/// }
+///
+/// mruby_enum!(Difficulty, { Easy, Normal, Hard });
+///
+/// Difficulty::require(mruby.clone()); // needs to be required manually
+///
+/// let result = mruby.run("[Difficulty::HARD, Difficulty.from_sym(:normal)]").unwrap();
+///
+/// assert_eq!(result.to_rust::<Vec<Difficulty>>().unwrap(),
+///            vec![Difficulty::Hard, Difficulty::Normal]);
+/// assert_eq!(mruby.run(":easy").unwrap().to_rust::<Difficulty>().unwrap(), Difficulty::Easy);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! mruby_enum {
+    ( $name:ident, { $( $variant:ident ),+ } ) => {
+        mruby_enum!($name, stringify!($name), { $( $variant ),+ });
+    };
+    ( $name:ident, $mrname:expr, { $( $variant:ident ),+ } ) => {
+        impl $crate::MrubyFile for $name {
+            fn require(mruby: $crate::MrubyType) {
+                $crate::def_enum(&mruby, $mrname, &[$( stringify!($variant) ),+]);
+            }
+        }
+
+        impl $crate::ToValue for $name {
+            fn to_value(&self, mruby: &$crate::MrubyType) -> $crate::Value {
+                let variant = match *self {
+                    $( $name::$variant => stringify!($variant) ),+
+                };
+                let index = [$( stringify!($variant) ),+].iter().position(|&v| v == variant);
+
+                $crate::MrubyImpl::fixnum(mruby, index.unwrap() as i32)
+            }
+        }
+
+        impl $crate::FromValue for $name {
+            fn from_value(value: &$crate::Value) -> Result<$name, $crate::MrubyError> {
+                let index = $crate::enum_index(value, $mrname, &[$( stringify!($variant) ),+])?;
+
+                Ok(vec![$( $name::$variant ),+].swap_remove(index))
+            }
+        }
+    };
+}
+
+/// A `macro` that comes in handy when defining a pure mruby `Class`. It lets you define and
+/// control pure mruby types and returns the newly defined `Class`, unlike `mrusty_class!` which
+/// also handles Rust types.
+///
+/// The macro takes an mruby `MrubyType`, an mruby `Class` name, and a block as arguments. Inside
+/// of the block you can define mruby methods with the `def!` and `def_self!` helpers which are
+/// not visible outside of this macro.
+///
+/// `def!` and `def_self!` are analogous to `mrfn!` which has more usage examples.
+///
+/// # Examples
+///
+/// Use `def!` to define mruby instance methods.
+///
+/// *Note:* `mruby` argument is optional.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// mruby_class!(mruby, "Container", {
+///     def!("initialize", |mruby, slf: Value, v: i32| {
+///         slf.set_var("value", mruby.fixnum(v));
+///
+///         slf
+///     });
+///
+///     def!("value", |mruby, slf: Value| {
+///         slf.get_var("value").unwrap()
+///     });
+/// });
+///
+/// let result = mruby.run("Container.new(3).value").unwrap();
+///
+/// assert_eq!(result.to_i32().unwrap(), 3);
+/// # }
+/// ```
+/// <br/>
+///
+/// Use `def_self!` to define mruby class methods.
+///
+/// *Note:* `mruby` argument is optional.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl};
 ///
 /// # fn main() {
 /// let mruby = Mruby::new();
@@ -1241,6 +2274,38 @@ macro_rules! mrusty_class {
 /// ```
 /// <br/>
 ///
+/// `private!();`, `protected!();` and `public!();` set the visibility of the `def!`s after them,
+/// while `include!`, `const!` and `cvar!` work like in `mrusty_class!`.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// mruby_class!(mruby, "Container", {
+///     protected!();
+///
+///     def!("value", |mruby, _slf: Value| {
+///         mruby.fixnum(3)
+///     });
+/// });
+///
+/// mruby.run("
+///   class Container
+///     def value_of(other)
+///       other.value
+///     end
+///   end
+/// ").unwrap();
+///
+/// assert_eq!(mruby.run("Container.new.value_of(Container.new)").unwrap().to_i32().unwrap(), 3);
+/// assert!(mruby.run("Container.new.value").is_err());
+/// # }
+/// ```
+/// <br/>
+///
 /// `mruby_class!` also works on mruby primitive types.
 ///
 /// ```
@@ -1290,6 +2355,95 @@ macro_rules! mruby_class {
     };
 }
 
+/// Not meant to be called directly.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! mruby_module_defines {
+    // end recursion
+    ( $mruby:expr, $module:expr, ) => ();
+
+    // module functions
+    ( $mruby:expr, $module:expr, def_self!($method:expr, | $slf:ident : $( $def:tt )* ); $( $rest:tt )* ) => {
+        $crate::MrubyImpl::def_module_method(&$mruby, $module.clone(), $method, mrfn!(|_mruby, $slf : $( $def )*));
+
+        mruby_module_defines!($mruby, $module, $( $rest )*);
+    };
+    ( $mruby:expr, $module:expr, def_self!($method:expr, $( $def:tt )* ); $( $rest:tt )* ) => {
+        $crate::MrubyImpl::def_module_method(&$mruby, $module.clone(), $method, mrfn!($( $def )*));
+
+        mruby_module_defines!($mruby, $module, $( $rest )*);
+    };
+
+    // modules
+    ( $mruby:expr, $module:expr, include!($other:expr); $( $rest:tt )* ) => {
+        $module.include($crate::MrubyImpl::get_module(&$mruby, $other).unwrap());
+
+        mruby_module_defines!($mruby, $module, $( $rest )*);
+    };
+}
+
+/// A `macro` that comes in handy when defining a pure mruby `Module`, like `mruby_class!` does
+/// for `Class`es. It returns the newly defined `Module`.
+///
+/// The macro takes an mruby `MrubyType`, an mruby `Module` name, and a block as arguments.
+/// Inside of the block you can define module functions with the `def_self!` helper and include
+/// other modules with `include!`.
+///
+/// # Examples
+///
+/// *Note:* `mruby` argument is optional.
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyImpl};
+///
+/// # fn main() {
+/// let mruby = Mruby::new();
+///
+/// let module = mruby_module!(mruby, "MathUtils", {
+///     def_self!("clamp", |mruby, _slf: Value, v: f64, lo: f64, hi: f64| {
+///         mruby.float(v.max(lo).min(hi))
+///     });
+/// });
+///
+/// let result = mruby.run("MathUtils.clamp(3.5, 0.0, 1.0)").unwrap();
+///
+/// assert_eq!(module.to_str(), "MathUtils");
+/// assert_eq!(result.to_f64().unwrap(), 1.0);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! mruby_module {
+    ( $mruby:expr, $mrname:expr ) => {
+        $crate::MrubyImpl::def_module(&$mruby, $mrname)
+    };
+    ( $mruby:expr, $mrname:expr, { $( $rest:tt )* } ) => {
+        {
+            let module = $crate::MrubyImpl::def_module(&$mruby, $mrname);
+
+            mruby_module_defines!($mruby, module, $( $rest )*);
+
+            module
+        }
+    };
+}
+
+/// A `macro` that bakes compiled (.mrb) mruby gem bytecode into the binary so that it can be
+/// passed to `load_gem`. `path` is resolved relative to the current file, like `include_bytes!`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mruby = Mruby::new();
+/// mruby.load_gem("gem", include_gem!("gem.mrb")).unwrap();
+/// ```
+#[macro_export]
+macro_rules! include_gem {
+    ( $path:expr ) => {
+        &include_bytes!($path)[..]
+    };
+}
+
 #[path="tests/macros.rs"]
 #[cfg(test)]
 mod tests;
@@ -5,17 +5,324 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+#include <assert.h>
+#include <limits.h>
+#include <stdio.h>
 #include <stdlib.h>
+#include <string.h>
 
 #include <mruby.h>
 #include <mruby/array.h>
 #include <mruby/class.h>
+#include <mruby/compile.h>
 #include <mruby/data.h>
 #include <mruby/error.h>
+#include <mruby/hash.h>
+#include <mruby/irep.h>
+#include <mruby/khash.h>
+#include <mruby/opcode.h>
 #include <mruby/proc.h>
+#include <mruby/range.h>
+#include <mruby/string.h>
+#include <mruby/throw.h>
 #include <mruby/value.h>
 #include <mruby/variable.h>
 
+// Every block handed to mruby is prefixed with its size so that the heap can
+// account for frees and reallocations. The header is padded to keep blocks
+// aligned for doubles and pointers.
+#define MRB_EXT_HEADER 16
+
+// Once the limit has been hit, mruby still needs some room to raise and
+// rescue NoMemoryError. Each raise lets allocations through up to this much
+// over the current usage, but never further than twice this over the limit.
+#define MRB_EXT_SLACK (256 * 1024)
+
+// Wrappers of Rust objects by object address. Wrappers are not marked,
+// so every collected one removes itself from its data type's free function.
+KHASH_DECLARE(mrb_ext_shared, uint64_t, mrb_value, TRUE)
+KHASH_DEFINE(mrb_ext_shared, uint64_t, mrb_value, TRUE, kh_int64_hash_func,
+  kh_int64_hash_equal)
+
+// Marks the Values held by the Rust objects of one data type.
+struct mrb_ext_data_mark {
+  const mrb_data_type* type;
+  void (*mark)(struct mrb_state*, void*);
+};
+
+// Per-state bookkeeping for the memory and instruction limits, the data marks,
+// the shared wrappers and the finalizers, stored in allocf_ud since the
+// allocator needs it before mrb_state exists.
+struct mrb_ext_limits {
+  size_t used;
+  size_t limit;
+  size_t ceiling;
+  uint64_t instructions;
+  uint64_t instruction_limit;
+  mrb_bool instructions_exceeded;
+  struct mrb_ext_data_mark* marks;
+  size_t marks_len;
+  khash_t(mrb_ext_shared)* shared;
+  mrb_bool finalizing;
+};
+
+static void* mrb_ext_allocf(struct mrb_state* mrb, void* p, size_t size,
+  void* ud) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) ud;
+  char* block = (char*) p;
+  size_t old = 0;
+  size_t used;
+
+  if (mrb && mrb->gc.out_of_memory) {
+    // mruby does not survive two failures in a row, so nothing is refused
+    // and room is made while it is still raising the previous one.
+    limits->ceiling = limits->used + MRB_EXT_SLACK;
+
+    if (limits->ceiling > limits->limit + 2 * MRB_EXT_SLACK) {
+      limits->ceiling = limits->limit + 2 * MRB_EXT_SLACK;
+    }
+  }
+
+  if (block) {
+    block -= MRB_EXT_HEADER;
+    old = *(size_t*) block;
+  }
+
+  // Finalizers run while the GC frees objects, when calling back into mruby
+  // is not safe.
+  assert(!(limits->finalizing && size > old));
+
+  if (size == 0) {
+    limits->used -= old;
+    free(block);
+
+    return NULL;
+  }
+
+  used = limits->used - old + size;
+
+  // Refusing the allocation makes mruby run a full GC and, failing that,
+  // raise NoMemoryError.
+  if (limits->limit && size > old && used > limits->limit && used > limits->ceiling &&
+      !(mrb && mrb->gc.out_of_memory)) {
+    return NULL;
+  }
+
+  block = (char*) realloc(block, size + MRB_EXT_HEADER);
+
+  if (!block) return NULL;
+
+  limits->used = used;
+  *(size_t*) block = size;
+
+  return block + MRB_EXT_HEADER;
+}
+
+struct mrb_state* mrb_ext_open() {
+  struct mrb_ext_limits* limits = calloc(1, sizeof(struct mrb_ext_limits));
+  struct mrb_state* mrb = mrb_open_allocf(mrb_ext_allocf, limits);
+  struct RClass* nomem;
+
+  if (!mrb) {
+    free(limits);
+
+    return NULL;
+  }
+
+  nomem = mrb_define_class(mrb, "NoMemoryError", mrb->eException_class);
+  mrb->nomem_err = mrb_obj_ptr(mrb_exc_new_str_lit(mrb, nomem,
+    "failed to allocate memory"));
+
+  return mrb;
+}
+
+void mrb_ext_close(struct mrb_state* mrb) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  if (limits->shared) {
+    kh_destroy(mrb_ext_shared, mrb, limits->shared);
+    limits->shared = NULL;
+  }
+
+  mrb_close(mrb);
+  free(limits->marks);
+  free(limits);
+}
+
+void mrb_ext_set_finalizing(struct mrb_state* mrb, mrb_bool finalizing) {
+  ((struct mrb_ext_limits*) mrb->allocf_ud)->finalizing = finalizing;
+}
+
+mrb_bool mrb_ext_shared_get(struct mrb_state* mrb, const void* obj,
+  mrb_value* wrapper) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+  khiter_t k;
+
+  if (!limits->shared) return FALSE;
+
+  k = kh_get(mrb_ext_shared, mrb, limits->shared, (uint64_t) (uintptr_t) obj);
+
+  if (k == kh_end(limits->shared)) return FALSE;
+
+  *wrapper = kh_value(limits->shared, k);
+
+  return TRUE;
+}
+
+void mrb_ext_shared_set(struct mrb_state* mrb, const void* obj,
+  mrb_value wrapper) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+  khiter_t k;
+
+  if (!limits->shared) {
+    limits->shared = kh_init(mrb_ext_shared, mrb);
+  }
+
+  k = kh_put(mrb_ext_shared, mrb, limits->shared, (uint64_t) (uintptr_t) obj);
+  kh_value(limits->shared, k) = wrapper;
+}
+
+void mrb_ext_shared_remove(struct mrb_state* mrb, const void* obj) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+  khiter_t k;
+
+  if (!limits || !limits->shared) return;
+
+  k = kh_get(mrb_ext_shared, mrb, limits->shared, (uint64_t) (uintptr_t) obj);
+
+  if (k != kh_end(limits->shared)) {
+    kh_del(mrb_ext_shared, mrb, limits->shared, k);
+  }
+}
+
+// Defined in gc.c by build.rs, which has change_gen_gc_mode in scope.
+void mrb_ext_gc_incremental_mode(struct mrb_state* mrb);
+
+void mrb_ext_set_data_mark(struct mrb_state* mrb, const mrb_data_type* type,
+  void (*mark)(struct mrb_state*, void*)) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+  struct mrb_ext_data_mark* marks;
+  size_t i;
+
+  for (i = 0; i < limits->marks_len; i++) {
+    if (limits->marks[i].type == type) {
+      limits->marks[i].mark = mark;
+
+      return;
+    }
+  }
+
+  marks = realloc(limits->marks,
+    (limits->marks_len + 1) * sizeof(struct mrb_ext_data_mark));
+
+  if (!marks) return;
+
+  marks[limits->marks_len].type = type;
+  marks[limits->marks_len].mark = mark;
+
+  limits->marks = marks;
+  limits->marks_len++;
+
+  // Rust objects change their Values without write barriers, which only
+  // stays safe if no mruby code runs in the middle of a GC. Switching off the
+  // generational mode and taking steps large enough to cover the whole heap
+  // makes every GC run to completion at once. The GC module may have been
+  // removed by a sandbox, so the mode is changed directly, and from now on
+  // mrb_ext_gc_locked keeps GC's setters from changing it back.
+  mrb_ext_gc_incremental_mode(mrb);
+  mrb->gc.step_ratio = INT_MAX / 10;
+}
+
+mrb_bool mrb_ext_gc_locked(struct mrb_state* mrb) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  return limits && limits->marks_len > 0;
+}
+
+// Called by the GC for every data object it traces, through the hook build.rs
+// adds to gc.c since mruby's data types have no mark function.
+void mrb_ext_data_mark(struct mrb_state* mrb, struct RData* data) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+  size_t i;
+
+  if (!limits || !data->data) return;
+
+  for (i = 0; i < limits->marks_len; i++) {
+    if (limits->marks[i].type == data->type) {
+      limits->marks[i].mark(mrb, data->data);
+
+      return;
+    }
+  }
+}
+
+void mrb_ext_gc_mark_value(struct mrb_state* mrb, mrb_value value) {
+  mrb_gc_mark_value(mrb, value);
+}
+
+size_t mrb_ext_memory_used(struct mrb_state* mrb) {
+  return ((struct mrb_ext_limits*) mrb->allocf_ud)->used;
+}
+
+void mrb_ext_set_memory_limit(struct mrb_state* mrb, size_t limit) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  limits->limit = limit;
+  limits->ceiling = 0;
+}
+
+void mrb_ext_gc_enable(struct mrb_state* mrb) {
+  mrb->gc.disabled = FALSE;
+}
+
+void mrb_ext_gc_disable(struct mrb_state* mrb) {
+  mrb->gc.disabled = TRUE;
+}
+
+// The instruction limit relies on the VM's code fetch hook, which is only
+// compiled in with the instruction-limit feature.
+#ifdef MRB_ENABLE_DEBUG_HOOK
+static void mrb_ext_count_instruction(struct mrb_state* mrb,
+  struct mrb_irep* irep, mrb_code* pc, mrb_value* regs) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  (void) irep;
+  (void) pc;
+  (void) regs;
+
+  // Once exhausted, every further instruction raises so that rescue clauses
+  // cannot keep the script running.
+  if (limits->instructions == 0) {
+    limits->instructions_exceeded = TRUE;
+    mrb_raise(mrb, E_RUNTIME_ERROR, "instruction limit exceeded");
+  }
+
+  limits->instructions--;
+}
+
+void mrb_ext_set_instruction_limit(struct mrb_state* mrb, uint64_t count) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  limits->instructions = count;
+  limits->instruction_limit = count;
+  limits->instructions_exceeded = FALSE;
+  mrb->code_fetch_hook = count ? mrb_ext_count_instruction : NULL;
+}
+
+void mrb_ext_reset_instruction_counter(struct mrb_state* mrb) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  limits->instructions = limits->instruction_limit;
+  limits->instructions_exceeded = FALSE;
+}
+
+mrb_bool mrb_ext_instruction_limit_exceeded(struct mrb_state* mrb) {
+  struct mrb_ext_limits* limits = (struct mrb_ext_limits*) mrb->allocf_ud;
+
+  return limits->instructions_exceeded;
+}
+#endif
+
 void* mrb_ext_get_ud(struct mrb_state* mrb) {
   return mrb->ud;
 }
@@ -68,6 +375,27 @@ const char* mrb_ext_sym2name(struct mrb_state* mrb, mrb_value value) {
   return mrb_sym2name(mrb, mrb_symbol(value));
 }
 
+mrb_sym mrb_ext_sym_id(mrb_value value) {
+  return mrb_symbol(value);
+}
+
+mrb_value mrb_ext_str_new_static_frozen(struct mrb_state* mrb, const char* string,
+                                        size_t len) {
+  mrb_value value = mrb_str_new_static(mrb, string, len);
+
+  RSTR_SET_FROZEN_FLAG(mrb_str_ptr(value));
+
+  return value;
+}
+
+const char* mrb_ext_str_ptr(mrb_value value) {
+  return RSTRING_PTR(value);
+}
+
+size_t mrb_ext_str_len(mrb_value value) {
+  return RSTRING_LEN(value);
+}
+
 mrb_value mrb_ext_sym_new(struct mrb_state* mrb, const char* string,
                           size_t len) {
   mrb_value value;
@@ -130,10 +458,387 @@ int mrb_ext_ary_len(struct mrb_state* mrb, mrb_value array) {
   return mrb_ary_len(mrb, array);
 }
 
+// Mirrors the private declaration in mruby's hash.c so that the table's size
+// can be read without allocating.
+typedef struct {
+  mrb_value v;
+  mrb_int n;
+} mrb_hash_value;
+
+KHASH_DECLARE(ht, mrb_value, mrb_hash_value, TRUE)
+
+size_t mrb_ext_hash_size(mrb_value hash) {
+  khash_t(ht)* table = RHASH_TBL(hash);
+
+  return table ? kh_size(table) : 0;
+}
+
+// mruby's own mrb_hash_values is static.
+mrb_value mrb_ext_hash_values(struct mrb_state* mrb, mrb_value hash) {
+  khash_t(ht)* table = RHASH_TBL(hash);
+  khiter_t k;
+  mrb_value array;
+
+  if (!table) return mrb_ary_new(mrb);
+
+  array = mrb_ary_new_capa(mrb, kh_size(table));
+
+  for (k = kh_begin(table); k != kh_end(table); k++) {
+    if (kh_exist(table, k)) {
+      mrb_hash_value entry = kh_value(table, k);
+
+      mrb_ary_set(mrb, array, entry.n, entry.v);
+    }
+  }
+
+  return array;
+}
+
 unsigned int mrb_ext_get_mid(struct mrb_state* mrb) {
   return mrb_get_mid(mrb);
 }
 
+struct RClass* mrb_ext_get_target_class(struct mrb_state* mrb) {
+  struct RClass* class = mrb->c->ci->target_class;
+
+  if (class->tt == MRB_TT_ICLASS) {
+    return class->c;
+  }
+
+  return class;
+}
+
+// Stores the self of the Ruby code that called the current method in caller
+// and returns TRUE if it was called by its name, as opposed to through send,
+// an operator instruction or from C.
+mrb_bool mrb_ext_named_caller(struct mrb_state* mrb, mrb_value* caller) {
+  mrb_callinfo* ci = mrb->c->ci;
+  struct RProc* proc;
+  mrb_code code;
+
+  if (!ci->pc || ci == mrb->c->cibase) return FALSE;
+
+  proc = ci[-1].proc;
+
+  if (!proc || MRB_PROC_CFUNC_P(proc)) return FALSE;
+
+  code = ci->pc[-1];
+
+  if (GET_OPCODE(code) != OP_SEND && GET_OPCODE(code) != OP_SENDB) return FALSE;
+  if (proc->body.irep->syms[GETARG_B(code)] != ci->mid) return FALSE;
+
+  *caller = ci->stackent[0];
+
+  return TRUE;
+}
+
+// Keeps the method that class itself defines for mid, if any, before a Rust
+// method replaces it, so that mrb_ext_call_super can still call it. Only the
+// first method replaced is kept.
+void mrb_ext_keep_previous_method(struct mrb_state* mrb, struct RClass* class,
+                                  mrb_sym mid) {
+  struct RClass* owner = class;
+  struct RProc* method = mrb_method_search_vm(mrb, &owner, mid);
+  mrb_sym sym = mrb_intern_lit(mrb, "__previous_methods__");
+  mrb_value previous;
+
+  if (!method || owner != class) return;
+
+  previous = mrb_iv_get(mrb, mrb_obj_value(class), sym);
+
+  if (!mrb_hash_p(previous)) {
+    previous = mrb_hash_new(mrb);
+    mrb_iv_set(mrb, mrb_obj_value(class), sym, previous);
+  }
+
+  if (mrb_nil_p(mrb_hash_get(mrb, previous, mrb_symbol_value(mid)))) {
+    mrb_hash_set(mrb, previous, mrb_symbol_value(mid), mrb_obj_value(method));
+  }
+}
+
+static struct RProc* previous_method(struct mrb_state* mrb, struct RClass* class,
+                                     mrb_sym mid) {
+  mrb_sym sym = mrb_intern_lit(mrb, "__previous_methods__");
+  mrb_value previous = mrb_iv_get(mrb, mrb_obj_value(class), sym);
+
+  if (!mrb_hash_p(previous)) return NULL;
+
+  previous = mrb_hash_get(mrb, previous, mrb_symbol_value(mid));
+
+  return mrb_nil_p(previous) ? NULL : mrb_proc_ptr(previous);
+}
+
+mrb_value mrb_ext_call_super(struct mrb_state* mrb, mrb_value self, mrb_sym mid,
+                             mrb_int argc, const mrb_value* argv) {
+  struct RClass* class;
+  struct RProc* method = NULL;
+  mrb_sym prev_mid = mrb->c->ci->mid;
+  ptrdiff_t ci_idx = mrb->c->ci - mrb->c->cibase;
+  ptrdiff_t stack_idx = mrb->c->stack - mrb->c->stbase;
+  struct mrb_jmpbuf* prev_jmp = mrb->jmp;
+  struct mrb_jmpbuf c_jmp;
+  mrb_value result;
+
+  // Inside the overriding method, call the method it replaced or start right
+  // above the class that defined it.
+  if (prev_mid == mid && mrb->c->ci->target_class) {
+    class = mrb->c->ci->target_class;
+    method = previous_method(mrb, class, mid);
+  } else {
+    class = mrb_class(mrb, self);
+
+    if (!mrb_method_search_vm(mrb, &class, mid)) {
+      mrb_no_method_error(mrb, mid, mrb_nil_value(), "undefined method '%S'",
+                          mrb_sym2str(mrb, mid));
+    }
+  }
+
+  if (!method) {
+    class = class->super;
+    method = mrb_method_search_vm(mrb, &class, mid);
+  }
+
+  if (!method) {
+    mrb_no_method_error(mrb, mid, mrb_nil_value(), "super: no superclass method '%S'",
+                        mrb_sym2str(mrb, mid));
+  }
+
+  mrb->c->ci->mid = mid;
+
+  MRB_TRY(&c_jmp) {
+    mrb->jmp = &c_jmp;
+    result = mrb_yield_with_class(mrb, mrb_obj_value(method), argc, argv, self, class);
+    mrb->jmp = prev_jmp;
+  } MRB_CATCH(&c_jmp) {
+    // mrb_yield_with_class leaves the frame of a C function that raised pushed.
+    mrb->jmp = prev_jmp;
+    mrb->c->ci = mrb->c->cibase + ci_idx;
+    mrb->c->stack = mrb->c->stbase + stack_idx;
+    mrb->c->ci->mid = prev_mid;
+    MRB_THROW(prev_jmp);
+  } MRB_END_EXC(&c_jmp);
+
+  mrb->c->ci->mid = prev_mid;
+
+  return result;
+}
+
+mrb_value mrb_ext_method_proc(struct mrb_state* mrb, mrb_value self, mrb_sym mid) {
+  struct RClass* class = mrb_class(mrb, self);
+  struct RProc* method = mrb_method_search_vm(mrb, &class, mid);
+
+  if (!method) return mrb_nil_value();
+
+  return mrb_obj_value(method);
+}
+
+// Returns the Proc that instances of class run for mid, or nil if there is
+// none.
+mrb_value mrb_ext_instance_method_proc(struct mrb_state* mrb, mrb_value class,
+                                       mrb_sym mid) {
+  struct RClass* c = mrb_class_ptr(class);
+  struct RProc* method = mrb_method_search_vm(mrb, &c, mid);
+
+  if (!method) return mrb_nil_value();
+
+  return mrb_obj_value(method);
+}
+
+// Defines a Proc returned by mrb_ext_method_proc or
+// mrb_ext_instance_method_proc again, e.g. after it was undefined.
+void mrb_ext_define_method_proc(struct mrb_state* mrb, mrb_value target,
+                                mrb_bool singleton, mrb_sym mid,
+                                mrb_value proc) {
+  struct RClass* c = singleton ? mrb_class(mrb, target) : mrb_class_ptr(target);
+
+  mrb_define_method_raw(mrb, c, mid, mrb_proc_ptr(proc));
+}
+
+// mruby switches away from a Fiber an exception escapes from, but leaves it
+// running so that resuming it again raises "double resume". Marks it dead
+// instead, unless it is still running up the stack.
+void mrb_ext_fiber_terminate_raised(struct mrb_state* mrb, mrb_value fiber) {
+  struct mrb_context* c = ((struct RFiber*) mrb_ptr(fiber))->cxt;
+
+  if (c && c != mrb->c && c->status == MRB_FIBER_RUNNING) {
+    c->status = MRB_FIBER_TERMINATED;
+  }
+}
+
+// Same as mirb's is_code_block_open: whether the code needs more input to be
+// complete, e.g. a missing end, an open string or heredoc, or a trailing dot.
+static mrb_bool code_block_open(struct mrb_parser_state* parser) {
+  if (parser->parsing_heredoc != NULL) return TRUE;
+  if (parser->heredoc_end_now) return FALSE;
+  if (parser->lex_strterm) return TRUE;
+
+  if (parser->nerr > 0) {
+    const char unexpected_end[] = "syntax error, unexpected $end";
+    const char* message = parser->error_buffer[0].message;
+
+    return message &&
+           strncmp(message, unexpected_end, sizeof(unexpected_end) - 1) == 0;
+  }
+
+  switch (parser->lstate) {
+    case EXPR_DOT:
+    case EXPR_CLASS:
+    case EXPR_FNAME:
+    case EXPR_VALUE:
+      return TRUE;
+    default:
+      return FALSE;
+  }
+}
+
+// Parses code without running it. state is set to 0 if the code is complete,
+// to 1 if it needs more input and to 2 on syntax errors, in which case the
+// first error is returned.
+mrb_value mrb_ext_check_syntax(struct mrb_state* mrb, const char* code,
+                               size_t len, int* state) {
+  struct mrb_parser_state* parser = mrb_parser_new(mrb);
+  mrb_value message = mrb_nil_value();
+  char buffer[256];
+
+  if (!parser) {
+    *state = 2;
+
+    return mrb_str_new_cstr(mrb, "parser could not be allocated");
+  }
+
+  parser->s = code;
+  parser->send = code + len;
+  parser->lineno = 1;
+  parser->capture_errors = TRUE;
+
+  mrb_parser_parse(parser, NULL);
+
+  if (code_block_open(parser)) {
+    *state = 1;
+  } else if (parser->nerr > 0) {
+    struct mrb_parser_message* error = &parser->error_buffer[0];
+
+    snprintf(buffer, sizeof(buffer), "line %d:%d: %s", error->lineno,
+             error->column, error->message ? error->message : "syntax error");
+
+    *state = 2;
+    message = mrb_str_new_cstr(mrb, buffer);
+  } else {
+    *state = 0;
+  }
+
+  mrb_parser_free(parser);
+
+  return message;
+}
+
+void mrb_ext_set_capture_errors(mrbc_context* ctx, mrb_bool capture) {
+  ctx->capture_errors = capture;
+}
+
+static void parser_message(struct mrb_parser_state* parser,
+                           struct mrb_parser_message* message, char* buffer,
+                           size_t size) {
+  if (parser->filename) {
+    snprintf(buffer, size, "%s:%d:%d: %s", parser->filename, message->lineno,
+             message->column, message->message);
+  } else {
+    snprintf(buffer, size, "line %d:%d: %s", message->lineno, message->column,
+             message->message);
+  }
+}
+
+// Port of mrb_load_nstring_cxt (load_exec is static in mruby 1.2) which passes
+// the parser's warnings to warn instead of printing them to stderr. Falls back
+// to mrb_load_nstring_cxt itself when warn is NULL.
+mrb_value mrb_ext_load_nstring_cxt(struct mrb_state* mrb, const char* code,
+                                   int len, mrbc_context* ctx,
+                                   void (*warn)(struct mrb_state*, const char*)) {
+  struct RClass* target = mrb->object_class;
+  struct mrb_parser_state* parser;
+  struct RProc* proc;
+  mrb_bool capture_errors = ctx->capture_errors;
+  unsigned int keep = 0;
+  mrb_value result;
+  char buffer[256];
+  size_t i;
+
+  if (!warn) {
+    return mrb_load_nstring_cxt(mrb, code, len, ctx);
+  }
+
+  // Warnings are only kept by the parser when it captures errors as well.
+  ctx->capture_errors = TRUE;
+
+  parser = mrb_parse_nstring(mrb, code, len, ctx);
+
+  ctx->capture_errors = capture_errors;
+
+  if (!parser) {
+    return mrb_undef_value();
+  }
+
+  for (i = 0; i < parser->nwarn && i < 10; i++) {
+    parser_message(parser, &parser->warn_buffer[i], buffer, sizeof(buffer));
+    warn(mrb, buffer);
+  }
+
+  if (!capture_errors) {
+    for (i = 0; i < parser->nerr && i < 10; i++) {
+      parser_message(parser, &parser->error_buffer[i], buffer, sizeof(buffer));
+      fprintf(stderr, "%s\n", buffer);
+    }
+
+    parser->capture_errors = FALSE;
+  }
+
+  if (!parser->tree || parser->nerr) {
+    if (parser->capture_errors) {
+      int n = snprintf(buffer, sizeof(buffer), "line %d: %s\n",
+                       parser->error_buffer[0].lineno,
+                       parser->error_buffer[0].message);
+
+      mrb->exc = mrb_obj_ptr(mrb_exc_new(mrb, E_SYNTAX_ERROR, buffer, n));
+    } else {
+      mrb->exc = mrb_obj_ptr(mrb_exc_new_str_lit(mrb, E_SYNTAX_ERROR, "syntax error"));
+    }
+
+    mrb_parser_free(parser);
+
+    return mrb_undef_value();
+  }
+
+  proc = mrb_generate_code(mrb, parser);
+  mrb_parser_free(parser);
+
+  if (!proc) {
+    mrb->exc = mrb_obj_ptr(mrb_exc_new_str_lit(mrb, E_SCRIPT_ERROR, "codegen error"));
+
+    return mrb_undef_value();
+  }
+
+  if (ctx->no_exec) return mrb_obj_value(proc);
+  if (ctx->target_class) target = ctx->target_class;
+
+  if (ctx->keep_lv) {
+    keep = ctx->slen + 1;
+  } else {
+    ctx->keep_lv = TRUE;
+  }
+
+  proc->target_class = target;
+
+  if (mrb->c->ci) {
+    mrb->c->ci->target_class = target;
+  }
+
+  result = mrb_toplevel_run_keep(mrb, proc, keep);
+
+  if (mrb->exc) return mrb_nil_value();
+
+  return result;
+}
+
 mrb_value mrb_ext_get_exc(struct mrb_state* mrb) {
   if (mrb->exc) {
     mrb_value exc = mrb_funcall(mrb, mrb_obj_value(mrb->exc), "inspect", 0);
@@ -149,12 +854,57 @@ mrb_value mrb_ext_get_exc(struct mrb_state* mrb) {
   }
 }
 
+// mrb_exc_raise records the current frame in the exception, so the frame it was
+// first raised from is put aside for mrb_ext_exc_restore to put back. Like in
+// Ruby, raising the same exception again keeps its original backtrace.
 void mrb_ext_raise_current(struct mrb_state* mrb) {
     if (mrb->exc) {
-        mrb_exc_raise(mrb, mrb_obj_value(mrb->exc));
+        mrb_value exc = mrb_obj_value(mrb->exc);
+        mrb_sym sym = mrb_intern_lit(mrb, "origin");
+
+        if (!mrb->gc.out_of_memory && !mrb_iv_defined(mrb, exc, sym)) {
+            mrb_value origin = mrb_ary_new_capa(mrb, 3);
+
+            mrb_ary_push(mrb, origin, mrb_iv_get(mrb, exc, mrb_intern_lit(mrb, "ciidx")));
+            mrb_ary_push(mrb, origin, mrb_iv_get(mrb, exc, mrb_intern_lit(mrb, "file")));
+            mrb_ary_push(mrb, origin, mrb_iv_get(mrb, exc, mrb_intern_lit(mrb, "line")));
+
+            mrb_iv_set(mrb, exc, sym, origin);
+        }
+
+        mrb_exc_raise(mrb, exc);
+    }
+}
+
+static void restore_iv(struct mrb_state* mrb, mrb_value exc, const char* name,
+                       mrb_value value) {
+    mrb_sym sym = mrb_intern_cstr(mrb, name);
+
+    if (mrb_nil_p(value)) {
+        mrb_iv_remove(mrb, exc, sym);
+    } else {
+        mrb_iv_set(mrb, exc, sym, value);
+    }
+}
+
+void mrb_ext_exc_restore(struct mrb_state* mrb, mrb_value exc) {
+    mrb_sym sym = mrb_intern_lit(mrb, "origin");
+
+    if (mrb_type(exc) == MRB_TT_EXCEPTION && mrb_iv_defined(mrb, exc, sym)) {
+        mrb_value origin = mrb_iv_remove(mrb, exc, sym);
+
+        restore_iv(mrb, exc, "ciidx", mrb_ary_ref(mrb, origin, 0));
+        restore_iv(mrb, exc, "file", mrb_ary_ref(mrb, origin, 1));
+        restore_iv(mrb, exc, "line", mrb_ary_ref(mrb, origin, 2));
     }
 }
 
+mrb_noreturn void mrb_ext_raise_nomem(struct mrb_state* mrb) {
+  // Mirror mrb_realloc so that the heap grants room to handle the exception.
+  mrb->gc.out_of_memory = TRUE;
+  mrb_exc_raise(mrb, mrb_obj_value(mrb->nomem_err));
+}
+
 mrb_value mrb_ext_exc_str(struct mrb_state* mrb, mrb_value exc) {
     return mrb_funcall(mrb, exc, "inspect", 0);
 }
@@ -173,6 +923,27 @@ mrb_bool mrb_ext_class_defined_under(struct mrb_state* mrb,
   return mrb_const_defined(mrb, mrb_obj_value(outer), mrb_symbol(sym));
 }
 
+mrb_bool mrb_ext_is_a_named(struct mrb_state* mrb, mrb_value value,
+                            const char* name) {
+  mrb_value object = mrb_obj_value(mrb->object_class);
+  mrb_value sym = mrb_check_intern_cstr(mrb, name);
+  mrb_value class;
+
+  if (mrb_nil_p(sym)) return FALSE;
+  if (!mrb_const_defined(mrb, object, mrb_symbol(sym))) return FALSE;
+
+  class = mrb_const_get(mrb, object, mrb_symbol(sym));
+
+  switch (mrb_type(class)) {
+    case MRB_TT_CLASS:
+    case MRB_TT_MODULE:
+    case MRB_TT_SCLASS:
+      return mrb_obj_is_kind_of(mrb, value, mrb_class_ptr(class));
+    default:
+      return FALSE;
+  }
+}
+
 struct RClass* mrb_ext_get_class(mrb_value value) {
   return (struct RClass*) value.value.p;
 }
@@ -180,3 +951,15 @@ struct RClass* mrb_ext_get_class(mrb_value value) {
 struct RClass* mrb_ext_class(struct mrb_state* mrb, mrb_value value) {
   return mrb_class(mrb, value);
 }
+
+mrb_value mrb_ext_range_beg(mrb_value range) {
+  return mrb_range_ptr(range)->edges->beg;
+}
+
+mrb_value mrb_ext_range_end(mrb_value range) {
+  return mrb_range_ptr(range)->edges->end;
+}
+
+mrb_bool mrb_ext_range_excl(mrb_value range) {
+  return mrb_range_ptr(range)->excl;
+}
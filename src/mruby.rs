@@ -5,24 +5,47 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::any::{Any, TypeId};
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::any::{type_name, Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::fs::File;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::io::{self, Read};
+use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Range, RangeInclusive};
 use std::os::raw::{c_char, c_void};
 use std::panic::{self, AssertUnwindSafe};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::rc::Rc;
+use std::slice;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::mruby_ffi::*;
 
+#[path="fiber.rs"]
+mod fiber;
+#[path="json.rs"]
+mod json;
+
+pub use self::fiber::{Fiber, FiberResult};
+
+#[cfg(feature = "serde")]
+#[path="serde_value.rs"]
+mod serde_value;
+
 /// A `type` wrapper around a `Rc<RefCell<Mruby>>`. Created with `Mruby::new()`.
 pub type MrubyType = Rc<RefCell<Mruby>>;
 
+/// The integer type backing mruby `Fixnum`s.
+pub type MrInt = i32;
+
 /// A safe `struct` for the mruby API. The `struct` only contains creation and desctruction
 /// methods. Creating an `Mruby` returns a `MrubyType` (`Rc<RefCell<Mruby>>`) which implements
 /// `MrubyImpl` where the rest of the implemented API is found.
@@ -41,13 +64,29 @@ pub struct Mruby {
     pub mrb:             *const MrState,
     ctx:                 *const MrContext,
     filename:            Option<String>,
-    classes:             HashMap<TypeId, (*const MrClass, MrDataType, String)>,
+    classes:             HashMap<TypeId, (*const MrClass, Box<MrDataType>, String)>,
     methods:             HashMap<TypeId, HashMap<u32, Rc<Fn(MrubyType, Value) -> Value>>>,
     class_methods:       HashMap<TypeId, HashMap<u32, Rc<Fn(MrubyType, Value) -> Value>>>,
     mruby_methods:       HashMap<String, HashMap<u32, Rc<Fn(MrubyType, Value) -> Value>>>,
+    finalizers:          HashMap<TypeId, Rc<Any>>,
+    userdata:            HashMap<TypeId, Rc<Any>>,
+    downcasts:           HashMap<String, fn(&Value) -> Result<Rc<AnyCell>, MrubyError>>,
     mruby_class_methods: HashMap<String, HashMap<u32, Rc<Fn(MrubyType, Value) -> Value>>>,
     files:               HashMap<String, Vec<fn(MrubyType)>>,
-    required:            HashSet<String>
+    required:            HashSet<String>,
+    load_paths:          Option<Vec<PathBuf>>,
+    loading:             Vec<PathBuf>,
+    sandbox:             Option<SandboxConfig>,
+    sandboxed:           Vec<(Capability, Removed)>,
+    symbols:             HashMap<String, u32>,
+    visibilities:        HashMap<(usize, u32), Visibility>,
+    gems:                Vec<Vec<u32>>,
+    print_handler:       Option<Rc<Fn(&str)>>,
+    error_handler:       Option<Rc<Fn(&str)>>,
+    warning_handler:     Option<Rc<RefCell<FnMut(&str)>>>,
+    exception:           Option<MrValue>,
+    thread:              ThreadId,
+    closed:              Cell<bool>
 }
 
 impl Mruby {
@@ -61,7 +100,7 @@ impl Mruby {
     /// ```
     pub fn new() -> MrubyType {
         unsafe {
-            let mrb = mrb_open();
+            let mrb = mrb_ext_open();
 
             let mruby = Rc::new(RefCell::new(
                 Mruby {
@@ -72,9 +111,25 @@ impl Mruby {
                     methods:             HashMap::new(),
                     class_methods:       HashMap::new(),
                     mruby_methods:       HashMap::new(),
+                    finalizers:          HashMap::new(),
+                    userdata:            HashMap::new(),
+                    downcasts:           HashMap::new(),
                     mruby_class_methods: HashMap::new(),
                     files:               HashMap::new(),
-                    required:            HashSet::new()
+                    required:            HashSet::new(),
+                    load_paths:          None,
+                    loading:             vec![],
+                    sandbox:             None,
+                    sandboxed:           vec![],
+                    symbols:             HashMap::new(),
+                    visibilities:        HashMap::new(),
+                    gems:                Vec::new(),
+                    print_handler:       None,
+                    error_handler:       None,
+                    warning_handler:     None,
+                    exception:           None,
+                    thread:              thread::current().id(),
+                    closed:              Cell::new(false)
                 }
             ));
 
@@ -88,18 +143,22 @@ impl Mruby {
 
                     let name = mem::uninitialized::<*const c_char>();
 
-                    let sig_str = CString::new("z").unwrap();
-
-                    mrb_get_args(mrb, sig_str.as_ptr(), &name as *const *const c_char);
+                    // No Rust values needing drop may be alive when mrb_get_args raises.
+                    mrb_get_args(mrb, b"z\0".as_ptr() as *const c_char,
+                                 &name as *const *const c_char);
 
                     let name = CStr::from_ptr(name).to_str().unwrap();
 
-                    let already_required = {
-                        mruby.borrow().required.contains(name)
+                    let (already_required, loader) = {
+                        let borrow = mruby.borrow();
+
+                        (borrow.required.contains(name), borrow.load_paths.is_some())
                     };
 
-                    let result = if already_required {
-                        mruby.bool(false)
+                    let result = if loader {
+                        load_feature(&mruby, mrb, name, None)
+                    } else if already_required {
+                        Ok(mruby.bool(false))
                     } else {
                         let reqs = {
                             let borrow = mruby.borrow();
@@ -115,7 +174,7 @@ impl Mruby {
                                     req(mruby.clone());
                                 }
 
-                                mruby.bool(true)
+                                Ok(mruby.bool(true))
                             },
                             None => {
                                 let filename = {
@@ -137,12 +196,11 @@ impl Mruby {
 
                                     match result {
                                         Err(err) => {
-                                            Mruby::raise(mrb, "RuntimeError", &format!("{}", err));
+                                            Err(Mruby::exception(mrb, "RuntimeError",
+                                                                 &format!("{}", err)))
                                         }
-                                        _ => ()
+                                        _ => Ok(mruby.bool(true))
                                     }
-
-                                    mruby.bool(true)
                                 };
 
                                 let path = Path::new(name);
@@ -158,19 +216,22 @@ impl Mruby {
                                 } else if path.is_file() {
                                     execute(path, name.to_owned(), filename)
                                 } else {
-                                    Mruby::raise(mrb, "RuntimeError",
-                                                 &format!("cannot load {}.rb or {}.mrb",
-                                                 name, name));
-
-                                    mruby.nil()
+                                    Err(Mruby::exception(mrb, "RuntimeError",
+                                                         &format!("cannot load {}.rb or {}.mrb",
+                                                         name, name)))
                                 }
                             }
                         }
                     };
 
+                    let result = result.map(|value| value.value);
+
                     mem::forget(mruby);
 
-                    result.value
+                    match result {
+                        Ok(value) => value,
+                        Err(exc)  => mrb_exc_raise(mrb, exc)
+                    }
                 }
             }
 
@@ -189,28 +250,103 @@ impl Mruby {
                   super message
                 end
               end
+
+              class ReentrantBorrowError < RuntimeError; end
             ");
 
             mruby
         }
     }
 
+    /// Creates an mruby state like `new` and takes away the capabilities `config` does not allow
+    /// with `sandbox` before any script runs. Capabilities are given back with `relax_sandbox`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use mrusty::SandboxConfig;
+    ///
+    /// let mruby = Mruby::new_sandboxed(&SandboxConfig::new().allow_print());
+    ///
+    /// assert!(mruby.run("1.instance_eval { self + 1 }").is_err());
+    /// assert!(mruby.run("GC.start").is_err());
+    /// assert_eq!(mruby.run("respond_to?(:puts)").unwrap(), mruby.bool(true));
+    /// ```
+    pub fn new_sandboxed(config: &SandboxConfig) -> MrubyType {
+        let mruby = Mruby::new();
+
+        // Nothing has been defined by scripts yet, so listing the methods to undefine cannot
+        // raise.
+        restrict(&mruby, config).unwrap();
+
+        mruby
+    }
+
+    // The last raised Exception is registered with the GC until it is replaced or taken, so that
+    // rescue can still hand it out after further allocations.
+    fn set_exception(&mut self, exc: Option<MrValue>) {
+        unsafe {
+            if let Some(previous) = self.exception.take() {
+                mrb_gc_unregister(self.mrb, previous);
+            }
+
+            if let Some(exc) = exc {
+                mrb_gc_register(self.mrb, exc);
+            }
+        }
+
+        self.exception = exc;
+    }
+
+    fn take_exception(&mut self) -> Option<MrValue> {
+        let exc = self.exception.take();
+
+        if let Some(exc) = exc {
+            unsafe {
+                mrb_gc_unregister(self.mrb, exc);
+            }
+        }
+
+        exc
+    }
+
+    // Forgets the Exception of an earlier run or call once a new one starts.
+    fn clear_exception(mruby: &MrubyType) {
+        if let Ok(mut borrow) = mruby.try_borrow_mut() {
+            borrow.set_exception(None);
+        }
+    }
+
     #[inline]
-    fn raise(mrb: *const MrState, eclass: &str, message: &str) -> MrValue {
+    fn exception(mrb: *const MrState, eclass: &str, message: &str) -> MrValue {
         unsafe {
             let eclass_str = CString::new(eclass).unwrap();
-            let message_str = CString::new(message).unwrap();
+            let eclass = mrb_class_get(mrb, eclass_str.as_ptr());
 
-            mrb_ext_raise(mrb, eclass_str.as_ptr(), message_str.as_ptr());
+            mrb_exc_new(mrb, eclass, message.as_ptr(), message.len())
+        }
+    }
 
-            MrValue::nil()
+    fn intern_cached(&mut self, name: &str) -> u32 {
+        if let Some(&sym) = self.symbols.get(name) {
+            return sym;
         }
+
+        let sym = unsafe {
+            mrb_intern(self.mrb, name.as_ptr() as *const c_char, name.len())
+        };
+
+        self.symbols.insert(name.to_owned(), sym);
+
+        sym
     }
 
     fn close(&self) {
         unsafe {
             mrbc_context_free(self.mrb, self.ctx);
-            mrb_close(self.mrb);
+            mrb_ext_close(self.mrb);
         }
     }
 }
@@ -223,11 +359,32 @@ pub enum MrubyError {
     /// undefined type error
     Undef,
     /// mruby runtime error
-    Runtime(String),
+    Runtime {
+        /// `Exception`'s message, prefixed with its `Class`
+        message:   String,
+        /// `Exception`'s backtrace
+        backtrace: Vec<BacktraceEntry>
+    },
     /// unrecognized file type error
     Filetype,
     /// Rust `Io` error
-    Io(io::Error)
+    Io(io::Error),
+    /// serde conversion error naming the path of the failing value
+    Serde(String),
+    /// JSON encoding or parsing error
+    Json(String),
+    /// numeric conversion error naming the type the value does not fit in
+    OutOfRange(String),
+    /// script stopped after running out of instructions in `run_with_timeout`, which needs the
+    /// `instruction-limit` feature
+    Timeout,
+    /// Rust type used before its `Class` was defined, naming the type and what was attempted
+    Unregistered(String),
+    /// interpreter used from a thread other than the one that created it; methods that cannot
+    /// return an error panic instead
+    WrongThread,
+    /// interpreter used after `MrubyImpl::close`
+    Closed
 }
 
 impl fmt::Display for MrubyError {
@@ -239,13 +396,36 @@ impl fmt::Display for MrubyError {
             MrubyError::Undef => {
                 write!(f, "Undefined error: type is not defined")
             },
-            MrubyError::Runtime(ref err) => {
-                write!(f, "Runtime error: {}", err)
+            MrubyError::Runtime { ref message, .. } => {
+                write!(f, "Runtime error: {}", message.replace("\n", "\\n"))
             },
             MrubyError::Filetype => {
                 write!(f, "Filetype error: script needs a compatible (.rb, .mrb) extension")
             },
-            MrubyError::Io(ref err) => err.fmt(f)
+            MrubyError::Io(ref err) => err.fmt(f),
+            MrubyError::Serde(ref err) => {
+                write!(f, "Serde error: {}", err)
+            },
+            MrubyError::Json(ref err) => {
+                write!(f, "JSON error: {}", err)
+            },
+            MrubyError::OutOfRange(ref typ) => {
+                write!(f, "Out of range error: value does not fit in {}", typ)
+            },
+            MrubyError::Timeout => {
+                write!(f, "Timeout error: instruction limit exceeded")
+            },
+            MrubyError::Unregistered(ref err) => {
+                write!(f, "Unregistered error: {}; define it with def_class_for or require it \
+                           first", err)
+            },
+            MrubyError::WrongThread => {
+                write!(f, "Thread error: mruby used from a thread other than the one that \
+                           created it")
+            },
+            MrubyError::Closed => {
+                write!(f, "Closed error: mruby was closed while this handle was alive")
+            }
         }
     }
 }
@@ -255,9 +435,23 @@ impl Error for MrubyError {
         match *self {
             MrubyError::Cast(_)     => "mruby value cast error",
             MrubyError::Undef       => "mruby undefined error",
-            MrubyError::Runtime(_)  => "mruby runtime error",
+            MrubyError::Runtime { .. } => "mruby runtime error",
             MrubyError::Filetype    => "filetype mistmatch",
-            MrubyError::Io(ref err) => err.description()
+            MrubyError::Io(ref err) => err.description(),
+            MrubyError::Serde(_)    => "mruby serde conversion error",
+            MrubyError::Json(_)     => "mruby JSON error",
+            MrubyError::OutOfRange(_) => "mruby value out of range",
+            MrubyError::Timeout       => "mruby instruction limit exceeded",
+            MrubyError::Unregistered(_) => "Rust type without an mruby Class",
+            MrubyError::WrongThread     => "mruby used from another thread",
+            MrubyError::Closed          => "mruby used after being closed"
+        }
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            MrubyError::Io(ref err) => Some(err),
+            _                       => None
         }
     }
 }
@@ -268,6 +462,135 @@ impl From<io::Error> for MrubyError {
     }
 }
 
+impl MrubyError {
+    /// Returns the backtrace of a `Runtime` error in a `Some` or `None` for other errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let err = mruby.run_with_filename("fail 'oops'", "script.rb").err().unwrap();
+    ///
+    /// assert_eq!(err.backtrace().unwrap()[0].line, 1);
+    /// ```
+    pub fn backtrace(&self) -> Option<&[BacktraceEntry]> {
+        match *self {
+            MrubyError::Runtime { ref backtrace, .. } => Some(backtrace),
+            _                                         => None
+        }
+    }
+
+    fn runtime(mrb: *const MrState, exc: MrValue) -> MrubyError {
+        unsafe {
+            mrb_ext_exc_restore(mrb, exc);
+
+            // Backtrace needs to be read before calling any method that would overwrite the stack.
+            let backtrace = BacktraceEntry::read(mrb, exc);
+
+            let str = mrb_ext_exc_str(mrb, exc).to_str(mrb).unwrap();
+
+            // Kept so that rescue can hand the Exception itself to its handler.
+            let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+
+            if let Ok(mut borrow) = mruby.try_borrow_mut() {
+                borrow.set_exception(Some(exc));
+            }
+
+            mem::forget(mruby);
+
+            MrubyError::Runtime {
+                message:   str.to_owned(),
+                backtrace: backtrace
+            }
+        }
+    }
+}
+
+/// A `struct` returned by `MrubyImpl::close` when the interpreter was still in use. Outstanding
+/// handles keep the interpreter alive until they are dropped, but return `MrubyError::Closed`
+/// from then on.
+#[derive(Debug, PartialEq)]
+pub struct CloseError {
+    /// number of handles, like `Value`s or `Class`es, still pointing to the interpreter
+    pub handles: usize,
+    /// number of `Value`s still registered with `Value::gc_register` by `Class` name
+    pub registered: BTreeMap<String, usize>
+}
+
+impl fmt::Display for CloseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Close error: {} handles still alive", self.handles)?;
+
+        if !self.registered.is_empty() {
+            let registered = self.registered.iter().map(|(class, count)| {
+                format!("{} {}", count, class)
+            }).collect::<Vec<_>>();
+
+            write!(f, ", registered values: {}", registered.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for CloseError {
+    fn description(&self) -> &str {
+        "mruby closed while still in use"
+    }
+}
+
+/// A `struct` containing a frame of an mruby `Exception`'s backtrace.
+///
+/// Line information is only available for scripts run with a filename.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktraceEntry {
+    /// file the frame belongs to
+    pub file: String,
+    /// line of the frame
+    pub line: u32,
+    /// method of the frame, e.g. `Class#method`
+    pub method: Option<String>
+}
+
+impl BacktraceEntry {
+    unsafe fn read(mrb: *const MrState, exc: MrValue) -> Vec<BacktraceEntry> {
+        BacktraceEntry::read_array(mrb, mrb_exc_backtrace(mrb, exc))
+    }
+
+    unsafe fn read_array(mrb: *const MrState, backtrace: MrValue) -> Vec<BacktraceEntry> {
+        let len = mrb_ext_ary_len(mrb, backtrace);
+
+        (0..len).map(|i| {
+            BacktraceEntry::parse(mrb_ary_ref(mrb, backtrace, i).to_str(mrb).unwrap())
+        }).collect()
+    }
+
+    fn parse(frame: &str) -> BacktraceEntry {
+        let (location, method) = match frame.find(":in ") {
+            Some(i) => (&frame[..i], Some(frame[i + 4..].to_owned())),
+            None    => (frame, None)
+        };
+
+        let (file, line) = match location.rfind(':') {
+            Some(i) => {
+                match location[i + 1..].parse() {
+                    Ok(line) => (&location[..i], line),
+                    Err(_)   => (location, 0)
+                }
+            },
+            None    => (location, 0)
+        };
+
+        BacktraceEntry {
+            file: file.to_owned(),
+            line: line,
+            method: method
+        }
+    }
+}
+
 /// A `trait` useful for organising Rust types into dynamic mruby files.
 ///
 /// # Examples
@@ -295,6 +618,234 @@ pub trait MrubyFile {
     fn require(mruby: MrubyType);
 }
 
+/// A `trait` for Rust types that hold mruby `Value`s, e.g. procs registered by a script. Once
+/// registered with `def_mark_for`, the GC keeps the `Value`s marked by `mark` alive for as long as
+/// the object itself instead of collecting them when only Rust references them.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Marker, Mruby, MrubyImpl, MrubyMark, Value};
+///
+/// # fn main() {
+/// struct Hooks {
+///     hooks: Vec<Value>
+/// }
+///
+/// impl MrubyMark for Hooks {
+///     fn mark(&self, marker: &Marker) {
+///         for hook in &self.hooks {
+///             marker.mark(hook);
+///         }
+///     }
+/// }
+///
+/// let mruby = Mruby::new();
+///
+/// mruby.def_class_for::<Hooks>("Hooks");
+/// mruby.def_mark_for::<Hooks>();
+/// mruby.def_method_for::<Hooks, _>("initialize", mrfn!(|_mruby, slf: Value| {
+///     slf.init(Hooks { hooks: vec![] })
+/// }));
+/// mruby.def_method_for::<Hooks, _>("on", mrfn!(|mruby, slf: (&mut Hooks); &block| {
+///     slf.hooks.push(block);
+///
+///     mruby.nil()
+/// }));
+/// mruby.def_method_for::<Hooks, _>("fire", mrfn!(|mruby, slf: (&Hooks)| {
+///     mruby.array(slf.hooks.iter().map(|hook| hook.call("call", vec![]).unwrap()).collect())
+/// }));
+///
+/// let result = mruby.run("
+///   hooks = Hooks.new
+///   hooks.on { 'only held by Rust' }
+///
+///   GC.start
+///
+///   hooks.fire
+/// ").unwrap();
+///
+/// assert_eq!(result.to_vec().unwrap()[0].to_str().unwrap(), "only held by Rust");
+/// # }
+/// ```
+pub trait MrubyMark {
+    /// Marks every `Value` held by `self` with `marker`.
+    fn mark(&self, marker: &Marker);
+}
+
+/// A `struct` that marks the `Value`s held by a Rust object for the GC. Passed to
+/// `MrubyMark::mark`.
+pub struct Marker {
+    mrb:   *const MrState,
+    mruby: *const RefCell<Mruby>
+}
+
+impl Marker {
+    /// Marks `value` as held by the object being traced.
+    pub fn mark(&self, value: &Value) {
+        // Values of other interpreters are not traced by this one's GC.
+        if ptr::eq(&*value.mruby, self.mruby) {
+            unsafe {
+                mrb_ext_gc_mark_value(self.mrb, value.value);
+            }
+        }
+    }
+}
+
+/// A `trait` implemented by every Rust type which lets `Value::to_obj_downcast` find the concrete
+/// type behind a trait object. Traits whose `Box`es are defined as classes need it as a
+/// supertrait, and `def_downcast_for` to be called on them.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate mrusty;
+/// use mrusty::{Mruby, MrubyAny, MrubyImpl};
+///
+/// # fn main() {
+/// trait Shape: MrubyAny {
+///     fn area(&self) -> f64;
+/// }
+///
+/// struct Square(f64);
+///
+/// impl Shape for Square {
+///     fn area(&self) -> f64 {
+///         self.0 * self.0
+///     }
+/// }
+///
+/// struct Circle(f64);
+///
+/// impl Shape for Circle {
+///     fn area(&self) -> f64 {
+///         3.0 * self.0 * self.0
+///     }
+/// }
+///
+/// let mruby = Mruby::new();
+///
+/// mruby.def_class_for::<Box<Shape>>("Shape");
+/// mruby.def_downcast_for::<Shape>();
+/// mruby.def_method_for::<Box<Shape>, _>("area", mrfn!(|mruby, slf: (&dyn Shape)| {
+///     mruby.float(slf.area())
+/// }));
+/// mruby.def_method_for::<Box<Shape>, _>("side", mrfn!(|mruby, slf: Value| {
+///     match slf.to_obj_downcast::<Square>() {
+///         Ok(square) => mruby.float(square.borrow().0),
+///         Err(_)     => mruby.nil()
+///     }
+/// }));
+///
+/// let square: Box<Shape> = Box::new(Square(2.0));
+/// let circle: Box<Shape> = Box::new(Circle(1.0));
+///
+/// assert_eq!(mruby.obj(square).call("area", vec![]).unwrap().to_f64().unwrap(), 4.0);
+/// assert_eq!(mruby.obj(circle).call("side", vec![]).unwrap(), mruby.nil());
+/// # }
+/// ```
+pub trait MrubyAny: Any {
+    /// Returns `self` as `Any`.
+    fn as_any(&self) -> &Any;
+
+    /// Returns `self` as mutable `Any`.
+    fn as_any_mut(&mut self) -> &mut Any;
+
+    /// Returns the name of the concrete type of `self`.
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T: Any> MrubyAny for T {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+}
+
+/// A `struct` that borrows an object of a trait object `Class` as its concrete type `T`.
+/// Returned by `Value::to_obj_downcast`.
+pub struct Downcast<T> {
+    cell:    Rc<AnyCell>,
+    phantom: PhantomData<T>
+}
+
+impl<T: Any> Downcast<T> {
+    /// Immutably borrows the object like `RefCell::borrow`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is mutably borrowed or if it was replaced by one of another type.
+    pub fn borrow(&self) -> Ref<T> {
+        let any = self.cell.borrow_any();
+        let name = any.1;
+
+        Ref::map(any.0, |any| match any.downcast_ref::<T>() {
+            Some(obj) => obj,
+            None      => panic!("{}", downcast_error::<T>(name))
+        })
+    }
+
+    /// Mutably borrows the object like `RefCell::borrow_mut`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is borrowed or if it was replaced by one of another type.
+    pub fn borrow_mut(&self) -> RefMut<T> {
+        let any = self.cell.borrow_any_mut();
+        let name = any.1;
+
+        RefMut::map(any.0, |any| match any.downcast_mut::<T>() {
+            Some(obj) => obj,
+            None      => panic!("{}", downcast_error::<T>(name))
+        })
+    }
+}
+
+// An object of a Box<T> Class borrowed as the concrete type behind T, along with its name.
+trait AnyCell {
+    fn borrow_any(&self) -> (Ref<Any>, &'static str);
+    fn borrow_any_mut(&self) -> (RefMut<Any>, &'static str);
+    fn concrete_name(&self) -> Option<&'static str>;
+}
+
+impl<T: ?Sized + MrubyAny> AnyCell for RefCell<Box<T>> {
+    fn borrow_any(&self) -> (Ref<Any>, &'static str) {
+        let obj = self.borrow();
+        let name = (**obj).type_name();
+
+        (Ref::map(obj, |obj| (**obj).as_any()), name)
+    }
+
+    fn borrow_any_mut(&self) -> (RefMut<Any>, &'static str) {
+        let obj = self.borrow_mut();
+        let name = (**obj).type_name();
+
+        (RefMut::map(obj, |obj| (**obj).as_any_mut()), name)
+    }
+
+    fn concrete_name(&self) -> Option<&'static str> {
+        self.try_borrow().ok().map(|obj| (**obj).type_name())
+    }
+}
+
+fn any_cell<T: ?Sized + MrubyAny>(value: &Value) -> Result<Rc<AnyCell>, MrubyError> {
+    let obj: Rc<AnyCell> = value.to_obj::<Box<T>>()?;
+
+    Ok(obj)
+}
+
+fn downcast_error<T>(found: &str) -> MrubyError {
+    MrubyError::Cast(format!("{}, found {}", type_name::<T>(), found))
+}
+
 /// A `trait` used on `MrubyType` which implements mruby functionality.
 pub trait MrubyImpl {
     /// Adds a filename to the mruby context.
@@ -311,7 +862,7 @@ pub trait MrubyImpl {
     /// let result = mruby.run("1.nope");
     ///
     /// match result {
-    ///     Err(MrubyError::Runtime(err)) => {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
     ///         assert_eq!(err, "script.rb:1: undefined method \'nope\' for 1 (NoMethodError)");
     /// },
     ///     _ => assert!(false)
@@ -320,43 +871,47 @@ pub trait MrubyImpl {
     #[inline]
     fn filename(&self, filename: &str);
 
-    /// Runs mruby `script` on a state and context and returns a `Value` in an `Ok`
-    /// or an `Err` containing an mruby `Exception`'s message.
+    /// Bounds the memory mruby may hold to `bytes`. Allocations past the limit first trigger a
+    /// full garbage collection, and then raise a `NoMemoryError` in mruby instead of growing
+    /// the heap further. A limit of `0` removes the bound.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("true").unwrap();
-    ///
-    /// assert_eq!(result.to_bool().unwrap(), true);
-    /// ```
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
     /// # use mrusty::MrubyError;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
-    /// let result = mruby.run("'' + 1");
+    /// mruby.set_memory_limit(mruby.memory_used() + 1024 * 1024);
+    ///
+    /// let result = mruby.run("'a' * 2_000_000");
     ///
     /// match result {
-    ///     Err(MrubyError::Runtime(err)) => {
-    ///         assert_eq!(err, "TypeError: expected String");
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert_eq!(err, "NoMemoryError: failed to allocate memory");
     /// },
     ///     _ => assert!(false)
     /// }
     /// ```
-    #[inline]
-    fn run(&self, script: &str) -> Result<Value, MrubyError>;
+    fn set_memory_limit(&self, bytes: usize);
 
-    /// Runs mruby `script` on a state and context and returns a `Value`. If an mruby Exception is
-    /// raised, mruby will be left to handle it.
+    /// Returns the number of bytes currently allocated by mruby.
     ///
-    /// The method is unsafe because running it within a Rust context will interrupt drops,
-    /// potentially leading to memory leaks.
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let used = mruby.memory_used();
     ///
+    /// let _string = mruby.run("'a' * 100_000").unwrap();
+    ///
+    /// assert!(mruby.memory_used() > used + 100_000);
+    /// ```
+    fn memory_used(&self) -> usize;
+
+    /// Enables the GC again after `gc_disable`, like `GC.enable`.
     ///
     /// # Examples
     ///
@@ -364,237 +919,308 @@ pub trait MrubyImpl {
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
-    /// let result = unsafe { mruby.run_unchecked("true") };
     ///
-    /// assert_eq!(result.to_bool().unwrap(), true);
-    /// ```
+    /// mruby.gc_disable();
+    /// mruby.gc_enable();
     ///
+    /// assert_eq!(mruby.run("GC.enable").unwrap().to_bool().unwrap(), false);
     /// ```
-    /// # #[macro_use] extern crate mrusty;
-    /// use mrusty::{Mruby, MrubyImpl};
+    fn gc_enable(&self);
+
+    /// Disables the GC, like `GC.disable`, so that bulk object creation is not interrupted by
+    /// collections. Memory keeps growing until `gc_enable` is called.
     ///
-    /// # fn main() {
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont;
+    /// mruby.gc_disable();
     ///
-    /// mruby.def_class_for::<Cont>("Container");
-    /// mruby.def_class_method_for::<Cont, _>("raise", mrfn!(|mruby, _slf: Value| {
-    ///     unsafe { mruby.run_unchecked("fail 'surprize'") }
-    /// }));
-    ///
-    /// let result = mruby.run("
-    ///   begin
-    ///     Container.raise
-    ///   rescue => e
-    ///     e.message
-    ///   end
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_str().unwrap(), "surprize");
-    /// # }
+    /// assert_eq!(mruby.run("GC.disable").unwrap().to_bool().unwrap(), true);
     /// ```
-    #[inline]
-    unsafe fn run_unchecked(&self, script: &str) -> Value;
+    fn gc_disable(&self);
 
-    /// Runs mruby compiled (.mrb) `script` on a state and context and returns a `Value` in an `Ok`
-    /// or an `Err` containing an mruby `Exception`'s message.
+    /// Runs a full GC, like `GC.start`. `Value`s only referenced from Rust are collected unless
+    /// registered with `gc_register` or still in the GC arena.
     ///
     /// # Examples
     ///
-    /// ```ignore
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
-    /// let result = mruby.runb(include_bytes!("script.mrb")).unwrap();
+    ///
+    /// mruby.run("a = 'a' * 100_000; a = nil").unwrap();
+    ///
+    /// let used = mruby.memory_used();
+    ///
+    /// mruby.gc_run();
+    ///
+    /// assert!(mruby.memory_used() < used);
     /// ```
-    #[inline]
-    fn runb(&self, script: &[u8]) -> Result<Value, MrubyError>;
+    fn gc_run(&self);
 
-    /// Runs mruby (compiled (.mrb) or not (.rb)) `script` on a state and context and returns a
-    /// `Value` in an `Ok` or an `Err` containing an mruby `Exception`'s message.
+    /// Saves the GC arena index so that the temporary objects created after it can be released
+    /// with `gc_arena_restore`. Objects created from Rust stay in the arena, and so alive, until
+    /// then.
     ///
     /// # Examples
     ///
-    /// ```no_run
+    /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
-    /// # use std::path::Path;
     /// let mruby = Mruby::new();
-    /// let result = mruby.execute(&Path::new("script.rb")).unwrap();
+    ///
+    /// let sum = mruby.run("[]").unwrap();
+    ///
+    /// for i in 0..1_000 {
+    ///     let arena = mruby.gc_arena_save();
+    ///
+    ///     sum.call("push", vec![mruby.string(&i.to_string())]).unwrap();
+    ///
+    ///     mruby.gc_arena_restore(arena);
+    /// }
+    ///
+    /// assert_eq!(sum.call("size", vec![]).unwrap().to_i32().unwrap(), 1_000);
     /// ```
-    #[inline]
-    fn execute(&self, script: &Path) -> Result<Value, MrubyError>;
+    fn gc_arena_save(&self) -> i32;
 
-    /// Returns whether the mruby `Class` or `Module` named `name` is defined.
+    /// Restores the GC arena index saved with `gc_arena_save`, releasing the temporary objects
+    /// created since then to the GC. `Value`s created since then must not be used afterwards
+    /// unless reachable from mruby or registered with `gc_register`.
+    fn gc_arena_restore(&self, state: i32);
+
+    /// Limits scripts to running `count` VM instructions, after which every further instruction
+    /// raises a `RuntimeError`, so that not even a `rescue` can keep a runaway script going. The
+    /// budget is shared by all subsequent runs until it is refilled with
+    /// `reset_instruction_counter`. A `count` of `0` removes the limit.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
-    /// let object = mruby.is_defined("Object");
-    /// let objekt = mruby.is_defined("Objekt");
+    /// mruby.set_instruction_limit(10_000);
     ///
-    /// assert!(object);
-    /// assert!(!objekt);
+    /// let result = mruby.run("loop {}");
+    ///
+    /// match result {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert_eq!(err, "RuntimeError: instruction limit exceeded");
+    /// },
+    ///     _ => assert!(false)
+    /// }
+    ///
+    /// mruby.reset_instruction_counter();
+    ///
+    /// assert_eq!(mruby.run("1 + 1").unwrap().to_i32().unwrap(), 2);
     /// ```
-    #[inline]
-    fn is_defined(&self, name: &str) -> bool;
+    ///
+    /// *Note:* Only available with the `instruction-limit` feature, which compiles mruby with a
+    /// hook on every instruction.
+    #[cfg(feature = "instruction-limit")]
+    fn set_instruction_limit(&self, count: u64);
 
-    /// Returns whether the mruby `Class` or `Module` named `name` is defined under `outer` `Class`
-    /// or `Module`.
+    /// Refills the instruction budget set by `set_instruction_limit`.
+    ///
+    /// *Note:* Only available with the `instruction-limit` feature.
+    #[cfg(feature = "instruction-limit")]
+    fn reset_instruction_counter(&self);
+
+    /// Runs mruby `script` limited to `instruction_limit` VM instructions like
+    /// `set_instruction_limit`, returning `MrubyError::Timeout` if it runs out. The limit is
+    /// removed afterwards, replacing any set with `set_instruction_limit`.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// let module = mruby.def_module("Just");
-    /// mruby.def_module_under("Mine", &module);
+    /// match mruby.run_with_timeout("loop {}", 10_000) {
+    ///     Err(MrubyError::Timeout) => (),
+    ///     _                        => assert!(false)
+    /// }
     ///
-    /// assert!(mruby.is_defined_under("Mine", &module));
+    /// let result = mruby.run_with_timeout("1 + 1", 10_000).unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 2);
     /// ```
-    #[inline]
-    fn is_defined_under<T: ClassLike>(&self, name: &str, outer: &T) -> bool;
+    ///
+    /// *Note:* Only available with the `instruction-limit` feature.
+    #[cfg(feature = "instruction-limit")]
+    fn run_with_timeout(&self, script: &str, instruction_limit: u64) -> Result<Value, MrubyError>;
 
-    /// Returns the mruby `Class` named `name` in a `Some` or `None` if it is not defined.
+    /// Takes away the capabilities `config` does not allow, see `SandboxConfig`. The methods of
+    /// the `Class`es and `Module`s taken away are undefined before their constants are removed.
+    /// Scripts calling a method that was taken away get a `NoMethodError`, while using a `Class`
+    /// or `Module` that was taken away raises a `NameError`. Methods defined from Rust are not
+    /// affected.
+    ///
+    /// The sandbox applies to all later scripts run on `self` until `relax_sandbox` gives
+    /// capabilities back. Sandboxing again only takes more away. Returns an `Err` if listing the
+    /// methods to undefine raises.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
+    /// use mrusty::SandboxConfig;
+    ///
     /// let mruby = Mruby::new();
-    /// let object = mruby.get_class("Object");
-    /// let objekt = mruby.get_class("Objekt");
     ///
-    /// assert_eq!(object.unwrap().to_str(), "Object");
-    /// assert!(objekt.is_err());
+    /// mruby.run("
+    ///   module Process
+    ///     def self.pid
+    ///       1
+    ///     end
+    ///   end
+    /// ").unwrap();
+    ///
+    /// mruby.sandbox(&SandboxConfig::new().allow("Process")).unwrap();
+    ///
+    /// assert_eq!(mruby.run("Process.pid").unwrap().to_i32().unwrap(), 1);
+    /// assert!(mruby.run("require 'file'").is_err());
     /// ```
-    #[inline]
-    fn get_class(&self, name: &str) -> Result<Class, MrubyError>;
+    fn sandbox(&self, config: &SandboxConfig) -> Result<(), MrubyError>;
 
-    /// Returns the mruby `Class` named `name` under `outer` `Class` or `Module` in a `Some` or
-    /// `None` if it is not defined.
+    /// Runs mruby `script` like `run` under the strictest `sandbox`, which allows none of the
+    /// capabilities of `SandboxConfig`. Once `script` returns, everything is given back that was
+    /// not taken away by an earlier `sandbox`.
+    ///
+    /// *Note:* Methods and procs defined by `script` stay behind and run with every capability
+    /// once the sandbox is lifted, so untrusted scripts should rather get an interpreter of their
+    /// own from `Mruby::new_sandboxed`.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont;
-    ///
-    /// let module = mruby.def_module("Mine");
-    /// mruby.def_class_under_for::<Cont, _>("Container", &module);
-    ///
-    /// let result = mruby.get_class_under("Container", &module).unwrap();
-    ///
-    /// assert_eq!(result.to_str(), "Mine::Container");
+    /// match mruby.run_sandboxed("require 'file'") {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert!(err.starts_with("NoMethodError: "));
+    ///     },
+    ///     _ => assert!(false)
+    /// }
     /// ```
-    #[inline]
-    fn get_class_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Class, MrubyError>;
+    fn run_sandboxed(&self, script: &str) -> Result<Value, MrubyError>;
 
-    /// Returns the mruby `Module` named `name` in a `Some` or `None` if it is not defined.
+    /// Returns the capabilities left to scripts if `self` was sandboxed, including those given
+    /// back by `relax_sandbox`.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let kernel = mruby.get_module("Kernel");
-    /// let kernet = mruby.get_module("Kernet");
+    /// use mrusty::SandboxConfig;
     ///
-    /// assert_eq!(kernel.unwrap().to_str(), "Kernel");
-    /// assert!(kernet.is_err());
+    /// let config = SandboxConfig::new().allow_eval();
+    ///
+    /// assert_eq!(Mruby::new_sandboxed(&config).sandbox_config(), Some(config));
+    /// assert_eq!(Mruby::new().sandbox_config(), None);
     /// ```
-    #[inline]
-    fn get_module(&self, name: &str) -> Result<Module, MrubyError>;
+    fn sandbox_config(&self) -> Option<SandboxConfig>;
 
-    /// Returns the mruby `Module` named `name` under `outer` `Class` or `Module` in a `Some` or
-    /// `None` if it is not defined.
+    /// Gives back the capabilities `allowed` allows which were taken away by `sandbox` or
+    /// `Mruby::new_sandboxed`. Capabilities that are not allowed stay as they are, so the
+    /// sandbox can only be relaxed. Does nothing if `self` was not sandboxed.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
+    /// use mrusty::SandboxConfig;
     ///
-    /// let module = mruby.def_module("Just");
-    /// mruby.def_module_under("Mine", &module);
+    /// let mruby = Mruby::new_sandboxed(&SandboxConfig::new());
     ///
-    /// let result = mruby.get_module_under("Mine", &module).unwrap();
+    /// assert!(mruby.run("1.instance_eval { self + 1 }").is_err());
     ///
-    /// assert_eq!(result.to_str(), "Just::Mine");
+    /// mruby.relax_sandbox(&SandboxConfig::new().allow_eval());
+    ///
+    /// assert_eq!(mruby.run("1.instance_eval { self + 1 }").unwrap(), mruby.fixnum(2));
+    /// assert!(mruby.run("GC.start").is_err());
     /// ```
-    #[inline]
-    fn get_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Module, MrubyError>;
+    fn relax_sandbox(&self, allowed: &SandboxConfig);
 
-    /// Defines a dynamic file that can be `require`d containing the Rust type `T` and runs its
-    /// `MrubyFile`-inherited `require` method.
+    /// Passes everything mruby prints with `print`, `puts` or `p` to `handler` instead of writing
+    /// it to stdout.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #[macro_use] extern crate mrusty;
-    /// use mrusty::{Mruby, MrubyFile, MrubyImpl, MrubyType};
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
     ///
-    /// # fn main() {
     /// let mruby = Mruby::new();
+    /// let lines = Rc::new(RefCell::new(vec![]));
     ///
-    /// struct Cont {
-    ///     value: i32
-    /// };
+    /// let printed = lines.clone();
     ///
-    /// impl MrubyFile for Cont {
-    ///     fn require(mruby: MrubyType) {
-    ///         mruby.def_class_for::<Cont>("Container");
-    ///         mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
-    ///             let cont = Cont { value: v };
+    /// mruby.set_print_handler(move |s| printed.borrow_mut().push(s.to_owned()));
+    /// mruby.run("print 'hi'").unwrap();
     ///
-    ///             slf.init(cont)
-    ///         }));
-    ///         mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
-    ///             mruby.fixnum(slf.value)
-    ///         }));
-    ///     }
-    /// }
+    /// assert_eq!(*lines.borrow(), vec!["hi"]);
+    /// ```
+    fn set_print_handler<F: Fn(&str) + 'static>(&self, handler: F);
+
+    /// Collects everything mruby prints into the returned `OutputCapture` until it is dropped,
+    /// when the previous print handler is restored.
     ///
-    /// mruby.def_file::<Cont>("cont");
+    /// # Examples
     ///
-    /// let result = mruby.run("
-    ///     require 'cont'
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
     ///
-    ///     Container.new(3).value
-    /// ").unwrap();
+    /// let capture = mruby.redirect_stdout_to_string();
     ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
-    /// # }
+    /// mruby.run("puts 1, 2; p :three").unwrap();
+    ///
+    /// assert_eq!(capture.output(), "1\n2\n:three\n");
     /// ```
-    #[inline]
-    fn def_file<T: MrubyFile>(&self, name: &str);
+    fn redirect_stdout_to_string(&self) -> OutputCapture;
 
-    /// Defines an mruby `Class` named `name`.
+    /// Passes the syntax errors mruby's parser would otherwise write to stderr to `handler`.
+    /// `run` still returns them as a `SyntaxError` in an `Err`.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
     /// let mruby = Mruby::new();
+    /// let errors = Rc::new(RefCell::new(vec![]));
     ///
-    /// mruby.def_class("Container");
+    /// let reported = errors.clone();
     ///
-    /// assert!(mruby.is_defined("Container"));
+    /// mruby.set_error_handler(move |s| reported.borrow_mut().push(s.to_owned()));
+    ///
+    /// assert!(mruby.run("1 +").is_err());
+    /// assert!(errors.borrow()[0].contains("syntax error"));
     /// ```
-    fn def_class(&self, name: &str) -> Class;
+    fn set_error_handler<F: Fn(&str) + 'static>(&self, handler: F);
 
-    /// Defines an mruby `Class` named `name` under `outer` `Class` or `Module`.
+    /// Discards the syntax errors mruby's parser would otherwise write to stderr.
     ///
     /// # Examples
     ///
@@ -603,33 +1229,36 @@ pub trait MrubyImpl {
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// let module = mruby.def_module("Mine");
-    /// mruby.def_class_under("Container", &module);
+    /// mruby.silence_errors();
     ///
-    /// assert!(mruby.is_defined_under("Container", &module));
+    /// assert!(mruby.run("def").is_err());
     /// ```
-    fn def_class_under<U: ClassLike>(&self, name: &str, outer: &U) -> Class;
+    fn silence_errors(&self);
 
-    /// Defines Rust type `T` as an mruby `Class` named `name`.
+    /// Passes the warnings mruby's parser would otherwise write to stderr to `handler`.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
     /// let mruby = Mruby::new();
+    /// let warnings = Rc::new(RefCell::new(vec![]));
     ///
-    /// struct Cont {
-    ///     value: i32
-    /// }
+    /// let reported = warnings.clone();
     ///
-    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.set_warning_handler(move |s| reported.borrow_mut().push(s.to_owned()));
+    /// mruby.run("def f(*a); end; f *[1]").unwrap();
     ///
-    /// assert!(mruby.is_defined("Container"));
+    /// assert_eq!(*warnings.borrow(), vec!["line 1:22: '*' interpreted as argument prefix"]);
     /// ```
-    fn def_class_for<T: Any>(&self, name: &str) -> Class;
+    fn set_warning_handler<F: FnMut(&str) + 'static>(&self, handler: F);
 
-    /// Defines Rust type `T` as an mruby `Class` named `name` under `outer` `Class` or `Module`.
+    /// Runs mruby `script` on a state and context and returns a `Value` in an `Ok`
+    /// or an `Err` containing an mruby `Exception`'s message.
     ///
     /// # Examples
     ///
@@ -637,32 +1266,51 @@ pub trait MrubyImpl {
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
+    /// let result = mruby.run("true").unwrap();
     ///
-    /// struct Cont;
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// ```
     ///
-    /// let module = mruby.def_module("Mine");
-    /// mruby.def_class_under_for::<Cont, _>("Container", &module);
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("'' + 1");
     ///
-    /// assert!(mruby.is_defined_under("Container", &module));
+    /// match result {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert_eq!(err, "TypeError: expected String");
+    /// },
+    ///     _ => assert!(false)
+    /// }
     /// ```
-    fn def_class_under_for<T: Any, U: ClassLike>(&self, name: &str, outer: &U) -> Class;
+    #[inline]
+    fn run(&self, script: &str) -> Result<Value, MrubyError>;
 
-    /// Defines an mruby `Module` named `name`.
+    /// Runs mruby `script` like `run` while naming it `filename`, so that errors carry line
+    /// information. The previous filename of the context is restored afterwards.
     ///
     /// # Examples
     ///
     /// ```
     /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
+    /// let result = mruby.run_with_filename("\n1.nope", "script.rb");
     ///
-    /// mruby.def_module("Container");
-    ///
-    /// assert!(mruby.is_defined("Container"));
+    /// match result {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert_eq!(err, "script.rb:2: undefined method 'nope' for 1 (NoMethodError)");
+    /// },
+    ///     _ => assert!(false)
+    /// }
     /// ```
-    fn def_module(&self, name: &str) -> Module;
+    #[inline]
+    fn run_with_filename(&self, script: &str, filename: &str) -> Result<Value, MrubyError>;
 
-    /// Defines an mruby `Module` named `name` under `outer` `Class` or `Module`.
+    /// Forgets the top-level local variables that `run` keeps from one script to the next.
     ///
     /// # Examples
     ///
@@ -671,40 +1319,49 @@ pub trait MrubyImpl {
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// let module = mruby.def_module("Just");
-    /// mruby.def_module_under("Mine", &module);
+    /// mruby.run("a = 1").unwrap();
     ///
-    /// assert!(mruby.is_defined_under("Mine", &module));
+    /// assert_eq!(mruby.run("a").unwrap().to_i32().unwrap(), 1);
+    ///
+    /// mruby.reset_locals();
+    ///
+    /// assert!(mruby.run("a").is_err());
     /// ```
-    fn def_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Module;
+    fn reset_locals(&self);
 
-    /// Defines an mruby method named `name` on `Class` `class`. The closure to be run when the
-    /// `name` method is called should be passed through the `mrfn!` macro.
+    /// Closes the interpreter, returning an `Err` which counts the handles still pointing to it
+    /// and the `Value`s still registered with `Value::gc_register`. Since handles keep the
+    /// interpreter alive, it is only freed once they are dropped; until then, running scripts,
+    /// calling methods or converting `Value`s through them returns `MrubyError::Closed`.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #[macro_use] extern crate mrusty;
-    /// use mrusty::{Mruby, MrubyImpl};
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.run("1 + 1").unwrap();
+    ///
+    /// assert!(mruby.close().is_ok());
     ///
-    /// # fn main() {
     /// let mruby = Mruby::new();
+    /// let leaked = mruby.run("[1, 2]").unwrap();
     ///
-    /// let class = mruby.def_class("Container");
-    /// mruby.def_method(class, "value", mrfn!(|mruby, slf: Value| {
-    ///     mruby.fixnum(3)
-    /// }));
+    /// let err = mruby.close().unwrap_err();
     ///
-    /// let result = mruby.run("Container.new.value").unwrap();
+    /// assert_eq!(err.handles, 1);
     ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
-    /// # }
+    /// match leaked.to_vec() {
+    ///     Err(MrubyError::Closed) => (),
+    ///     _                       => assert!(false)
+    /// }
     /// ```
-    fn def_method<F>(&self, class: Class, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static;
+    fn close(self) -> Result<(), CloseError>;
 
-    /// Defines an mruby class method named `name` on `Class` `class`. The closure to be run when
-    /// the `name` method is called should be passed through the `mrfn!` macro.
+    /// Stores `value` on the interpreter so that methods can reach it through their `mruby`
+    /// handle with `userdata`. One value is kept per type `T`, replacing the previous one.
     ///
     /// # Examples
     ///
@@ -713,62 +1370,108 @@ pub trait MrubyImpl {
     /// use mrusty::{Mruby, MrubyImpl};
     ///
     /// # fn main() {
+    /// struct Assets {
+    ///     loaded: Vec<String>
+    /// }
+    ///
     /// let mruby = Mruby::new();
     ///
-    /// let class = mruby.def_class("Container");
-    /// mruby.def_class_method(class, "hi", mrfn!(|mruby, _slf: Value, v: i32| {
-    ///     mruby.fixnum(v)
+    /// mruby.set_userdata(Assets { loaded: vec![] });
+    ///
+    /// mruby.def_toplevel_method("load", mrfn!(|mruby, _slf: Value, name: (&str)| {
+    ///     let assets = mruby.userdata::<Assets>().unwrap();
+    ///
+    ///     assets.borrow_mut().loaded.push(name.to_owned());
+    ///
+    ///     mruby.nil()
     /// }));
     ///
-    /// let result = mruby.run("Container.hi 3").unwrap();
+    /// mruby.run("load 'map.png'").unwrap();
     ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// assert_eq!(mruby.userdata::<Assets>().unwrap().borrow().loaded, vec!["map.png"]);
     /// # }
     /// ```
-    fn def_class_method<F>(&self, class: Class, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static;
+    fn set_userdata<T: Any>(&self, value: T);
 
-    /// Defines an mruby method named `name` on the mruby `Class` reflecting type `T`. The closure
-    /// to be run when the `name` method is called should be passed through the `mrfn!` macro.
+    /// Returns the value of type `T` stored with `set_userdata`, if any.
     ///
     /// # Examples
     ///
     /// ```
-    /// # #[macro_use] extern crate mrusty;
-    /// use mrusty::{Mruby, MrubyImpl};
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
     ///
-    /// # fn main() {
+    /// mruby.set_userdata(1u8);
+    ///
+    /// assert_eq!(*mruby.userdata::<u8>().unwrap().borrow(), 1);
+    /// assert!(mruby.userdata::<u16>().is_none());
+    /// ```
+    fn userdata<T: Any>(&self) -> Option<Rc<RefCell<T>>>;
+
+    /// Creates a `Fiber` running `block`, which needs to be a `Proc` defined in mruby. Panics
+    /// otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::FiberResult;
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont {
-    ///     value: i32
-    /// };
+    /// let block = mruby.run("proc { 3.times { |i| Fiber.yield i } }").unwrap();
+    /// let fiber = mruby.fiber(block);
     ///
-    /// mruby.def_class_for::<Cont>("Container");
-    /// mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
-    ///     let cont = Cont { value: v };
+    /// for i in 0..3 {
+    ///     assert_eq!(fiber.resume(vec![]).unwrap(), FiberResult::Yielded(mruby.fixnum(i)));
+    /// }
     ///
-    ///     slf.init(cont)
-    /// }));
-    /// mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
-    ///     mruby.fixnum(slf.value)
-    /// }));
+    /// match fiber.resume(vec![]).unwrap() {
+    ///     FiberResult::Finished(_) => (),
+    ///     FiberResult::Yielded(_)  => assert!(false)
+    /// }
+    /// ```
+    fn fiber(&self, block: Value) -> Fiber;
+
+    /// Parses mruby `code` without running it and returns whether it is `Complete`, needs more
+    /// input, like a missing `end`, an open string or heredoc, or a trailing `.`, or has a syntax
+    /// `Error`. Useful for REPLs reading multi-line input.
     ///
-    /// let result = mruby.run("Container.new(3).value").unwrap();
+    /// # Examples
     ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
-    /// # }
     /// ```
-    fn def_method_for<T: Any, F>(&self, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static;
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::SyntaxResult;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.check_syntax("1 + 2"), SyntaxResult::Complete);
+    /// assert_eq!(mruby.check_syntax("def one\n  1"), SyntaxResult::Incomplete);
+    /// assert_eq!(mruby.check_syntax("1 + end"),
+    ///            SyntaxResult::Error("line 1:7: syntax error, unexpected keyword_end".to_owned()));
+    /// ```
+    fn check_syntax(&self, code: &str) -> SyntaxResult;
 
-    /// Defines an mruby class method named `name` on the mruby `Class` reflecting type `T`. The
-    /// closure to be run when the `name` method is called should be passed through the `mrfn!`
-    /// macro.
+    /// Runs mruby `script` on a state and context and returns a `Value`. If an mruby Exception is
+    /// raised, mruby will be left to handle it.
+    ///
+    /// The method is unsafe because running it within a Rust context will interrupt drops,
+    /// potentially leading to memory leaks.
+    ///
     ///
     /// # Examples
     ///
     /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = unsafe { mruby.run_unchecked("true") };
+    ///
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// ```
+    ///
+    /// ```
     /// # #[macro_use] extern crate mrusty;
     /// use mrusty::{Mruby, MrubyImpl};
     ///
@@ -778,36 +1481,73 @@ pub trait MrubyImpl {
     /// struct Cont;
     ///
     /// mruby.def_class_for::<Cont>("Container");
-    /// mruby.def_class_method_for::<Cont, _>("hi", mrfn!(|mruby, _slf: Value, v: i32| {
-    ///     mruby.fixnum(v)
+    /// mruby.def_class_method_for::<Cont, _>("raise", mrfn!(|mruby, _slf: Value| {
+    ///     unsafe { mruby.run_unchecked("fail 'surprize'") }
     /// }));
     ///
-    /// let result = mruby.run("Container.hi 3").unwrap();
+    /// let result = mruby.run("
+    ///   begin
+    ///     Container.raise
+    ///   rescue => e
+    ///     e.message
+    ///   end
+    /// ").unwrap();
     ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// assert_eq!(result.to_str().unwrap(), "surprize");
     /// # }
     /// ```
-    fn def_class_method_for<T: Any, F>(&self, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static;
+    #[inline]
+    unsafe fn run_unchecked(&self, script: &str) -> Value;
 
-    /// Return the mruby name of a previously defined Rust type `T` with `def_class`.
+    /// Runs mruby compiled (.mrb) `script` on a state and context and returns a `Value` in an `Ok`
+    /// or an `Err` containing an mruby `Exception`'s message.
     ///
     /// # Examples
     ///
+    /// ```ignore
+    /// let mruby = Mruby::new();
+    /// let result = mruby.runb(include_bytes!("script.mrb")).unwrap();
     /// ```
-    /// # use mrusty::{Mruby, MrubyImpl};
+    #[inline]
+    fn runb(&self, script: &[u8]) -> Result<Value, MrubyError>;
+
+    /// Loads the mruby gem `name` from compiled (.mrb) `bytecode` and marks it as required, so
+    /// that a later `require` of `name` does not load it again. Use `include_gem!` to bake the
+    /// bytecode into the binary. Returns `Err(MrubyError::Filetype)` if `bytecode` does not start
+    /// with a valid header.
+    ///
+    /// # Examples
     ///
+    /// ```ignore
     /// let mruby = Mruby::new();
+    /// mruby.load_gem("gem", include_gem!("gem.mrb")).unwrap();
+    /// ```
+    fn load_gem(&self, name: &str, bytecode: &[u8]) -> Result<(), MrubyError>;
+
+    /// Runs `body` and, like Ruby's `rescue`, passes any mruby `Exception` that escapes it to
+    /// `handler`, returning its result in an `Err`. Exceptions raised by unchecked calls are
+    /// caught as well, while other `MrubyError`s are passed as a `StandardError`.
     ///
-    /// struct Cont;
+    /// # Examples
     ///
-    /// mruby.def_class_for::<Cont>("Container");
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
     ///
-    /// assert_eq!(mruby.class_name_for::<Cont>().unwrap(), "Container");
+    /// let result = mruby.rescue(|| mruby.run("raise ArgumentError, 'bad'"), |exc| {
+    ///     exc.class().to_str().to_owned()
+    /// });
+    ///
+    /// assert_eq!(result.unwrap_err(), "ArgumentError");
     /// ```
-    fn class_name_for<T: Any>(&self) -> Result<String, MrubyError>;
+    fn rescue<F, H, E>(&self, body: F, handler: H) -> Result<Value, E>
+        where F: FnOnce() -> Result<Value, MrubyError>,
+              H: Fn(Value) -> E;
 
-    /// Creates mruby `Value` `nil`.
+    /// Runs mruby `script` and, like `rescue ClassName => e`, passes an `Exception` that is a
+    /// `class_name` to `handler`, returning its result in an `Ok`. Any other `Exception`, or one
+    /// raised when `class_name` is not defined, is returned as an `Err` like from `run`.
     ///
     /// # Examples
     ///
@@ -816,97 +1556,155 @@ pub trait MrubyImpl {
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont;
+    /// let result = mruby.rescue_from("ArgumentError", "raise ArgumentError, 'bad'", |exc| {
+    ///     exc.call("message", vec![]).unwrap()
+    /// });
     ///
-    /// mruby.def_class_for::<Cont>("Container");
-    /// mruby.def_method_for::<Cont, _>("nil", |mruby, _slf| mruby.nil());
+    /// assert_eq!(result.unwrap().to_str().unwrap(), "bad");
     ///
-    /// let result = mruby.run("Container.new.nil.nil?").unwrap();
+    /// let result = mruby.rescue_from("ArgumentError", "raise 'worse'", |exc| exc);
     ///
-    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// assert_eq!(result.unwrap_err().to_string(), "Runtime error: RuntimeError: worse");
     /// ```
-    #[inline]
-    fn nil(&self) -> Value;
+    fn rescue_from<F>(&self, class_name: &str, script: &str, handler: F)
+        -> Result<Value, MrubyError>
+        where F: FnOnce(Value) -> Value;
 
-    /// Creates mruby `Value` containing `true` or `false`.
+    /// Returns the current mruby call stack, innermost frame first, in the same form as an
+    /// `Exception`'s backtrace. Called from a method defined in Rust, it shows where in mruby the
+    /// method was called from.
     ///
     /// # Examples
+    ///
     /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
     /// let mruby = Mruby::new();
     ///
-    /// let b = mruby.bool(true);
+    /// let class = mruby.def_class("Container");
+    /// mruby.def_method(class, "depth", mrfn!(|mruby, _slf: Value| {
+    ///     mruby.fixnum(mruby.current_backtrace().len() as i32)
+    /// }));
     ///
-    /// assert_eq!(b.to_bool().unwrap(), true);
+    /// let result = mruby.run_with_filename("
+    ///   def outer
+    ///     Container.new.depth
+    ///   end
+    ///
+    ///   outer
+    /// ", "script.rb").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 2);
+    /// # }
     /// ```
-    #[inline]
-    fn bool(&self, value: bool) -> Value;
+    fn current_backtrace(&self) -> Vec<BacktraceEntry>;
 
-    /// Creates mruby `Value` of `Class` `Fixnum`.
+    /// Raises an `Exception` of `Class` `class` with `message` in mruby. Only meant to be called
+    /// from methods defined in Rust, which it leaves by unwinding.
     ///
     /// # Examples
     ///
     /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
     /// let mruby = Mruby::new();
     ///
-    /// let fixn = mruby.fixnum(2);
+    /// mruby.def_exception_class("GameError", None);
     ///
-    /// assert_eq!(fixn.to_i32().unwrap(), 2);
+    /// let class = mruby.def_class("Game");
+    /// mruby.def_method(class, "save", mrfn!(|mruby, _slf: Value| {
+    ///     let class = mruby.get_class("GameError").unwrap();
+    ///
+    ///     mruby.raise_exception(&class, "cannot save")
+    /// }));
+    ///
+    /// let result = mruby.rescue_from("GameError", "Game.new.save", |exc| {
+    ///     exc.call("message", vec![]).unwrap()
+    /// });
+    ///
+    /// assert_eq!(result.unwrap().to_str().unwrap(), "cannot save");
+    /// # }
     /// ```
-    #[inline]
-    fn fixnum(&self, value: i32) -> Value;
+    fn raise_exception(&self, class: &Class, message: &str) -> !;
 
-    /// Creates mruby `Value` of `Class` `Float`.
+    /// Runs `body` and then, like Ruby's `ensure`, always runs `cleanup`, even if `body` raised an
+    /// mruby `Exception` or panicked. The `Exception` is returned afterwards in an `Err`.
     ///
     /// # Examples
+    ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// let fl = mruby.float(2.3);
+    /// let mut closed = false;
     ///
-    /// assert_eq!(fl.to_f64().unwrap(), 2.3);
+    /// let result = mruby.ensure(|| mruby.run("raise 'oops'"), || closed = true);
+    ///
+    /// assert!(result.is_err());
+    /// assert!(closed);
+    /// ```
+    fn ensure<F, G>(&self, body: F, cleanup: G) -> Result<Value, MrubyError>
+        where F: FnOnce() -> Result<Value, MrubyError>,
+              G: FnOnce();
+
+    /// Runs mruby (compiled (.mrb) or not (.rb)) `script` on a state and context and returns a
+    /// `Value` in an `Ok` or an `Err` containing an mruby `Exception`'s message.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use std::path::Path;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.execute(&Path::new("script.rb")).unwrap();
     /// ```
     #[inline]
-    fn float(&self, value: f64) -> Value;
+    fn execute(&self, script: &Path) -> Result<Value, MrubyError>;
 
-    /// Creates mruby `Value` of `Class` `String`.
+    /// Returns whether the mruby `Class` or `Module` named `name` is defined.
     ///
     /// # Examples
+    ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
+    /// let object = mruby.is_defined("Object");
+    /// let objekt = mruby.is_defined("Objekt");
     ///
-    /// let s = mruby.string("hi");
-    ///
-    /// assert_eq!(s.to_str().unwrap(), "hi");
+    /// assert!(object);
+    /// assert!(!objekt);
     /// ```
     #[inline]
-    fn string(&self, value: &str) -> Value;
+    fn is_defined(&self, name: &str) -> bool;
 
-    /// Creates mruby `Value` of `Class` `Symbol`.
+    /// Returns whether the mruby `Class` or `Module` named `name` is defined under `outer` `Class`
+    /// or `Module`.
     ///
     /// # Examples
+    ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// let s = mruby.symbol("hi");
+    /// let module = mruby.def_module("Just");
+    /// mruby.def_module_under("Mine", &module);
     ///
-    /// assert_eq!(s.to_str().unwrap(), "hi");
+    /// assert!(mruby.is_defined_under("Mine", &module));
     /// ```
     #[inline]
-    fn symbol(&self, value: &str) -> Value;
+    fn is_defined_under<T: ClassLike>(&self, name: &str, outer: &T) -> bool;
 
-    /// Creates mruby `Value` of `Class` `name` containing a Rust object of type `T`.
-    ///
-    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    /// Returns whether a `Class` named `name` is defined, e.g. to tell whether `def_class` or
+    /// `mruby_class!` will reopen it. Unlike `is_defined`, returns `false` for `Module`s and other
+    /// constants.
     ///
     /// # Examples
     ///
@@ -915,20 +1713,35 @@ pub trait MrubyImpl {
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont {
-    ///     value: i32
-    /// }
+    /// mruby.def_module("Mine");
+    /// mruby.run("LIMIT = 3").unwrap();
     ///
-    /// mruby.def_class_for::<Cont>("Container");
+    /// assert!(mruby.is_class_defined("Object"));
+    /// assert!(!mruby.is_class_defined("Mine"));
+    /// assert!(!mruby.is_class_defined("LIMIT"));
+    /// assert!(!mruby.is_class_defined("Container"));
+    /// ```
+    fn is_class_defined(&self, name: &str) -> bool;
+
+    /// Returns the mruby `Class` named `name` in a `Some` or `None` if it is not defined.
     ///
-    /// let value = mruby.obj(Cont { value: 3 });
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let object = mruby.get_class("Object");
+    /// let objekt = mruby.get_class("Objekt");
+    ///
+    /// assert_eq!(object.unwrap().to_str(), "Object");
+    /// assert!(objekt.is_err());
     /// ```
     #[inline]
-    fn obj<T: Any>(&self, obj: T) -> Value;
+    fn get_class(&self, name: &str) -> Result<Class, MrubyError>;
 
-    /// Creates mruby `Value` of `Class` `name` containing a Rust `Option` of type `T`.
-    ///
-    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    /// Returns the mruby `Class` named `name` under `outer` `Class` or `Module` in a `Some` or
+    /// `None` if it is not defined.
     ///
     /// # Examples
     ///
@@ -937,1289 +1750,7368 @@ pub trait MrubyImpl {
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
     ///
-    /// struct Cont {
-    ///     value: i32
-    /// }
-    ///
-    /// mruby.def_class_for::<Cont>("Container");
+    /// struct Cont;
     ///
-    /// let none = mruby.option::<Cont>(None);
-    /// let some = mruby.option(Some(Cont { value: 3 }));
+    /// let module = mruby.def_module("Mine");
+    /// mruby.def_class_under_for::<Cont, _>("Container", &module);
     ///
-    /// let some = some.to_obj::<Cont>().unwrap();
-    /// let some = some.borrow();
+    /// let result = mruby.get_class_under("Container", &module).unwrap();
     ///
-    /// assert_eq!(none.call("nil?", vec![]).unwrap().to_bool().unwrap(), true);
-    /// assert_eq!(some.value, 3);
+    /// assert_eq!(result.to_str(), "Mine::Container");
     /// ```
     #[inline]
-    fn option<T: Any>(&self, obj: Option<T>) -> Value;
+    fn get_class_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Class, MrubyError>;
 
-    /// Creates mruby `Value` of `Class` `Array`.
+    /// Returns the mruby `Module` named `name` in a `Some` or `None` if it is not defined.
     ///
     /// # Examples
+    ///
     /// ```
     /// # use mrusty::Mruby;
     /// # use mrusty::MrubyImpl;
     /// let mruby = Mruby::new();
+    /// let kernel = mruby.get_module("Kernel");
+    /// let kernet = mruby.get_module("Kernet");
     ///
-    /// let array = mruby.array(vec![
-    ///     mruby.fixnum(1),
-    ///     mruby.fixnum(2),
-    ///     mruby.fixnum(3)
-    /// ]);
-    ///
-    /// assert_eq!(array.to_vec().unwrap(), vec![
-    ///     mruby.fixnum(1),
-    ///     mruby.fixnum(2),
-    ///     mruby.fixnum(3)
-    /// ]);
+    /// assert_eq!(kernel.unwrap().to_str(), "Kernel");
+    /// assert!(kernet.is_err());
     /// ```
     #[inline]
-    fn array(&self, value: Vec<Value>) -> Value;
+    fn get_module(&self, name: &str) -> Result<Module, MrubyError>;
+
+    /// Returns the mruby `Module` named `name` under `outer` `Class` or `Module` in a `Some` or
+    /// `None` if it is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let module = mruby.def_module("Just");
+    /// mruby.def_module_under("Mine", &module);
+    ///
+    /// let result = mruby.get_module_under("Mine", &module).unwrap();
+    ///
+    /// assert_eq!(result.to_str(), "Just::Mine");
+    /// ```
+    #[inline]
+    fn get_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Module, MrubyError>;
+
+    /// Returns the value of the constant `name` looked up in the `Class` or `Module` `scope` in an
+    /// `Ok` or an `Err` containing the mruby `Exception`'s message.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.run("
+    ///   module Config
+    ///     API_KEY = 'x'
+    ///   end
+    /// ").unwrap();
+    ///
+    /// let config = mruby.get_module("Config").unwrap().to_value();
+    /// let result = mruby.const_get(config, "API_KEY").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "x");
+    /// ```
+    #[inline]
+    fn const_get(&self, scope: Value, name: &str) -> Result<Value, MrubyError>;
+
+    /// Sets the constant `name` to `value` in the `Class` or `Module` `scope`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scope` is not a `Class` or `Module`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let config = mruby.def_module("Config").to_value();
+    /// mruby.const_set(config, "RETRIES", mruby.fixnum(3));
+    ///
+    /// let result = mruby.run("Config::RETRIES").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// ```
+    #[inline]
+    fn const_set(&self, scope: Value, name: &str, value: Value);
+
+    /// Returns the value of the top-level constant `name` in an `Ok` or an `Err` containing the
+    /// mruby `Exception`'s message.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.run("VERSION = '1.0'").unwrap();
+    ///
+    /// let result = mruby.top_const_get("VERSION").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "1.0");
+    /// ```
+    #[inline]
+    fn top_const_get(&self, name: &str) -> Result<Value, MrubyError>;
+
+    /// Defines a dynamic file that can be `require`d containing the Rust type `T` and runs its
+    /// `MrubyFile`-inherited `require` method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyFile, MrubyImpl, MrubyType};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// impl MrubyFile for Cont {
+    ///     fn require(mruby: MrubyType) {
+    ///         mruby.def_class_for::<Cont>("Container");
+    ///         mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///             let cont = Cont { value: v };
+    ///
+    ///             slf.init(cont)
+    ///         }));
+    ///         mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
+    ///             mruby.fixnum(slf.value)
+    ///         }));
+    ///     }
+    /// }
+    ///
+    /// mruby.def_file::<Cont>("cont");
+    ///
+    /// let result = mruby.run("
+    ///     require 'cont'
+    ///
+    ///     Container.new(3).value
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    #[inline]
+    fn def_file<T: MrubyFile>(&self, name: &str);
+
+    /// Makes `require` load Rust-registered `features`, mapping names to `MrubyFile::require`
+    /// functions, or `.rb` and `.mrb` files found in the ordered `load_paths`, and defines
+    /// `require_relative` for files next to the running one. Loaded features are listed in
+    /// `$LOADED_FEATURES` and are not loaded twice. Names that cannot be found raise a
+    /// `LoadError`.
+    ///
+    /// `load_paths` replace those of an earlier call. Names starting with `./`, `../` or `/` are
+    /// loaded from that path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// # use std::collections::HashMap;
+    /// # use std::env;
+    /// # use std::fs::File;
+    /// # use std::io::Write;
+    /// use mrusty::{Mruby, MrubyFile, MrubyImpl, MrubyType};
+    ///
+    /// # fn main() {
+    /// struct Cont;
+    ///
+    /// impl MrubyFile for Cont {
+    ///     fn require(mruby: MrubyType) {
+    ///         mruby.def_class_for::<Cont>("Container");
+    ///     }
+    /// }
+    ///
+    /// let dir = env::temp_dir();
+    /// let mut file = File::create(dir.join("doc_loader.rb")).unwrap();
+    ///
+    /// file.write_all(b"require 'cont'; class Loaded < Container; end").unwrap();
+    ///
+    /// let mut features: HashMap<String, fn(MrubyType)> = HashMap::new();
+    /// features.insert("cont".to_owned(), Cont::require);
+    ///
+    /// let mruby = Mruby::new();
+    /// mruby.def_require_loader(features, vec![dir]);
+    ///
+    /// assert_eq!(mruby.run("require 'doc_loader'").unwrap(), mruby.bool(true));
+    /// assert_eq!(mruby.run("require 'doc_loader'").unwrap(), mruby.bool(false));
+    /// assert_eq!(mruby.run("$LOADED_FEATURES.size").unwrap(), mruby.fixnum(2));
+    /// assert!(mruby.run("require 'missing'").is_err());
+    /// # }
+    /// ```
+    fn def_require_loader(&self, features: HashMap<String, fn(MrubyType)>,
+                          load_paths: Vec<PathBuf>);
+
+    /// Defines an mruby `Class` named `name`, or reopens it if it is already defined, keeping its
+    /// methods like Ruby does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class("Container");
+    ///
+    /// assert!(mruby.is_defined("Container"));
+    /// ```
+    fn def_class(&self, name: &str) -> Class;
+
+    /// Defines an mruby `Class` named `name` under `outer` `Class` or `Module`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let module = mruby.def_module("Mine");
+    /// mruby.def_class_under("Container", &module);
+    ///
+    /// assert!(mruby.is_defined_under("Container", &module));
+    /// ```
+    fn def_class_under<U: ClassLike>(&self, name: &str, outer: &U) -> Class;
+
+    /// Defines an mruby `Exception` `Class` named `name` inheriting from `parent`, or from
+    /// `StandardError` if `parent` is `None`. It can be raised from Rust with `raise_exception`
+    /// and rescued by name both in mruby and with `rescue_from`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let game_error = mruby.def_exception_class("GameError", None);
+    /// mruby.def_exception_class("SaveError", Some(&game_error));
+    ///
+    /// let result = mruby.run("
+    ///   begin
+    ///     raise SaveError, 'disk full'
+    ///   rescue GameError => e
+    ///     e.message
+    ///   end
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "disk full");
+    /// ```
+    fn def_exception_class(&self, name: &str, parent: Option<&Class>) -> Class;
+
+    /// Defines Rust type `T` as an mruby `Class` named `name`. Calling it again for the same `T`
+    /// returns the existing `Class` unchanged, whatever the `name`, so that more methods can be
+    /// defined on it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// assert!(mruby.is_defined("Container"));
+    /// ```
+    fn def_class_for<T: Any>(&self, name: &str) -> Class;
+
+    /// Defines Rust type `T` as an mruby `Class` named `name` under `outer` `Class` or `Module`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// let module = mruby.def_module("Mine");
+    /// mruby.def_class_under_for::<Cont, _>("Container", &module);
+    ///
+    /// assert!(mruby.is_defined_under("Container", &module));
+    /// ```
+    fn def_class_under_for<T: Any, U: ClassLike>(&self, name: &str, outer: &U) -> Class;
+
+    /// Defines Rust type `T` as an mruby `Class` named `name` inheriting from `superclass`. Only
+    /// methods defined in mruby are usable on instances of `T` through inheritance, since Rust
+    /// methods of `superclass` expect their own type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// let array = mruby.get_class("Array").unwrap();
+    /// mruby.def_subclass_for::<Cont>("Container", &array);
+    ///
+    /// let result = mruby.run("Container.ancestors.include?(Array)").unwrap();
+    ///
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// ```
+    fn def_subclass_for<T: Any>(&self, name: &str, superclass: &Class) -> Class;
+
+    /// Defines an mruby `Module` named `name`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_module("Container");
+    ///
+    /// assert!(mruby.is_defined("Container"));
+    /// ```
+    fn def_module(&self, name: &str) -> Module;
+
+    /// Defines an mruby `Module` named `name` under `outer` `Class` or `Module`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let module = mruby.def_module("Just");
+    /// mruby.def_module_under("Mine", &module);
+    ///
+    /// assert!(mruby.is_defined_under("Mine", &module));
+    /// ```
+    fn def_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Module;
+
+    /// Defines an mruby method named `name` on `Class` `class`. The closure to be run when the
+    /// `name` method is called should be passed through the `mrfn!` macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// let class = mruby.def_class("Container");
+    /// mruby.def_method(class, "value", mrfn!(|mruby, slf: Value| {
+    ///     mruby.fixnum(3)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.new.value").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_method<F>(&self, class: Class, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines an mruby class method named `name` on `Class` `class`. The closure to be run when
+    /// the `name` method is called should be passed through the `mrfn!` macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// let class = mruby.def_class("Container");
+    /// mruby.def_class_method(class, "hi", mrfn!(|mruby, _slf: Value, v: i32| {
+    ///     mruby.fixnum(v)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.hi 3").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_class_method<F>(&self, class: Class, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines an mruby module function named `name` on `Module` `module`. The closure to be run
+    /// when the `name` method is called should be passed through the `mrfn!` macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// let module = mruby.def_module("Container");
+    /// mruby.def_module_method(module, "hi", mrfn!(|mruby, _slf: Value, v: i32| {
+    ///     mruby.fixnum(v)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.hi 3").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_module_method<F>(&self, module: Module, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines a top-level mruby method named `name`, callable without a receiver from any
+    /// script, just like a `def` at script scope. The closure to be run when the `name` method is
+    /// called should be passed through the `mrfn!` macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_toplevel_method("double", mrfn!(|mruby, _slf: Value, v: i32| {
+    ///     mruby.fixnum(v * 2)
+    /// }));
+    ///
+    /// let result = mruby.run("double 3").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 6);
+    /// # }
+    /// ```
+    fn def_toplevel_method<F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines an mruby method named `name` on the mruby `Class` reflecting type `T`. The closure
+    /// to be run when the `name` method is called should be passed through the `mrfn!` macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///     let cont = Cont { value: v };
+    ///
+    ///     slf.init(cont)
+    /// }));
+    /// mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
+    ///     mruby.fixnum(slf.value)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.new(3).value").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Same as `def_method_for`, but returns an `Err` instead of panicking if `T` was not defined
+    /// with `def_class_for`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// let result = mruby.try_def_method_for::<Cont, _>("value", mrfn!(|mruby, _slf: Value| {
+    ///     mruby.fixnum(3)
+    /// }));
+    ///
+    /// assert!(result.unwrap_err().to_string().contains("cannot define method 'value'"));
+    /// # }
+    /// ```
+    fn try_def_method_for<T: Any, F>(&self, name: &str, method: F) -> Result<(), MrubyError>
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines an mruby class method named `name` on the mruby `Class` reflecting type `T`. The
+    /// closure to be run when the `name` method is called should be passed through the `mrfn!`
+    /// macro.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_class_method_for::<Cont, _>("hi", mrfn!(|mruby, _slf: Value, v: i32| {
+    ///     mruby.fixnum(v)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.hi 3").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_class_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Same as `def_class_method_for`, but returns an `Err` instead of panicking if `T` was not
+    /// defined with `def_class_for`.
+    fn try_def_class_method_for<T: Any, F>(&self, name: &str, method: F) -> Result<(), MrubyError>
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines a private mruby method named `name` on the mruby `Class` reflecting type `T`, like
+    /// `def_method_for`. Private methods can only be called by the object itself, e.g. from its
+    /// other methods, or through `send`. Calling them on another receiver raises a
+    /// `NoMethodError`.
+    ///
+    /// *Note:* mruby does not record whether a call had an explicit receiver, so this only checks
+    /// that the caller's `self` is the receiver. Unlike Ruby, where a private method must be
+    /// called without a receiver, `obj = self; obj.secret` is allowed as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_private_method_for::<Cont, _>("secret", mrfn!(|mruby, _slf: Value| {
+    ///     mruby.fixnum(3)
+    /// }));
+    ///
+    /// mruby.run("
+    ///   class Container
+    ///     def reveal
+    ///       secret
+    ///     end
+    ///   end
+    /// ").unwrap();
+    ///
+    /// assert_eq!(mruby.run("Container.new.reveal").unwrap().to_i32().unwrap(), 3);
+    /// assert!(mruby.run("Container.new.secret").is_err());
+    /// # }
+    /// ```
+    fn def_private_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines a protected mruby method named `name` on the mruby `Class` reflecting type `T`, like
+    /// `def_method_for`. Protected methods can only be called from methods of instances of the
+    /// `Class`, or through `send`. Calling them from elsewhere raises a `NoMethodError`.
+    fn def_protected_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static;
+
+    /// Defines `method_missing` and `respond_to_missing?` on the mruby `Class` reflecting type
+    /// `T`, so that a single `method` handles calls to any name the `Class` does not define.
+    /// `method` gets the receiver, the name and the arguments. `responds` gets the receiver and
+    /// the name and tells `respond_to?` which names `method` handles.
+    ///
+    /// When `method` returns `unhandled`, the call falls through to the default
+    /// `method_missing`, which raises a `NoMethodError`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    /// use std::collections::HashMap;
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Doc {
+    ///     fields: HashMap<String, i32>
+    /// };
+    ///
+    /// mruby.def_class_for::<Doc>("Doc");
+    /// mruby.def_method_missing_for::<Doc, _, _>(|mruby, slf, name, _args| {
+    ///     let doc = slf.to_obj::<Doc>().unwrap();
+    ///     let doc = doc.borrow();
+    ///
+    ///     match doc.fields.get(name) {
+    ///         Some(&value) => mruby.fixnum(value),
+    ///         None         => mruby.unhandled()
+    ///     }
+    /// }, |_mruby, slf, name| {
+    ///     slf.to_obj::<Doc>().unwrap().borrow().fields.contains_key(name)
+    /// });
+    ///
+    /// let mut fields = HashMap::new();
+    /// fields.insert("pages".to_owned(), 3);
+    ///
+    /// let doc = mruby.obj(Doc { fields: fields });
+    ///
+    /// assert_eq!(doc.call("pages", vec![]).unwrap(), mruby.fixnum(3));
+    /// assert_eq!(doc.call("respond_to?", vec![mruby.symbol("pages")]).unwrap(), mruby.bool(true));
+    /// assert!(doc.call("title", vec![]).is_err());
+    /// # }
+    /// ```
+    fn def_method_missing_for<T: Any, F, R>(&self, method: F, responds: R)
+        where F: Fn(MrubyType, Value, &str, Vec<Value>) -> Value + 'static,
+              R: Fn(MrubyType, Value, &str) -> bool + 'static;
+
+    /// Returns the `Value` that a `def_method_missing_for` handler returns for names it does not
+    /// handle.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.unhandled(), mruby.unhandled());
+    /// assert!(mruby.unhandled() != mruby.nil());
+    /// ```
+    fn unhandled(&self) -> Value;
+
+    /// Defines an mruby method named `name` on the mruby `Class` reflecting type `T`, like
+    /// `def_method_for`, but from a plain `fn` instead of a closure. The `fn` is stored in the
+    /// method itself, so calling it skips the method table lookup `def_method_for` needs. An
+    /// `mrfn!` that does not capture anything coerces to a `fn`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_method_fn::<Cont>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///     slf.init(Cont { value: v })
+    /// }));
+    /// mruby.def_method_fn::<Cont>("value", mrfn!(|mruby, slf: (&Cont)| {
+    ///     mruby.fixnum(slf.value)
+    /// }));
+    ///
+    /// let result = mruby.run("Container.new(3).value").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn def_method_fn<T: Any>(&self, name: &str, method: fn(MrubyType, Value) -> Value);
+
+    /// Returns a `ClassBuilder` which defines an mruby `Class` named `name` reflecting type `T`
+    /// together with all its methods at once. This is cheaper than calling `def_class_for` and
+    /// `def_method_for` for every method when many types are reflected at startup.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.class_builder::<Cont>("Container")
+    ///      .method_fn("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///          slf.init(Cont { value: v })
+    ///      }))
+    ///      .method("value", mrfn!(|mruby, slf: (&Cont)| {
+    ///          mruby.fixnum(slf.value)
+    ///      }))
+    ///      .class_method("zero", mrfn!(|mruby, _slf: Value| {
+    ///          mruby.obj(Cont { value: 0 })
+    ///      }))
+    ///      .define();
+    ///
+    /// let result = mruby.run("Container.new(3).value + Container.zero.value").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn class_builder<'a, T: Any>(&self, name: &'a str) -> ClassBuilder<'a, T>;
+
+    /// Returns a `ClassBuilder` which adds methods to the already defined mruby `Class`
+    /// reflecting type `T`, like a `class` reopened in mruby. Calling `define` on it adds them all
+    /// at once; a `superclass` set on it is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// mruby.open_class_for::<Cont>()
+    ///      .method("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///          slf.init(Cont { value: v })
+    ///      }))
+    ///      .method("value", mrfn!(|mruby, slf: (&Cont)| {
+    ///          mruby.fixnum(slf.value)
+    ///      }))
+    ///      .define();
+    ///
+    /// let result = mruby.run("Container.new(3).value").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    fn open_class_for<'a, T: Any>(&self) -> ClassBuilder<'a, T>;
+
+    /// Defines an `each` method on the mruby `Class` reflecting type `T` which yields all the
+    /// `Value`s returned by `iter` and includes `Enumerable` in the `Class`. The `Value`s are
+    /// collected before the first one is yielded, so the block is free to call back into the
+    /// object. Without a block, `each` returns an `Enumerator`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     values: Vec<i32>
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, a: i32, b: i32| {
+    ///     slf.init(Cont { values: vec![a, b] })
+    /// }));
+    /// mruby.def_each_for::<Cont, _, _>(|mruby, cont| {
+    ///     cont.values.iter().map(|&v| mruby.fixnum(v)).collect::<Vec<_>>()
+    /// });
+    ///
+    /// let result = mruby.run("Container.new(1, 2).map { |v| v * 2 }.inject(:+)").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 6);
+    /// # }
+    /// ```
+    fn def_each_for<T: Any, I, F>(&self, iter: F)
+        where I: IntoIterator<Item = Value>,
+              F: Fn(MrubyType, &T) -> I + 'static;
+
+    /// Defines `<=>` on the mruby `Class` reflecting type `T` in terms of `T`'s `Ord`
+    /// implementation and includes `Comparable` in the `Class`. Comparing with objects of other
+    /// types returns `nil`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Class` reflecting type `T` is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    /// struct Size(i32);
+    ///
+    /// mruby.def_class_for::<Size>("Size");
+    /// mruby.def_method_for::<Size, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///     slf.init(Size(v))
+    /// }));
+    /// mruby.def_comparable_for::<Size>();
+    ///
+    /// let result = mruby.run("[Size.new(3), Size.new(1)].min > Size.new(0)").unwrap();
+    ///
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// # }
+    /// ```
+    fn def_comparable_for<T: Any + Ord>(&self);
+
+    /// Defines `==`, `eql?` and `hash` on the mruby `Class` reflecting type `T` in terms of `T`'s
+    /// `PartialEq` and `Hash` implementations, so that equal objects can be used interchangeably
+    /// as `Hash` keys. Objects of other types are never equal.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Class` reflecting type `T` is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// #[derive(PartialEq, Hash)]
+    /// struct Name(i32);
+    ///
+    /// mruby.def_class_for::<Name>("Name");
+    /// mruby.def_method_for::<Name, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///     slf.init(Name(v))
+    /// }));
+    /// mruby.def_eq_for::<Name>();
+    ///
+    /// let result = mruby.run("{ Name.new(1) => :one }[Name.new(1)]").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "one");
+    /// # }
+    /// ```
+    fn def_eq_for<T: Any + PartialEq + Hash>(&self);
+
+    /// Defines `inspect` on the mruby `Class` reflecting type `T` in terms of `T`'s `Debug`
+    /// implementation. If the object is mutably borrowed at the time, e.g. while one of its own
+    /// methods is running, `inspect` falls back to the `Class` name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Class` reflecting type `T` is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// #[derive(Debug)]
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_display_for::<Cont>();
+    ///
+    /// let result = mruby.obj(Cont { value: 3 }).call("inspect", vec![]).unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "Cont { value: 3 }");
+    /// # }
+    /// ```
+    fn def_display_for<T: Any + fmt::Debug>(&self);
+
+    /// Defines `to_s` on the mruby `Class` reflecting type `T` in terms of `T`'s `Display`
+    /// implementation, falling back to the `Class` name like `def_display_for`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Class` reflecting type `T` is not defined.
+    fn def_to_s_for<T: Any + fmt::Display>(&self);
+
+    /// Defines the operator method `op` on the mruby `Class` reflecting type `T`. `f` receives
+    /// the object and the operand, which is `nil` for the unary operators `+@`, `-@`, `~` and
+    /// `!`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `op` is not an operator that Ruby lets classes define or if the `Class`
+    /// reflecting type `T` is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// struct Money(i32);
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class_for::<Money>("Money");
+    /// mruby.def_operator_for::<Money, _>("+", |mruby, money, other| {
+    ///     let other = other.to_obj::<Money>().unwrap();
+    ///     let sum = money.0 + other.borrow().0;
+    ///
+    ///     mruby.obj(Money(sum))
+    /// });
+    /// mruby.def_operator_for::<Money, _>("-@", |mruby, money, _| mruby.obj(Money(-money.0)));
+    ///
+    /// let a = mruby.obj(Money(3));
+    /// let b = mruby.obj(Money(2));
+    ///
+    /// let result = a.call("+", vec![b]).unwrap().call("-@", vec![]).unwrap();
+    ///
+    /// assert_eq!(result.to_obj::<Money>().unwrap().borrow().0, -5);
+    /// ```
+    fn def_operator_for<T: Any, F>(&self, op: &str, f: F)
+        where F: Fn(MrubyType, &T, Value) -> Value + 'static;
+
+    /// Defines all operator methods in `ops` on the mruby `Class` reflecting type `T`, like
+    /// `def_operator_for`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the operators is not one that Ruby lets classes define or if the `Class`
+    /// reflecting type `T` is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// struct Meters(f64);
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class_for::<Meters>("Meters");
+    /// mruby.def_operators_for::<Meters>(vec![
+    ///     ("*", Box::new(|mruby, m, k| mruby.obj(Meters(m.0 * k.to_f64().unwrap())))),
+    ///     ("/", Box::new(|mruby, m, k| mruby.obj(Meters(m.0 / k.to_f64().unwrap()))))
+    /// ]);
+    ///
+    /// let meters = mruby.obj(Meters(3.0));
+    /// let result = meters.call("*", vec![mruby.float(4.0)]).unwrap()
+    ///                    .call("/", vec![mruby.float(2.0)]).unwrap();
+    ///
+    /// assert_eq!(result.to_obj::<Meters>().unwrap().borrow().0, 6.0);
+    /// ```
+    fn def_operators_for<T: Any>(&self,
+                                 ops: Vec<(&str, Box<dyn Fn(MrubyType, &T, Value) -> Value>)>);
+
+    /// Defines a reader method on the mruby `Class` reflecting type `T` for every `(name, reader)`
+    /// pair in `attrs`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// struct Point {
+    ///     x: i32,
+    ///     y: i32
+    /// };
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class_for::<Point>("Point");
+    /// mruby.def_attr_reader_for::<Point>(&[
+    ///     ("x", |mruby, point| mruby.fixnum(point.x)),
+    ///     ("y", |mruby, point| mruby.fixnum(point.y))
+    /// ]);
+    ///
+    /// let point = mruby.obj(Point { x: 1, y: 2 });
+    ///
+    /// assert_eq!(point.call("y", vec![]).unwrap().to_i32().unwrap(), 2);
+    /// ```
+    fn def_attr_reader_for<T: Any>(&self, attrs: &[(&str, fn(MrubyType, &T) -> Value)]);
+
+    /// Defines a writer method `name=` on the mruby `Class` reflecting type `T` for every
+    /// `(name, writer)` pair in `attrs`. The writer methods return the assigned `Value`.
+    ///
+    /// *Note:* Writers that store the `Value` in `T` need `T` to mark it, see `def_mark_for`;
+    /// otherwise the GC may collect it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// struct Point {
+    ///     x: i32,
+    ///     y: i32
+    /// };
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class_for::<Point>("Point");
+    /// mruby.def_attr_reader_for::<Point>(&[("x", |mruby, point| mruby.fixnum(point.x))]);
+    /// mruby.def_attr_writer_for::<Point>(&[
+    ///     ("x", |point, value| point.x = value.to_i32().unwrap()),
+    ///     ("y", |point, value| point.y = value.to_i32().unwrap())
+    /// ]);
+    ///
+    /// let point = mruby.obj(Point { x: 1, y: 2 });
+    ///
+    /// point.call("x=", vec![mruby.fixnum(3)]).unwrap();
+    ///
+    /// assert_eq!(point.call("x", vec![]).unwrap().to_i32().unwrap(), 3);
+    /// ```
+    fn def_attr_writer_for<T: Any>(&self, attrs: &[(&str, fn(&mut T, Value))]);
+
+    /// Lets the GC trace the `Value`s held by objects of type `T` through `MrubyMark`, which it
+    /// calls whenever it marks such an object.
+    ///
+    /// *Note:* The `Value`s can change at any time without telling the GC, so from then on every
+    /// GC runs to completion at once instead of incrementally. Setting `GC.generational_mode` or
+    /// `GC.step_ratio` from mruby has no effect anymore.
+    ///
+    /// See `MrubyMark` for an example.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` was not defined with `def_class_for`.
+    fn def_mark_for<T: Any + MrubyMark>(&self);
+
+    /// Runs `finalizer` on every object of type `T` right before the GC frees it, or when the
+    /// `Mruby` is closed, e.g. to release the resources it holds early or to log leaks. The Rust
+    /// object itself is only dropped once Rust no longer references it either.
+    ///
+    /// *Note:* `finalizer` runs in the middle of a GC, so it must not call back into mruby, which
+    /// is asserted in debug builds, nor use the `Value`s held by the object.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Buffer {
+    ///     size: usize
+    /// }
+    ///
+    /// let released = Rc::new(Cell::new(0));
+    /// let counter = released.clone();
+    ///
+    /// mruby.def_class_for::<Buffer>("Buffer");
+    /// mruby.def_finalizer_for::<Buffer, _>(move |buffer| {
+    ///     counter.set(counter.get() + buffer.size);
+    /// });
+    ///
+    /// let arena = mruby.gc_arena_save();
+    ///
+    /// mruby.obj(Buffer { size: 64 });
+    ///
+    /// mruby.gc_arena_restore(arena);
+    /// mruby.gc_run();
+    ///
+    /// assert_eq!(released.get(), 64);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` was not defined with `def_class_for`.
+    fn def_finalizer_for<T: Any, F: Fn(&T) + 'static>(&self, finalizer: F);
+
+    /// Lets `Value::to_obj_downcast` borrow objects of the `Class` defined for `Box<T>` as their
+    /// concrete types, `T` usually being a trait with `MrubyAny` as a supertrait.
+    ///
+    /// See `MrubyAny` for an example.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `Box<T>` was not defined with `def_class_for`.
+    fn def_downcast_for<T: ?Sized + MrubyAny>(&self);
+
+    /// Return the mruby name of a previously defined Rust type `T` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// assert_eq!(mruby.class_name_for::<Cont>().unwrap(), "Container");
+    /// ```
+    fn class_name_for<T: Any>(&self) -> Result<String, MrubyError>;
+
+    /// Creates mruby `Value` `nil`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_method_for::<Cont, _>("nil", |mruby, _slf| mruby.nil());
+    ///
+    /// let result = mruby.run("Container.new.nil.nil?").unwrap();
+    ///
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// ```
+    #[inline]
+    fn nil(&self) -> Value;
+
+    /// Creates mruby `Value` containing `true` or `false`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let b = mruby.bool(true);
+    ///
+    /// assert_eq!(b.to_bool().unwrap(), true);
+    /// ```
+    #[inline]
+    fn bool(&self, value: bool) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Fixnum`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let fixn = mruby.fixnum(2);
+    ///
+    /// assert_eq!(fixn.to_i32().unwrap(), 2);
+    /// ```
+    #[inline]
+    fn fixnum(&self, value: i32) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Fixnum` from an `i64`. Values outside of the `MrInt`
+    /// range become a `Float` instead, just like mruby's own overflowing arithmetic, and lose
+    /// precision past 2^53.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.fixnum_i64(-2), mruby.fixnum(-2));
+    /// assert_eq!(mruby.fixnum_i64(1 << 40), mruby.float(1099511627776.0));
+    /// assert_eq!(mruby.fixnum_i64(1 << 40).to_i64().unwrap(), 1 << 40);
+    /// ```
+    fn fixnum_i64(&self, value: i64) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Fixnum` from a `u64`, becoming a `Float` outside of the
+    /// `MrInt` range like `fixnum_i64`.
+    fn fixnum_u64(&self, value: u64) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Float`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let fl = mruby.float(2.3);
+    ///
+    /// assert_eq!(fl.to_f64().unwrap(), 2.3);
+    /// ```
+    #[inline]
+    fn float(&self, value: f64) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Float` from an `f32`, widened to `f64`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let fl = mruby.float32(2.3);
+    ///
+    /// assert_eq!(fl.to_f64().unwrap() as f32, 2.3);
+    /// ```
+    #[inline]
+    fn float32(&self, value: f32) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Time` from a `SystemTime`, truncated to microseconds.
+    /// Times before the epoch become negative seconds, just like `Time.at(-1.5)`. Returns an
+    /// `Err` if `Time` is not defined because mruby was built without the mruby-time gem.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// let time = mruby.time(UNIX_EPOCH + Duration::new(1, 500_000_000)).unwrap();
+    ///
+    /// assert_eq!(time.call("to_f", vec![]).unwrap().to_f64().unwrap(), 1.5);
+    /// assert_eq!(time.to_system_time().unwrap(), UNIX_EPOCH + Duration::new(1, 500_000_000));
+    /// ```
+    fn time(&self, time: SystemTime) -> Result<Value, MrubyError>;
+
+    /// Creates mruby `Value` of `Class` `Float` holding the seconds of `duration`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::time::Duration;
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// let duration = mruby.duration(Duration::from_millis(2500));
+    ///
+    /// assert_eq!(duration.to_f64().unwrap(), 2.5);
+    /// assert_eq!(duration.to_duration().unwrap(), Duration::from_millis(2500));
+    /// ```
+    fn duration(&self, duration: Duration) -> Value;
+
+    /// Creates mruby `Value` of `Class` `String`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let s = mruby.string("hi");
+    ///
+    /// assert_eq!(s.to_str().unwrap(), "hi");
+    /// ```
+    #[inline]
+    fn string(&self, value: &str) -> Value;
+
+    /// Creates a frozen mruby `Value` of `Class` `String` that references `value` instead of
+    /// copying it. Since mruby never frees or writes to the buffer, `value` needs to be
+    /// `'static`; mutating the `String` raises a `RuntimeError`, while `dup` returns a mutable
+    /// copy.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let s = mruby.string_static("hi");
+    ///
+    /// assert_eq!(s.to_str().unwrap(), "hi");
+    /// assert!(s.call("<<", vec![mruby.string("!")]).is_err());
+    /// ```
+    #[inline]
+    fn string_static(&self, value: &'static str) -> Value;
+
+    /// Creates mruby `Value` of `Class` `String` from arbitrary bytes, which do not need to be
+    /// valid UTF-8.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let s = mruby.bytes(&[0xff, 0, 0x61]);
+    ///
+    /// assert_eq!(s.call("bytesize", vec![]).unwrap().to_i32().unwrap(), 3);
+    /// assert_eq!(s.to_bytes().unwrap(), &[0xff, 0, 0x61]);
+    /// ```
+    #[inline]
+    fn bytes(&self, value: &[u8]) -> Value;
+
+    /// Creates mruby `Value` of `Class` `String` from a `Path`. On Unix the path's bytes are
+    /// copied as they are, so that paths which are not valid UTF-8 round-trip through
+    /// `to_path_buf`. On other platforms paths need to be valid Unicode and return an `Err`
+    /// otherwise.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::path::Path;
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// let path = mruby.string_from_path(Path::new("scripts/main.rb")).unwrap();
+    ///
+    /// assert_eq!(path.call("end_with?", vec![mruby.string(".rb")]).unwrap().to_bool().unwrap(),
+    ///            true);
+    /// ```
+    fn string_from_path(&self, path: &Path) -> Result<Value, MrubyError>;
+
+    /// Appends `other` to the mruby `String` `string` in place, like `String#<<`. Values that
+    /// are not `String`s are converted with `to_str` or `to_s` first. Returns an `Err` if
+    /// `string` is not a `String` or if mruby raised, e.g. because `string` is frozen.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let s = mruby.string("");
+    ///
+    /// for i in 0..3 {
+    ///     mruby.string_concat(&s, &mruby.fixnum(i)).unwrap();
+    /// }
+    ///
+    /// assert_eq!(s.to_str().unwrap(), "012");
+    /// ```
+    fn string_concat(&self, string: &Value, other: &Value) -> Result<(), MrubyError>;
+
+    /// Appends `bytes` to the mruby `String` `string` in place without creating an intermediate
+    /// `String`. Returns an `Err` like `string_concat`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let packet = mruby.bytes(&[0x01]);
+    ///
+    /// mruby.string_append_bytes(&packet, &[0x00, 0xff]).unwrap();
+    ///
+    /// assert_eq!(packet.to_bytes().unwrap(), &[0x01, 0x00, 0xff]);
+    /// ```
+    fn string_append_bytes(&self, string: &Value, bytes: &[u8]) -> Result<(), MrubyError>;
+
+    /// Creates mruby `Value` of `Class` `Symbol`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let s = mruby.symbol("hi");
+    ///
+    /// assert_eq!(s.to_str().unwrap(), "hi");
+    /// ```
+    #[inline]
+    fn symbol(&self, value: &str) -> Value;
+
+    /// Interns `name` and returns its `MrubySymbolId`. Ids are cached per `Mruby`, so only the
+    /// first call for a given name reaches mruby's symbol table and later ones do not allocate.
+    /// While the interpreter is borrowed, e.g. from inside a `Ref` returned by `borrow()`, the
+    /// name is interned uncached.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let plus = mruby.intern("+");
+    ///
+    /// assert_eq!(mruby.intern("+"), plus);
+    /// ```
+    #[inline]
+    fn intern(&self, name: &str) -> MrubySymbolId;
+
+    /// Returns the name of the symbol `id` without allocating. Names live as long as the `Mruby`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let id = mruby.intern("read write");
+    ///
+    /// assert_eq!(mruby.lookup_symbol(id), "read write");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was not interned by this `Mruby`.
+    fn lookup_symbol(&self, id: MrubySymbolId) -> &str;
+
+    /// Calls the method named by the `Symbol` `method` on `receiver` passing `args`, for when the
+    /// name itself comes from mruby. Returns an `Err` like `Value::call`, holding a `TypeError`
+    /// if `method` is not a `Symbol`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let strategy = mruby.run(":+").unwrap();
+    ///
+    /// let result = mruby.send(&mruby.fixnum(1), strategy, vec![mruby.fixnum(2)]).unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// assert!(mruby.send(&mruby.fixnum(1), mruby.string("+"), vec![]).is_err());
+    /// ```
+    fn send(&self, receiver: &Value, method: Value, args: Vec<Value>) -> Result<Value, MrubyError>;
+
+    /// Calls method `sym` on `receiver` passing `args` straight to `mrb_funcall_argv`, without
+    /// looking up a name or collecting `args` into a `Vec` like `Value::call`. Meant for tight
+    /// loops reusing a `MrubySymbolId` from `intern`. Returns an `Err` like `Value::call`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let plus = mruby.intern("+");
+    /// let mut sum = mruby.fixnum(0);
+    ///
+    /// for i in 1..11 {
+    ///     sum = mruby.funcall_argv(&sum, plus, &[mruby.fixnum(i)]).unwrap();
+    /// }
+    ///
+    /// assert_eq!(sum.to_i32().unwrap(), 55);
+    /// ```
+    fn funcall_argv(&self, receiver: &Value, sym: MrubySymbolId, args: &[Value])
+        -> Result<Value, MrubyError>;
+
+    /// Creates mruby `Value` of `Class` `name` containing a Rust object of type `T`.
+    ///
+    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let value = mruby.obj(Cont { value: 3 });
+    /// ```
+    #[inline]
+    fn obj<T: Any>(&self, obj: T) -> Value;
+
+    /// Creates mruby `Value` of `Class` `name` sharing a Rust object of type `T` with `obj`.
+    /// Sharing the same object again, or one returned by `Value::to_obj`, returns the same mruby
+    /// object for as long as the GC keeps it, so that `equal?` holds between them and it can be
+    /// used as a `Hash` key. Collecting it only drops its reference to `obj`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let cont = Rc::new(RefCell::new(Cont { value: 3 }));
+    ///
+    /// let first = mruby.obj_shared(cont.clone());
+    /// let second = mruby.obj_shared(cont.clone());
+    ///
+    /// assert!(first.call("equal?", vec![second]).unwrap().to_bool().unwrap());
+    ///
+    /// cont.borrow_mut().value = 4;
+    ///
+    /// assert_eq!(first.to_obj::<Cont>().unwrap().borrow().value, 4);
+    /// ```
+    fn obj_shared<T: Any>(&self, obj: Rc<RefCell<T>>) -> Value;
+
+    /// Returns the mruby object currently wrapping `obj`, if the GC has not collected it yet.
+    /// Objects created with `obj`, `init` or `obj_shared` are all known.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont;
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let value = mruby.obj(Cont);
+    /// let cont = value.to_obj::<Cont>().unwrap();
+    ///
+    /// assert_eq!(mruby.find_obj(&cont), Some(value));
+    /// ```
+    fn find_obj<T: Any>(&self, obj: &Rc<RefCell<T>>) -> Option<Value>;
+
+    /// Creates mruby `Value` of `Class` `name` containing a Rust `Option` of type `T`.
+    ///
+    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let none = mruby.option::<Cont>(None);
+    /// let some = mruby.option(Some(Cont { value: 3 }));
+    ///
+    /// let some = some.to_obj::<Cont>().unwrap();
+    /// let some = some.borrow();
+    ///
+    /// assert_eq!(none.call("nil?", vec![]).unwrap().to_bool().unwrap(), true);
+    /// assert_eq!(some.value, 3);
+    /// ```
+    #[inline]
+    fn option<T: Any>(&self, obj: Option<T>) -> Value;
+
+    /// Creates mruby `Value` of `Class` `Array`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let array = mruby.array(vec![
+    ///     mruby.fixnum(1),
+    ///     mruby.fixnum(2),
+    ///     mruby.fixnum(3)
+    /// ]);
+    ///
+    /// assert_eq!(array.to_vec().unwrap(), vec![
+    ///     mruby.fixnum(1),
+    ///     mruby.fixnum(2),
+    ///     mruby.fixnum(3)
+    /// ]);
+    /// ```
+    #[inline]
+    fn array(&self, value: Vec<Value>) -> Value;
+
+    /// Creates an empty mruby `Value` of `Class` `Array` to be filled with `array_push` and
+    /// `array_concat`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let array = mruby.array_new();
+    ///
+    /// for i in 0..3 {
+    ///     mruby.array_push(&array, mruby.fixnum(i));
+    /// }
+    ///
+    /// mruby.array_concat(&array, &mruby.run("[3, 4]").unwrap());
+    ///
+    /// assert_eq!(array.to_rust::<Vec<i32>>().unwrap(), vec![0, 1, 2, 3, 4]);
+    /// ```
+    fn array_new(&self) -> Value;
+
+    /// Appends `value` to the mruby `Array` `array`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `array` is not an `Array`.
+    fn array_push(&self, array: &Value, value: Value);
+
+    /// Appends all elements of the mruby `Array` `other` to the mruby `Array` `array`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either `array` or `other` is not an `Array`.
+    fn array_concat(&self, array: &Value, other: &Value);
+
+    /// Creates mruby `Value` of `Class` `Hash` with `String` keys from a `HashMap` or
+    /// `BTreeMap`. `BTreeMap`s keep their keys' order.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let mruby = Mruby::new();
+    ///
+    /// let mut options = BTreeMap::new();
+    ///
+    /// options.insert("width", mruby.fixnum(640));
+    /// options.insert("title", mruby.string("mrusty"));
+    ///
+    /// let hash = mruby.map(&options);
+    ///
+    /// assert_eq!(hash.call("keys", vec![]).unwrap().to_rust::<Vec<String>>().unwrap(),
+    ///            vec!["title", "width"]);
+    /// ```
+    fn map<'a, K, I>(&self, map: I) -> Value
+        where K: AsRef<str> + 'a,
+              I: IntoIterator<Item = (&'a K, &'a Value)>;
+
+    /// Returns the value stored under `key` in the mruby `Hash` `hash` or `nil` when it is
+    /// missing. The `Hash`'s default value or default proc is not consulted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash` is not a `Hash`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let hash = mruby.run("{ a: 1 }").unwrap();
+    ///
+    /// mruby.hash_set(&hash, mruby.symbol("b"), mruby.fixnum(2));
+    ///
+    /// assert_eq!(mruby.hash_get(&hash, &mruby.symbol("b")).to_i32().unwrap(), 2);
+    /// assert_eq!(mruby.hash_delete(&hash, &mruby.symbol("a")).to_i32().unwrap(), 1);
+    /// assert_eq!(mruby.hash_get(&hash, &mruby.symbol("a")).call("nil?", vec![]).unwrap()
+    ///                                                      .to_bool().unwrap(), true);
+    /// assert_eq!(mruby.hash_len(&hash), 1);
+    /// ```
+    fn hash_get(&self, hash: &Value, key: &Value) -> Value;
+
+    /// Stores `value` under `key` in the mruby `Hash` `hash`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash` is not a `Hash`.
+    fn hash_set(&self, hash: &Value, key: Value, value: Value);
+
+    /// Removes `key` from the mruby `Hash` `hash`, returning its value or `nil` when it is
+    /// missing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash` is not a `Hash`.
+    fn hash_delete(&self, hash: &Value, key: &Value) -> Value;
+
+    /// Returns the number of entries in the mruby `Hash` `hash`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `hash` is not a `Hash`.
+    fn hash_len(&self, hash: &Value) -> usize;
+
+    /// Returns the keys of the mruby `Hash` `hash` in insertion order in an `Ok` or an `Err` if
+    /// `hash` is not a `Hash`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let hash = mruby.run("{ b: 1, a: 2 }").unwrap();
+    ///
+    /// let keys = mruby.hash_keys(&hash).unwrap();
+    /// let values = mruby.hash_values(&hash).unwrap();
+    ///
+    /// assert_eq!(keys, vec![mruby.symbol("b"), mruby.symbol("a")]);
+    /// assert_eq!(values, vec![mruby.fixnum(1), mruby.fixnum(2)]);
+    /// ```
+    fn hash_keys(&self, hash: &Value) -> Result<Vec<Value>, MrubyError>;
+
+    /// Returns the values of the mruby `Hash` `hash` in insertion order in an `Ok` or an `Err` if
+    /// `hash` is not a `Hash`.
+    fn hash_values(&self, hash: &Value) -> Result<Vec<Value>, MrubyError>;
+
+    /// Creates mruby `Value` of `Class` `Range` from `start` to `end`, excluding `end` when
+    /// `exclusive` is `true`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let range = mruby.range(mruby.fixnum(1), mruby.fixnum(5), true);
+    ///
+    /// assert_eq!(range.call("to_a", vec![]).unwrap().to_rust::<Vec<i32>>().unwrap(),
+    ///            vec![1, 2, 3, 4]);
+    /// ```
+    fn range(&self, start: Value, end: Value, exclusive: bool) -> Value;
+
+    /// Creates an mruby `Value` of `Class` `RustEnumerator` which lazily pulls its elements from
+    /// `iter`, converting them with `ToValue`. It includes `Enumerable`, yields to `each` and returns elements one at a time from
+    /// `next`, raising `StopIteration` once `iter` is exhausted. Since elements are not stored,
+    /// `each` and `next` share the same position and the enumerator can only be traversed once.
+    ///
+    /// Calling `next` again while `iter` is producing an element, e.g. from Ruby code it calls,
+    /// raises a `RuntimeError`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let squares = mruby.enumerator((1..).map(|i| i * i));
+    ///
+    /// let top = mruby.run("def first(enum); enum.take(3); end; self").unwrap();
+    /// let result = top.call("first", vec![squares]).unwrap();
+    ///
+    /// assert_eq!(result.to_rust::<Vec<i32>>().unwrap(), vec![1, 4, 9]);
+    /// ```
+    fn enumerator<I>(&self, iter: I) -> Value
+        where I: Iterator + 'static,
+              I::Item: ToValue;
+
+    /// Creates an mruby `Value` from any Rust value implementing `ToValue`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let array = mruby.value(vec![Some("a"), None]);
+    ///
+    /// assert_eq!(array.to_vec().unwrap(), vec![mruby.string("a"), mruby.nil()]);
+    /// ```
+    #[inline]
+    fn value<T: ToValue>(&self, value: T) -> Value;
+
+    /// Parses a JSON document into an mruby `Value`. Objects become `Hash`es with `String` keys,
+    /// integers that fit become `Fixnum`s and other numbers `Float`s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let config = mruby.json_parse(r#"{ "sizes": [1, 2.5], "name": null }"#).unwrap();
+    /// let sizes = config.call("[]", vec![mruby.string("sizes")]).unwrap();
+    ///
+    /// assert_eq!(sizes.to_vec().unwrap(), vec![mruby.fixnum(1), mruby.float(2.5)]);
+    /// ```
+    fn json_parse(&self, json: &str) -> Result<Value, MrubyError>;
+
+    /// Builds an mruby `Value` tree from a JSON document. Same as `json_parse`: `null` becomes
+    /// `nil` and `true` and `false` their mruby counterparts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let value = mruby.from_json("[null, true, \"caf\\u00e9\"]").unwrap();
+    ///
+    /// assert_eq!(value.to_vec().unwrap(),
+    ///            vec![mruby.nil(), mruby.bool(true), mruby.string("café")]);
+    /// ```
+    fn from_json(&self, json: &str) -> Result<Value, MrubyError>;
+
+    /// Serializes `value` to an mruby `Value`. Structs become `Hash`es with `Symbol` keys, enums
+    /// become a `Symbol` or a `Hash` tagged by the variant's `Symbol`, sequences become
+    /// `Array`s, and numbers, strings, `bool`s and `None` become their mruby counterparts.
+    ///
+    /// *Note:* Only available with the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// #[derive(Serialize)]
+    /// struct Window {
+    ///     width: i32
+    /// }
+    ///
+    /// let mruby = Mruby::new();
+    /// let window = mruby.to_value_serde(&Window { width: 640 }).unwrap();
+    ///
+    /// let width = window.call("[]", vec![mruby.symbol("width")]).unwrap();
+    ///
+    /// assert_eq!(width.to_i32().unwrap(), 640);
+    /// ```
+    #[cfg(feature = "serde")]
+    fn to_value_serde<T: ::serde::Serialize + ?Sized>(&self, value: &T)
+        -> Result<Value, MrubyError>;
+
+    /// Deserializes an mruby `Value` built like the ones `to_value_serde` returns. `Hash` keys can
+    /// be either `String`s or `Symbol`s. Errors name the path of the value that failed, e.g.
+    /// `window.width`.
+    ///
+    /// *Note:* Only available with the `serde` feature.
+    #[cfg(feature = "serde")]
+    fn from_value_serde<T: ::serde::de::DeserializeOwned>(&self, value: &Value)
+        -> Result<T, MrubyError>;
+}
+
+#[inline]
+fn get_class<F>(mruby: &MrubyType, name: &str, class: Result<Class, MrubyError>, get: F) -> Class
+    where F: Fn(*const MrState, *const c_char, *const MrClass) -> *const MrClass {
+
+    unsafe {
+        let class = if let Ok(class) = class {
+            class
+        } else {
+            let name = name.to_owned();
+
+            let c_name = CString::new(name.clone()).unwrap();
+            let object = CString::new("Object").unwrap();
+            let mrb = mruby.borrow().mrb;
+
+            let object = mrb_class_get(mrb, object.as_ptr());
+
+            let class = get(mrb, c_name.as_ptr(), object);
+
+            Class::new(mruby.clone(), class)
+        };
+
+        // Reopening a class keeps the methods defined so far.
+        mruby.borrow_mut().mruby_methods.entry(class.to_str().to_owned())
+            .or_insert_with(HashMap::new);
+        mruby.borrow_mut().mruby_class_methods.entry(class.to_str().to_owned())
+            .or_insert_with(HashMap::new);
+
+        class
+    }
+}
+
+#[inline]
+fn get_class_for<T: Any, F>(mruby: &MrubyType, name: &str, get: F) -> Class
+    where F: Fn(*const MrState, *const c_char, *const MrClass) -> *const MrClass {
+
+    // Existing objects keep pointing to the data type and methods of the first definition.
+    let defined = mruby.borrow().classes.get(&TypeId::of::<T>()).map(|class| class.0);
+
+    if let Some(class) = defined {
+        return Class::new(mruby.clone(), class);
+    }
+
+    let class = unsafe {
+        let name = name.to_owned();
+
+        let c_name = CString::new(name.clone()).unwrap();
+        let object = CString::new("Object").unwrap();
+        let mrb = mruby.borrow().mrb;
+
+        let object = mrb_class_get(mrb, object.as_ptr());
+
+        let class = get(mrb, c_name.as_ptr(), object);
+
+        mrb_ext_set_instance_tt(class, MrType::MRB_TT_DATA);
+
+        extern "C" fn free<T: Any>(mrb: *const MrState, ptr: *const u8) {
+            unsafe {
+                let rc = mem::transmute::<*const u8, Rc<RefCell<T>>>(ptr);
+
+                // A collected wrapper must not be handed out again by obj_shared.
+                mrb_ext_shared_remove(mrb, &*rc as *const RefCell<T> as *const c_void);
+
+                finalize(mrb, &rc);
+            }
+        }
+
+        // Boxed since objects keep pointing to their data type.
+        let data_type = Box::new(MrDataType { name: c_name.as_ptr(), free: free::<T> });
+
+        mruby.borrow_mut().classes.insert(TypeId::of::<T>(), (class, data_type, name));
+        mruby.borrow_mut().methods.insert(TypeId::of::<T>(), HashMap::new());
+        mruby.borrow_mut().class_methods.insert(TypeId::of::<T>(), HashMap::new());
+
+        Class::new(mruby.clone(), class)
+    };
+
+    mruby.def_method_for::<T, _>("dup", |_mruby, slf| {
+        slf.clone()
+    });
+    mruby.def_method_for::<T, _>("clone", |_mruby, slf| {
+        slf.clone()
+    });
+
+    class
+}
+
+macro_rules! insert_method {
+    ( $mruby:expr, $name:expr, $method:expr, $methods:ident, $key:expr ) => {
+        {
+            let sym = $mruby.intern($name).0;
+
+            let mut borrow = $mruby.borrow_mut();
+
+            let methods = match borrow.$methods.get_mut($key) {
+                Some(methods) => methods,
+                None          => panic!("Class not found.")
+            };
+
+            methods.insert(sym, Rc::new($method));
+
+            sym
+        }
+    };
+}
+
+/// Payload `mrfn!` unwinds with when `slf` cannot be converted to its declared type.
+#[doc(hidden)]
+pub struct SlfTypeError(pub &'static str);
+
+/// Payload `mrfn!` unwinds with when a `(&T)` or `(&mut T)` argument conflicts with a borrow that
+/// is still alive, e.g. of a method that called back into itself.
+#[doc(hidden)]
+pub struct BorrowConflict(pub &'static str);
+
+/// Payload `mrfn!` unwinds with when mruby raised an `Exception` while parsing arguments.
+#[doc(hidden)]
+pub struct RaisedException(pub MrValue);
+
+/// Payload `mrfn!` unwinds with when an argument cannot be converted with `FromValue`.
+#[doc(hidden)]
+pub struct ArgConversionError(pub String);
+
+/// Converts the return type declared with `-> T` in `mrfn!` into the `Value` the method
+/// returns, packing tuples into an `Array` so that they can be unpacked with `a, b = method`.
+#[doc(hidden)]
+pub trait MrfnReturn {
+    fn into_value(self) -> Value;
+}
+
+impl MrfnReturn for Value {
+    #[inline]
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+fn values_array(mruby: &MrubyType, values: &[MrValue]) -> Value {
+    unsafe {
+        let mrb = mruby.borrow().mrb;
+
+        let array = mrb_ary_new_from_values(mrb, values.len() as i32, values.as_ptr());
+
+        Value::new(mruby.clone(), array)
+    }
+}
+
+impl MrfnReturn for (Value, Value) {
+    #[inline]
+    fn into_value(self) -> Value {
+        values_array(&self.0.mruby, &[self.0.value, self.1.value])
+    }
+}
+
+impl MrfnReturn for (Value, Value, Value) {
+    #[inline]
+    fn into_value(self) -> Value {
+        values_array(&self.0.mruby, &[self.0.value, self.1.value, self.2.value])
+    }
+}
+
+/// Checks at compile time that an `mrfn!` signature has no NUL besides its terminating one.
+#[doc(hidden)]
+pub const fn valid_sig(sig: &str) -> bool {
+    let bytes = sig.as_bytes();
+    let mut i = 0;
+
+    while i + 1 < bytes.len() {
+        if bytes[i] == 0 {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    !bytes.is_empty() && bytes[bytes.len() - 1] == 0
+}
+
+/// Runs `f`, which calls `mrb_get_args`, and turns a raised `Exception` into a Rust unwind so that
+/// Rust values are dropped before the trampoline re-raises it.
+#[doc(hidden)]
+pub unsafe fn protect_args<F: FnOnce()>(mrb: *const MrState, f: F) {
+    extern "C" fn args_protected<F: FnOnce()>(_mrb: *const MrState, data: MrValue) -> MrValue {
+        unsafe {
+            let f = data.to_ptr().unwrap() as *mut Option<F>;
+
+            (*f).take().unwrap()();
+
+            MrValue::nil()
+        }
+    }
+
+    let mut f = Some(f);
+    let data = MrValue::ptr(mrb, &mut f as *mut Option<F> as *const u8);
+
+    let mut state = false;
+
+    let exc = mrb_protect(mrb, args_protected::<F>, data, &mut state as *mut bool);
+
+    if state {
+        panic::resume_unwind(Box::new(RaisedException(exc)));
+    }
+}
+
+// Symbols of enum variants are snake case, e.g. `VeryHard` becomes `very_hard`.
+fn enum_symbol(variant: &str) -> String {
+    let mut symbol = String::new();
+
+    for (i, c) in variant.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            symbol.push('_');
+        }
+
+        symbol.extend(c.to_lowercase());
+    }
+
+    symbol
+}
+
+fn enum_choices(variants: &[&str]) -> String {
+    variants.iter().map(|variant| format!(":{}", enum_symbol(variant)))
+            .collect::<Vec<_>>().join(", ")
+}
+
+/// Defines the mruby `Module` `name` of an enum reflected by `mruby_enum!`.
+#[doc(hidden)]
+pub fn def_enum(mruby: &MrubyType, name: &str, variants: &[&str]) {
+    let constants = variants.iter().enumerate().map(|(i, variant)| {
+        format!("{} = {}", enum_symbol(variant).to_uppercase(), i)
+    }).collect::<Vec<_>>();
+    let choices = enum_choices(variants);
+
+    mruby.run(&format!("
+      module {0}
+        {1}
+
+        def self.from_sym(sym)
+          index = [{2}].index(sym)
+
+          unless index
+            raise ArgumentError, \"expected {0} ({2}) instead of #{{sym.inspect}}\"
+          end
+
+          index
+        end
+      end
+    ", name, constants.join("; "), choices)).unwrap();
+}
+
+/// Converts a `Value` to the index of an enum variant reflected by `mruby_enum!`. Accepts the
+/// variant's constant or its `Symbol`.
+#[doc(hidden)]
+pub fn enum_index(value: &Value, name: &str, variants: &[&str]) -> Result<usize, MrubyError> {
+    let index = match value.value.typ {
+        MrType::MRB_TT_FIXNUM => {
+            let index = value.to_i32()?;
+
+            if index >= 0 && (index as usize) < variants.len() {
+                Some(index as usize)
+            } else {
+                None
+            }
+        },
+        MrType::MRB_TT_SYMBOL => {
+            let symbol = value.to_str()?;
+
+            variants.iter().position(|variant| enum_symbol(variant) == symbol)
+        },
+        _ => None
+    };
+
+    match index {
+        Some(index) => Ok(index),
+        None        => {
+            let inspect = value.call("inspect", vec![])?;
+
+            Err(MrubyError::Cast(format!("{} ({}) instead of {}", name, enum_choices(variants),
+                                         inspect.to_str()?)))
+        }
+    }
+}
+
+/// Returns the last argument passed to the Rust method being called, i.e. the value assigned by
+/// a `[]=` defined with `def!`.
+#[doc(hidden)]
+pub fn last_arg(mruby: &MrubyType) -> Value {
+    let mrb = mruby.borrow().mrb;
+
+    unsafe {
+        let mut args = ptr::null_mut::<MrValue>();
+        let mut count = 0i32;
+
+        protect_args(mrb, || {
+            mrb_get_args(mrb, b"*\0".as_ptr() as *const c_char,
+                         &mut args as *mut *mut MrValue, &mut count as *mut i32);
+        });
+
+        match count {
+            0 => mruby.nil(),
+            _ => Value::new(mruby.clone(), *args.offset(count as isize - 1))
+        }
+    }
+}
+
+/// Returns the name of `sym` as it was interned, unlike `mrb_sym2name` which quotes names that
+/// are not valid identifiers. Symbols live as long as the `Mruby`.
+#[doc(hidden)]
+pub unsafe fn sym_name<'a>(mrb: *const MrState, sym: u32) -> &'a str {
+    let mut len = 0i32;
+    let name = mrb_sym2name_len(mrb, sym, &mut len as *mut i32);
+
+    str::from_utf8(slice::from_raw_parts(name as *const u8, len as usize)).unwrap()
+}
+
+// Runs `f` and returns the `Exception` it raised, if any, in an `Err`.
+unsafe fn protect<F: FnOnce() -> MrValue>(mrb: *const MrState, f: F) -> Result<MrValue, MrValue> {
+    extern "C" fn protected<F: FnOnce() -> MrValue>(_mrb: *const MrState,
+                                                    data: MrValue) -> MrValue {
+        unsafe {
+            let f = data.to_ptr().unwrap() as *mut Option<F>;
+
+            (*f).take().unwrap()()
+        }
+    }
+
+    let mut f = Some(f);
+    let data = MrValue::ptr(mrb, &mut f as *mut Option<F> as *const u8);
+
+    let mut state = false;
+
+    let value = mrb_protect(mrb, protected::<F>, data, &mut state as *mut bool as *const bool);
+
+    if state {
+        Err(value)
+    } else {
+        Ok(value)
+    }
+}
+
+fn method_error(mrb: *const MrState, class: &Class, error: Box<Any + Send>) -> MrValue {
+    if let Some(&RaisedException(exc)) = error.downcast_ref::<RaisedException>() {
+        return exc;
+    }
+
+    if let Some(&SlfTypeError(expected)) = error.downcast_ref::<SlfTypeError>() {
+        let method = unsafe {
+            CStr::from_ptr(mrb_sym2name(mrb, mrb_ext_get_mid(mrb))).to_str().unwrap()
+        };
+
+        return Mruby::exception(mrb, "TypeError",
+                                &format!("{} cannot be converted to {} in #{}; declare \
+                                          `slf: Value` to accept any receiver",
+                                         class.to_str(), expected, method));
+    }
+
+    if let Some(&ArgConversionError(ref message)) = error.downcast_ref::<ArgConversionError>() {
+        return Mruby::exception(mrb, "ArgumentError", message);
+    }
+
+    if let Some(&BorrowConflict(typ)) = error.downcast_ref::<BorrowConflict>() {
+        let method = unsafe {
+            CStr::from_ptr(mrb_sym2name(mrb, mrb_ext_get_mid(mrb))).to_str().unwrap()
+        };
+
+        return Mruby::exception(mrb, "ReentrantBorrowError",
+                                &format!("Rust borrow conflict: reentrant borrow of {} in #{}",
+                                         typ, method));
+    }
+
+    let message = match error.downcast_ref::<&'static str>() {
+        Some(s) => *s,
+        None    => match error.downcast_ref::<String>() {
+            Some(s) => &s[..],
+            None    => ""
+        }
+    };
+
+    Mruby::exception(mrb, "RustPanic", message)
+}
+
+macro_rules! callback {
+    ( $name:ident, $methods:ident, $key:expr ) => {
+        extern "C" fn $name<T: Any>(mrb: *const MrState, slf: MrValue) -> MrValue {
+            unsafe {
+                let ptr = mrb_ext_get_ud(mrb);
+                let mruby: MrubyType = mem::transmute(ptr);
+
+                let arena = mrb_gc_arena_save(mrb);
+
+                let result = {
+                    let value = Value::new(mruby.clone(), slf);
+                    let class = value.class();
+
+                    let method = {
+                        let borrow = mruby.borrow();
+
+                        borrow.$methods.get($key).map(|methods| {
+                            let sym = mrb_ext_get_mid(mrb);
+
+                            methods.get(&sym).map(|method| method.clone())
+                        })
+                    };
+
+                    if let Some(exc) = visibility_error(&mruby, slf) {
+                        Err(exc)
+                    } else if let Some(Some(method)) = method {
+                        panic::catch_unwind(AssertUnwindSafe(|| {
+                            method(mruby.clone(), value).value
+                        })).map_err(|error| method_error(mrb, &class, error))
+                    } else {
+                        Err(Mruby::exception(mrb, "TypeError", "Class not found."))
+                    }
+                };
+
+                mem::forget(mruby);
+
+                // Only the result needs to survive the call; temporaries the method created
+                // are left to the GC.
+                mrb_gc_arena_restore(mrb, arena);
+
+                // Raise only after all Rust values of the trampoline have been dropped.
+                match result {
+                    Ok(value) => {
+                        mrb_gc_protect(mrb, value);
+
+                        value
+                    },
+                    Err(exc)  => mrb_exc_raise(mrb, exc)
+                }
+            }
+        }
+    };
+}
+
+macro_rules! mruby_callback {
+    ( @key $mruby:expr, $value:expr, target_class ) => {
+        Class::new($mruby.clone(), mrb_ext_get_target_class($mruby.borrow().mrb)).to_str()
+    };
+    ( @key $mruby:expr, $value:expr, to_class )     => ($value.to_class().unwrap().to_str());
+    ( @key $mruby:expr, $value:expr, to_module )    => ($value.to_module().unwrap().to_str());
+    ( $name:ident, $methods:ident, $conv:tt ) => {
+        extern "C" fn $name(mrb: *const MrState, slf: MrValue) -> MrValue {
+            unsafe {
+                let ptr = mrb_ext_get_ud(mrb);
+                let mruby: MrubyType = mem::transmute(ptr);
+
+                let arena = mrb_gc_arena_save(mrb);
+
+                let result = {
+                    let value = Value::new(mruby.clone(), slf);
+                    let class = value.class();
+
+                    let method = {
+                        let borrow = mruby.borrow();
+
+                        borrow.$methods.get(mruby_callback!(@key mruby, value, $conv)).map(|methods| {
+                            let sym = mrb_ext_get_mid(mrb);
+
+                            methods.get(&sym).map(|method| method.clone())
+                        })
+                    };
+
+                    if let Some(exc) = visibility_error(&mruby, slf) {
+                        Err(exc)
+                    } else if let Some(Some(method)) = method {
+                        panic::catch_unwind(AssertUnwindSafe(|| method(mruby.clone(),
+                                                                       value).value))
+                            .map_err(|error| method_error(mrb, &class, error))
+                    } else {
+                        Err(Mruby::exception(mrb, "TypeError", "Class not found."))
+                    }
+                };
+
+                mem::forget(mruby);
+
+                // Only the result needs to survive the call; temporaries the method created
+                // are left to the GC.
+                mrb_gc_arena_restore(mrb, arena);
+
+                // Raise only after all Rust values of the trampoline have been dropped.
+                match result {
+                    Ok(value) => {
+                        mrb_gc_protect(mrb, value);
+
+                        value
+                    },
+                    Err(exc)  => mrb_exc_raise(mrb, exc)
+                }
+            }
+        }
+    };
+}
+
+callback!(call_method, methods, &TypeId::of::<T>());
+callback!(call_class_method, class_methods, &TypeId::of::<T>());
+
+// Runs the finalizer of T, if any, on an object the GC is about to free.
+unsafe fn finalize<T: Any>(mrb: *const MrState, rc: &Rc<RefCell<T>>) {
+    let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+
+    // Same as in mark, the Mruby may be borrowed by the call that started the GC.
+    let finalizer = (*mruby.as_ptr()).finalizers.get(&TypeId::of::<T>()).cloned();
+
+    mem::forget(mruby);
+
+    if let Some(finalizer) = finalizer {
+        if let Some(finalizer) = finalizer.downcast_ref::<Box<Fn(&T)>>() {
+            mrb_ext_set_finalizing(mrb, true);
+
+            // Unwinding must not reach the GC.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| finalizer(&*rc.as_ptr())));
+
+            mrb_ext_set_finalizing(mrb, false);
+        }
+    }
+}
+
+// Called by the GC for every object of type T that it marks.
+extern "C" fn mark<T: Any + MrubyMark>(mrb: *const MrState, ptr: *const u8) {
+    unsafe {
+        let rc: Rc<RefCell<T>> = mem::transmute(ptr);
+        let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+
+        let marker = Marker {
+            mrb,
+            mruby: &*mruby as *const RefCell<Mruby>
+        };
+
+        // The GC also runs from inside methods, where the object may be mutably borrowed by the
+        // method waiting for it to finish, so the RefCell cannot be borrowed here.
+        let obj = &*rc.as_ptr();
+
+        // Unwinding must not reach the GC.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| obj.mark(&marker)));
+
+        mem::forget(rc);
+        mem::forget(mruby);
+    }
+}
+
+// Returns the `NoMethodError` to raise when a private or protected Rust method is called from
+// outside of its receiver or `Class`, since mruby itself ignores visibility.
+unsafe fn visibility_error(mruby: &MrubyType, slf: MrValue) -> Option<MrValue> {
+    let (mrb, visibility) = {
+        let borrow = mruby.borrow();
+
+        if borrow.visibilities.is_empty() {
+            return None;
+        }
+
+        let key = (mrb_ext_get_target_class(borrow.mrb) as usize, mrb_ext_get_mid(borrow.mrb));
+
+        (borrow.mrb, *borrow.visibilities.get(&key)?)
+    };
+
+    let mut caller = MrValue::nil();
+
+    if !mrb_ext_named_caller(mrb, &mut caller) {
+        return None;
+    }
+
+    let allowed = match visibility {
+        Visibility::Public    => true,
+        Visibility::Private   => mrb_obj_equal(mrb, caller, slf),
+        Visibility::Protected => mrb_obj_is_kind_of(mrb, caller, mrb_ext_get_target_class(mrb))
+    };
+
+    if allowed {
+        return None;
+    }
+
+    let name = CStr::from_ptr(mrb_sym2name(mrb, mrb_ext_get_mid(mrb))).to_str().unwrap();
+    let receiver = Value::new(mruby.clone(), slf).class();
+
+    Some(Mruby::exception(mrb, "NoMethodError",
+                          &format!("{} method '{}' called for {}", visibility, name,
+                                   receiver.to_str())))
+}
+
+extern "C" fn call_method_fn(mrb: *const MrState, slf: MrValue) -> MrValue {
+    unsafe {
+        let method: fn(MrubyType, Value) -> Value =
+            mem::transmute(mrb_proc_cfunc_env_get(mrb, 0).to_ptr().unwrap());
+
+        let ptr = mrb_ext_get_ud(mrb);
+        let mruby: MrubyType = mem::transmute(ptr);
+
+        let arena = mrb_gc_arena_save(mrb);
+
+        let result = {
+            let value = Value::new(mruby.clone(), slf);
+            let class = value.class();
+
+            match visibility_error(&mruby, slf) {
+                Some(exc) => Err(exc),
+                None      => {
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        method(mruby.clone(), value).value
+                    })).map_err(|error| method_error(mrb, &class, error))
+                }
+            }
+        };
+
+        mem::forget(mruby);
+
+        mrb_gc_arena_restore(mrb, arena);
+
+        match result {
+            Ok(value) => {
+                mrb_gc_protect(mrb, value);
+
+                value
+            },
+            Err(exc)  => mrb_exc_raise(mrb, exc)
+        }
+    }
+}
+
+unsafe fn define_method_fn(mrb: *const MrState, class: *const MrClass, sym: u32,
+                           method: fn(MrubyType, Value) -> Value) {
+    let env = MrValue::ptr(mrb, method as *const u8);
+    let proc_ = mrb_proc_new_cfunc_with_env(mrb, call_method_fn, 1, &env);
+
+    mrb_define_method_raw(mrb, class, sym, proc_);
+}
+
+fn class_for<T: Any>(mruby: &MrubyType) -> Class {
+    let class = {
+        let borrow = mruby.borrow();
+
+        match borrow.classes.get(&TypeId::of::<T>()) {
+            Some(class) => class.0,
+            None        => panic!("{}", unregistered::<T>("cannot find the Class"))
+        }
+    };
+
+    Class::new(mruby.clone(), class)
+}
+
+// Returns the Class and data type of T. They are copied out since allocating objects may run the
+// GC, and with it finalizers, which must be able to borrow mruby.
+fn data_type_for<T: Any>(mruby: &MrubyType,
+                         attempt: &str) -> (*const MrState, *const MrClass, *const MrDataType) {
+    let borrow = mruby.borrow();
+
+    match borrow.classes.get(&TypeId::of::<T>()) {
+        Some(class) => (borrow.mrb, class.0, &*class.1 as *const MrDataType),
+        None        => panic!("{}", unregistered::<T>(attempt))
+    }
+}
+
+// Names T in the error for using it before def_class_for.
+fn unregistered<T: Any>(attempt: &str) -> MrubyError {
+    MrubyError::Unregistered(format!("{} for {}", attempt, type_name::<T>()))
+}
+
+thread_local! {
+    static CURRENT_THREAD: ThreadId = thread::current().id();
+}
+
+// Refuses to touch mruby from a thread other than its owner or after it was closed. Reads mruby
+// through the pointer so that it works while mruby is borrowed.
+fn check_state(mruby: &MrubyType) -> Result<(), MrubyError> {
+    let state = unsafe { &*mruby.as_ptr() };
+
+    if !CURRENT_THREAD.with(|current| *current == state.thread) {
+        return Err(MrubyError::WrongThread);
+    }
+
+    if state.closed.get() {
+        return Err(MrubyError::Closed);
+    }
+
+    Ok(())
+}
+
+// Like check_state for methods that cannot return an error, which panic instead. A closed mruby is
+// only freed once its last handle is dropped, so they keep working on it.
+fn check_thread(mruby: &MrubyType) {
+    if let Err(err @ MrubyError::WrongThread) = check_state(mruby) {
+        panic!("{}", err);
+    }
+}
+
+// mruby's Fixnum and Float arithmetic raise a TypeError on non-numeric arguments instead of
+// calling their coerce, so it is patched in once a Rust type defines one.
+fn def_coercions(mruby: &MrubyType) {
+    mruby.run("
+      [Fixnum, Float].each do |numeric|
+        next if numeric.method_defined? :__uncoerced_add__
+
+        { :+ => :__uncoerced_add__, :- => :__uncoerced_sub__, :* => :__uncoerced_mul__,
+          :/ => :__uncoerced_div__, :% => :__uncoerced_mod__,
+          :** => :__uncoerced_pow__ }.each do |op, uncoerced|
+          numeric.__send__(:alias_method, uncoerced, op)
+
+          numeric.__send__(:define_method, op) do |other|
+            if other.is_a?(Numeric) || !other.respond_to?(:coerce)
+              __send__(uncoerced, other)
+            else
+              left, right = other.coerce(self)
+
+              left.__send__(op, right)
+            end
+          end
+        end
+      end
+    ").unwrap();
+}
+
+const OPERATORS: &[&str] = &["+", "-", "*", "/", "%", "**", "==", "!=", "<", "<=", ">", ">=",
+                             "<=>", "===", "=~", "<<", ">>", "&", "|", "^", "[]", "+@", "-@",
+                             "~", "!"];
+
+const UNARY_OPERATORS: &[&str] = &["+@", "-@", "~", "!"];
+
+// A capability that a SandboxConfig can take away.
+#[derive(Clone, Copy, PartialEq)]
+enum Capability {
+    Require,
+    Print,
+    Eval,
+    GcControl,
+    Const(&'static str)
+}
+
+impl Capability {
+    fn allowed(self, config: &SandboxConfig) -> bool {
+        match self {
+            Capability::Require     => config.require,
+            Capability::Print       => config.print,
+            Capability::Eval        => config.eval,
+            Capability::GcControl   => config.gc_control,
+            Capability::Const(name) => config.allows(name)
+        }
+    }
+}
+
+// Methods taken away by a sandbox, with the Module or Class defining them.
+const SANDBOXED_METHODS: &[(Capability, &str, &str)] = &[
+    (Capability::Require, "Kernel", "require"),
+    (Capability::Require, "Kernel", "require_relative"),
+    (Capability::Print,   "Kernel", "print"),
+    (Capability::Print,   "Kernel", "puts"),
+    (Capability::Print,   "Kernel", "p"),
+    (Capability::Print,   "Kernel", "__printstr__"),
+    (Capability::Eval,    "Kernel", "eval"),
+    (Capability::Eval,    "Kernel", "instance_eval"),
+    (Capability::Eval,    "Object", "instance_exec"),
+    (Capability::Eval,    "Module", "class_eval"),
+    (Capability::Eval,    "Module", "module_eval")
+];
+
+// Classes and Modules taken away by a sandbox. Their methods are undefined before their constants
+// are removed, so that references kept elsewhere are of no use either.
+const SANDBOXED_CONSTANTS: &[(Capability, &str)] = &[
+    (Capability::Const("File"),    "File"),
+    (Capability::Const("IO"),      "IO"),
+    (Capability::Const("Process"), "Process"),
+    (Capability::Const("Dir"),     "Dir"),
+    (Capability::Const("Socket"),  "Socket"),
+    (Capability::GcControl,        "GC"),
+    (Capability::GcControl,        "ObjectSpace")
+];
+
+// What a sandbox took away, kept to be given back by relax_sandbox.
+enum Removed {
+    Method(MrValue, u32, bool, MrValue),
+    Const(u32, MrValue)
+}
+
+// Takes away what config does not allow on top of what was taken away before, which is not found
+// again, so that sandboxing twice only takes away more.
+fn restrict(mruby: &MrubyType, config: &SandboxConfig) -> Result<(), MrubyError> {
+    let mut removed = mem::replace(&mut mruby.borrow_mut().sandboxed, vec![]);
+    let result = take_away(mruby, config, &mut removed);
+
+    {
+        let mut borrow = mruby.borrow_mut();
+
+        let config = match borrow.sandbox.take() {
+            Some(current) => current.intersect(config),
+            None          => config.clone()
+        };
+
+        borrow.sandbox = Some(config);
+        borrow.sandboxed = removed;
+    }
+
+    keep_sandboxed(mruby);
+
+    result
+}
+
+fn take_away(mruby: &MrubyType, config: &SandboxConfig, removed: &mut Vec<(Capability, Removed)>)
+    -> Result<(), MrubyError> {
+
+    for &(capability, target, name) in SANDBOXED_METHODS {
+        if capability.allowed(config) {
+            continue;
+        }
+
+        let target = mruby.top_const_get(target)?;
+
+        // Instance methods go first, so that module functions are the only singleton methods
+        // left to find.
+        for &singleton in &[false, true] {
+            remove_method(mruby, capability, &target, name, singleton, removed);
+        }
+    }
+
+    let object = mruby.get_class("Object")?.to_value();
+
+    for &(capability, name) in SANDBOXED_CONSTANTS {
+        if capability.allowed(config) || !mruby.is_defined(name) {
+            continue;
+        }
+
+        let value = mruby.top_const_get(name)?;
+
+        match value.value.typ {
+            MrType::MRB_TT_CLASS | MrType::MRB_TT_MODULE => {
+                for &(list, singleton) in &[("instance_methods", false),
+                                            ("singleton_methods", true)] {
+                    let methods = value.call(list, vec![mruby.bool(false)])?.to_vec()?;
+
+                    for method in methods {
+                        let method = method.call("to_s", vec![])?;
+
+                        remove_method(mruby, capability, &value, method.to_str()?, singleton,
+                                      removed);
+                    }
+                }
+            },
+            _ => ()
+        }
+
+        let sym = mruby.intern(name).0;
+
+        unsafe {
+            mrb_const_remove(mruby.borrow().mrb, object.value, sym);
+        }
+
+        removed.push((capability, Removed::Const(sym, value.value)));
+    }
+
+    Ok(())
+}
+
+fn remove_method(mruby: &MrubyType, capability: Capability, target: &Value, name: &str,
+                 singleton: bool, removed: &mut Vec<(Capability, Removed)>) {
+    let mrb = mruby.borrow().mrb;
+    let sym = mruby.intern(name).0;
+    let name_str = CString::new(name).unwrap();
+
+    unsafe {
+        let proc_ = if singleton {
+            mrb_ext_method_proc(mrb, target.value, sym)
+        } else {
+            mrb_ext_instance_method_proc(mrb, target.value, sym)
+        };
+
+        // Methods that are undefined already are not found.
+        if proc_.typ != MrType::MRB_TT_PROC {
+            return;
+        }
+
+        let class = mrb_ext_get_class(target.value);
+
+        if singleton {
+            mrb_undef_class_method(mrb, class, name_str.as_ptr());
+        } else {
+            mrb_undef_method(mrb, class, name_str.as_ptr());
+        }
+
+        removed.push((capability, Removed::Method(target.value, sym, singleton, proc_)));
+    }
+}
+
+// Gives back what config allows, or everything and lifts the sandbox if there is no config.
+fn give_back(mruby: &MrubyType, config: Option<SandboxConfig>) {
+    let removed = mem::replace(&mut mruby.borrow_mut().sandboxed, vec![]);
+    let (restored, kept): (Vec<_>, Vec<_>) = removed.into_iter().partition(|&(capability, _)| {
+        config.as_ref().map_or(true, |config| capability.allowed(config))
+    });
+
+    let mrb = mruby.borrow().mrb;
+    let object = mruby.get_class("Object").unwrap().to_value();
+
+    // Undone in reverse, so that a constant is back before the methods it was emptied of.
+    for (_, removed) in restored.into_iter().rev() {
+        unsafe {
+            match removed {
+                Removed::Method(target, sym, singleton, proc_) => {
+                    mrb_ext_define_method_proc(mrb, target, singleton, sym, proc_);
+                },
+                Removed::Const(sym, value) => mrb_const_set(mrb, object.value, sym, value)
+            }
+        }
+    }
+
+    {
+        let mut borrow = mruby.borrow_mut();
+
+        borrow.sandbox = config;
+        borrow.sandboxed = kept;
+    }
+
+    keep_sandboxed(mruby);
+}
+
+// Procs and constants taken away are only referenced from Rust, so they are kept alive in an
+// instance variable of Kernel that scripts cannot name.
+fn keep_sandboxed(mruby: &MrubyType) {
+    let values = {
+        let borrow = mruby.borrow();
+
+        borrow.sandboxed.iter().map(|&(_, ref removed)| {
+            match *removed {
+                Removed::Method(_, _, _, proc_) => proc_,
+                Removed::Const(_, value)        => value
+            }
+        }).collect::<Vec<_>>()
+    };
+
+    let values = values.into_iter().map(|value| Value::new(mruby.clone(), value)).collect();
+    let values = mruby.array(values);
+
+    let mrb = mruby.borrow().mrb;
+    let kernel = mruby.get_module("Kernel").unwrap().to_value();
+    let sym = mruby.intern("__sandboxed__").0;
+
+    unsafe {
+        mrb_iv_set(mrb, kernel.value, sym, values.value);
+    }
+}
+
+// Loads the Rust-registered feature or file that name refers to, relative to base for
+// require_relative, unless it is listed in $LOADED_FEATURES already.
+fn load_feature(mruby: &MrubyType, mrb: *const MrState, name: &str, base: Option<PathBuf>)
+    -> Result<Value, MrValue> {
+
+    let reqs = match base {
+        Some(_) => None,
+        None    => {
+            let borrow = mruby.borrow();
+
+            if borrow.required.contains(name) {
+                return Ok(mruby.bool(false));
+            }
+
+            borrow.files.get(name).map(|reqs| reqs.clone())
+        }
+    };
+
+    let path = match reqs {
+        Some(_) => None,
+        None    => {
+            let explicit = name.starts_with("./") || name.starts_with("../") ||
+                           Path::new(name).is_absolute();
+
+            let candidates = match base {
+                Some(base)        => vec![base.join(name)],
+                None if explicit  => vec![PathBuf::from(name)],
+                None              => {
+                    let borrow = mruby.borrow();
+                    let load_paths = borrow.load_paths.as_ref().unwrap();
+
+                    load_paths.iter().map(|dir| dir.join(name)).collect()
+                }
+            };
+
+            match candidates.iter().filter_map(|path| feature_file(path)).next() {
+                Some(path) => Some(path),
+                None       => {
+                    return Err(Mruby::exception(mrb, "LoadError",
+                                                &format!("cannot load such file -- {}", name)));
+                }
+            }
+        }
+    };
+
+    let feature = match path {
+        Some(ref path) => mruby.string(&path.to_string_lossy()),
+        None           => mruby.string(name)
+    };
+    let features = loaded_features(mruby, mrb);
+
+    if features.call("include?", vec![feature.clone()]).unwrap().to_bool().unwrap() {
+        return Ok(mruby.bool(false));
+    }
+
+    features.call("push", vec![feature.clone()]).unwrap();
+
+    let result = match path {
+        Some(path) => {
+            let filename = {
+                let borrow = mruby.borrow();
+
+                borrow.filename.clone()
+            };
+
+            let result = mruby.execute(&path);
+
+            match filename {
+                Some(filename) => mruby.filename(&filename),
+                None           => mruby.borrow_mut().filename = None
+            }
+
+            result
+        },
+        None => {
+            for req in reqs.unwrap() {
+                req(mruby.clone());
+            }
+
+            Ok(mruby.nil())
+        }
+    };
+
+    match result {
+        Ok(_)    => Ok(mruby.bool(true)),
+        Err(err) => {
+            features.call("delete", vec![feature]).unwrap();
+
+            let exception = mruby.borrow_mut().take_exception();
+
+            match (err, exception) {
+                (MrubyError::Runtime { .. }, Some(exc)) => Err(exc),
+                (err, _) => Err(Mruby::exception(mrb, "LoadError", &err.to_string()))
+            }
+        }
+    }
+}
+
+// Returns the .rb or .mrb file that path names, with or without its extension.
+fn feature_file(path: &Path) -> Option<PathBuf> {
+    let with_ext = |ext: &str| {
+        let mut path = path.as_os_str().to_owned();
+        path.push(ext);
+
+        PathBuf::from(path)
+    };
+
+    let mut candidates = vec![with_ext(".rb"), with_ext(".mrb")];
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("rb") | Some("mrb") => candidates.insert(0, path.to_path_buf()),
+        _                        => ()
+    }
+
+    candidates.into_iter()
+              .filter(|path| path.is_file())
+              .filter_map(|path| path.canonicalize().ok())
+              .next()
+}
+
+// Returns $LOADED_FEATURES, setting it to an empty Array if a script removed it.
+fn loaded_features(mruby: &MrubyType, mrb: *const MrState) -> Value {
+    let sym = mruby.borrow_mut().intern_cached("$LOADED_FEATURES");
+
+    unsafe {
+        let features = Value::new(mruby.clone(), mrb_gv_get(mrb, sym));
+
+        if features.value.typ == MrType::MRB_TT_ARRAY {
+            return features;
+        }
+
+        let features = mruby.array(vec![]);
+
+        mrb_gv_set(mrb, sym, features.value);
+
+        features
+    }
+}
+
+extern "C" fn require_relative(mrb: *const MrState, _slf: MrValue) -> MrValue {
+    unsafe {
+        let ptr = mrb_ext_get_ud(mrb);
+        let mruby: MrubyType = mem::transmute(ptr);
+
+        let mut name: *const c_char = ptr::null();
+
+        // No Rust values needing drop may be alive when mrb_get_args raises.
+        mrb_get_args(mrb, b"z\0".as_ptr() as *const c_char, &mut name as *mut *const c_char);
+
+        let name = CStr::from_ptr(name).to_str().unwrap();
+
+        let base = {
+            let borrow = mruby.borrow();
+
+            borrow.loading.last().cloned()
+        };
+
+        let result = match base {
+            Some(base) => load_feature(&mruby, mrb, name, Some(base)),
+            None       => Err(Mruby::exception(mrb, "LoadError", "cannot infer basepath"))
+        };
+
+        let result = result.map(|value| value.value);
+
+        mem::forget(mruby);
+
+        match result {
+            Ok(value) => value,
+            Err(exc)  => mrb_exc_raise(mrb, exc)
+        }
+    }
+}
+
+fn format_obj<T: Any, F>(mruby: &MrubyType, slf: &Value, format: F) -> Value
+    where F: FnOnce(&T) -> String {
+
+    let obj = slf.to_obj::<T>().unwrap();
+
+    let string = match obj.try_borrow() {
+        Ok(obj) => format(&obj),
+        Err(_)  => format!("#<{}>", mruby.class_name_for::<T>().unwrap())
+    };
+
+    mruby.string(&string)
+}
+
+// Converts slf like mrfn!'s typed slf, raising a TypeError for receivers of other types.
+fn slf_obj<T: Any>(slf: &Value) -> Rc<RefCell<T>> {
+    match slf.to_obj::<T>() {
+        Ok(obj) => obj,
+        Err(_)  => panic::resume_unwind(Box::new(SlfTypeError(type_name::<T>())))
+    }
+}
+
+// Borrows like mrfn!'s (&T), raising a ReentrantBorrowError on conflicts.
+fn borrow_obj<T>(obj: &RefCell<T>) -> Ref<T> {
+    match obj.try_borrow() {
+        Ok(obj) => obj,
+        Err(_)  => panic::resume_unwind(Box::new(BorrowConflict(type_name::<T>())))
+    }
+}
+
+// Borrows like mrfn!'s (&mut T), raising a ReentrantBorrowError on conflicts.
+fn borrow_obj_mut<T>(obj: &RefCell<T>) -> RefMut<T> {
+    match obj.try_borrow_mut() {
+        Ok(obj) => obj,
+        Err(_)  => panic::resume_unwind(Box::new(BorrowConflict(type_name::<T>())))
+    }
+}
+
+fn single_arg(mruby: &MrubyType) -> Value {
+    let mrb = mruby.borrow().mrb;
+
+    unsafe {
+        let mut value = MrValue::nil();
+
+        protect_args(mrb, || {
+            mrb_get_args(mrb, b"o\0".as_ptr() as *const c_char, &mut value as *mut MrValue);
+        });
+
+        Value::new(mruby.clone(), value)
+    }
+}
+
+// The method a Rust method replaces is kept for call_super, unless it was defined from Rust too.
+fn keep_previous_method(mruby: &MrubyType, class: *const MrClass, sym: u32) {
+    let mrb = mruby.borrow().mrb;
+
+    unsafe {
+        mrb_ext_keep_previous_method(mrb, class, sym);
+    }
+}
+
+fn all_args(mruby: &MrubyType) -> Vec<Value> {
+    let mrb = mruby.borrow().mrb;
+
+    unsafe {
+        let mut args = ptr::null_mut::<MrValue>();
+        let mut count = 0i32;
+
+        protect_args(mrb, || {
+            mrb_get_args(mrb, b"*\0".as_ptr() as *const c_char,
+                         &mut args as *mut *mut MrValue, &mut count as *mut i32);
+        });
+
+        (0..count as isize).map(|i| Value::new(mruby.clone(), *args.offset(i))).collect()
+    }
+}
+
+// method_missing gets a Symbol, while respond_to? passes on a String if it got one.
+fn method_name(name: &Value) -> String {
+    name.call("to_s", vec![]).unwrap().to_str().unwrap().to_owned()
+}
+
+// Kernel#print, puts and p all write through __printstr__, so overriding it on Object catches them.
+fn def_print_hook(mruby: &MrubyType) {
+    mruby.def_toplevel_method("__printstr__", |mruby, _slf| {
+        let value = single_arg(&mruby);
+
+        if let Ok(s) = value.to_str() {
+            let handler = mruby.borrow().print_handler.clone();
+
+            match handler {
+                Some(handler) => handler(s),
+                None          => print!("{}", s)
+            }
+        }
+
+        value
+    });
+}
+
+// Parser warnings only go through Rust when a handler is set so that they reach stderr otherwise.
+unsafe fn warning_hook(mrb: *const MrState) -> Option<extern "C" fn(*const MrState, *const c_char)> {
+    extern "C" fn report_warning(mrb: *const MrState, message: *const c_char) {
+        unsafe {
+            let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+            let handler = mruby.try_borrow().ok().and_then(|borrow| {
+                borrow.warning_handler.clone()
+            });
+
+            mem::forget(mruby);
+
+            if let Some(handler) = handler {
+                let message = CStr::from_ptr(message).to_string_lossy();
+
+                // A handler that runs code warning in turn does not see those warnings, since
+                // unwinding from here would go through the parser.
+                if let Ok(mut handler) = handler.try_borrow_mut() {
+                    (&mut *handler)(&message);
+                }
+            }
+        }
+    }
+
+    let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+
+    let handled = mruby.try_borrow().map(|borrow| borrow.warning_handler.is_some());
+
+    mem::forget(mruby);
+
+    match handled {
+        Ok(true) => Some(report_warning),
+        _        => None
+    }
+}
+
+// Elements are converted as they are pulled so that the iterator does not need to keep the Mruby
+// alive.
+struct RustEnumerator(Box<dyn FnMut(&MrubyType) -> Option<Value>>);
+
+fn def_rust_enumerator(mruby: &MrubyType) {
+    mruby.def_class_for::<RustEnumerator>("RustEnumerator");
+
+    mruby.def_method_for::<RustEnumerator, _>("next", |mruby, slf| {
+        let mrb = mruby.borrow().mrb;
+        let enumerator = slf.to_obj::<RustEnumerator>().unwrap();
+
+        let next = match enumerator.try_borrow_mut() {
+            Ok(mut enumerator) => (enumerator.0)(&mruby),
+            Err(_)             => {
+                let exc = Mruby::exception(mrb, "RuntimeError",
+                                           "RustEnumerator#next called while producing an \
+                                            element");
+
+                panic::resume_unwind(Box::new(RaisedException(exc)));
+            }
+        };
+
+        match next {
+            Some(value) => value,
+            None        => {
+                let exc = Mruby::exception(mrb, "StopIteration", "iteration reached an end");
+
+                panic::resume_unwind(Box::new(RaisedException(exc)));
+            }
+        }
+    });
+
+    mruby.run("
+      class RustEnumerator
+        include Enumerable
+
+        class << self
+          undef_method :new
+        end
+
+        def each
+          return self unless block_given?
+
+          while true
+            begin
+              value = self.next
+            rescue StopIteration
+              return self
+            end
+
+            yield value
+          end
+        end
+
+        # Enumerable's versions pull one element too many, which would be lost.
+        def take(n)
+          raise ArgumentError, 'attempt to take negative size' if n < 0
+
+          taken = []
+
+          while taken.size < n
+            begin
+              taken << self.next
+            rescue StopIteration
+              break
+            end
+          end
+
+          taken
+        end
+
+        def first(*args)
+          return take(*args) unless args.empty?
+
+          begin
+            self.next
+          rescue StopIteration
+            nil
+          end
+        end
+      end
+    ").unwrap();
+}
+
+impl MrubyImpl for MrubyType {
+    #[inline]
+    fn filename(&self, filename: &str) {
+        check_thread(self);
+
+        self.borrow_mut().filename = Some(filename.to_owned());
+
+        unsafe {
+            let filename_str = CString::new(filename).unwrap();
+
+            mrbc_filename(self.borrow().mrb, self.borrow().ctx, filename_str.as_ptr());
+        }
+    }
+
+    #[inline]
+    fn set_memory_limit(&self, bytes: usize) {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_set_memory_limit(self.borrow().mrb, bytes);
+        }
+    }
+
+    #[inline]
+    fn memory_used(&self) -> usize {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_memory_used(self.borrow().mrb)
+        }
+    }
+
+    #[inline]
+    fn gc_enable(&self) {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_gc_enable(self.borrow().mrb);
+        }
+    }
+
+    #[inline]
+    fn gc_disable(&self) {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_gc_disable(self.borrow().mrb);
+        }
+    }
+
+    #[inline]
+    fn gc_run(&self) {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_full_gc(mrb);
+        }
+    }
+
+    #[inline]
+    fn gc_arena_save(&self) -> i32 {
+        check_thread(self);
+
+        unsafe {
+            mrb_gc_arena_save(self.borrow().mrb)
+        }
+    }
+
+    #[inline]
+    fn gc_arena_restore(&self, state: i32) {
+        check_thread(self);
+
+        unsafe {
+            mrb_gc_arena_restore(self.borrow().mrb, state);
+        }
+    }
+
+    #[cfg(feature = "instruction-limit")]
+    #[inline]
+    fn set_instruction_limit(&self, count: u64) {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_set_instruction_limit(self.borrow().mrb, count);
+        }
+    }
+
+    #[cfg(feature = "instruction-limit")]
+    #[inline]
+    fn reset_instruction_counter(&self) {
+        check_thread(self);
+
+        unsafe {
+            mrb_ext_reset_instruction_counter(self.borrow().mrb);
+        }
+    }
+
+    #[cfg(feature = "instruction-limit")]
+    fn run_with_timeout(&self, script: &str, instruction_limit: u64) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        self.set_instruction_limit(instruction_limit);
+
+        let result = self.run(script);
+        let exceeded = unsafe { mrb_ext_instruction_limit_exceeded(self.borrow().mrb) };
+
+        self.set_instruction_limit(0);
+
+        match result {
+            Err(_) if exceeded => Err(MrubyError::Timeout),
+            result             => result
+        }
+    }
+
+    fn sandbox(&self, config: &SandboxConfig) -> Result<(), MrubyError> {
+        check_state(self)?;
+
+        restrict(self, config)
+    }
+
+    fn run_sandboxed(&self, script: &str) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        let previous = self.sandbox_config();
+        let result = self.sandbox(&SandboxConfig::new()).and_then(|_| self.run(script));
+
+        give_back(self, previous);
+
+        result
+    }
+
+    #[inline]
+    fn sandbox_config(&self) -> Option<SandboxConfig> {
+        self.borrow().sandbox.clone()
+    }
+
+    fn relax_sandbox(&self, allowed: &SandboxConfig) {
+        check_thread(self);
+
+        let config = match self.sandbox_config() {
+            Some(config) => config.union(allowed),
+            None         => return
+        };
+
+        give_back(self, Some(config));
+    }
+
+    fn set_print_handler<F: Fn(&str) + 'static>(&self, handler: F) {
+        self.borrow_mut().print_handler = Some(Rc::new(handler));
+
+        def_print_hook(self);
+    }
+
+    fn redirect_stdout_to_string(&self) -> OutputCapture {
+        let output = Rc::new(RefCell::new(String::new()));
+        let previous = self.borrow_mut().print_handler.take();
+
+        let captured = output.clone();
+
+        self.set_print_handler(move |s| captured.borrow_mut().push_str(s));
+
+        OutputCapture {
+            mruby:    self.clone(),
+            output,
+            previous
+        }
+    }
+
+    #[inline]
+    fn set_error_handler<F: Fn(&str) + 'static>(&self, handler: F) {
+        check_thread(self);
+
+        let mut borrow = self.borrow_mut();
+
+        borrow.error_handler = Some(Rc::new(handler));
+
+        unsafe {
+            mrb_ext_set_capture_errors(borrow.ctx, true);
+        }
+    }
+
+    #[inline]
+    fn silence_errors(&self) {
+        self.set_error_handler(|_| {});
+    }
+
+    #[inline]
+    fn set_warning_handler<F: FnMut(&str) + 'static>(&self, handler: F) {
+        self.borrow_mut().warning_handler = Some(Rc::new(RefCell::new(handler)));
+    }
+
+    #[inline]
+    fn run(&self, script: &str) -> Result<Value, MrubyError> {
+        extern "C" fn run_protected(mrb: *const MrState, data: MrValue) -> MrValue {
+            unsafe {
+                let ptr = data.to_ptr().unwrap();
+                let args = *mem::transmute::<*const u8, *const [*const u8; 4]>(ptr);
+
+                let script_len: &i32 = mem::transmute(args[1]);
+                let ctx: *const MrContext = mem::transmute(args[2]);
+                let unparsed: &mut bool = mem::transmute(args[3]);
+
+                let result = mrb_ext_load_nstring_cxt(mrb, args[0], *script_len, ctx,
+                                                      warning_hook(mrb));
+
+                // Parse errors leave undef and an exception behind instead of raising.
+                *unparsed = result.typ == MrType::MRB_TT_UNDEF;
+
+                mrb_ext_raise_current(mrb);
+
+                // The parser returns undef without an exception when it runs out of memory.
+                if result.typ == MrType::MRB_TT_UNDEF {
+                    mrb_ext_raise_nomem(mrb);
+                }
+
+                result
+            }
+        }
+
+        check_state(self)?;
+
+        Mruby::clear_exception(self);
+
+        unsafe {
+            let (mrb, ctx) = {
+                let borrow = self.borrow();
+
+                (borrow.mrb, borrow.ctx)
+            };
+
+            let script_ptr = script.as_ptr();
+            let script_len = script.len();
+            let script_len_ptr: *const u8 = mem::transmute(&script_len);
+            let ctx_ptr: *const u8 = mem::transmute(ctx);
+            let mut unparsed = false;
+            let unparsed_ptr: *const u8 = mem::transmute(&mut unparsed);
+
+            let args = [script_ptr, script_len_ptr, ctx_ptr, unparsed_ptr];
+            let args_ptr: *const u8 = mem::transmute(&args);
+            let data = MrValue::ptr(mrb, args_ptr);
+
+            let state = mem::uninitialized::<bool>();
+
+            let value = mrb_protect(mrb, run_protected, data, &state as *const bool);
+
+            if state {
+                let error = MrubyError::runtime(mrb, value);
+                let handler = self.borrow().error_handler.clone();
+
+                if let (true, Some(handler)) = (unparsed, handler) {
+                    let message = Value::new(self.clone(), value).call("message", vec![])
+                        .and_then(|message| message.to_str().map(|s| s.to_owned()));
+
+                    if let Ok(message) = message {
+                        handler(message.trim_end());
+                    }
+                }
+
+                Err(error)
+            } else {
+                Ok(Value::new(self.clone(), value))
+            }
+        }
+    }
+
+    #[inline]
+    fn run_with_filename(&self, script: &str, filename: &str) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        let previous = {
+            let borrow = self.borrow();
+
+            borrow.filename.clone()
+        };
+
+        self.filename(filename);
+
+        let result = self.run(script);
+
+        match previous {
+            Some(previous) => self.filename(&previous),
+            None           => self.borrow_mut().filename = None
+        }
+
+        result
+    }
+
+    fn reset_locals(&self) {
+        check_thread(self);
+
+        let mut borrow = self.borrow_mut();
+
+        unsafe {
+            mrbc_context_free(borrow.mrb, borrow.ctx);
+
+            borrow.ctx = mrbc_context_new(borrow.mrb);
+
+            if let Some(ref filename) = borrow.filename {
+                let filename_str = CString::new(filename.as_str()).unwrap();
+
+                mrbc_filename(borrow.mrb, borrow.ctx, filename_str.as_ptr());
+            }
+        }
+    }
+
+    fn close(self) -> Result<(), CloseError> {
+        let mut registered = BTreeMap::new();
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+            let root = self.intern("_gc_root_").0;
+            let root = Value::new(self.clone(), mrb_gv_get(mrb, root));
+
+            if let Ok(values) = root.to_vec() {
+                for value in values {
+                    *registered.entry(value.class().to_str().to_owned()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let handles = Rc::strong_count(&self) - 1;
+
+        if handles == 0 && registered.is_empty() {
+            return Ok(());
+        }
+
+        // Set through the pointer since a method running on mruby may be borrowing it.
+        unsafe {
+            (*self.as_ptr()).closed.set(true);
+        }
+
+        Err(CloseError {
+            handles:    handles,
+            registered: registered
+        })
+    }
+
+    fn set_userdata<T: Any>(&self, value: T) {
+        self.borrow_mut().userdata.insert(TypeId::of::<T>(), Rc::new(RefCell::new(value)));
+    }
+
+    fn userdata<T: Any>(&self) -> Option<Rc<RefCell<T>>> {
+        let userdata = self.borrow().userdata.get(&TypeId::of::<T>()).cloned();
+
+        userdata.and_then(|userdata| userdata.downcast::<RefCell<T>>().ok())
+    }
+
+    #[inline]
+    fn fiber(&self, block: Value) -> Fiber {
+        Fiber::new(self, block)
+    }
+
+    fn check_syntax(&self, code: &str) -> SyntaxResult {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+            let mut state = 0;
+
+            let message = mrb_ext_check_syntax(mrb, code.as_ptr(), code.len(), &mut state);
+
+            match state {
+                0 => SyntaxResult::Complete,
+                1 => SyntaxResult::Incomplete,
+                _ => SyntaxResult::Error(message.to_str(mrb).unwrap().to_owned())
+            }
+        }
+    }
+
+    #[inline]
+    unsafe fn run_unchecked(&self, script: &str) -> Value {
+        check_thread(self);
+
+        let (mrb, ctx) = {
+            let borrow = self.borrow();
+
+            (borrow.mrb, borrow.ctx)
+        };
+
+        let value = mrb_ext_load_nstring_cxt(mrb, script.as_ptr(), script.len() as i32, ctx,
+                                             warning_hook(mrb));
+
+        Value::new(self.clone(), value)
+    }
+
+    #[inline]
+    fn runb(&self, script: &[u8]) -> Result<Value, MrubyError> {
+        extern "C" fn runb_protected(mrb: *const MrState, data: MrValue) -> MrValue {
+            unsafe {
+                let ptr = data.to_ptr().unwrap();
+                let args = *mem::transmute::<*const u8, *const [*const u8; 2]>(ptr);
+
+                let ctx: *const MrContext = mem::transmute(args[1]);
+
+                let result = mrb_load_irep_cxt(mrb, args[0], ctx);
+
+                mrb_ext_raise_current(mrb);
+
+                result
+            }
+        }
+
+        check_state(self)?;
+
+        Mruby::clear_exception(self);
+
+        unsafe {
+            let (mrb, ctx) = {
+                let borrow = self.borrow();
+
+                (borrow.mrb, borrow.ctx)
+            };
+
+            let script_ptr = script.as_ptr();
+            let ctx_ptr: *const u8 = mem::transmute(ctx);
+
+            let args = [script_ptr, ctx_ptr];
+            let args_ptr: *const u8 = mem::transmute(&args);
+            let data = MrValue::ptr(mrb, args_ptr);
+
+            let state = mem::uninitialized::<bool>();
+
+            let value = mrb_protect(mrb, runb_protected, data, &state as *const bool);
+
+            if state {
+                Err(MrubyError::runtime(mrb, value))
+            } else {
+                Ok(Value::new(self.clone(), value))
+            }
+        }
+    }
+
+    fn load_gem(&self, name: &str, bytecode: &[u8]) -> Result<(), MrubyError> {
+        check_state(self)?;
+
+        // mruby trusts the RITE header, so check it before letting it read past the slice.
+        if bytecode.len() < 22 {
+            return Err(MrubyError::Filetype);
+        }
+
+        let size = &bytecode[10..14];
+        let size = match &bytecode[0..4] {
+            b"RITE" => (size[0] as usize) << 24 | (size[1] as usize) << 16 |
+                       (size[2] as usize) << 8 | size[3] as usize,
+            b"ETIR" => (size[3] as usize) << 24 | (size[2] as usize) << 16 |
+                       (size[1] as usize) << 8 | size[0] as usize,
+            _       => return Err(MrubyError::Filetype)
+        };
+
+        if size > bytecode.len() {
+            return Err(MrubyError::Filetype);
+        }
+
+        // mruby keeps pointers into the bytecode and expects its sections to be 4-byte aligned, so
+        // it gets copied into a buffer that lives as long as the interpreter.
+        let mut gem = vec![0u32; (bytecode.len() + 3) / 4];
+
+        let result = unsafe {
+            ptr::copy_nonoverlapping(bytecode.as_ptr(), gem.as_mut_ptr() as *mut u8, bytecode.len());
+
+            self.runb(slice::from_raw_parts(gem.as_ptr() as *const u8, bytecode.len()))
+        };
+
+        self.borrow_mut().gems.push(gem);
+
+        try!(result);
+
+        self.borrow_mut().required.insert(name.to_owned());
+
+        Ok(())
+    }
+
+    fn rescue<F, H, E>(&self, body: F, handler: H) -> Result<Value, E>
+        where F: FnOnce() -> Result<Value, MrubyError>,
+              H: Fn(Value) -> E {
+
+        check_thread(self);
+
+        extern "C" fn rescue_protected<F>(mrb: *const MrState, data: MrValue) -> MrValue
+            where F: FnOnce() -> Result<Value, MrubyError> {
+
+            unsafe {
+                let data = data.to_ptr().unwrap() as *mut (Option<F>, Option<Box<Any + Send>>);
+
+                let body = (*data).0.take().unwrap();
+
+                let exc = match panic::catch_unwind(AssertUnwindSafe(body)) {
+                    Ok(Ok(value)) => return value.value,
+                    Ok(Err(err))  => {
+                        let mruby: MrubyType = mem::transmute(mrb_ext_get_ud(mrb));
+                        let exception = mruby.borrow_mut().take_exception();
+
+                        mem::forget(mruby);
+
+                        match (err, exception) {
+                            (MrubyError::Runtime { .. }, Some(exc)) => exc,
+                            (err, _) => Mruby::exception(mrb, "StandardError", &err.to_string())
+                        }
+                    },
+                    Err(error) => {
+                        (*data).1 = Some(error);
+
+                        return MrValue::nil();
+                    }
+                };
+
+                mrb_exc_raise(mrb, exc)
+            }
+        }
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            self.borrow_mut().set_exception(None);
+
+            let mut data: (Option<F>, Option<Box<Any + Send>>) = (Some(body), None);
+            let ptr = MrValue::ptr(mrb, &mut data as *mut _ as *const u8);
+
+            let mut state = false;
+
+            let value = mrb_protect(mrb, rescue_protected::<F>, ptr, &mut state as *mut bool);
+
+            if let Some(error) = data.1 {
+                panic::resume_unwind(error);
+            }
+
+            if state {
+                Err(handler(Value::new(self.clone(), value)))
+            } else {
+                Ok(Value::new(self.clone(), value))
+            }
+        }
+    }
+
+    fn rescue_from<F>(&self, class_name: &str, script: &str, handler: F)
+        -> Result<Value, MrubyError>
+        where F: FnOnce(Value) -> Value {
+
+        check_state(self)?;
+
+        match self.run(script) {
+            Err(err @ MrubyError::Runtime { .. }) => {
+                let exc = self.borrow_mut().take_exception();
+
+                match exc.map(|exc| Value::new(self.clone(), exc)) {
+                    Some(ref exc) if exc.is_a_named(class_name) => Ok(handler(exc.clone())),
+                    _                                            => Err(err)
+                }
+            },
+            result => result
+        }
+    }
+
+    fn current_backtrace(&self) -> Vec<BacktraceEntry> {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            BacktraceEntry::read_array(mrb, mrb_get_backtrace(mrb))
+        }
+    }
+
+    fn raise_exception(&self, class: &Class, message: &str) -> ! {
+        check_thread(self);
+
+        let exc = unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_exc_new(mrb, class.class, message.as_ptr(), message.len())
+        };
+
+        panic::resume_unwind(Box::new(RaisedException(exc)))
+    }
+
+    fn ensure<F, G>(&self, body: F, cleanup: G) -> Result<Value, MrubyError>
+        where F: FnOnce() -> Result<Value, MrubyError>,
+              G: FnOnce() {
+
+        check_state(self)?;
+
+        let mrb = self.borrow().mrb;
+        let mut error = None;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.rescue(|| {
+                match body() {
+                    Err(err @ MrubyError::Runtime { .. }) => Err(err),
+                    // Errors that are not Exceptions are passed through untouched.
+                    Err(err) => {
+                        error = Some(err);
+
+                        Ok(self.nil())
+                    },
+                    ok => ok
+                }
+            }, |exc| MrubyError::runtime(mrb, exc.value))
+        }));
+
+        cleanup();
+
+        match (result, error) {
+            (Err(panic), _)    => panic::resume_unwind(panic),
+            (_, Some(err))     => Err(err),
+            (Ok(result), None) => result
+        }
+    }
+
+    #[inline]
+    fn execute(&self, script: &Path) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        match script.extension() {
+            Some(ext) => {
+                self.filename(script.file_name().unwrap().to_str().unwrap());
+
+                let mut file = try!(File::open(script));
+
+                // Kept so that require_relative resolves names next to the running file.
+                let dir = try!(script.canonicalize()).parent().unwrap().to_path_buf();
+
+                self.borrow_mut().loading.push(dir);
+
+                let result = match ext.to_str().unwrap() {
+                    "rb" => {
+                        let mut script = String::new();
+
+                        file.read_to_string(&mut script).map_err(MrubyError::Io)
+                            .and_then(|_| self.run(&script))
+                    },
+                    "mrb" => {
+                        let mut script = Vec::new();
+
+                        file.read_to_end(&mut script).map_err(MrubyError::Io)
+                            .and_then(|_| self.runb(&script))
+                    },
+                    _ => {
+                        Err(MrubyError::Filetype)
+                    }
+                };
+
+                self.borrow_mut().loading.pop();
+
+                result
+            },
+            None => Err(MrubyError::Filetype)
+        }
+    }
+
+    #[inline]
+    fn is_defined(&self, name: &str) -> bool {
+        check_thread(self);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            mrb_class_defined(self.borrow().mrb, name_str.as_ptr())
+        }
+    }
+
+    #[inline]
+    fn is_defined_under<T: ClassLike>(&self, name: &str, outer: &T) -> bool {
+        check_thread(self);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr())
+        }
+    }
+
+    fn is_class_defined(&self, name: &str) -> bool {
+        if !self.is_defined(name) {
+            return false;
+        }
+
+        let object = self.get_class("Object").unwrap().to_value();
+
+        match self.const_get(object, name) {
+            Ok(value) => value.value.typ == MrType::MRB_TT_CLASS,
+            Err(_)    => false
+        }
+    }
+
+    #[inline]
+    fn get_class(&self, name: &str) -> Result<Class, MrubyError> {
+        check_state(self)?;
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            if mrb_class_defined(self.borrow().mrb, name_str.as_ptr()) {
+                let class = mrb_class_get(self.borrow().mrb, name_str.as_ptr());
+
+                Ok(Class::new(self.clone(), class))
+            } else {
+                Err(MrubyError::Undef)
+            }
+        }
+    }
+
+    #[inline]
+    fn get_class_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Class, MrubyError> {
+        check_state(self)?;
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            if mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr()) {
+                let class = mrb_class_get_under(self.borrow().mrb, outer.class(),
+                                                name_str.as_ptr());
+
+                Ok(Class::new(self.clone(), class))
+            } else {
+                Err(MrubyError::Undef)
+            }
+        }
+    }
+
+    #[inline]
+    fn get_module(&self, name: &str) -> Result<Module, MrubyError> {
+        check_state(self)?;
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            if mrb_class_defined(self.borrow().mrb, name_str.as_ptr()) {
+                let class = mrb_module_get(self.borrow().mrb, name_str.as_ptr());
+
+                Ok(Module::new(self.clone(), class))
+            } else {
+                Err(MrubyError::Undef)
+            }
+        }
+    }
+
+    #[inline]
+    fn get_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Module, MrubyError> {
+        check_state(self)?;
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            if mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr()) {
+                let class = mrb_module_get_under(self.borrow().mrb, outer.class(),
+                                                 name_str.as_ptr());
+
+                Ok(Module::new(self.clone(), class))
+            } else {
+                Err(MrubyError::Undef)
+            }
+        }
+    }
+
+    #[inline]
+    fn const_get(&self, scope: Value, name: &str) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        extern "C" fn const_get_protected(mrb: *const MrState, data: MrValue) -> MrValue {
+            unsafe {
+                let ptr = data.to_ptr().unwrap();
+                let args = *mem::transmute::<*const u8, *const [*const u8; 2]>(ptr);
+
+                let scope = *(args[0] as *const MrValue);
+                let sym: &u32 = mem::transmute(args[1]);
+
+                mrb_const_get(mrb, scope, *sym)
+            }
+        }
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            let name_str = CString::new(name).unwrap();
+            let sym = mrb_intern(mrb, name_str.as_ptr(), name.len());
+
+            let scope_ptr: *const u8 = mem::transmute(&scope.value);
+            let sym_ptr: *const u8 = mem::transmute(&sym);
+
+            let args = [scope_ptr, sym_ptr];
+            let args_ptr: *const u8 = mem::transmute(&args);
+            let data = MrValue::ptr(mrb, args_ptr);
+
+            let mut state = false;
+
+            let value = mrb_protect(mrb, const_get_protected, data, &mut state as *mut bool);
+
+            if state {
+                Err(MrubyError::runtime(mrb, value))
+            } else {
+                Ok(Value::new(self.clone(), value))
+            }
+        }
+    }
+
+    #[inline]
+    fn const_set(&self, scope: Value, name: &str, value: Value) {
+        check_thread(self);
+
+        match scope.value.typ {
+            MrType::MRB_TT_CLASS | MrType::MRB_TT_MODULE => (),
+            _ => panic!("Scope needs to be a Class or Module.")
+        }
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            let name_str = CString::new(name).unwrap();
+            let sym = mrb_intern(mrb, name_str.as_ptr(), name.len());
+
+            mrb_const_set(mrb, scope.value, sym, value.value);
+        }
+    }
+
+    #[inline]
+    fn top_const_get(&self, name: &str) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        let object = self.get_class("Object").unwrap().to_value();
+
+        self.const_get(object, name)
+    }
+
+    fn def_require_loader(&self, features: HashMap<String, fn(MrubyType)>,
+                          load_paths: Vec<PathBuf>) {
+        check_thread(self);
+
+        {
+            let mut borrow = self.borrow_mut();
+
+            for (name, req) in features {
+                borrow.files.entry(name).or_insert_with(Vec::new).push(req);
+            }
+
+            borrow.load_paths = Some(load_paths);
+        }
+
+        if !self.is_defined("LoadError") {
+            self.run("class LoadError < ScriptError; end").unwrap();
+        }
+
+        let mrb = self.borrow().mrb;
+        let kernel = self.get_module("Kernel").unwrap();
+
+        unsafe {
+            let name_str = CString::new("require_relative").unwrap();
+
+            mrb_define_module_function(mrb, kernel.class(), name_str.as_ptr(), require_relative,
+                                       1 << 12);
+        }
+
+        loaded_features(self, mrb);
+    }
+
+    fn def_file<T: MrubyFile>(&self, name: &str) {
+        let mut borrow = self.borrow_mut();
+
+        if borrow.files.contains_key(name) {
+            let mut file = borrow.files.get_mut(name).unwrap();
+
+            file.push(T::require);
+        } else {
+            borrow.files.insert(name.to_owned(), vec![T::require]);
+        }
+    }
+
+    fn def_class(&self, name: &str) -> Class {
+        check_thread(self);
+
+        get_class(self, name, self.get_class(name), |mrb: *const MrState, name: *const c_char,
+                                                     object: *const MrClass| {
+            unsafe { mrb_define_class(mrb, name, object) }
+        })
+    }
+
+    fn def_class_under<U: ClassLike>(&self, name: &str, outer: &U) -> Class {
+        check_thread(self);
+
+        get_class(self, name, self.get_class_under(name, outer),
+                  |mrb: *const MrState, name: *const c_char,
+                   object: *const MrClass| {
+            unsafe { mrb_define_class_under(mrb, outer.class(), name, object) }
+        })
+    }
+
+    fn def_exception_class(&self, name: &str, parent: Option<&Class>) -> Class {
+        check_thread(self);
+
+        let parent = match parent {
+            Some(parent) => parent.class,
+            None         => self.get_class("StandardError").unwrap().class
+        };
+
+        get_class(self, name, self.get_class(name), |mrb: *const MrState, name: *const c_char,
+                                                     _object: *const MrClass| {
+            unsafe { mrb_define_class(mrb, name, parent) }
+        })
+    }
+
+    fn def_class_for<T: Any>(&self, name: &str) -> Class {
+        check_thread(self);
+
+        get_class_for::<T, _>(self, name, |mrb: *const MrState, name: *const c_char,
+                                        object: *const MrClass| {
+            unsafe { mrb_define_class(mrb, name, object) }
+        })
+    }
+
+    fn def_class_under_for<T: Any, U: ClassLike>(&self, name: &str, outer: &U) -> Class {
+        check_thread(self);
+
+        get_class_for::<T, _>(self, name, |mrb: *const MrState, name: *const c_char,
+                                        object: *const MrClass| {
+            unsafe { mrb_define_class_under(mrb, outer.class(), name, object) }
+        })
+    }
+
+    fn def_subclass_for<T: Any>(&self, name: &str, superclass: &Class) -> Class {
+        check_thread(self);
+
+        get_class_for::<T, _>(self, name, |mrb: *const MrState, name: *const c_char,
+                                        _object: *const MrClass| {
+            unsafe { mrb_define_class(mrb, name, superclass.class) }
+        })
+    }
+
+    fn def_module(&self, name: &str) -> Module {
+        check_thread(self);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let mrb = self.borrow().mrb;
+
+            let module = mrb_define_module(mrb, name_str.as_ptr());
+
+            Module::new(self.clone(), module)
+        }
+    }
+
+    fn def_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Module {
+        check_thread(self);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let mrb = self.borrow().mrb;
+
+            let module = mrb_define_module_under(mrb, outer.class(), name_str.as_ptr());
+
+            Module::new(self.clone(), module)
+        }
+    }
+
+    fn def_method<F>(&self, class: Class, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        check_thread(self);
+
+        let sym = self.intern(name).0;
+        let redefined = self.borrow().mruby_methods.get(class.to_str()).map_or(false, |methods| {
+            methods.contains_key(&sym)
+        });
+
+        if !redefined {
+            keep_previous_method(self, class.class, sym);
+        }
+
+        let sym = insert_method!(self, name, method, mruby_methods, class.to_str());
+
+        mruby_callback!(call_mruby_method, mruby_methods, target_class);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_define_method_id(mrb, class.class, sym, call_mruby_method, 1 << 12);
+        }
+    }
+
+    fn def_class_method<F>(&self, class: Class, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        check_thread(self);
+
+        insert_method!(self, name, method, mruby_class_methods, class.to_str());
+
+        mruby_callback!(call_mruby_class_method, mruby_class_methods, to_class);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let mrb = self.borrow().mrb;
+
+            mrb_define_class_method(mrb, class.class, name_str.as_ptr(),
+                                    call_mruby_class_method, 1 << 12);
+        }
+    }
+
+    fn def_module_method<F>(&self, module: Module, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        check_thread(self);
+
+        self.borrow_mut().mruby_class_methods.entry(module.to_str().to_owned())
+            .or_insert_with(HashMap::new);
+
+        insert_method!(self, name, method, mruby_class_methods, module.to_str());
+
+        mruby_callback!(call_mruby_module_method, mruby_class_methods, to_module);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let mrb = self.borrow().mrb;
+
+            mrb_define_class_method(mrb, module.module, name_str.as_ptr(),
+                                    call_mruby_module_method, 1 << 12);
+        }
+    }
+
+    fn def_toplevel_method<F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        let object = self.get_class("Object").unwrap();
+
+        self.borrow_mut().mruby_methods.entry(object.to_str().to_owned())
+            .or_insert_with(HashMap::new);
+
+        self.def_method(object, name, method);
+    }
+
+    fn def_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        if let Err(err) = self.try_def_method_for::<T, F>(name, method) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_def_method_for<T: Any, F>(&self, name: &str, method: F) -> Result<(), MrubyError>
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        check_state(self)?;
+
+        if !self.borrow().classes.contains_key(&TypeId::of::<T>()) {
+            return Err(unregistered::<T>(&format!("cannot define method '{}'", name)));
+        }
+
+        let sym = self.intern(name).0;
+        let redefined = self.borrow().methods.get(&TypeId::of::<T>()).map_or(false, |methods| {
+            methods.contains_key(&sym)
+        });
+
+        if !redefined {
+            keep_previous_method(self, class_for::<T>(self).class, sym);
+        }
+
+        let sym = insert_method!(self, name, method, methods, &TypeId::of::<T>());
+        let class = class_for::<T>(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_define_method_id(mrb, class.class, sym, call_method::<T>, 1 << 12);
+        }
+
+        if name == "coerce" {
+            def_coercions(self);
+        }
+
+        Ok(())
+    }
+
+    fn def_class_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        if let Err(err) = self.try_def_class_method_for::<T, F>(name, method) {
+            panic!("{}", err);
+        }
+    }
+
+    fn try_def_class_method_for<T: Any, F>(&self, name: &str, method: F) -> Result<(), MrubyError>
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        check_state(self)?;
+
+        if !self.borrow().classes.contains_key(&TypeId::of::<T>()) {
+            return Err(unregistered::<T>(&format!("cannot define class method '{}'", name)));
+        }
+
+        insert_method!(self, name, method, class_methods, &TypeId::of::<T>());
+
+        let class = class_for::<T>(self);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let mrb = self.borrow().mrb;
+
+            mrb_define_class_method(mrb, class.class, name_str.as_ptr(),
+                                    call_class_method::<T>, 1 << 12);
+        }
+
+        Ok(())
+    }
+
+    fn def_private_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        self.def_method_for::<T, _>(name, method);
+
+        class_for::<T>(self).set_visibility(name, Visibility::Private);
+    }
+
+    fn def_protected_method_for<T: Any, F>(&self, name: &str, method: F)
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        self.def_method_for::<T, _>(name, method);
+
+        class_for::<T>(self).set_visibility(name, Visibility::Protected);
+    }
+
+    fn def_method_missing_for<T: Any, F, R>(&self, method: F, responds: R)
+        where F: Fn(MrubyType, Value, &str, Vec<Value>) -> Value + 'static,
+              R: Fn(MrubyType, Value, &str) -> bool + 'static {
+
+        check_thread(self);
+
+        self.def_method_for::<T, _>("method_missing", move |mruby, slf| {
+            let args = all_args(&mruby);
+            let name = method_name(&args[0]);
+
+            let result = method(mruby.clone(), slf.clone(), &name, args[1..].to_vec());
+
+            if result.value != mruby.unhandled().value {
+                return result;
+            }
+
+            match slf.call_super("method_missing", args) {
+                Ok(value) => value,
+                Err(err)  => {
+                    let mrb = mruby.borrow().mrb;
+                    let exc = mruby.borrow_mut().take_exception();
+                    let exc = exc.unwrap_or_else(|| {
+                        Mruby::exception(mrb, "NoMethodError", &err.to_string())
+                    });
+
+                    panic::resume_unwind(Box::new(RaisedException(exc)));
+                }
+            }
+        });
+
+        self.def_method_for::<T, _>("respond_to_missing?", move |mruby, slf| {
+            let args = all_args(&mruby);
+            let name = method_name(&args[0]);
+
+            mruby.bool(responds(mruby.clone(), slf, &name))
+        });
+    }
+
+    #[inline]
+    fn unhandled(&self) -> Value {
+        self.symbol("__mrusty_unhandled__")
+    }
+
+    fn def_method_fn<T: Any>(&self, name: &str, method: fn(MrubyType, Value) -> Value) {
+        check_thread(self);
+
+        let sym = self.intern(name).0;
+        let class = class_for::<T>(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            define_method_fn(mrb, class.class, sym, method);
+        }
+
+        if name == "coerce" {
+            def_coercions(self);
+        }
+    }
+
+    fn def_attr_reader_for<T: Any>(&self, attrs: &[(&str, fn(MrubyType, &T) -> Value)]) {
+        for &(name, reader) in attrs {
+            self.def_method_for::<T, _>(name, move |mruby, slf| {
+                let obj = slf_obj::<T>(&slf);
+                let obj = borrow_obj(&obj);
+
+                reader(mruby, &obj)
+            });
+        }
+    }
+
+    fn def_attr_writer_for<T: Any>(&self, attrs: &[(&str, fn(&mut T, Value))]) {
+        for &(name, writer) in attrs {
+            self.def_method_for::<T, _>(&format!("{}=", name), move |mruby, slf| {
+                let value = single_arg(&mruby);
+                let obj = slf_obj::<T>(&slf);
+
+                writer(&mut borrow_obj_mut(&obj), value.clone());
+
+                value
+            });
+        }
+    }
+
+    #[inline]
+    fn def_mark_for<T: Any + MrubyMark>(&self) {
+        check_thread(self);
+
+        let borrow = self.borrow();
+
+        let class = match borrow.classes.get(&TypeId::of::<T>()) {
+            Some(class) => class,
+            None        => panic!("{}", unregistered::<T>("cannot mark the Values held"))
+        };
+
+        unsafe {
+            mrb_ext_set_data_mark(borrow.mrb, &*class.1, mark::<T>);
+        }
+    }
+
+    fn def_finalizer_for<T: Any, F: Fn(&T) + 'static>(&self, finalizer: F) {
+        if !self.borrow().classes.contains_key(&TypeId::of::<T>()) {
+            panic!("{}", unregistered::<T>("cannot define a finalizer"));
+        }
+
+        let finalizer: Box<Fn(&T)> = Box::new(finalizer);
+
+        self.borrow_mut().finalizers.insert(TypeId::of::<T>(), Rc::new(finalizer));
+    }
+
+    fn def_downcast_for<T: ?Sized + MrubyAny>(&self) {
+        let mut borrow = self.borrow_mut();
+
+        let name = match borrow.classes.get(&TypeId::of::<Box<T>>()) {
+            Some(class) => class.2.clone(),
+            None        => panic!("{}", unregistered::<Box<T>>("cannot downcast objects"))
+        };
+
+        borrow.downcasts.insert(name, any_cell::<T>);
+    }
+
+    fn class_builder<'a, T: Any>(&self, name: &'a str) -> ClassBuilder<'a, T> {
+        ClassBuilder {
+            mruby:         self.clone(),
+            name,
+            methods:       vec![],
+            class_methods: vec![],
+            hooks:         vec![],
+            requires:      vec![],
+            superclass:    None,
+            modules:       vec![],
+            consts:        vec![],
+            class_vars:    vec![],
+            visibility:    Visibility::Public,
+            visibilities:  vec![],
+            open:          false,
+            phantom:       PhantomData
+        }
+    }
+
+    fn open_class_for<'a, T: Any>(&self) -> ClassBuilder<'a, T> {
+        let mut builder = self.class_builder::<T>("");
+
+        builder.open = true;
+
+        builder
+    }
+
+    fn def_each_for<T: Any, I, F>(&self, iter: F)
+        where I: IntoIterator<Item = Value>,
+              F: Fn(MrubyType, &T) -> I + 'static {
+
+        // Only callable without a receiver, i.e. from each below.
+        self.def_private_method_for::<T, _>("__each__", move |mruby, slf| {
+            let values: Vec<Value> = {
+                let obj = slf_obj::<T>(&slf);
+                let obj = borrow_obj(&obj);
+
+                iter(mruby.clone(), &obj).into_iter().collect()
+            };
+
+            mruby.array(values)
+        });
+
+        // Yielding from Ruby lets break, return and exceptions in the block behave as usual, which
+        // mruby does not support for blocks yielded to from C.
+        let each = self.run("
+          Module.new do
+            def each(&block)
+              return to_enum :each unless block
+
+              __each__.each(&block)
+
+              self
+            end
+          end
+        ").unwrap();
+
+        let class = class_for::<T>(self);
+
+        class.include(each.to_module().unwrap());
+        class.include(self.get_module("Enumerable").unwrap());
+    }
+
+    fn def_comparable_for<T: Any + Ord>(&self) {
+        self.def_method_for::<T, _>("<=>", |mruby, slf| {
+            let other = single_arg(&mruby);
+
+            match other.to_obj::<T>() {
+                Ok(other) => {
+                    let ordering = slf.to_obj::<T>().unwrap().borrow().cmp(&other.borrow());
+
+                    mruby.fixnum(ordering as i32)
+                },
+                Err(_)    => mruby.nil()
+            }
+        });
+
+        let comparable = self.get_module("Comparable").unwrap();
+
+        class_for::<T>(self).include(comparable);
+    }
+
+    fn def_eq_for<T: Any + PartialEq + Hash>(&self) {
+        fn eq<T: Any + PartialEq>(mruby: MrubyType, slf: Value) -> Value {
+            let other = single_arg(&mruby);
+
+            let eq = match other.to_obj::<T>() {
+                Ok(other) => *slf.to_obj::<T>().unwrap().borrow() == *other.borrow(),
+                Err(_)    => false
+            };
+
+            mruby.bool(eq)
+        }
+
+        self.def_method_for::<T, _>("==", eq::<T>);
+        self.def_method_for::<T, _>("eql?", eq::<T>);
+        self.def_method_for::<T, _>("hash", |mruby, slf| {
+            let mut hasher = DefaultHasher::new();
+
+            slf.to_obj::<T>().unwrap().borrow().hash(&mut hasher);
+
+            mruby.fixnum(hasher.finish() as i32)
+        });
+    }
+
+    fn def_display_for<T: Any + fmt::Debug>(&self) {
+        self.def_method_for::<T, _>("inspect", |mruby, slf| {
+            format_obj::<T, _>(&mruby, &slf, |obj| format!("{:?}", obj))
+        });
+    }
+
+    fn def_to_s_for<T: Any + fmt::Display>(&self) {
+        self.def_method_for::<T, _>("to_s", |mruby, slf| {
+            format_obj::<T, _>(&mruby, &slf, |obj| obj.to_string())
+        });
+    }
+
+    fn def_operator_for<T: Any, F>(&self, op: &str, f: F)
+        where F: Fn(MrubyType, &T, Value) -> Value + 'static {
+
+        if !OPERATORS.contains(&op) {
+            panic!("unknown operator `{}`", op);
+        }
+
+        let unary = UNARY_OPERATORS.contains(&op);
+
+        self.def_method_for::<T, _>(op, move |mruby, slf| {
+            let other = if unary { mruby.nil() } else { single_arg(&mruby) };
+
+            let obj = slf.to_obj::<T>().unwrap();
+            let obj = obj.borrow();
+
+            f(mruby, &obj, other)
+        });
+    }
+
+    fn def_operators_for<T: Any>(&self,
+                                 ops: Vec<(&str, Box<dyn Fn(MrubyType, &T, Value) -> Value>)>) {
+        for (op, f) in ops {
+            self.def_operator_for::<T, _>(op, f);
+        }
+    }
+
+    #[inline]
+    fn class_name_for<T: Any>(&self) -> Result<String, MrubyError> {
+        check_state(self)?;
+
+        let borrow = self.borrow();
+
+        match borrow.classes.get(&TypeId::of::<T>()) {
+            Some(class) => Ok(class.2.clone()),
+            None        => Err(MrubyError::Undef)
+        }
+    }
+
+    #[inline]
+    fn nil(&self) -> Value {
+        unsafe {
+            Value::new(self.clone(), MrValue::nil())
+        }
+    }
+
+    #[inline]
+    fn bool(&self, value: bool) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn fixnum(&self, value: i32) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn fixnum_i64(&self, value: i64) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn fixnum_u64(&self, value: u64) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn float(&self, value: f64) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn float32(&self, value: f32) -> Value {
+        value.to_value(self)
+    }
+
+    fn time(&self, time: SystemTime) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        let class = time_class(self)?;
+
+        // Time.at carries negative microseconds over into the seconds itself.
+        let (sec, usec) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since)  => (since.as_secs() as f64, since.subsec_micros() as f64),
+            Err(error) => {
+                let before = error.duration();
+
+                (-(before.as_secs() as f64), -(before.subsec_micros() as f64))
+            }
+        };
+
+        class.to_value().call("at", vec![self.float(sec), self.float(usec)])
+    }
+
+    #[inline]
+    fn duration(&self, duration: Duration) -> Value {
+        duration.to_value(self)
+    }
+
+    #[inline]
+    fn string(&self, value: &str) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn string_static(&self, value: &'static str) -> Value {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), MrValue::string_static(mrb, value))
+        }
+    }
+
+    #[inline]
+    fn bytes(&self, value: &[u8]) -> Value {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), MrValue::bytes(mrb, value))
+        }
+    }
+
+    #[inline]
+    fn string_from_path(&self, path: &Path) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        path_to_bytes(path).map(|bytes| self.bytes(bytes))
+    }
+
+    fn string_concat(&self, string: &Value, other: &Value) -> Result<(), MrubyError> {
+        check_state(self)?;
+
+        string.string_len()?;
+
+        let mrb = self.borrow().mrb;
+
+        unsafe {
+            protect(mrb, || {
+                mrb_str_concat(mrb, string.value, other.value);
+
+                MrValue::nil()
+            }).map(|_| ()).map_err(|exc| MrubyError::runtime(mrb, exc))
+        }
+    }
+
+    fn string_append_bytes(&self, string: &Value, bytes: &[u8]) -> Result<(), MrubyError> {
+        check_state(self)?;
+
+        string.string_len()?;
+
+        let mrb = self.borrow().mrb;
+
+        unsafe {
+            protect(mrb, || {
+                mrb_str_cat(mrb, string.value, bytes.as_ptr(), bytes.len())
+            }).map(|_| ()).map_err(|exc| MrubyError::runtime(mrb, exc))
+        }
+    }
+
+    #[inline]
+    fn symbol(&self, value: &str) -> Value {
+        check_thread(self);
+
+        unsafe {
+            Value::new(self.clone(), MrValue::symbol(self.borrow().mrb, value))
+        }
+    }
+
+    #[inline]
+    fn intern(&self, name: &str) -> MrubySymbolId {
+        check_thread(self);
+
+        match self.try_borrow_mut() {
+            Ok(mut mruby) => MrubySymbolId(mruby.intern_cached(name)),
+            // Someone up the stack still holds the interpreter, so skip the cache.
+            Err(_)        => unsafe {
+                MrubySymbolId(mrb_intern(self.borrow().mrb, name.as_ptr() as *const c_char, name.len()))
+            }
+        }
+    }
+
+    fn lookup_symbol(&self, id: MrubySymbolId) -> &str {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            if mrb_sym2name_len(mrb, id.0, ptr::null_mut()).is_null() {
+                panic!("symbol {} is not interned in this Mruby", id.0);
+            }
+
+            sym_name(mrb, id.0)
+        }
+    }
+
+    fn funcall_argv(&self, receiver: &Value, sym: MrubySymbolId, args: &[Value])
+        -> Result<Value, MrubyError> {
+
+        check_state(self)?;
+
+        Mruby::clear_exception(self);
+
+        // Calls with few arguments are the common case, so their arguments stay on the stack.
+        let mut inline = [receiver.value; 8];
+
+        if args.len() <= inline.len() {
+            for (slot, arg) in inline.iter_mut().zip(args.iter()) {
+                *slot = arg.value;
+            }
+
+            receiver.funcall(sym, &inline[..args.len()])
+        } else {
+            let heap: Vec<MrValue> = args.iter().map(|value| value.value).collect();
+
+            receiver.funcall(sym, &heap)
+        }
+    }
+
+    fn send(&self, receiver: &Value, method: Value, args: Vec<Value>) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        if method.value.typ != MrType::MRB_TT_SYMBOL {
+            let mrb = self.borrow().mrb;
+            let inspect = method.call("inspect", vec![])?;
+            let exc = Mruby::exception(mrb, "TypeError",
+                                       &format!("{} is not a symbol", inspect.to_str()?));
+
+            // Raised so that the Exception gets a backtrace like any other.
+            return unsafe {
+                Err(MrubyError::runtime(mrb, protect(mrb, || mrb_exc_raise(mrb, exc)).unwrap_err()))
+            };
+        }
+
+        let sym = unsafe { MrubySymbolId(mrb_ext_sym_id(method.value)) };
+
+        receiver.call_sym(sym, args)
+    }
+
+    #[inline]
+    fn obj<T: Any>(&self, obj: T) -> Value {
+        check_thread(self);
+
+        let (mrb, class, data_type) = data_type_for::<T>(self, "cannot create an object");
+
+        let rc = Rc::new(RefCell::new(obj));
+        let key = &*rc as *const RefCell<T> as *const c_void;
+
+        unsafe {
+            let value = MrValue::rc(mrb, class, rc, &*data_type);
+
+            mrb_ext_shared_set(mrb, key, value);
+
+            Value::new(self.clone(), value)
+        }
+    }
+
+    fn obj_shared<T: Any>(&self, obj: Rc<RefCell<T>>) -> Value {
+        check_thread(self);
+
+        let (mrb, class, data_type) = data_type_for::<T>(self, "cannot share an object");
+
+        let key = &*obj as *const RefCell<T> as *const c_void;
+
+        unsafe {
+            let mut value = MrValue::nil();
+
+            if !mrb_ext_shared_get(mrb, key, &mut value) {
+                value = MrValue::rc(mrb, class, obj, &*data_type);
+
+                mrb_ext_shared_set(mrb, key, value);
+            }
+
+            Value::new(self.clone(), value)
+        }
+    }
+
+    fn find_obj<T: Any>(&self, obj: &Rc<RefCell<T>>) -> Option<Value> {
+        check_thread(self);
+
+        let key = &**obj as *const RefCell<T> as *const c_void;
+
+        unsafe {
+            let mut value = MrValue::nil();
+
+            if mrb_ext_shared_get(self.borrow().mrb, key, &mut value) {
+                Some(Value::new(self.clone(), value))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn option<T: Any>(&self, obj: Option<T>) -> Value {
+        match obj {
+            Some(obj) => self.obj(obj),
+            None      => self.nil()
+        }
+    }
+
+    #[inline]
+    fn array(&self, value: Vec<Value>) -> Value {
+        value.to_value(self)
+    }
+
+    #[inline]
+    fn array_new(&self) -> Value {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_ary_new(mrb))
+        }
+    }
+
+    #[inline]
+    fn array_push(&self, array: &Value, value: Value) {
+        check_thread(self);
+
+        assert!(array.value.typ == MrType::MRB_TT_ARRAY, "array_push expects an Array");
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_ary_push(mrb, array.value, value.value);
+        }
+    }
+
+    #[inline]
+    fn array_concat(&self, array: &Value, other: &Value) {
+        check_thread(self);
+
+        assert!(array.value.typ == MrType::MRB_TT_ARRAY && other.value.typ == MrType::MRB_TT_ARRAY,
+                "array_concat expects two Arrays");
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_ary_concat(mrb, array.value, other.value);
+        }
+    }
+
+    fn map<'a, K, I>(&self, map: I) -> Value
+        where K: AsRef<str> + 'a,
+              I: IntoIterator<Item = (&'a K, &'a Value)> {
+
+        hash_from_entries(self, map)
+    }
+
+    #[inline]
+    fn hash_get(&self, hash: &Value, key: &Value) -> Value {
+        check_thread(self);
+
+        assert!(hash.value.typ == MrType::MRB_TT_HASH, "hash_get expects a Hash");
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_hash_fetch(mrb, hash.value, key.value, MrValue::nil()))
+        }
+    }
+
+    #[inline]
+    fn hash_set(&self, hash: &Value, key: Value, value: Value) {
+        check_thread(self);
+
+        assert!(hash.value.typ == MrType::MRB_TT_HASH, "hash_set expects a Hash");
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            mrb_hash_set(mrb, hash.value, key.value, value.value);
+        }
+    }
+
+    #[inline]
+    fn hash_delete(&self, hash: &Value, key: &Value) -> Value {
+        check_thread(self);
+
+        assert!(hash.value.typ == MrType::MRB_TT_HASH, "hash_delete expects a Hash");
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_hash_delete_key(mrb, hash.value, key.value))
+        }
+    }
+
+    #[inline]
+    fn hash_len(&self, hash: &Value) -> usize {
+        check_thread(self);
+
+        assert!(hash.value.typ == MrType::MRB_TT_HASH, "hash_len expects a Hash");
+
+        unsafe {
+            mrb_ext_hash_size(hash.value)
+        }
+    }
+
+    fn hash_keys(&self, hash: &Value) -> Result<Vec<Value>, MrubyError> {
+        check_state(self)?;
+
+        if hash.value.typ != MrType::MRB_TT_HASH {
+            return Err(MrubyError::Cast("Hash".to_owned()));
+        }
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_hash_keys(mrb, hash.value)).to_vec()
+        }
+    }
+
+    fn hash_values(&self, hash: &Value) -> Result<Vec<Value>, MrubyError> {
+        check_state(self)?;
+
+        if hash.value.typ != MrType::MRB_TT_HASH {
+            return Err(MrubyError::Cast("Hash".to_owned()));
+        }
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_ext_hash_values(mrb, hash.value)).to_vec()
+        }
+    }
+
+    #[inline]
+    fn range(&self, start: Value, end: Value, exclusive: bool) -> Value {
+        check_thread(self);
+
+        unsafe {
+            let mrb = self.borrow().mrb;
+
+            Value::new(self.clone(), mrb_range_new(mrb, start.value, end.value, exclusive))
+        }
+    }
+
+    fn enumerator<I>(&self, mut iter: I) -> Value
+        where I: Iterator + 'static,
+              I::Item: ToValue {
+
+        if self.class_name_for::<RustEnumerator>().is_err() {
+            def_rust_enumerator(self);
+        }
+
+        self.obj(RustEnumerator(Box::new(move |mruby| {
+            iter.next().map(|value| value.to_value(mruby))
+        })))
+    }
+
+    #[inline]
+    fn value<T: ToValue>(&self, value: T) -> Value {
+        value.to_value(self)
+    }
+
+    fn json_parse(&self, json: &str) -> Result<Value, MrubyError> {
+        check_state(self)?;
+
+        json::parse(self, json)
+    }
+
+    #[inline]
+    fn from_json(&self, json: &str) -> Result<Value, MrubyError> {
+        self.json_parse(json)
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_value_serde<T: ::serde::Serialize + ?Sized>(&self, value: &T)
+        -> Result<Value, MrubyError> {
+
+        check_state(self)?;
+
+        serde_value::to_value(self, value)
+    }
+
+    #[cfg(feature = "serde")]
+    fn from_value_serde<T: ::serde::de::DeserializeOwned>(&self, value: &Value)
+        -> Result<T, MrubyError> {
+
+        check_state(self)?;
+
+        serde_value::from_value(value)
+    }
+}
+
+impl Drop for Mruby {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// A `struct` that wraps an interned mruby symbol. Obtained from `mruby.intern` and only valid on
+/// the `Mruby` that created it.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// let mruby = Mruby::new();
+///
+/// let to_s = mruby.intern("to_s");
+/// let result = mruby.fixnum(2).call_sym(to_s, vec![]).unwrap();
+///
+/// assert_eq!(result.to_str().unwrap(), "2");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MrubySymbolId(u32);
+
+/// A `struct` that wraps around any mruby variable.
+///
+/// `Values` are created from the `Mruby` instance:
+///
+/// * [`nil`](../mrusty/trait.MrubyImpl.html#tymethod.nil)
+/// * [`bool`](../mrusty/trait.MrubyImpl.html#tymethod.bool)
+/// * [`fixnum`](../mrusty/trait.MrubyImpl.html#tymethod.fixnum)
+/// * [`float`](../mrusty/trait.MrubyImpl.html#tymethod.float)
+/// * [`string`](../mrusty/trait.MrubyImpl.html#tymethod.string)
+/// * [`obj`](../mrusty/trait.MrubyImpl.html#tymethod.obj)
+/// * [`option`](../mrusty/trait.MrubyImpl.html#tymethod.option)
+/// * [`array`](../mrusty/trait.MrubyImpl.html#tymethod.array)
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// let mruby = Mruby::new();
+/// let result = mruby.run("true").unwrap(); // Value
+///
+/// // Values need to be unwrapped in order to make sure they have the right mruby type.
+/// assert_eq!(result.to_bool().unwrap(), true);
+/// ```
+pub struct Value {
+    mruby: MrubyType,
+    value: MrValue
 }
 
-#[inline]
-fn get_class<F>(mruby: &MrubyType, name: &str, class: Result<Class, MrubyError>, get: F) -> Class
-    where F: Fn(*const MrState, *const c_char, *const MrClass) -> *const MrClass {
+impl Value {
+    /// Not meant to be called directly.
+    #[doc(hidden)]
+    pub fn new(mruby: MrubyType, value: MrValue) -> Value {
+        Value {
+            mruby: mruby,
+            value: value
+        }
+    }
+
+    /// Initializes the `self` mruby object passed to `initialize` with a Rust object of type `T`.
+    ///
+    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// };
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    /// mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+    ///     let cont = Cont { value: v };
+    ///
+    ///     slf.init(cont) // Return the same slf value.
+    /// }));
+    ///
+    /// let result = mruby.run("Container.new 3").unwrap();
+    /// let result = result.to_obj::<Cont>().unwrap();
+    /// let result = result.borrow();
+    ///
+    /// assert_eq!(result.value, 3);
+    /// # }
+    /// ```
+    pub fn init<T: Any>(self, obj: T) -> Value {
+        unsafe {
+            let rc = Rc::new(RefCell::new(obj));
+            let key = &*rc as *const RefCell<T> as *const c_void;
+            let ptr: *const u8 = mem::transmute(rc);
+
+            let (mrb, _, data_type) = data_type_for::<T>(&self.mruby,
+                                                         "cannot initialize an object");
+
+            mrb_ext_data_init(&self.value as *const MrValue, ptr, data_type);
+            mrb_ext_shared_set(mrb, key, self.value);
+        }
+
+        self
+    }
+
+    /// Calls method `name` on a `Value` passing `args`. An `Exception` raised by the method is
+    /// returned as an `MrubyError::Runtime` in an `Err`, holding its `Class` name, message and
+    /// backtrace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let one = mruby.fixnum(1);
+    /// let result = one.call("+", vec![mruby.fixnum(2)]).unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    ///
+    /// match one.call("missing", vec![]) {
+    ///     Err(MrubyError::Runtime { message: err, .. }) => {
+    ///         assert!(err.starts_with("NoMethodError: "));
+    ///     },
+    ///     result => panic!("unexpected {:?}", result)
+    /// }
+    /// ```
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
+
+        let sym = self.mruby.intern(name);
+
+        self.call_sym(sym, args)
+    }
+
+    /// Calls method `sym` on a `Value` passing `args`. Reusing a `MrubySymbolId` obtained from
+    /// `mruby.intern` skips interning the method name on every call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let plus = mruby.intern("+");
+    /// let one = mruby.fixnum(1);
+    ///
+    /// let result = one.call_sym(plus, vec![mruby.fixnum(2)]).unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// ```
+    #[inline]
+    pub fn call_sym(&self, sym: MrubySymbolId, args: Vec<Value>) -> Result<Value, MrubyError> {
+        self.mruby.funcall_argv(self, sym, &args)
+    }
+
+    /// Calls method `name` on a `Value` without any arguments.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let result = mruby.fixnum(1).call0("succ").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 2);
+    /// ```
+    pub fn call0(&self, name: &str) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
+
+        let sym = self.mruby.intern(name);
+
+        self.funcall(sym, &[])
+    }
+
+    /// Calls method `name` on a `Value` passing a single `arg`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let result = mruby.fixnum(1).call1("+", mruby.fixnum(2)).unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// ```
+    pub fn call1(&self, name: &str, arg: Value) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
+
+        let sym = self.mruby.intern(name);
+
+        self.funcall(sym, &[arg.value])
+    }
+
+    /// Calls method `name` on a `Value` passing `args` like `call`, but returns a raised
+    /// `Exception` itself in an `Err`, so that it can be inspected or re-raised.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let one = mruby.fixnum(1);
+    ///
+    /// assert_eq!(one.protected_call("+", vec![mruby.fixnum(2)]).unwrap(), mruby.fixnum(3));
+    ///
+    /// let exc = one.protected_call("/", vec![mruby.string("a")]).unwrap_err();
+    ///
+    /// assert_eq!(exc.class().to_str(), "TypeError");
+    /// ```
+    pub fn protected_call(&self, name: &str, args: Vec<Value>) -> Result<Value, Value> {
+        let sym = self.mruby.intern(name);
+        let argv: Vec<MrValue> = args.iter().map(|value| value.value).collect();
+
+        match self.protected_funcall(sym, &argv) {
+            Ok(value) => Ok(Value::new(self.mruby.clone(), value)),
+            Err(exc)  => Err(Value::new(self.mruby.clone(), exc))
+        }
+    }
+
+    fn funcall(&self, sym: MrubySymbolId, argv: &[MrValue]) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
+
+        let mrb = self.mruby.borrow().mrb;
+
+        Mruby::clear_exception(&self.mruby);
+
+        match self.protected_funcall(sym, argv) {
+            Ok(value) => Ok(Value::new(self.mruby.clone(), value)),
+            Err(exc)  => Err(MrubyError::runtime(mrb, exc))
+        }
+    }
+
+    fn protected_funcall(&self, sym: MrubySymbolId, argv: &[MrValue]) -> Result<MrValue, MrValue> {
+        check_thread(&self.mruby);
+
+        extern "C" fn call_protected(mrb: *const MrState, data: MrValue) -> MrValue {
+            unsafe {
+                let ptr = data.to_ptr().unwrap();
+                let args = *mem::transmute::<*const u8, *const [*const u8; 4]>(ptr);
+
+                let value = *(args[0] as *const MrValue);
+                let sym: &u32 = mem::transmute(args[1]);
+                let argc: &i32 = mem::transmute(args[2]);
+                let argv: *const MrValue = mem::transmute(args[3]);
+
+                let result = mrb_funcall_argv(mrb, value, *sym, *argc, argv);
+
+                mrb_ext_raise_current(mrb);
+
+                result
+            }
+        }
+
+        unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            let value_ptr: *const u8 = mem::transmute(&self.value);
+            let sym_ptr: *const u8 = mem::transmute(&sym.0);
+            let argc = argv.len() as i32;
+            let argc_ptr: * const u8 = mem::transmute(&argc);
+            let argv_ptr: *const u8 = mem::transmute(argv.as_ptr());
+
+            let args = [value_ptr, sym_ptr, argc_ptr, argv_ptr];
+            let args_ptr: *const u8 = mem::transmute(&args);
+            let data = MrValue::ptr(mrb, args_ptr);
+
+            let state = mem::uninitialized::<bool>();
+
+            let value = mrb_protect(mrb, call_protected, data, &state as *const bool);
+
+            if state {
+                mrb_ext_exc_restore(mrb, value);
+
+                Err(value)
+            } else {
+                Ok(value)
+            }
+        }
+    }
+
+    /// Calls method `name` on a `Value` passing `args`. If call fails, mruby will be left to
+    /// handle the exception.
+    ///
+    /// The method is unsafe because running it within a Rust context will interrupt drops,
+    /// potentially leading to memory leaks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let one = mruby.fixnum(1);
+    /// let result = unsafe { one.call_unchecked("+", vec![mruby.fixnum(2)]) };
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// ```
+    pub unsafe fn call_unchecked(&self, name: &str, args: Vec<Value>) -> Value {
+        check_thread(&self.mruby);
+
+        let sym = self.mruby.intern(name).0;
+
+        let mrb = self.mruby.borrow().mrb;
+
+        let args: Vec<MrValue> = args.iter().map(|value| value.value).collect();
+
+        let result = mrb_funcall_argv(mrb, self.value, sym, args.len() as i32, args.as_ptr());
+
+        Value::new(self.mruby.clone(), result)
+    }
+
+    /// Calls the superclass implementation of method `name` on a `Value` passing `args`. When
+    /// called from within the overriding method, lookup starts above the class defining it.
+    ///
+    /// A method defined with `def_method` or `def_method_for` on a `Class` which already defined
+    /// `name` itself, e.g. `to_s` on a reopened `Fixnum`, replaces it. Calling `call_super`
+    /// from within the Rust method then calls the replaced method instead. Replacing the Rust
+    /// method again keeps the method that was there before Rust, so wrappers do not stack.
+    ///
+    /// *Note:* Only calls made from the overriding method itself with the same `name` count as
+    /// from within it. Anywhere else, e.g. from a block or on another `Value`, lookup starts
+    /// above the `Class` that defines `name` for the `Value`.
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.run("
+    ///   class Base
+    ///     def greet(name)
+    ///       \"Hello, #{name}\"
+    ///     end
+    ///   end
+    ///
+    ///   class Greeter < Base; end
+    /// ").unwrap();
+    ///
+    /// mruby_class!(mruby, "Greeter", {
+    ///     def!("greet", |mruby, slf: Value, name: (&str)| {
+    ///         let greeting = slf.call_super("greet", vec![mruby.string(name)]).unwrap();
+    ///
+    ///         mruby.string(&format!("{}!", greeting.to_str().unwrap()))
+    ///     });
+    /// });
+    ///
+    /// let result = mruby.run("Greeter.new.greet 'mruby'").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "Hello, mruby!");
+    /// # }
+    /// ```
+    pub fn call_super(&self, name: &str, args: Vec<Value>) -> Result<Value, MrubyError> {
+        extern "C" fn call_super_protected(mrb: *const MrState, data: MrValue) -> MrValue {
+            unsafe {
+                let ptr = data.to_ptr().unwrap();
+                let args = *mem::transmute::<*const u8, *const [*const u8; 4]>(ptr);
+
+                let value = *(args[0] as *const MrValue);
+                let sym: &u32 = mem::transmute(args[1]);
+                let argc: &i32 = mem::transmute(args[2]);
+                let argv: *const MrValue = mem::transmute(args[3]);
+
+                mrb_ext_call_super(mrb, value, *sym, *argc, argv)
+            }
+        }
+
+        check_state(&self.mruby)?;
+
+        unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            let name_str = CString::new(name).unwrap();
+            let sym = mrb_intern(mrb, name_str.as_ptr(), name.len());
+
+            let args: Vec<MrValue> = args.iter().map(|value| value.value).collect();
+
+            let value_ptr: *const u8 = mem::transmute(&self.value);
+            let sym_ptr: *const u8 = mem::transmute(&sym);
+            let argc = args.len();
+            let argc_ptr: * const u8 = mem::transmute(&argc);
+            let argv_ptr: *const u8 = mem::transmute(args.as_ptr());
+
+            let args = [value_ptr, sym_ptr, argc_ptr, argv_ptr];
+            let args_ptr: *const u8 = mem::transmute(&args);
+            let data = MrValue::ptr(mrb, args_ptr);
+
+            let mut state = false;
+
+            let value = mrb_protect(mrb, call_super_protected, data, &mut state as *mut bool);
+
+            if state {
+                Err(MrubyError::runtime(mrb, value))
+            } else {
+                Ok(Value::new(self.mruby.clone(), value))
+            }
+        }
+    }
+
+    /// Returns the number of arguments the method `name` of a `Value` expects, like Ruby's
+    /// `Method#arity`: negative for methods with optional arguments, in which case `-n - 1`
+    /// means `n` are required. Methods defined from Rust accept any arguments and return `-1`.
+    /// Returns an `Err` if the method is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let cont = mruby.run("
+    ///   class Container
+    ///     def add(a, b); end
+    ///     def fill(a, b = 1); end
+    ///   end
+    ///
+    ///   Container.new
+    /// ").unwrap();
+    ///
+    /// assert_eq!(cont.method_arity("add").unwrap(), 2);
+    /// assert_eq!(cont.method_arity("fill").unwrap(), -2);
+    /// assert!(cont.method_arity("missing").is_err());
+    /// ```
+    pub fn method_arity(&self, name: &str) -> Result<i32, MrubyError> {
+        check_state(&self.mruby)?;
+
+        let sym = self.mruby.intern(name).0;
+
+        let method = unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            mrb_ext_method_proc(mrb, self.value, sym)
+        };
 
-    unsafe {
-        let class = if let Ok(class) = class {
-            class
-        } else {
-            let name = name.to_owned();
+        if method.typ == MrType::MRB_TT_FALSE {
+            return Err(MrubyError::Undef);
+        }
 
-            let c_name = CString::new(name.clone()).unwrap();
-            let object = CString::new("Object").unwrap();
-            let object = mrb_class_get(mruby.borrow().mrb, object.as_ptr());
+        Value::new(self.mruby.clone(), method).call("arity", vec![])?.to_i32()
+    }
 
-            let class = get(mruby.borrow().mrb, c_name.as_ptr(), object);
+    /// Returns whether the instance variable `name` is defined on a `Value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class("Container");
+    ///
+    /// let cont = mruby.run("Container.new").unwrap();
+    ///
+    /// assert!(!cont.has_var("value"));
+    /// ```
+    #[inline]
+    pub fn has_var(&self, name: &str) -> bool {
+        check_thread(&self.mruby);
 
-            Class::new(mruby.clone(), class)
-        };
+        unsafe {
+            let name_str = CString::new(name).unwrap();
 
-        mruby.borrow_mut().mruby_methods.insert(class.to_str().to_owned(), HashMap::new());
-        mruby.borrow_mut().mruby_class_methods.insert(class.to_str().to_owned(),
-                                                      HashMap::new());
+            let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
 
-        class
+            mrb_iv_defined(self.mruby.borrow().mrb, self.value, sym)
+        }
     }
-}
 
-#[inline]
-fn get_class_for<T: Any, F>(mruby: &MrubyType, name: &str, get: F) -> Class
-    where F: Fn(*const MrState, *const c_char, *const MrClass) -> *const MrClass {
+    /// Returns the value of the instance variable `name` in a `Some` or `None` if it is not
+    /// defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class("Container");
+    ///
+    /// let cont = mruby.run("Container.new").unwrap();
+    ///
+    /// cont.set_var("value", mruby.fixnum(2));
+    ///
+    /// assert_eq!(cont.get_var("value").unwrap().to_i32().unwrap(), 2);
+    /// assert!(cont.get_var("valup").is_none());
+    /// ```
+    #[inline]
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        check_thread(&self.mruby);
+
+        unsafe {
+            let name_str = CString::new(name).unwrap();
+
+            let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
+
+            if mrb_iv_defined(self.mruby.borrow().mrb, self.value, sym) {
+                Some(Value::new(self.mruby.clone(),
+                                mrb_iv_get(self.mruby.borrow().mrb, self.value, sym)))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Sets the value of the instance variable `name` to `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.def_class("Container");
+    ///
+    /// let cont = mruby.run("Container.new").unwrap();
+    ///
+    /// cont.set_var("value", mruby.fixnum(2));
+    ///
+    /// assert!(cont.has_var("value"));
+    /// assert_eq!(cont.get_var("value").unwrap().to_i32().unwrap(), 2);
+    /// ```
+    /// <br/>
+    ///
+    /// Method panics if called on non-objects.
+    ///
+    /// ```should_panic
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let one = mruby.fixnum(1);
+    ///
+    /// one.set_var("value", mruby.fixnum(2)); // panics because Fixnum cannot have instance vars
+    /// ```
+    #[inline]
+    pub fn set_var(&self, name: &str, value: Value) {
+        check_thread(&self.mruby);
+
+        match self.value.typ {
+            MrType::MRB_TT_OBJECT |
+            MrType::MRB_TT_CLASS |
+            MrType::MRB_TT_MODULE |
+            MrType::MRB_TT_SCLASS |
+            MrType::MRB_TT_HASH |
+            MrType::MRB_TT_DATA |
+            MrType::MRB_TT_EXCEPTION => unsafe {
+                let name_str = CString::new(name).unwrap();
+
+                let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
+
+                mrb_iv_set(self.mruby.borrow().mrb, self.value, sym, value.value)
+            },
+            _ => panic!("Cannot set instance variable on non-object.")
+        }
+    }
+
+    /// Returns a shallow mruby-level copy of a `Value` by calling its `clone`: a distinct object of
+    /// the same `Class` with copied instance variables. Unlike `Clone`, which returns another
+    /// handle to the same object, changes to the copy do not affect the original. Immediate
+    /// values like `Fixnum`, `Float`, `Symbol`, `true`, `false` and `nil` are returned as they
+    /// are, and so are objects reflecting Rust types, whose `clone` and `dup` return `self`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let original = mruby.string("hi");
+    /// let copy = original.shallow_clone().unwrap();
+    ///
+    /// copy.call("<<", vec![mruby.string("!")]).unwrap();
+    ///
+    /// assert_eq!(original.to_str().unwrap(), "hi");
+    /// assert_eq!(copy.to_str().unwrap(), "hi!");
+    ///
+    /// assert_eq!(mruby.fixnum(2).shallow_clone().unwrap().to_i32().unwrap(), 2);
+    /// ```
+    pub fn shallow_clone(&self) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
+
+        match self.value.typ {
+            MrType::MRB_TT_FALSE | MrType::MRB_TT_TRUE | MrType::MRB_TT_FIXNUM |
+            MrType::MRB_TT_SYMBOL | MrType::MRB_TT_FLOAT => Ok(self.clone()),
+            _ => self.call("clone", vec![])
+        }
+    }
+
+    /// Registers a `Value` as a GC root so that it survives while only referenced from Rust, e.g.
+    /// when stored by a method defined with `def_method`. Needs a matching `gc_unregister`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let kept = mruby.string("kept");
+    ///
+    /// kept.gc_register();
+    /// mruby.run("GC.start").unwrap();
+    ///
+    /// assert_eq!(kept.to_str().unwrap(), "kept");
+    ///
+    /// kept.gc_unregister();
+    /// ```
+    #[inline]
+    pub fn gc_register(&self) {
+        check_thread(&self.mruby);
+
+        unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            mrb_gc_register(mrb, self.value);
+        }
+    }
+
+    /// Removes a `Value` registered with `gc_register` from the GC roots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let kept = mruby.string("kept");
+    ///
+    /// kept.gc_register();
+    /// kept.gc_unregister();
+    /// ```
+    #[inline]
+    pub fn gc_unregister(&self) {
+        check_thread(&self.mruby);
+
+        unsafe {
+            mrb_gc_unregister(self.mruby.borrow().mrb, self.value);
+        }
+    }
+
+    /// Returns the mruby `object_id` of a `Value`. IDs are only consistent within the same
+    /// `Mruby`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let array = mruby.run("$a = []").unwrap();
+    /// let same = mruby.run("$a").unwrap();
+    /// let other = mruby.run("[]").unwrap();
+    ///
+    /// assert_eq!(array.object_id(), same.object_id());
+    /// assert!(array.object_id() != other.object_id());
+    /// ```
+    #[inline]
+    pub fn object_id(&self) -> usize {
+        check_thread(&self.mruby);
+
+        unsafe {
+            mrb_obj_id(self.value) as u32 as usize
+        }
+    }
+
+    /// Returns the `Class` of an mruby `Value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let one = mruby.run("1").unwrap();
+    /// assert_eq!(one.class().to_str(), "Fixnum");
+    /// ```
+    #[inline]
+    pub fn class(&self) -> Class {
+        check_thread(&self.mruby);
+
+        unsafe {
+            let class = mrb_ext_class(self.mruby.borrow().mrb, self.value);
+
+            Class::new(self.mruby.clone(), class)
+        }
+    }
+
+    /// Returns the backtrace of an `Exception` `Value`, e.g. one returned by `protected_call`, or
+    /// an empty `Vec` for other `Value`s. mruby rebuilds it from the call stack, so it needs to be
+    /// read before any other method call overwrites the frames the `Exception` was raised from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// mruby.run_with_filename("
+    ///   def fail_deep
+    ///     fail 'deep'
+    ///   end
+    /// ", "deep.rb").unwrap();
+    ///
+    /// let main = mruby.run("self").unwrap();
+    /// let exc = main.protected_call("fail_deep", vec![]).unwrap_err();
+    /// let backtrace = exc.backtrace();
+    ///
+    /// assert_eq!(backtrace[0].file, "deep.rb");
+    /// assert_eq!(backtrace[0].line, 3);
+    /// assert!(mruby.fixnum(1).backtrace().is_empty());
+    /// ```
+    pub fn backtrace(&self) -> Vec<BacktraceEntry> {
+        check_thread(&self.mruby);
+
+        if self.value.typ != MrType::MRB_TT_EXCEPTION {
+            return vec![];
+        }
+
+        unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            BacktraceEntry::read(mrb, self.value)
+        }
+    }
+
+    /// Returns the result of calling `inspect` on a `Value` as a `String`. Never fails: if
+    /// `inspect` raises or does not return a `String`, `"<inspect raised: ClassName>"` is
+    /// returned instead. Invalid UTF-8 is replaced lossily.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let result = mruby.run("[1, 'a', :b, nil]").unwrap();
+    /// assert_eq!(result.inspect(), "[1, \"a\", :b, nil]");
+    ///
+    /// let broken = mruby.run("
+    ///   class Broken
+    ///     def inspect
+    ///       raise 'no'
+    ///     end
+    ///   end
+    ///
+    ///   Broken.new
+    /// ").unwrap();
+    /// assert_eq!(broken.inspect(), "<inspect raised: Broken>");
+    /// ```
+    pub fn inspect(&self) -> String {
+        let inspect = self.call("inspect", vec![]);
 
-    let class = unsafe {
-        let name = name.to_owned();
+        match inspect.as_ref().map(|inspect| inspect.to_bytes()) {
+            Ok(Ok(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+            _             => format!("<inspect raised: {}>", self.class().to_str())
+        }
+    }
 
-        let c_name = CString::new(name.clone()).unwrap();
-        let object = CString::new("Object").unwrap();
-        let object = mrb_class_get(mruby.borrow().mrb, object.as_ptr());
+    /// Returns a debug representation of a `Value`, the same as `inspect`. Meant for logging and
+    /// REPL output, since `to_str` only works on `String`s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.fixnum(3).to_ruby_string(), "3");
+    /// assert_eq!(mruby.string("3").to_ruby_string(), "\"3\"");
+    /// ```
+    #[inline]
+    pub fn to_ruby_string(&self) -> String {
+        self.inspect()
+    }
 
-        let class = get(mruby.borrow().mrb, c_name.as_ptr(), object);
+    /// Returns whether an mruby `Value` is a kind of the `Class` or `Module` named `name`, like
+    /// `is_a?`. Returns `false` if no such `Class` or `Module` is defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let hash = mruby.run("{}").unwrap();
+    ///
+    /// assert!(hash.is_a_named("Hash"));
+    /// assert!(hash.is_a_named("Enumerable"));
+    /// assert!(!hash.is_a_named("Array"));
+    /// assert!(!hash.is_a_named("Missing"));
+    /// ```
+    #[inline]
+    pub fn is_a_named(&self, name: &str) -> bool {
+        check_thread(&self.mruby);
 
-        mrb_ext_set_instance_tt(class, MrType::MRB_TT_DATA);
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_)   => return false
+        };
 
-        extern "C" fn free<T>(_mrb: *const MrState, ptr: *const u8) {
-            unsafe {
-                mem::transmute::<*const u8, Rc<RefCell<T>>>(ptr);
-            }
+        unsafe {
+            mrb_ext_is_a_named(self.mruby.borrow().mrb, self.value, name.as_ptr())
         }
+    }
 
-        let data_type = MrDataType { name: c_name.as_ptr(), free: free::<T> };
-
-        mruby.borrow_mut().classes.insert(TypeId::of::<T>(), (class, data_type, name));
-        mruby.borrow_mut().methods.insert(TypeId::of::<T>(), HashMap::new());
-        mruby.borrow_mut().class_methods.insert(TypeId::of::<T>(), HashMap::new());
-
-        Class::new(mruby.clone(), class)
-    };
-
-    mruby.def_method_for::<T, _>("dup", |_mruby, slf| {
-        slf.clone()
-    });
+    /// Casts a `Value` and returns a `bool` in an `Ok` or an `Err` if the types mismatch.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("
+    ///   def pos(n)
+    ///     n > 0
+    ///   end
+    ///
+    ///   pos 1
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_bool().unwrap(), true);
+    /// ```
+    #[inline]
+    pub fn to_bool(&self) -> Result<bool, MrubyError> {
+        self.to_rust()
+    }
 
-    class
-}
+    /// Casts a `Value` and returns an `i32` in an `Ok` or an `Err` if the types mismatch.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("
+    ///   def fact(n)
+    ///     n > 1 ? fact(n - 1) * n : 1
+    ///   end
+    ///
+    ///   fact 5
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 120);
+    /// ```
+    #[inline]
+    pub fn to_i32(&self) -> Result<i32, MrubyError> {
+        self.to_rust()
+    }
 
-macro_rules! insert_method {
-    ( $mruby:expr, $name:expr, $method:expr, $methods:ident, $key:expr ) => {
-        {
-            let sym = unsafe {
-                let name_str = CString::new($name).unwrap();
+    /// Casts a `Fixnum` or an integral `Float` `Value` and returns an `i64` in an `Ok`. Returns
+    /// an `Err` if the types mismatch, if the `Float` has a fractional part or if it does not fit
+    /// in an `i64`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyError;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.run("2 ** 40").unwrap().to_i64().unwrap(), 1 << 40);
+    ///
+    /// match mruby.run("2.0 ** 70").unwrap().to_i64() {
+    ///     Err(MrubyError::OutOfRange(typ)) => assert_eq!(typ, "i64"),
+    ///     _                                => assert!(false)
+    /// }
+    /// ```
+    #[inline]
+    pub fn to_i64(&self) -> Result<i64, MrubyError> {
+        self.to_rust()
+    }
 
-                mrb_intern($mruby.borrow().mrb, name_str.as_ptr(), $name.len())
-            };
+    /// Casts a `Value` like `to_i64` and returns a `u32`.
+    #[inline]
+    pub fn to_u32(&self) -> Result<u32, MrubyError> {
+        self.to_rust()
+    }
 
-            let mut borrow = $mruby.borrow_mut();
+    /// Casts a `Value` like `to_i64` and returns a `u64`.
+    #[inline]
+    pub fn to_u64(&self) -> Result<u64, MrubyError> {
+        self.to_rust()
+    }
 
-            let methods = match borrow.$methods.get_mut($key) {
-                Some(methods) => methods,
-                None          => panic!("Class not found.")
-            };
+    /// Casts a `Fixnum` or `Float` `Value` and returns an `i64` in an `Ok` or an `Err` if the
+    /// types mismatch. Unlike `to_i64`, `Float`s are truncated and saturate at the bounds of
+    /// `i64`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.float(-2.75).to_i64_lossy().unwrap(), -2);
+    /// assert_eq!(mruby.float(1e30).to_i64_lossy().unwrap(), i64::max_value());
+    /// ```
+    pub fn to_i64_lossy(&self) -> Result<i64, MrubyError> {
+        check_state(&self.mruby)?;
 
-            methods.insert(sym, Rc::new($method));
+        match self.value.typ {
+            MrType::MRB_TT_FIXNUM => Ok(self.to_i32()? as i64),
+            MrType::MRB_TT_FLOAT  => Ok(self.to_f64()? as i64),
+            _                     => Err(MrubyError::Cast("Fixnum or Float".to_owned()))
         }
-    };
-}
-
-macro_rules! callback {
-    ( $name:ident, $methods:ident, $key:expr ) => {
-        extern "C" fn $name<T: Any>(mrb: *const MrState, slf: MrValue) -> MrValue {
-            unsafe {
-                let ptr = mrb_ext_get_ud(mrb);
-                let mruby: MrubyType = mem::transmute(ptr);
-
-                let result = {
-                    let value = Value::new(mruby.clone(), slf);
-
-                    let method = {
-                        let borrow = mruby.borrow();
+    }
 
-                        borrow.$methods.get($key).map(|methods| {
-                            let sym = mrb_ext_get_mid(mrb);
+    /// Returns the length of an mruby `Array` in an `Ok` or an `Err` if the `Value` is not an
+    /// `Array`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.run("[1, 2, 3]").unwrap().array_len().unwrap(), 3);
+    /// ```
+    pub fn array_len(&self) -> Result<usize, MrubyError> {
+        check_state(&self.mruby)?;
 
-                            methods.get(&sym).map(|method| method.clone())
-                        })
-                    };
+        if self.value.typ != MrType::MRB_TT_ARRAY {
+            return Err(MrubyError::Cast("Array".to_owned()));
+        }
 
-                    if let Some(Some(method)) = method {
-                        match panic::catch_unwind(AssertUnwindSafe(|| method(mruby.clone(),
-                                                                             value).value)) {
-                            Ok(value)  => value,
-                            Err(error) => {
-                                let message = match error.downcast_ref::<&'static str>() {
-                                    Some(s) => *s,
-                                    None    => match error.downcast_ref::<String>() {
-                                        Some(s) => &s[..],
-                                        None    => ""
-                                    }
-                                };
+        unsafe {
+            Ok(mrb_ext_ary_len(self.mruby.borrow().mrb, self.value) as usize)
+        }
+    }
 
-                                Mruby::raise(mrb, "RustPanic", message)
-                            }
-                        }
-                    } else {
-                        Mruby::raise(mrb, "TypeError", "Class not found.")
-                    }
-                };
+    /// Returns the element at `index` of an mruby `Array` without converting the whole `Array`.
+    /// Negative indices count from the end and indices out of bounds return `nil`, like
+    /// `Array#[]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let array = mruby.run("[1, 2, 3]").unwrap();
+    ///
+    /// assert_eq!(array.array_get(0).unwrap(), mruby.fixnum(1));
+    /// assert_eq!(array.array_get(-1).unwrap(), mruby.fixnum(3));
+    /// assert_eq!(array.array_get(3).unwrap(), mruby.nil());
+    /// ```
+    pub fn array_get(&self, index: i64) -> Result<Value, MrubyError> {
+        check_state(&self.mruby)?;
 
-                mem::forget(mruby);
+        let len = self.array_len()? as i64;
+        let index = if index < 0 { index + len } else { index };
 
-                result
-            }
+        if index < 0 || index >= len {
+            return Ok(self.mruby.nil());
         }
-    };
-}
-
-macro_rules! mruby_callback {
-    ( $value:expr, class )    => ($value.class().to_str());
-    ( $value:expr, to_class ) => ($value.to_class().unwrap().to_str());
-    ( $name:ident, $methods:ident, $conv:tt ) => {
-        extern "C" fn $name(mrb: *const MrState, slf: MrValue) -> MrValue {
-            unsafe {
-                let ptr = mrb_ext_get_ud(mrb);
-                let mruby: MrubyType = mem::transmute(ptr);
 
-                let result = {
-                    let value = Value::new(mruby.clone(), slf);
+        unsafe {
+            Ok(Value::new(self.mruby.clone(),
+                          mrb_ary_ref(self.mruby.borrow().mrb, self.value, index as MrInt)))
+        }
+    }
 
-                    let method = {
-                        let borrow = mruby.borrow();
+    /// Sets the element at `index` of an mruby `Array` to `value` like `Array#[]=`. Negative
+    /// indices count from the end, while indices past the end grow the `Array` with `nil`s.
+    /// Negative indices before the start return an `Err` with mruby's `IndexError`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let array = mruby.run("[1, 2, 3]").unwrap();
+    ///
+    /// array.array_set(-1, mruby.fixnum(4)).unwrap();
+    /// array.array_set(4, mruby.fixnum(5)).unwrap();
+    ///
+    /// assert_eq!(array.to_vec().unwrap(), vec![mruby.fixnum(1), mruby.fixnum(2),
+    ///                                          mruby.fixnum(4), mruby.nil(), mruby.fixnum(5)]);
+    /// assert!(array.array_set(-6, mruby.nil()).is_err());
+    /// ```
+    pub fn array_set(&self, index: i64, value: Value) -> Result<(), MrubyError> {
+        check_state(&self.mruby)?;
 
-                        borrow.$methods.get(mruby_callback!(value, $conv)).map(|methods| {
-                            let sym = mrb_ext_get_mid(mrb);
+        self.array_len()?;
 
-                            methods.get(&sym).map(|method| method.clone())
-                        })
-                    };
+        let index = MrInt::try_from(index).map_err(|_| {
+            MrubyError::OutOfRange("MrInt".to_owned())
+        })?;
+        let mrb = self.mruby.borrow().mrb;
 
-                    if let Some(Some(method)) = method {
-                        match panic::catch_unwind(AssertUnwindSafe(|| method(mruby.clone(),
-                                                                             value).value)) {
-                            Ok(value)  => value,
-                            Err(error) => {
-                                let message = match error.downcast_ref::<&'static str>() {
-                                    Some(s) => *s,
-                                    None    => match error.downcast_ref::<String>() {
-                                        Some(s) => &s[..],
-                                        None    => ""
-                                    }
-                                };
+        unsafe {
+            protect(mrb, || {
+                mrb_ary_set(mrb, self.value, index, value.value);
 
-                                Mruby::raise(mrb, "RustPanic", message)
-                            }
-                        }
-                    } else {
-                        Mruby::raise(mrb, "TypeError", "Class not found.")
-                    }
-                };
+                MrValue::nil()
+            }).map(|_| ()).map_err(|exc| MrubyError::runtime(mrb, exc))
+        }
+    }
 
-                mem::forget(mruby);
+    /// Casts a `Value` and returns an `f64` in an `Ok` or an `Err` if the types mismatch.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("
+    ///   3 / 2.0
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_f64().unwrap(), 1.5);
+    /// ```
+    #[inline]
+    pub fn to_f64(&self) -> Result<f64, MrubyError> {
+        self.to_rust()
+    }
 
-                result
-            }
-        }
-    };
-}
+    /// Casts mruby `Value` of `Class` `Time` to a `SystemTime`, with microsecond precision. Returns
+    /// an `Err` if the `Value` is not a `Time` or if `Time` is not defined because mruby was built
+    /// without the mruby-time gem.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("Time.at(-1, -250_000)").unwrap();
+    ///
+    /// assert_eq!(result.to_system_time().unwrap(), UNIX_EPOCH - Duration::from_millis(1250));
+    /// ```
+    #[inline]
+    pub fn to_system_time(&self) -> Result<SystemTime, MrubyError> {
+        self.to_rust()
+    }
 
-impl MrubyImpl for MrubyType {
+    /// Casts a `Float` or `Fixnum` `Value` of seconds to a `Duration`, rounded to microseconds
+    /// like `Time`. Returns an `Err` if the types mismatch or if the seconds are negative.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::time::Duration;
+    ///
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("0.000_001 + 2").unwrap();
+    ///
+    /// assert_eq!(result.to_duration().unwrap(), Duration::new(2, 1_000));
+    /// ```
     #[inline]
-    fn filename(&self, filename: &str) {
-        self.borrow_mut().filename = Some(filename.to_owned());
+    pub fn to_duration(&self) -> Result<Duration, MrubyError> {
+        self.to_rust()
+    }
+
+    /// Casts a `Value` and returns a `&str` in an `Ok` or an `Err` if the types mismatch.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("
+    ///   [1, 2, 3].map(&:to_s).join
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "123");
+    /// ```
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run(":symbol").unwrap();
+    ///
+    /// assert_eq!(result.to_str().unwrap(), "symbol");
+    /// ```
+    #[inline]
+    pub fn to_str<'a>(&self) -> Result<&'a str, MrubyError> {
+        check_state(&self.mruby)?;
 
         unsafe {
-            let filename_str = CString::new(filename).unwrap();
+            let mrb = self.mruby.borrow().mrb;
 
-            mrbc_filename(self.borrow().mrb, self.borrow().ctx, filename_str.as_ptr());
+            self.value.to_str(mrb)
         }
     }
 
+    /// Casts mruby `Value` of `Class` `String` and returns its bytes in an `Ok` or an `Err` if
+    /// the types mismatch. Unlike `to_str`, the bytes do not need to be valid UTF-8.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("\"caf\\xff\"").unwrap();
+    ///
+    /// assert_eq!(result.to_bytes().unwrap(), b"caf\xff");
+    /// ```
     #[inline]
-    fn run(&self, script: &str) -> Result<Value, MrubyError> {
-        extern "C" fn run_protected(mrb: *const MrState, data: MrValue) -> MrValue {
-            unsafe {
-                let ptr = data.to_ptr().unwrap();
-                let args = *mem::transmute::<*const u8, *const [*const u8; 3]>(ptr);
-
-                let script_len: &i32 = mem::transmute(args[1]);
-                let ctx: *const MrContext = mem::transmute(args[2]);
-
-                let result = mrb_load_nstring_cxt(mrb, args[0], *script_len, ctx);
-
-                mrb_ext_raise_current(mrb);
-
-                result
-            }
-        }
+    pub fn to_bytes(&self) -> Result<&[u8], MrubyError> {
+        check_state(&self.mruby)?;
 
         unsafe {
-            let (mrb, ctx) = {
-                let borrow = self.borrow();
-
-                (borrow.mrb, borrow.ctx)
-            };
-
-            let script_ptr = script.as_ptr();
-            let script_len = script.len();
-            let script_len_ptr: *const u8 = mem::transmute(&script_len);
-            let ctx_ptr: *const u8 = mem::transmute(ctx);
-
-            let args = [script_ptr, script_len_ptr, ctx_ptr];
-            let args_ptr: *const u8 = mem::transmute(&args);
-            let data = MrValue::ptr(mrb, args_ptr);
-
-            let state = mem::uninitialized::<bool>();
-
-            let value = mrb_protect(mrb, run_protected, data, &state as *const bool);
-
-            if state {
-                let str = mrb_ext_exc_str(mrb, value).to_str(mrb).unwrap();
-
-                Err(MrubyError::Runtime(str.to_owned()))
-            } else {
-                Ok(Value::new(self.clone(), value))
-            }
+            self.value.to_bytes()
         }
     }
 
+    /// Returns the length in bytes of an mruby `String` in an `Ok` or an `Err` if the `Value` is
+    /// not a `String`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.string("café").string_len().unwrap(), 5);
+    /// ```
     #[inline]
-    unsafe fn run_unchecked(&self, script: &str) -> Value {
-        let (mrb, ctx) = {
-            let borrow = self.borrow();
+    pub fn string_len(&self) -> Result<usize, MrubyError> {
+        self.to_bytes().map(|bytes| bytes.len())
+    }
 
-            (borrow.mrb, borrow.ctx)
-        };
+    /// Casts mruby `Value` of `Class` `String` to a `PathBuf`. On Unix the `String`'s bytes are
+    /// used as they are, while on other platforms they need to be valid UTF-8. `String`s
+    /// containing NUL bytes cannot be paths and return an `Err`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::path::Path;
+    ///
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("'scripts/' + 'main.rb'").unwrap();
+    ///
+    /// assert_eq!(result.to_path_buf().unwrap(), Path::new("scripts/main.rb"));
+    /// ```
+    pub fn to_path_buf(&self) -> Result<PathBuf, MrubyError> {
+        check_state(&self.mruby)?;
 
-        let value = mrb_load_nstring_cxt(mrb, script.as_ptr(), script.len() as i32, ctx);
+        if self.value.typ != MrType::MRB_TT_STRING {
+            return Err(MrubyError::Cast("String".to_owned()));
+        }
 
-        Value::new(self.clone(), value)
+        path_from_bytes(self.to_bytes()?)
     }
 
+    /// Casts mruby `Value` of `Class` `name` to Rust type `Rc<T>`.
+    ///
+    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let value = mruby.obj(Cont { value: 3 });
+    /// let cont = value.to_obj::<Cont>().unwrap();
+    /// let cont = cont.borrow();
+    ///
+    /// assert_eq!(cont.value, 3);
+    /// ```
     #[inline]
-    fn runb(&self, script: &[u8]) -> Result<Value, MrubyError> {
-        extern "C" fn runb_protected(mrb: *const MrState, data: MrValue) -> MrValue {
-            unsafe {
-                let ptr = data.to_ptr().unwrap();
-                let args = *mem::transmute::<*const u8, *const [*const u8; 2]>(ptr);
-
-                let ctx: *const MrContext = mem::transmute(args[1]);
-
-                let result = mrb_load_irep_cxt(mrb, args[0], ctx);
-
-                mrb_ext_raise_current(mrb);
+    pub fn to_obj<T: Any>(&self) -> Result<Rc<RefCell<T>>, MrubyError> {
+        check_state(&self.mruby)?;
 
-                result
-            }
-        }
+        // Naming the Class may allocate, so it happens before borrowing.
+        let self_class = self.class();
 
         unsafe {
-            let (mrb, ctx) = {
-                let borrow = self.borrow();
+            let borrow = self.mruby.borrow();
 
-                (borrow.mrb, borrow.ctx)
+            let class = match borrow.classes.get(&TypeId::of::<T>()) {
+                Some(class) => class,
+                None        => {
+                    return Err(unregistered::<T>("cannot convert a Value"))
+                }
             };
 
-            let script_ptr = script.as_ptr();
-            let ctx_ptr: *const u8 = mem::transmute(ctx);
-
-            let args = [script_ptr, ctx_ptr];
-            let args_ptr: *const u8 = mem::transmute(&args);
-            let data = MrValue::ptr(mrb, args_ptr);
-
-            let state = mem::uninitialized::<bool>();
-
-            let value = mrb_protect(mrb, runb_protected, data, &state as *const bool);
-
-            if state {
-                let str = mrb_ext_exc_str(mrb, value).to_str(mrb).unwrap();
-
-                Err(MrubyError::Runtime(str.to_owned()))
-            } else {
-                Ok(Value::new(self.clone(), value))
+            if self_class.to_str() != class.2 {
+                return Err(MrubyError::Undef)
             }
+
+            self.value.to_obj::<T>(borrow.mrb, &class.1)
         }
     }
 
-    #[inline]
-    fn execute(&self, script: &Path) -> Result<Value, MrubyError> {
-        match script.extension() {
-            Some(ext) => {
-                self.filename(script.file_name().unwrap().to_str().unwrap());
+    /// Casts mruby `Value` of a trait object `Class` to its concrete Rust type `T`. Returns a
+    /// `Cast` error naming the type it holds instead if it is not a `T`.
+    ///
+    /// *Note:* The `Class` must be defined for `Box<Trait>` with `def_class_for` and
+    /// `def_downcast_for`. See `MrubyAny` for an example.
+    pub fn to_obj_downcast<T: Any>(&self) -> Result<Downcast<T>, MrubyError> {
+        check_state(&self.mruby)?;
 
-                let mut file = try!(File::open(script));
+        let class = self.class();
+        let class = class.to_str();
 
-                match ext.to_str().unwrap() {
-                    "rb" => {
-                        let mut script = String::new();
-                        try!(file.read_to_string(&mut script));
+        let any_cell = match self.mruby.borrow().downcasts.get(class) {
+            Some(&any_cell) => any_cell,
+            None            => return Err(MrubyError::Undef)
+        };
 
-                        self.run(&script)
-                    },
-                    "mrb" => {
-                        let mut script = Vec::new();
-                        try!(file.read_to_end(&mut script));
+        let cell = any_cell(self)?;
 
-                        self.runb(&script)
-                    },
-                    _ => {
-                        Err(MrubyError::Filetype)
-                    }
-                }
-            },
-            None => Err(MrubyError::Filetype)
+        // A mutably borrowed object is only checked once it is borrowed as a T.
+        if let Some(name) = cell.concrete_name() {
+            if !cell.borrow_any().0.is::<T>() {
+                return Err(downcast_error::<T>(name));
+            }
         }
+
+        Ok(Downcast { cell: cell, phantom: PhantomData })
     }
 
+    /// Casts mruby `Value` of `Class` `name` to Rust `Option` of `Rc<T>`.
+    ///
+    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// struct Cont {
+    ///     value: i32
+    /// }
+    ///
+    /// mruby.def_class_for::<Cont>("Container");
+    ///
+    /// let value = mruby.obj(Cont { value: 3 });
+    /// let cont = value.to_option::<Cont>().unwrap().unwrap();
+    /// let cont = cont.borrow();
+    ///
+    /// assert_eq!(cont.value, 3);
+    /// assert!(mruby.nil().to_option::<Cont>().unwrap().is_none());
+    /// ```
     #[inline]
-    fn is_defined(&self, name: &str) -> bool {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+    pub fn to_option<T: Any>(&self) -> Result<Option<Rc<RefCell<T>>>, MrubyError> {
+        check_state(&self.mruby)?;
 
-            mrb_class_defined(self.borrow().mrb, name_str.as_ptr())
+        if self.value.typ == MrType::MRB_TT_DATA {
+            self.to_obj::<T>().map(|obj| Some(obj))
+        } else {
+            Ok(None)
         }
     }
 
+    /// Casts mruby `Value` of `Class` `Array` to Rust type `Vec<Value>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("
+    ///   [1, 2, 3].map(&:to_s)
+    /// ").unwrap();
+    ///
+    /// assert_eq!(result.to_vec().unwrap(), vec![
+    ///     mruby.string("1"),
+    ///     mruby.string("2"),
+    ///     mruby.string("3")
+    /// ]);
+    /// ```
+    #[inline]
+    pub fn to_vec(&self) -> Result<Vec<Value>, MrubyError> {
+        self.to_rust()
+    }
+
+    /// Casts a `Value` of `Class` `Hash` and returns a `HashMap` in an `Ok` or an `Err` if the
+    /// types mismatch. Keys can be `String`s or `Symbol`s; any other key is an `Err`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("{ width: 640, 'title' => 'mrusty' }").unwrap();
+    ///
+    /// let map = result.to_map().unwrap();
+    ///
+    /// assert_eq!(map["width"], mruby.fixnum(640));
+    /// assert_eq!(map["title"], mruby.string("mrusty"));
+    /// ```
     #[inline]
-    fn is_defined_under<T: ClassLike>(&self, name: &str, outer: &T) -> bool {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr())
-        }
+    pub fn to_map(&self) -> Result<HashMap<String, Value>, MrubyError> {
+        self.to_rust()
     }
 
+    /// Casts a `Value` of `Class` `Hash` like `to_map` and returns a `BTreeMap`, sorted by key.
     #[inline]
-    fn get_class(&self, name: &str) -> Result<Class, MrubyError> {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            if mrb_class_defined(self.borrow().mrb, name_str.as_ptr()) {
-                let class = mrb_class_get(self.borrow().mrb, name_str.as_ptr());
-
-                Ok(Class::new(self.clone(), class))
-            } else {
-                Err(MrubyError::Undef)
-            }
-        }
+    pub fn to_btree_map(&self) -> Result<BTreeMap<String, Value>, MrubyError> {
+        self.to_rust()
     }
 
+    /// Casts a `Value` to any Rust type implementing `FromValue`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// use std::collections::HashMap;
+    ///
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("{ a: [1, nil], 'b' => [] }").unwrap();
+    ///
+    /// let map = result.to_rust::<HashMap<String, Vec<Option<i32>>>>().unwrap();
+    ///
+    /// assert_eq!(map["a"], vec![Some(1), None]);
+    /// assert!(map["b"].is_empty());
+    /// ```
+    /// Deep-copies a plain-data `Value` into a `ValueSnapshot` which can be sent to other
+    /// threads. Returns an `Err` if the `Value` contains anything else, like objects.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::ValueSnapshot;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("[1, 'two', :three, nil]").unwrap();
+    ///
+    /// assert_eq!(result.to_snapshot().unwrap(), ValueSnapshot::Array(vec![
+    ///     ValueSnapshot::Fixnum(1),
+    ///     ValueSnapshot::String("two".to_owned()),
+    ///     ValueSnapshot::Symbol("three".to_owned()),
+    ///     ValueSnapshot::Nil
+    /// ]));
+    ///
+    /// assert!(mruby.run("[Object.new]").unwrap().to_snapshot().is_err());
+    /// ```
     #[inline]
-    fn get_class_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Class, MrubyError> {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            if mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr()) {
-                let class = mrb_class_get_under(self.borrow().mrb, outer.class(),
-                                                name_str.as_ptr());
-
-                Ok(Class::new(self.clone(), class))
-            } else {
-                Err(MrubyError::Undef)
-            }
-        }
+    pub fn to_snapshot(&self) -> Result<ValueSnapshot, MrubyError> {
+        self.to_rust()
     }
 
     #[inline]
-    fn get_module(&self, name: &str) -> Result<Module, MrubyError> {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            if mrb_class_defined(self.borrow().mrb, name_str.as_ptr()) {
-                let class = mrb_module_get(self.borrow().mrb, name_str.as_ptr());
+    pub fn to_rust<T: FromValue>(&self) -> Result<T, MrubyError> {
+        check_state(&self.mruby)?;
 
-                Ok(Module::new(self.clone(), class))
-            } else {
-                Err(MrubyError::Undef)
-            }
-        }
+        T::from_value(self)
     }
 
-    #[inline]
-    fn get_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Result<Module, MrubyError> {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            if mrb_ext_class_defined_under(self.borrow().mrb, outer.class(), name_str.as_ptr()) {
-                let class = mrb_module_get_under(self.borrow().mrb, outer.class(),
-                                                 name_str.as_ptr());
+    /// Encodes a `Value` as JSON. `Symbol`s are encoded as strings, while values with no JSON
+    /// counterpart, like `Proc`s, return an `Err` naming their `Class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("{ name: :box, sizes: [1, 2.5], tag: nil }").unwrap();
+    ///
+    /// assert_eq!(result.to_json().unwrap(), r#"{"name":"box","sizes":[1,2.5],"tag":null}"#);
+    ///
+    /// let result = mruby.run("[proc {}]").unwrap();
+    ///
+    /// assert_eq!(result.to_json().err().unwrap().to_string(),
+    ///            "JSON error: Proc cannot be converted to JSON");
+    /// ```
+    pub fn to_json(&self) -> Result<String, MrubyError> {
+        check_state(&self.mruby)?;
 
-                Ok(Module::new(self.clone(), class))
-            } else {
-                Err(MrubyError::Undef)
-            }
-        }
+        json::to_json(self)
     }
 
-    fn def_file<T: MrubyFile>(&self, name: &str) {
-        let mut borrow = self.borrow_mut();
+    /// Encodes a `Value` as JSON. Same as `to_json`, which is written in Rust and does not need
+    /// a JSON gem in the mruby build.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("[1, 'two', { three: 3.0 }]").unwrap();
+    ///
+    /// assert_eq!(result.to_json_string().unwrap(), r#"[1,"two",{"three":3.0}]"#);
+    /// ```
+    #[inline]
+    pub fn to_json_string(&self) -> Result<String, MrubyError> {
+        self.to_json()
+    }
 
-        if borrow.files.contains_key(name) {
-            let mut file = borrow.files.get_mut(name).unwrap();
+    /// Casts mruby `Value` of `Class` `Range` and returns its start, its end and whether the end
+    /// is excluded in an `Ok`, or an `Err` if the types mismatch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("'a'...'z'").unwrap();
+    ///
+    /// let (start, end, exclusive) = result.to_range().unwrap();
+    ///
+    /// assert_eq!(start.to_str().unwrap(), "a");
+    /// assert_eq!(end.to_str().unwrap(), "z");
+    /// assert_eq!(exclusive, true);
+    /// ```
+    pub fn to_range(&self) -> Result<(Value, Value, bool), MrubyError> {
+        check_state(&self.mruby)?;
 
-            file.push(T::require);
-        } else {
-            borrow.files.insert(name.to_owned(), vec![T::require]);
+        if self.value.typ != MrType::MRB_TT_RANGE {
+            return Err(MrubyError::Cast("Range".to_owned()));
         }
-    }
 
-    fn def_class(&self, name: &str) -> Class {
-        get_class(self, name, self.get_class(name), |mrb: *const MrState, name: *const c_char,
-                                                     object: *const MrClass| {
-            unsafe { mrb_define_class(mrb, name, object) }
-        })
-    }
-
-    fn def_class_under<U: ClassLike>(&self, name: &str, outer: &U) -> Class {
-        get_class(self, name, self.get_class_under(name, outer),
-                  |mrb: *const MrState, name: *const c_char,
-                   object: *const MrClass| {
-            unsafe { mrb_define_class_under(mrb, outer.class(), name, object) }
-        })
+        unsafe {
+            Ok((Value::new(self.mruby.clone(), mrb_ext_range_beg(self.value)),
+                Value::new(self.mruby.clone(), mrb_ext_range_end(self.value)),
+                mrb_ext_range_excl(self.value)))
+        }
     }
 
-    fn def_class_for<T: Any>(&self, name: &str) -> Class {
-        get_class_for::<T, _>(self, name, |mrb: *const MrState, name: *const c_char,
-                                        object: *const MrClass| {
-            unsafe { mrb_define_class(mrb, name, object) }
-        })
+    /// Casts mruby `Value` of `Class` `Range` with `Fixnum` ends to a Rust `Range<i32>`. Only
+    /// exclusive (`a...b`) `Range`s match Rust's `a..b`; inclusive ones return an `Err` and can
+    /// be read with `to_rust::<RangeInclusive<i32>>` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// assert_eq!(mruby.run("3...10").unwrap().to_i32_range().unwrap(), 3..10);
+    /// assert!(mruby.run("3..10").unwrap().to_i32_range().is_err());
+    /// ```
+    #[inline]
+    pub fn to_i32_range(&self) -> Result<Range<i32>, MrubyError> {
+        self.to_rust()
     }
 
-    fn def_class_under_for<T: Any, U: ClassLike>(&self, name: &str, outer: &U) -> Class {
-        get_class_for::<T, _>(self, name, |mrb: *const MrState, name: *const c_char,
-                                        object: *const MrClass| {
-            unsafe { mrb_define_class_under(mrb, outer.class(), name, object) }
-        })
-    }
+    /// Casts mruby `Value` of `Class` `Class` to Rust type `Class`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("Object").unwrap();
+    ///
+    /// assert_eq!(result.to_class().unwrap().to_str(), "Object");
+    /// ```
+    #[inline]
+    pub fn to_class(&self) -> Result<Class, MrubyError> {
+        check_state(&self.mruby)?;
 
-    fn def_module(&self, name: &str) -> Module {
         unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            let module = mrb_define_module(self.borrow().mrb, name_str.as_ptr());
+            let class = try!(self.value.to_class());
 
-            Module::new(self.clone(), module)
+            Ok(Class::new(self.mruby.clone(), class))
         }
     }
 
-    fn def_module_under<T: ClassLike>(&self, name: &str, outer: &T) -> Module {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+    /// Casts mruby `Value` of `Class` `Module` to Rust type `Module`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    /// let result = mruby.run("Kernel").unwrap();
+    ///
+    /// assert_eq!(result.to_module().unwrap().to_str(), "Kernel");
+    /// ```
+    #[inline]
+    pub fn to_module(&self) -> Result<Module, MrubyError> {
+        check_state(&self.mruby)?;
 
-            let module = mrb_define_module_under(self.borrow().mrb, outer.class(),
-                                                 name_str.as_ptr());
+        unsafe {
+            let module = try!(self.value.to_module());
 
-            Module::new(self.clone(), module)
+            Ok(Module::new(self.mruby.clone(), module))
         }
     }
+}
 
-    fn def_method<F>(&self, class: Class, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static {
-
-        insert_method!(self, name, method, mruby_methods, class.to_str());
+use std::fmt;
 
-        mruby_callback!(call_mruby_method, mruby_methods, class);
+impl Clone for Value {
+    fn clone(&self) -> Value {
+        if self.value.typ == MrType::MRB_TT_DATA {
+            unsafe {
+                let ptr = mrb_ext_data_ptr(self.value);
+                let rc: Rc<c_void> = mem::transmute(ptr);
 
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+                rc.clone();
 
-            mrb_define_method(self.borrow().mrb, class.class, name_str.as_ptr(),
-                              call_mruby_method, 1 << 12);
+                mem::forget(rc);
+            }
         }
-    }
-
-    fn def_class_method<F>(&self, class: Class, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static {
-
-        insert_method!(self, name, method, mruby_class_methods, class.to_str());
 
-        mruby_callback!(call_mruby_class_method, mruby_class_methods, to_class);
+        Value::new(self.mruby.clone(), self.value.clone())
+    }
+}
 
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+impl PartialEq<Value> for Value {
+    fn eq(&self, other: &Value) -> bool {
+        // Values of different Mruby instances cannot be passed to each other.
+        if !Rc::ptr_eq(&self.mruby, &other.mruby) {
+            return false;
+        }
 
-            mrb_define_class_method(self.borrow().mrb, class.class, name_str.as_ptr(),
-                                    call_mruby_class_method, 1 << 12);
+        match self.call1("==", other.clone()) {
+            Ok(result) => result.to_bool().unwrap_or(false),
+            Err(_)     => false
         }
     }
+}
 
-    fn def_method_for<T: Any, F>(&self, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static {
-
-        insert_method!(self, name, method, methods, &TypeId::of::<T>());
-
-        callback!(call_method, methods, &TypeId::of::<T>());
-
-        let borrow = self.borrow();
-
-        let class = match borrow.classes.get(&TypeId::of::<T>()) {
-            Some(class) => class,
-            None       => panic!("Class not found.")
-        };
+/// Formats a `Value` with mruby's `inspect`. Never fails, see `Value::inspect`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// let mruby = Mruby::new();
+///
+/// let result = mruby.run("[1, 'a', :b]").unwrap();
+///
+/// assert_eq!(format!("{:?}", result), "[1, \"a\", :b]");
+/// ```
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.inspect())
+    }
+}
 
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+/// Formats a `Value` with mruby's `to_s`, falling back to `inspect` if `to_s` raises or does not
+/// return a `String`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// let mruby = Mruby::new();
+///
+/// assert_eq!(format!("{}", mruby.string("a")), "a");
+/// assert_eq!(format!("{}", mruby.run("[1, :b]").unwrap()), "[1, :b]");
+/// assert_eq!(format!("{}", mruby.nil()), "");
+/// ```
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let to_s = self.call("to_s", vec![]);
 
-            mrb_define_method(borrow.mrb, class.0, name_str.as_ptr(), call_method::<T>, 1 << 12);
+        match to_s.as_ref().map(|to_s| to_s.to_bytes()) {
+            Ok(Ok(bytes)) => write!(f, "{}", String::from_utf8_lossy(bytes)),
+            _             => write!(f, "{}", self.inspect())
         }
     }
+}
 
-    fn def_class_method_for<T: Any, F>(&self, name: &str, method: F)
-        where F: Fn(MrubyType, Value) -> Value + 'static {
-
-        insert_method!(self, name, method, class_methods, &TypeId::of::<T>());
-
-        callback!(call_class_method, class_methods, &TypeId::of::<T>());
-
-        let borrow = self.borrow();
-
-        let class = match borrow.classes.get(&TypeId::of::<T>()) {
-            Some(class) => class,
-            None       => panic!("Class not found.")
-        };
+/// A `trait` for Rust types that can be converted to an mruby `Value` with `mruby.value`.
+/// Implemented for `bool`, integers that fit in a `Fixnum`, floats, strings, paths, `Option`,
+/// `Vec`, `HashMap` and `Rc<RefCell<T>>` of types defined with `def_class_for`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// use mrusty::{MrubyType, ToValue, Value};
+///
+/// struct Point(i32, i32);
+///
+/// impl ToValue for Point {
+///     fn to_value(&self, mruby: &MrubyType) -> Value {
+///         mruby.value(vec![self.0, self.1])
+///     }
+/// }
+///
+/// let mruby = Mruby::new();
+/// let points = mruby.value(vec![Point(1, 2), Point(3, 4)]);
+///
+/// assert_eq!(points.call("flatten", vec![]).unwrap().to_rust::<Vec<i32>>().unwrap(),
+///            vec![1, 2, 3, 4]);
+/// ```
+pub trait ToValue {
+    /// Converts `self` to a `Value` of `mruby`.
+    fn to_value(&self, mruby: &MrubyType) -> Value;
+}
 
-        unsafe {
-            let name_str = CString::new(name).unwrap();
+/// A `trait` for Rust types that can be converted from an mruby `Value` with `value.to_rust`.
+/// Implemented for the same types as `ToValue`, returning an `Err` if the types mismatch.
+pub trait FromValue: Sized {
+    /// Converts `value` to `Self`.
+    fn from_value(value: &Value) -> Result<Self, MrubyError>;
+}
 
-            mrb_define_class_method(borrow.mrb, class.0, name_str.as_ptr(),
-                                    call_class_method::<T>, 1 << 12);
-        }
+impl<T: ToValue + ?Sized> ToValue for &T {
+    #[inline]
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        (**self).to_value(mruby)
     }
+}
 
+impl ToValue for Value {
     #[inline]
-    fn class_name_for<T: Any>(&self) -> Result<String, MrubyError> {
-        let borrow = self.borrow();
-
-        match borrow.classes.get(&TypeId::of::<T>()) {
-            Some(class) => Ok(class.2.clone()),
-            None        => Err(MrubyError::Undef)
-        }
+    fn to_value(&self, _mruby: &MrubyType) -> Value {
+        self.clone()
     }
+}
 
+impl FromValue for Value {
     #[inline]
-    fn nil(&self) -> Value {
-        unsafe {
-            Value::new(self.clone(), MrValue::nil())
-        }
+    fn from_value(value: &Value) -> Result<Value, MrubyError> {
+        Ok(value.clone())
     }
+}
 
+impl ToValue for () {
     #[inline]
-    fn bool(&self, value: bool) -> Value {
-        unsafe {
-            Value::new(self.clone(), MrValue::bool(value))
-        }
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        mruby.nil()
     }
+}
 
+impl ToValue for bool {
     #[inline]
-    fn fixnum(&self, value: i32) -> Value {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
         unsafe {
-            Value::new(self.clone(), MrValue::fixnum(value))
+            Value::new(mruby.clone(), MrValue::bool(*self))
         }
     }
+}
 
+impl FromValue for bool {
     #[inline]
-    fn float(&self, value: f64) -> Value {
+    fn from_value(value: &Value) -> Result<bool, MrubyError> {
         unsafe {
-            Value::new(self.clone(), MrValue::float(self.borrow().mrb, value))
+            value.value.to_bool()
         }
     }
+}
 
+impl ToValue for i32 {
     #[inline]
-    fn string(&self, value: &str) -> Value {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
         unsafe {
-            Value::new(self.clone(), MrValue::string(self.borrow().mrb, value))
+            Value::new(mruby.clone(), MrValue::fixnum(*self))
         }
     }
+}
 
+impl FromValue for i32 {
     #[inline]
-    fn symbol(&self, value: &str) -> Value {
+    fn from_value(value: &Value) -> Result<i32, MrubyError> {
         unsafe {
-            Value::new(self.clone(), MrValue::symbol(self.borrow().mrb, value))
+            value.value.to_i32()
         }
     }
+}
 
-    #[inline]
-    fn obj<T: Any>(&self, obj: T) -> Value {
-        let borrow = self.borrow();
+macro_rules! fixnum_conversions {
+    ( @narrow $( $t:ty ),* ) => {
+        $(
+            impl ToValue for $t {
+                #[inline]
+                fn to_value(&self, mruby: &MrubyType) -> Value {
+                    (*self as MrInt).to_value(mruby)
+                }
+            }
 
-        let class = match borrow.classes.get(&TypeId::of::<T>()) {
-            Some(class) => class,
-            None       => panic!("Class not found.")
-        };
+            impl FromValue for $t {
+                #[inline]
+                fn from_value(value: &Value) -> Result<$t, MrubyError> {
+                    <$t as TryFrom<MrInt>>::try_from(value.to_i32()?).map_err(|_| {
+                        MrubyError::OutOfRange(stringify!($t).to_owned())
+                    })
+                }
+            }
+        )*
+    };
+    ( @wide $( $t:ty ),* ) => {
+        $(
+            impl ToValue for $t {
+                #[inline]
+                fn to_value(&self, mruby: &MrubyType) -> Value {
+                    match MrInt::try_from(*self) {
+                        Ok(fixnum) => fixnum.to_value(mruby),
+                        Err(_)     => (*self as f64).to_value(mruby)
+                    }
+                }
+            }
 
-        unsafe {
-            Value::new(self.clone(), MrValue::obj(borrow.mrb, class.0 as *const MrClass, obj,
-                                                  &class.1))
-        }
-    }
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> Result<$t, MrubyError> {
+                    if value.value.typ != MrType::MRB_TT_FLOAT {
+                        return <$t as TryFrom<MrInt>>::try_from(value.to_i32()?).map_err(|_| {
+                            MrubyError::OutOfRange(stringify!($t).to_owned())
+                        });
+                    }
+
+                    let float = value.to_f64()?;
+
+                    if float.fract() != 0.0 && float.is_finite() {
+                        return Err(MrubyError::Cast("integral Float".to_owned()));
+                    }
+
+                    // MAX as f64 rounds up to the next power of two for 64-bit types, which is
+                    // exactly the first value out of range.
+                    if float >= <$t>::min_value() as f64 && float < <$t>::max_value() as f64 + 1.0 {
+                        Ok(float as $t)
+                    } else {
+                        Err(MrubyError::OutOfRange(stringify!($t).to_owned()))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+// Integers that always fit in a Fixnum are converted directly. Wider ones become a Float past the
+// Fixnum range, just like mruby's own arithmetic, and are read back from either. Converting back
+// always checks the range instead of wrapping.
+fixnum_conversions!(@narrow i8, i16, u8, u16);
+fixnum_conversions!(@wide u32, i64, u64, isize, usize);
 
+impl ToValue for f64 {
     #[inline]
-    fn option<T: Any>(&self, obj: Option<T>) -> Value {
-        match obj {
-            Some(obj) => self.obj(obj),
-            None      => self.nil()
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        unsafe {
+            Value::new(mruby.clone(), MrValue::float(mruby.borrow().mrb, *self))
         }
     }
+}
 
+impl FromValue for f64 {
     #[inline]
-    fn array(&self, value: Vec<Value>) -> Value {
-        let array: Vec<MrValue> = value.iter().map(|value| {
-            value.value
-        }).collect();
-
+    fn from_value(value: &Value) -> Result<f64, MrubyError> {
         unsafe {
-            Value::new(self.clone(), MrValue::array(self.borrow().mrb, array))
+            value.value.to_f64()
         }
     }
 }
 
-impl Drop for Mruby {
-    fn drop(&mut self) {
-        self.close();
+impl ToValue for f32 {
+    #[inline]
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        (*self as f64).to_value(mruby)
     }
 }
 
-/// A `struct` that wraps around any mruby variable.
-///
-/// `Values` are created from the `Mruby` instance:
-///
-/// * [`nil`](../mrusty/trait.MrubyImpl.html#tymethod.nil)
-/// * [`bool`](../mrusty/trait.MrubyImpl.html#tymethod.bool)
-/// * [`fixnum`](../mrusty/trait.MrubyImpl.html#tymethod.fixnum)
-/// * [`float`](../mrusty/trait.MrubyImpl.html#tymethod.float)
-/// * [`string`](../mrusty/trait.MrubyImpl.html#tymethod.string)
-/// * [`obj`](../mrusty/trait.MrubyImpl.html#tymethod.obj)
-/// * [`option`](../mrusty/trait.MrubyImpl.html#tymethod.option)
-/// * [`array`](../mrusty/trait.MrubyImpl.html#tymethod.array)
-///
-/// # Examples
-///
-/// ```
-/// # use mrusty::Mruby;
-/// # use mrusty::MrubyImpl;
-/// let mruby = Mruby::new();
-/// let result = mruby.run("true").unwrap(); // Value
-///
-/// // Values need to be unwrapped in order to make sure they have the right mruby type.
-/// assert_eq!(result.to_bool().unwrap(), true);
-/// ```
-pub struct Value {
-    mruby: MrubyType,
-    value: MrValue
-}
-
-impl Value {
-    /// Not meant to be called directly.
-    #[doc(hidden)]
-    pub fn new(mruby: MrubyType, value: MrValue) -> Value {
-        Value {
-            mruby: mruby,
-            value: value
-        }
+impl FromValue for f32 {
+    #[inline]
+    fn from_value(value: &Value) -> Result<f32, MrubyError> {
+        value.to_f64().map(|value| value as f32)
     }
+}
 
-    /// Initializes the `self` mruby object passed to `initialize` with a Rust object of type `T`.
-    ///
-    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # #[macro_use] extern crate mrusty;
-    /// use mrusty::{Mruby, MrubyImpl};
-    ///
-    /// # fn main() {
-    /// let mruby = Mruby::new();
-    ///
-    /// struct Cont {
-    ///     value: i32
-    /// };
-    ///
-    /// mruby.def_class_for::<Cont>("Container");
-    /// mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
-    ///     let cont = Cont { value: v };
-    ///
-    ///     slf.init(cont) // Return the same slf value.
-    /// }));
-    ///
-    /// let result = mruby.run("Container.new 3").unwrap();
-    /// let result = result.to_obj::<Cont>().unwrap();
-    /// let result = result.borrow();
-    ///
-    /// assert_eq!(result.value, 3);
-    /// # }
-    /// ```
-    pub fn init<T: Any>(self, obj: T) -> Value {
+impl ToValue for str {
+    #[inline]
+    fn to_value(&self, mruby: &MrubyType) -> Value {
         unsafe {
-            let rc = Rc::new(RefCell::new(obj));
-            let ptr: *const u8 = mem::transmute(rc);
-
-            let borrow = self.mruby.borrow();
-
-            let class = match borrow.classes.get(&TypeId::of::<T>()) {
-                Some(class) => class,
-                None       => panic!("Class not found.")
-            };
+            let mrb = mruby.borrow().mrb;
 
-            let data_type = &class.1;
-
-            mrb_ext_data_init(&self.value as *const MrValue, ptr, data_type as *const MrDataType);
+            Value::new(mruby.clone(), MrValue::string(mrb, self))
         }
-
-        self
     }
+}
 
-    /// Calls method `name` on a `Value` passing `args`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// let one = mruby.fixnum(1);
-    /// let result = one.call("+", vec![mruby.fixnum(2)]).unwrap();
-    ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
-    /// ```
-    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, MrubyError> {
-        extern "C" fn call_protected(mrb: *const MrState, data: MrValue) -> MrValue {
-            unsafe {
-                let ptr = data.to_ptr().unwrap();
-                let args = *mem::transmute::<*const u8, *const [*const u8; 4]>(ptr);
-
-                let value: MrValue = mem::transmute_copy(&*args[0]);
-                let sym: &u32 = mem::transmute(args[1]);
-                let argc: &i32 = mem::transmute(args[2]);
-                let argv: *const MrValue = mem::transmute(args[3]);
-
-                let result = mrb_funcall_argv(mrb, value, *sym, *argc, argv);
-
-                mrb_ext_raise_current(mrb);
-
-                result
-            }
-        }
-
-        unsafe {
-            let mrb = self.mruby.borrow().mrb;
-
-            let name_str = CString::new(name).unwrap();
-            let sym = mrb_intern(mrb, name_str.as_ptr(), name.len());
-
-            let args: Vec<MrValue> = args.iter().map(|value| value.value).collect();
-
-            let value_ptr: *const u8 = mem::transmute(&self.value);
-            let sym_ptr: *const u8 = mem::transmute(&sym);
-            let argc = args.len();
-            let argc_ptr: * const u8 = mem::transmute(&argc);
-            let argv_ptr: *const u8 = mem::transmute(args.as_ptr());
+impl ToValue for String {
+    #[inline]
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        self[..].to_value(mruby)
+    }
+}
 
-            let args = [value_ptr, sym_ptr, argc_ptr, argv_ptr];
-            let args_ptr: *const u8 = mem::transmute(&args);
-            let data = MrValue::ptr(mrb, args_ptr);
+/// Accepts `String`s as well as `Symbol`s.
+impl FromValue for String {
+    #[inline]
+    fn from_value(value: &Value) -> Result<String, MrubyError> {
+        value.to_str().map(|value| value.to_owned())
+    }
+}
 
-            let state = mem::uninitialized::<bool>();
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Result<&[u8], MrubyError> {
+    use std::os::unix::ffi::OsStrExt;
 
-            let value = mrb_protect(mrb, call_protected, data, &state as *const bool);
+    Ok(path.as_os_str().as_bytes())
+}
 
-            if state {
-                let str = mrb_ext_exc_str(mrb, value).to_str(mrb).unwrap();
+// Paths elsewhere are not bytes, e.g. UTF-16 on Windows which allows unpaired surrogates that
+// UTF-8 cannot encode.
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Result<&[u8], MrubyError> {
+    match path.to_str() {
+        Some(path) => Ok(path.as_bytes()),
+        None       => Err(MrubyError::Cast("Unicode path".to_owned()))
+    }
+}
 
-                Err(MrubyError::Runtime(str.to_owned()))
-            } else {
-                Ok(Value::new(self.mruby.clone(), value))
-            }
-        }
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, MrubyError> {
+    if bytes.contains(&0) {
+        return Err(MrubyError::Cast("String without NUL bytes".to_owned()));
     }
 
-    /// Calls method `name` on a `Value` passing `args`. If call fails, mruby will be left to
-    /// handle the exception.
-    ///
-    /// The method is unsafe because running it within a Rust context will interrupt drops,
-    /// potentially leading to memory leaks.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// let one = mruby.fixnum(1);
-    /// let result = unsafe { one.call_unchecked("+", vec![mruby.fixnum(2)]) };
-    ///
-    /// assert_eq!(result.to_i32().unwrap(), 3);
-    /// ```
-    pub unsafe fn call_unchecked(&self, name: &str, args: Vec<Value>) -> Value {
-        let name_str = CString::new(name).unwrap();
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
 
-        let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
+        Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+    }
 
-        let args: Vec<MrValue> = args.iter().map(|value| value.value).collect();
+    #[cfg(not(unix))]
+    {
+        use std::str;
 
-        let result = mrb_funcall_argv(self.mruby.borrow().mrb, self.value, sym,
-                                      args.len() as i32, args.as_ptr());
+        str::from_utf8(bytes).map(PathBuf::from).map_err(|_| {
+            MrubyError::Cast("UTF-8 String".to_owned())
+        })
+    }
+}
 
-        Value::new(self.mruby.clone(), result)
+/// Paths that `string_from_path` rejects are converted lossily.
+impl ToValue for Path {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        mruby.string_from_path(self).unwrap_or_else(|_| mruby.string(&self.to_string_lossy()))
     }
+}
 
-    /// Returns whether the instance variable `name` is defined on a `Value`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// mruby.def_class("Container");
-    ///
-    /// let cont = mruby.run("Container.new").unwrap();
-    ///
-    /// assert!(!cont.has_var("value"));
-    /// ```
+impl ToValue for PathBuf {
     #[inline]
-    pub fn has_var(&self, name: &str) -> bool {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
-
-            mrb_iv_defined(self.mruby.borrow().mrb, self.value, sym)
-        }
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        self.as_path().to_value(mruby)
     }
+}
 
-    /// Returns the value of the instance variable `name` in a `Some` or `None` if it is not
-    /// defined.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// mruby.def_class("Container");
-    ///
-    /// let cont = mruby.run("Container.new").unwrap();
-    ///
-    /// cont.set_var("value", mruby.fixnum(2));
-    ///
-    /// assert_eq!(cont.get_var("value").unwrap().to_i32().unwrap(), 2);
-    /// assert!(cont.get_var("valup").is_none());
-    /// ```
+impl FromValue for PathBuf {
     #[inline]
-    pub fn get_var(&self, name: &str) -> Option<Value> {
-        unsafe {
-            let name_str = CString::new(name).unwrap();
-
-            let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
-
-            if mrb_iv_defined(self.mruby.borrow().mrb, self.value, sym) {
-                Some(Value::new(self.mruby.clone(),
-                                mrb_iv_get(self.mruby.borrow().mrb, self.value, sym)))
-            } else {
-                None
-            }
-        }
+    fn from_value(value: &Value) -> Result<PathBuf, MrubyError> {
+        value.to_path_buf()
     }
+}
 
-    /// Sets the value of the instance variable `name` to `value`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// mruby.def_class("Container");
-    ///
-    /// let cont = mruby.run("Container.new").unwrap();
-    ///
-    /// cont.set_var("value", mruby.fixnum(2));
-    ///
-    /// assert!(cont.has_var("value"));
-    /// assert_eq!(cont.get_var("value").unwrap().to_i32().unwrap(), 2);
-    /// ```
-    /// <br/>
-    ///
-    /// Method panics if called on non-objects.
-    ///
-    /// ```should_panic
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// let one = mruby.fixnum(1);
-    ///
-    /// one.set_var("value", mruby.fixnum(2)); // panics because Fixnum cannot have instance vars
-    /// ```
+/// Converted to `Float` seconds.
+impl ToValue for Duration {
     #[inline]
-    pub fn set_var(&self, name: &str, value: Value) {
-        match self.value.typ {
-            MrType::MRB_TT_OBJECT |
-            MrType::MRB_TT_CLASS |
-            MrType::MRB_TT_MODULE |
-            MrType::MRB_TT_SCLASS |
-            MrType::MRB_TT_HASH |
-            MrType::MRB_TT_DATA |
-            MrType::MRB_TT_EXCEPTION => unsafe {
-                let name_str = CString::new(name).unwrap();
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        (self.as_secs() as f64 + self.subsec_nanos() as f64 / 1e9).to_value(mruby)
+    }
+}
 
-                let sym = mrb_intern(self.mruby.borrow().mrb, name_str.as_ptr(), name.len());
+impl FromValue for Duration {
+    fn from_value(value: &Value) -> Result<Duration, MrubyError> {
+        let secs = match value.value.typ {
+            MrType::MRB_TT_FIXNUM => value.to_i32()? as f64,
+            MrType::MRB_TT_FLOAT  => value.to_f64()?,
+            _                     => return Err(MrubyError::Cast("Float or Fixnum".to_owned()))
+        };
 
-                mrb_iv_set(self.mruby.borrow().mrb, self.value, sym, value.value)
-            },
-            _ => panic!("Cannot set instance variable on non-object.")
+        let micros = (secs * 1e6).round();
+
+        if micros >= 0.0 && micros < u64::max_value() as f64 {
+            Ok(Duration::from_micros(micros as u64))
+        } else {
+            Err(MrubyError::OutOfRange("Duration".to_owned()))
         }
     }
+}
 
-    /// Returns the `Class` of an mruby `Value`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// let one = mruby.run("1").unwrap();
-    /// assert_eq!(one.class().to_str(), "Fixnum");
-    /// ```
-    #[inline]
-    pub fn class(&self) -> Class {
-        unsafe {
-            let class = mrb_ext_class(self.mruby.borrow().mrb, self.value);
+/// Only from `Time`, see `MrubyImpl::time` for the other way around.
+impl FromValue for SystemTime {
+    fn from_value(value: &Value) -> Result<SystemTime, MrubyError> {
+        let class = time_class(&value.mruby)?;
 
-            Class::new(self.mruby.clone(), class)
+        if !value.call("is_a?", vec![class.to_value()])?.to_bool()? {
+            return Err(MrubyError::Cast("Time".to_owned()));
         }
+
+        let sec = value.call("to_i", vec![])?.to_i64()?;
+        let usec = Duration::from_micros(value.call("usec", vec![])?.to_u64()?);
+
+        let time = if sec >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(sec as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(sec.wrapping_neg() as u64))
+        };
+
+        time.and_then(|time| time.checked_add(usec)).ok_or_else(|| {
+            MrubyError::OutOfRange("SystemTime".to_owned())
+        })
     }
+}
 
-    /// Casts a `Value` and returns a `bool` in an `Ok` or an `Err` if the types mismatch.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("
-    ///   def pos(n)
-    ///     n > 0
-    ///   end
-    ///
-    ///   pos 1
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_bool().unwrap(), true);
-    /// ```
-    #[inline]
-    pub fn to_bool(&self) -> Result<bool, MrubyError> {
-        unsafe {
-            self.value.to_bool()
+// Time comes from the mruby-time gem, which custom builds of mruby can leave out.
+fn time_class(mruby: &MrubyType) -> Result<Class, MrubyError> {
+    mruby.get_class("Time").map_err(|_| {
+        MrubyError::Runtime {
+            message:   "Time is not defined, mruby was built without the mruby-time gem".to_owned(),
+            backtrace: vec![]
         }
-    }
+    })
+}
 
-    /// Casts a `Value` and returns an `i32` in an `Ok` or an `Err` if the types mismatch.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("
-    ///   def fact(n)
-    ///     n > 1 ? fact(n - 1) * n : 1
-    ///   end
-    ///
-    ///   fact 5
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_i32().unwrap(), 120);
-    /// ```
+impl<T: ToValue> ToValue for Option<T> {
     #[inline]
-    pub fn to_i32(&self) -> Result<i32, MrubyError> {
-        unsafe {
-            self.value.to_i32()
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        match *self {
+            Some(ref value) => value.to_value(mruby),
+            None            => mruby.nil()
         }
     }
+}
 
-    /// Casts a `Value` and returns an `f64` in an `Ok` or an `Err` if the types mismatch.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("
-    ///   3 / 2.0
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_f64().unwrap(), 1.5);
-    /// ```
+impl<T: FromValue> FromValue for Option<T> {
     #[inline]
-    pub fn to_f64(&self) -> Result<f64, MrubyError> {
-        unsafe {
-            self.value.to_f64()
+    fn from_value(value: &Value) -> Result<Option<T>, MrubyError> {
+        // nil is a false value with a zero 32-bit payload, like mrb_nil_p checks
+        if value.value.typ == MrType::MRB_TT_FALSE && value.value.value as u32 == 0 {
+            Ok(None)
+        } else {
+            T::from_value(value).map(Some)
         }
     }
+}
 
-    /// Casts a `Value` and returns a `&str` in an `Ok` or an `Err` if the types mismatch.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("
-    ///   [1, 2, 3].map(&:to_s).join
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_str().unwrap(), "123");
-    /// ```
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run(":symbol").unwrap();
-    ///
-    /// assert_eq!(result.to_str().unwrap(), "symbol");
-    /// ```
+impl<T: ToValue> ToValue for Vec<T> {
     #[inline]
-    pub fn to_str<'a>(&self) -> Result<&'a str, MrubyError> {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        self[..].to_value(mruby)
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        let values: Vec<Value> = self.iter().map(|value| value.to_value(mruby)).collect();
+        let array = values.iter().map(|value| value.value).collect();
+
         unsafe {
-            self.value.to_str(self.mruby.borrow().mrb)
+            let mrb = mruby.borrow().mrb;
+
+            Value::new(mruby.clone(), MrValue::array(mrb, array))
         }
     }
+}
 
-    /// Casts mruby `Value` of `Class` `name` to Rust type `Rc<T>`.
-    ///
-    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// struct Cont {
-    ///     value: i32
-    /// }
-    ///
-    /// mruby.def_class_for::<Cont>("Container");
-    ///
-    /// let value = mruby.obj(Cont { value: 3 });
-    /// let cont = value.to_obj::<Cont>().unwrap();
-    /// let cont = cont.borrow();
-    ///
-    /// assert_eq!(cont.value, 3);
-    /// ```
-    #[inline]
-    pub fn to_obj<T: Any>(&self) -> Result<Rc<RefCell<T>>, MrubyError> {
-        unsafe {
-            let borrow = self.mruby.borrow();
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, MrubyError> {
+        let array = unsafe {
+            value.value.to_vec(value.mruby.borrow().mrb)?
+        };
 
-            let class = match borrow.classes.get(&TypeId::of::<T>()) {
-                Some(class) => class,
-                None        => {
-                    return Err(MrubyError::Undef)
+        array.into_iter().map(|element| {
+            T::from_value(&Value::new(value.mruby.clone(), element))
+        }).collect()
+    }
+}
+
+fn hash_from_entries<'a, K, T, I>(mruby: &MrubyType, entries: I) -> Value
+    where K: AsRef<str> + 'a,
+          T: ToValue + 'a,
+          I: IntoIterator<Item = (&'a K, &'a T)> {
+
+    let mrb = mruby.borrow().mrb;
+
+    unsafe {
+        let hash = Value::new(mruby.clone(), mrb_hash_new(mrb));
+
+        for (key, value) in entries {
+            let key = key.as_ref().to_value(mruby);
+            let value = value.to_value(mruby);
+
+            mrb_hash_set(mrb, hash.value, key.value, value.value);
+        }
+
+        hash
+    }
+}
+
+fn hash_entries<T: FromValue>(value: &Value) -> Result<Vec<(String, T)>, MrubyError> {
+    if value.value.typ != MrType::MRB_TT_HASH {
+        return Err(MrubyError::Cast("Hash".to_owned()));
+    }
+
+    let mrb = value.mruby.borrow().mrb;
+
+    unsafe {
+        let keys = Value::new(value.mruby.clone(), mrb_hash_keys(mrb, value.value));
+
+        keys.to_vec()?.iter().map(|key| {
+            let name = match key.value.typ {
+                MrType::MRB_TT_STRING | MrType::MRB_TT_SYMBOL => String::from_value(key)?,
+                _ => {
+                    let inspect = key.call("inspect", vec![])?;
+
+                    return Err(MrubyError::Cast(format!("String or Symbol key instead of {}",
+                                                        inspect.to_str()?)));
                 }
             };
 
-            let self_class = self.class();
+            let element = Value::new(value.mruby.clone(),
+                                     mrb_hash_get(mrb, value.value, key.value));
 
-            if self_class.to_str() != class.2 {
-                return Err(MrubyError::Undef)
-            }
+            Ok((name, T::from_value(&element)?))
+        }).collect()
+    }
+}
 
-            self.value.to_obj::<T>(borrow.mrb, &class.1)
+/// Keys are converted to mruby `String`s.
+impl<K, T, S> ToValue for HashMap<K, T, S>
+    where K: AsRef<str> + Eq + Hash,
+          T: ToValue,
+          S: BuildHasher {
+
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        hash_from_entries(mruby, self)
+    }
+}
+
+/// Accepts `String` as well as `Symbol` keys.
+impl<T, S> FromValue for HashMap<String, T, S>
+    where T: FromValue,
+          S: BuildHasher + Default {
+
+    fn from_value(value: &Value) -> Result<HashMap<String, T, S>, MrubyError> {
+        Ok(hash_entries(value)?.into_iter().collect())
+    }
+}
+
+/// Keys are converted to mruby `String`s and inserted in order.
+impl<K: AsRef<str> + Ord, T: ToValue> ToValue for BTreeMap<K, T> {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        hash_from_entries(mruby, self)
+    }
+}
+
+/// Accepts `String` as well as `Symbol` keys.
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(value: &Value) -> Result<BTreeMap<String, T>, MrubyError> {
+        Ok(hash_entries(value)?.into_iter().collect())
+    }
+}
+
+impl ToValue for Range<i32> {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        mruby.range(mruby.fixnum(self.start), mruby.fixnum(self.end), true)
+    }
+}
+
+/// Only accepts exclusive `Range`s.
+impl FromValue for Range<i32> {
+    fn from_value(value: &Value) -> Result<Range<i32>, MrubyError> {
+        match value.to_range()? {
+            (start, end, true) => Ok(start.to_i32()?..end.to_i32()?),
+            _                  => Err(MrubyError::Cast("exclusive Range (a...b)".to_owned()))
         }
     }
+}
 
-    /// Casts mruby `Value` of `Class` `name` to Rust `Option` of `Rc<T>`.
-    ///
-    /// *Note:* `T` must be defined on the current `Mruby` with `def_class`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    ///
-    /// struct Cont {
-    ///     value: i32
-    /// }
-    ///
-    /// mruby.def_class_for::<Cont>("Container");
-    ///
-    /// let value = mruby.obj(Cont { value: 3 });
-    /// let cont = value.to_option::<Cont>().unwrap().unwrap();
-    /// let cont = cont.borrow();
-    ///
-    /// assert_eq!(cont.value, 3);
-    /// assert!(mruby.nil().to_option::<Cont>().unwrap().is_none());
-    /// ```
-    #[inline]
-    pub fn to_option<T: Any>(&self) -> Result<Option<Rc<RefCell<T>>>, MrubyError> {
-        if self.value.typ == MrType::MRB_TT_DATA {
-            self.to_obj::<T>().map(|obj| Some(obj))
-        } else {
-            Ok(None)
+impl ToValue for RangeInclusive<i32> {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        mruby.range(mruby.fixnum(*self.start()), mruby.fixnum(*self.end()), false)
+    }
+}
+
+/// Only accepts inclusive `Range`s.
+impl FromValue for RangeInclusive<i32> {
+    fn from_value(value: &Value) -> Result<RangeInclusive<i32>, MrubyError> {
+        match value.to_range()? {
+            (start, end, false) => Ok(start.to_i32()?..=end.to_i32()?),
+            _                   => Err(MrubyError::Cast("inclusive Range (a..b)".to_owned()))
         }
     }
+}
 
-    /// Casts mruby `Value` of `Class` `Array` to Rust type `Vec<Value>`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("
-    ///   [1, 2, 3].map(&:to_s)
-    /// ").unwrap();
-    ///
-    /// assert_eq!(result.to_vec().unwrap(), vec![
-    ///     mruby.string("1"),
-    ///     mruby.string("2"),
-    ///     mruby.string("3")
-    /// ]);
-    /// ```
+/// Only for types defined with `def_class_for`. The `Value` shares the object with the `Rc`, like
+/// `MrubyImpl::obj_shared`.
+impl<T: Any> ToValue for Rc<RefCell<T>> {
     #[inline]
-    pub fn to_vec(&self) -> Result<Vec<Value>, MrubyError> {
-        unsafe {
-            self.value.to_vec(self.mruby.borrow().mrb).map(|vec| {
-                vec.iter().map(|mrvalue| {
-                    Value::new(self.mruby.clone(), *mrvalue)
-                }).collect()
-            })
-        }
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        mruby.obj_shared(self.clone())
     }
+}
 
-    /// Casts mruby `Value` of `Class` `Class` to Rust type `Class`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("Object").unwrap();
-    ///
-    /// assert_eq!(result.to_class().unwrap().to_str(), "Object");
-    /// ```
+impl<T: Any> FromValue for Rc<RefCell<T>> {
     #[inline]
-    pub fn to_class(&self) -> Result<Class, MrubyError> {
-        unsafe {
-            let class = try!(self.value.to_class());
+    fn from_value(value: &Value) -> Result<Rc<RefCell<T>>, MrubyError> {
+        value.to_obj::<T>()
+    }
+}
 
-            Ok(Class::new(self.mruby.clone(), class))
+/// A `struct` that wraps a `Value` in order to compare it with mruby's `eql?` instead of `==`.
+/// Like mruby `Hash` keys, `1` and `1.0` are then different. Implements `Eq` and `Hash` so that
+/// `Value`s can be used as keys in Rust collections.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// # use mrusty::ValueEql;
+/// use std::collections::HashSet;
+///
+/// let mruby = Mruby::new();
+///
+/// let mut set = HashSet::new();
+///
+/// set.insert(ValueEql(mruby.string("a")));
+/// set.insert(ValueEql(mruby.string("a")));
+/// set.insert(ValueEql(mruby.fixnum(1)));
+/// set.insert(ValueEql(mruby.float(1.0)));
+///
+/// assert_eq!(set.len(), 3);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ValueEql(pub Value);
+
+impl PartialEq<ValueEql> for ValueEql {
+    fn eq(&self, other: &ValueEql) -> bool {
+        if !Rc::ptr_eq(&self.0.mruby, &other.0.mruby) {
+            return false;
+        }
+
+        match self.0.call1("eql?", other.0.clone()) {
+            Ok(result) => result.to_bool().unwrap_or(false),
+            Err(_)     => false
         }
     }
+}
 
-    /// Casts mruby `Value` of `Class` `Module` to Rust type `Module`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// # use mrusty::Mruby;
-    /// # use mrusty::MrubyImpl;
-    /// let mruby = Mruby::new();
-    /// let result = mruby.run("Kernel").unwrap();
-    ///
-    /// assert_eq!(result.to_module().unwrap().to_str(), "Kernel");
-    /// ```
-    #[inline]
-    pub fn to_module(&self) -> Result<Module, MrubyError> {
-        unsafe {
-            let module = try!(self.value.to_module());
+impl Eq for ValueEql {}
 
-            Ok(Module::new(self.mruby.clone(), module))
+/// Uses mruby's `hash`, which is consistent with `eql?`, falling back to `object_id` if `hash`
+/// does not return a `Fixnum`.
+impl Hash for ValueEql {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0.call0("hash").and_then(|hash| hash.to_i32()) {
+            Ok(hash) => hash.hash(state),
+            Err(_)   => self.0.object_id().hash(state)
         }
     }
 }
 
-use std::fmt;
+/// An `enum` holding a deep copy of a plain-data `Value`. Unlike `Value`, which only lives as
+/// long as its interpreter and on the thread that created it, a `ValueSnapshot` is `Send` and
+/// can carry results from one interpreter to another, e.g. to a different thread.
+///
+/// Only `nil`, `true`, `false`, `Fixnum`, `Float`, `String`, `Symbol`, `Array` and `Hash`
+/// values, nested at most 100 levels deep, can be copied. Anything else, like objects or
+/// `Proc`s, is refused with an `Err` naming its `Class`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// # use mrusty::ToValue;
+/// # use mrusty::ValueSnapshot;
+/// use std::thread;
+///
+/// let mruby = Mruby::new();
+/// let snapshot = mruby.run("{ sum: 1 + 2.5 }").unwrap().to_snapshot().unwrap();
+///
+/// let sum = thread::spawn(move || {
+///     let mruby = Mruby::new();
+///     let hash = snapshot.to_value(&mruby);
+///
+///     hash.call("[]", vec![mruby.symbol("sum")]).unwrap().to_f64().unwrap()
+/// }).join().unwrap();
+///
+/// assert_eq!(sum, 3.5);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueSnapshot {
+    /// `nil`
+    Nil,
+    /// `true` or `false`
+    Bool(bool),
+    /// `Fixnum`
+    Fixnum(i32),
+    /// `Float`
+    Float(f64),
+    /// `String`
+    String(String),
+    /// `Symbol`, holding its name
+    Symbol(String),
+    /// `Array`
+    Array(Vec<ValueSnapshot>),
+    /// `Hash`, holding its entries in order
+    Hash(Vec<(ValueSnapshot, ValueSnapshot)>)
+}
 
-impl Clone for Value {
-    fn clone(&self) -> Value {
-        if self.value.typ == MrType::MRB_TT_DATA {
-            unsafe {
-                let ptr = mrb_ext_data_ptr(self.value);
-                let rc: Rc<c_void> = mem::transmute(ptr);
+const MAX_SNAPSHOT_NESTING: usize = 100;
 
-                rc.clone();
+fn snapshot(value: &Value, depth: usize) -> Result<ValueSnapshot, MrubyError> {
+    if depth > MAX_SNAPSHOT_NESTING {
+        return Err(MrubyError::Cast(format!("plain data nested at most {} deep",
+                                            MAX_SNAPSHOT_NESTING)));
+    }
 
-                mem::forget(rc);
-            }
+    let mrb = value.mruby.borrow().mrb;
+
+    let snapshot = match value.value.typ {
+        MrType::MRB_TT_FALSE if value.value.value as u32 == 0 => ValueSnapshot::Nil,
+        MrType::MRB_TT_FALSE  => ValueSnapshot::Bool(false),
+        MrType::MRB_TT_TRUE   => ValueSnapshot::Bool(true),
+        MrType::MRB_TT_FIXNUM => ValueSnapshot::Fixnum(value.to_i32()?),
+        MrType::MRB_TT_FLOAT  => ValueSnapshot::Float(value.to_f64()?),
+        MrType::MRB_TT_STRING => ValueSnapshot::String(value.to_str()?.to_owned()),
+        MrType::MRB_TT_SYMBOL => {
+            let name = unsafe { sym_name(mrb, mrb_ext_sym_id(value.value)) };
+
+            ValueSnapshot::Symbol(name.to_owned())
+        },
+        MrType::MRB_TT_ARRAY  => {
+            let elements = value.to_vec()?.iter().map(|element| {
+                snapshot(element, depth + 1)
+            }).collect::<Result<_, MrubyError>>()?;
+
+            ValueSnapshot::Array(elements)
+        },
+        MrType::MRB_TT_HASH   => {
+            let keys = unsafe { Value::new(value.mruby.clone(), mrb_hash_keys(mrb, value.value)) };
+
+            let entries = keys.to_vec()?.iter().map(|key| {
+                let element = unsafe {
+                    Value::new(value.mruby.clone(), mrb_hash_get(mrb, value.value, key.value))
+                };
+
+                Ok((snapshot(key, depth + 1)?, snapshot(&element, depth + 1)?))
+            }).collect::<Result<_, MrubyError>>()?;
+
+            ValueSnapshot::Hash(entries)
+        },
+        _ => {
+            return Err(MrubyError::Cast(format!("plain data instead of {}",
+                                                value.class().to_str())));
         }
+    };
 
-        Value::new(self.mruby.clone(), self.value.clone())
-    }
+    Ok(snapshot)
 }
 
-impl PartialEq<Value> for Value {
-    fn eq(&self, other: &Value) -> bool {
-        let result = self.call("==", vec![other.clone()]).unwrap();
+impl ToValue for ValueSnapshot {
+    fn to_value(&self, mruby: &MrubyType) -> Value {
+        match *self {
+            ValueSnapshot::Nil               => mruby.nil(),
+            ValueSnapshot::Bool(value)       => mruby.bool(value),
+            ValueSnapshot::Fixnum(value)     => mruby.fixnum(value),
+            ValueSnapshot::Float(value)      => mruby.float(value),
+            ValueSnapshot::String(ref value) => mruby.string(value),
+            ValueSnapshot::Symbol(ref value) => mruby.symbol(value),
+            ValueSnapshot::Array(ref values) => {
+                mruby.array(values.iter().map(|value| value.to_value(mruby)).collect())
+            },
+            ValueSnapshot::Hash(ref entries) => {
+                let mrb = mruby.borrow().mrb;
 
-        result.to_bool().unwrap()
+                unsafe {
+                    let hash = Value::new(mruby.clone(), mrb_hash_new(mrb));
+
+                    for &(ref key, ref value) in entries {
+                        let key = key.to_value(mruby);
+                        let value = value.to_value(mruby);
+
+                        mrb_hash_set(mrb, hash.value, key.value, value.value);
+                    }
+
+                    hash
+                }
+            }
+        }
     }
 }
 
-impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Value {{ {:?} }}", self.value)
+impl FromValue for ValueSnapshot {
+    fn from_value(value: &Value) -> Result<ValueSnapshot, MrubyError> {
+        snapshot(value, 0)
     }
 }
 
@@ -2254,16 +9146,56 @@ impl Class {
     #[doc(hidden)]
     pub fn new(mruby: MrubyType, class: *const MrClass) -> Class {
         let name = unsafe {
-            let name = mrb_class_name(mruby.borrow().mrb, class);
+            let mrb = mruby.borrow().mrb;
+
+            let name = mrb_class_name(mrb, class);
+
+            CStr::from_ptr(name).to_str().unwrap()
+        };
+
+        Class {
+            mruby:  mruby,
+            class:  class,
+            name:   name.to_owned()
+        }
+    }
+
+    /// Sets the visibility of the mruby method named `name` defined from Rust on a `Class`. Since
+    /// mruby ignores `private` and `protected`, mrusty checks the visibility of the methods it
+    /// defines itself; calling a method from outside its receiver or `Class` by name raises a
+    /// `NoMethodError`, while `send` still reaches it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate mrusty;
+    /// use mrusty::{Mruby, MrubyImpl, Visibility};
+    ///
+    /// # fn main() {
+    /// let mruby = Mruby::new();
+    ///
+    /// let class = mruby_class!(mruby, "Container", {
+    ///     def!("secret", |mruby, _slf: Value| {
+    ///         mruby.fixnum(3)
+    ///     });
+    /// });
+    ///
+    /// class.set_visibility("secret", Visibility::Private);
+    ///
+    /// assert!(mruby.run("Container.new.secret").is_err());
+    /// assert_eq!(mruby.run("Container.new.send(:secret)").unwrap().to_i32().unwrap(), 3);
+    /// # }
+    /// ```
+    pub fn set_visibility(&self, name: &str, visibility: Visibility) {
+        let sym = self.mruby.intern(name).0;
+        let key = (self.class as usize, sym);
 
-            CStr::from_ptr(name).to_str().unwrap()
-        };
+        let mut borrow = self.mruby.borrow_mut();
 
-        Class {
-            mruby:  mruby,
-            class:  class,
-            name:   name.to_owned()
-        }
+        match visibility {
+            Visibility::Public => borrow.visibilities.remove(&key),
+            _                  => borrow.visibilities.insert(key, visibility)
+        };
     }
 
     /// Includes a `Module` in a `Class`.
@@ -2294,7 +9226,9 @@ impl Class {
     /// ```
     pub fn include(&self, module: Module) {
         unsafe {
-            mrb_include_module(self.mruby.borrow().mrb, self.class, module.module);
+            let mrb = self.mruby.borrow().mrb;
+
+            mrb_include_module(mrb, self.class, module.module);
         }
     }
 
@@ -2327,6 +9261,62 @@ impl Class {
         }
     }
 
+    /// Returns the value of the class variable `name` in a `Some` or `None` if it is not defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let cont = mruby.def_class("Container");
+    ///
+    /// cont.set_class_var("@@count", mruby.fixnum(2));
+    ///
+    /// assert_eq!(cont.get_class_var("@@count").unwrap().to_i32().unwrap(), 2);
+    /// assert!(cont.get_class_var("@@cound").is_none());
+    /// ```
+    pub fn get_class_var(&self, name: &str) -> Option<Value> {
+        let sym = self.mruby.intern(name).0;
+        let mrb = self.mruby.borrow().mrb;
+
+        unsafe {
+            let class = self.to_value().value;
+
+            if mrb_cv_defined(mrb, class, sym) {
+                Some(Value::new(self.mruby.clone(), mrb_cv_get(mrb, class, sym)))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Sets the value of the class variable `name` to `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// let mruby = Mruby::new();
+    ///
+    /// let cont = mruby.def_class("Container");
+    ///
+    /// cont.set_class_var("@@count", mruby.fixnum(2));
+    ///
+    /// let result = mruby.run("class Container; @@count + 1; end").unwrap();
+    ///
+    /// assert_eq!(result.to_i32().unwrap(), 3);
+    /// ```
+    pub fn set_class_var(&self, name: &str, value: Value) {
+        let sym = self.mruby.intern(name).0;
+
+        unsafe {
+            mrb_mod_cv_set(self.mruby.borrow().mrb, self.class, sym, value.value);
+        }
+    }
+
     /// Returns a `&str` with the mruby `Class` name.
     ///
     /// # Examples
@@ -2401,6 +9391,447 @@ impl fmt::Debug for Class {
     }
 }
 
+/// A `struct` of the capabilities that a `sandbox` leaves to scripts. Created with
+/// `SandboxConfig::new`, which allows none of them:
+///
+/// * `require` and `require_relative`
+/// * printing with `print`, `puts` and `p`
+/// * `instance_eval`, `instance_exec`, `class_eval`, `module_eval` and `eval`, which the bundled
+///   mruby does not compile in
+/// * the `GC` and `ObjectSpace` `Module`s
+/// * the `File`, `IO`, `Process`, `Dir` and `Socket` `Class`es or `Module`s, one by one
+///
+/// Allowing `"File"` without `"IO"` leaves `File` without the methods it inherits from `IO`.
+///
+/// # Examples
+///
+/// ```
+/// use mrusty::SandboxConfig;
+///
+/// let config = SandboxConfig::new().allow_require().allow("Dir");
+///
+/// assert!(config.allows_require());
+/// assert!(config.allows("Dir"));
+/// assert!(!config.allows_eval());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SandboxConfig {
+    require:    bool,
+    print:      bool,
+    eval:       bool,
+    gc_control: bool,
+    allowed:    Vec<String>
+}
+
+impl SandboxConfig {
+    /// Creates a `SandboxConfig` that allows nothing.
+    pub fn new() -> SandboxConfig {
+        SandboxConfig::default()
+    }
+
+    /// Keeps `Kernel#require` and `Kernel#require_relative` defined.
+    pub fn allow_require(mut self) -> SandboxConfig {
+        self.require = true;
+
+        self
+    }
+
+    /// Keeps `print`, `puts` and `p` defined.
+    pub fn allow_print(mut self) -> SandboxConfig {
+        self.print = true;
+
+        self
+    }
+
+    /// Keeps evaluating strings and blocks on other receivers.
+    pub fn allow_eval(mut self) -> SandboxConfig {
+        self.eval = true;
+
+        self
+    }
+
+    /// Keeps the `GC` and `ObjectSpace` `Module`s.
+    pub fn allow_gc_control(mut self) -> SandboxConfig {
+        self.gc_control = true;
+
+        self
+    }
+
+    /// Keeps the `Class` or `Module` named `name`, one of `"File"`, `"IO"`, `"Process"`, `"Dir"`
+    /// and `"Socket"`.
+    pub fn allow(mut self, name: &str) -> SandboxConfig {
+        if !self.allows(name) {
+            self.allowed.push(name.to_owned());
+            self.allowed.sort();
+        }
+
+        self
+    }
+
+    /// Returns whether `require` is allowed.
+    pub fn allows_require(&self) -> bool {
+        self.require
+    }
+
+    /// Returns whether printing is allowed.
+    pub fn allows_print(&self) -> bool {
+        self.print
+    }
+
+    /// Returns whether evaluating is allowed.
+    pub fn allows_eval(&self) -> bool {
+        self.eval
+    }
+
+    /// Returns whether `GC` and `ObjectSpace` are allowed.
+    pub fn allows_gc_control(&self) -> bool {
+        self.gc_control
+    }
+
+    /// Returns whether the `Class` or `Module` named `name` is allowed.
+    pub fn allows(&self, name: &str) -> bool {
+        self.allowed.iter().any(|allowed| allowed == name)
+    }
+
+    fn intersect(&self, other: &SandboxConfig) -> SandboxConfig {
+        SandboxConfig {
+            require:    self.require && other.require,
+            print:      self.print && other.print,
+            eval:       self.eval && other.eval,
+            gc_control: self.gc_control && other.gc_control,
+            allowed:    self.allowed.iter().filter(|name| other.allows(name)).cloned().collect()
+        }
+    }
+
+    fn union(&self, other: &SandboxConfig) -> SandboxConfig {
+        let config = SandboxConfig {
+            require:    self.require || other.require,
+            print:      self.print || other.print,
+            eval:       self.eval || other.eval,
+            gc_control: self.gc_control || other.gc_control,
+            allowed:    self.allowed.clone()
+        };
+
+        other.allowed.iter().fold(config, |config, name| config.allow(name))
+    }
+}
+
+/// A `struct` collecting what mruby prints while it is alive. Created with
+/// `mruby.redirect_stdout_to_string`.
+pub struct OutputCapture {
+    mruby:    MrubyType,
+    output:   Rc<RefCell<String>>,
+    previous: Option<Rc<Fn(&str)>>
+}
+
+impl OutputCapture {
+    /// Returns everything printed so far.
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+}
+
+impl Drop for OutputCapture {
+    fn drop(&mut self) {
+        self.mruby.borrow_mut().print_handler = self.previous.take();
+    }
+}
+
+/// An `enum` of the results of `check_syntax`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxResult {
+    /// code can be run
+    Complete,
+    /// code needs more input, e.g. a closing `end`
+    Incomplete,
+    /// syntax error with its line and column
+    Error(String)
+}
+
+/// An `enum` of the visibilities of mruby methods defined from Rust.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    /// callable from anywhere
+    Public,
+    /// callable only by the receiver itself, see `def_private_method_for`
+    Private,
+    /// callable only from instances of the method's `Class`
+    Protected
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Visibility::Public    => write!(f, "public"),
+            Visibility::Private   => write!(f, "private"),
+            Visibility::Protected => write!(f, "protected")
+        }
+    }
+}
+
+/// A `struct` that collects the methods of an mruby `Class` reflecting type `T` in order to define
+/// them all at once. Created with `mruby.class_builder`, or `mruby.open_class_for` to add them to
+/// a `Class` that is already defined.
+pub struct ClassBuilder<'a, T: Any> {
+    mruby:         MrubyType,
+    name:          &'a str,
+    methods:       Vec<(&'a str, BuilderMethod)>,
+    class_methods: Vec<(&'a str, BuilderClosure)>,
+    hooks:         Vec<fn(&MrubyType)>,
+    requires:      Vec<fn(&MrubyType)>,
+    superclass:    Option<&'a str>,
+    modules:       Vec<&'a str>,
+    consts:        Vec<(&'a str, Value)>,
+    class_vars:    Vec<(&'a str, Value)>,
+    visibility:    Visibility,
+    visibilities:  Vec<(&'a str, Visibility)>,
+    open:          bool,
+    phantom:       PhantomData<T>
+}
+
+type BuilderClosure = Rc<dyn Fn(MrubyType, Value) -> Value>;
+
+enum BuilderMethod {
+    Closure(BuilderClosure),
+    Fn(fn(MrubyType, Value) -> Value)
+}
+
+impl<'a, T: Any> ClassBuilder<'a, T> {
+    /// Adds an mruby method named `name`, like `def_method_for`.
+    #[inline]
+    pub fn method<F>(mut self, name: &'a str, method: F) -> ClassBuilder<'a, T>
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        self.methods.push((name, BuilderMethod::Closure(Rc::new(method))));
+        self.visibilities.push((name, self.visibility));
+
+        self
+    }
+
+    /// Adds an mruby method named `name` from a plain `fn`, like `def_method_fn`.
+    #[inline]
+    pub fn method_fn(mut self, name: &'a str,
+                     method: fn(MrubyType, Value) -> Value) -> ClassBuilder<'a, T> {
+        self.methods.push((name, BuilderMethod::Fn(method)));
+        self.visibilities.push((name, self.visibility));
+
+        self
+    }
+
+    /// Makes the mruby methods added after it private, like `def_private_method_for`.
+    #[inline]
+    pub fn private(mut self) -> ClassBuilder<'a, T> {
+        self.visibility = Visibility::Private;
+
+        self
+    }
+
+    /// Makes the mruby methods added after it protected, like `def_protected_method_for`.
+    #[inline]
+    pub fn protected(mut self) -> ClassBuilder<'a, T> {
+        self.visibility = Visibility::Protected;
+
+        self
+    }
+
+    /// Makes the mruby methods added after it public again.
+    #[inline]
+    pub fn public(mut self) -> ClassBuilder<'a, T> {
+        self.visibility = Visibility::Public;
+
+        self
+    }
+
+    /// Adds an mruby class method named `name`, like `def_class_method_for`.
+    #[inline]
+    pub fn class_method<F>(mut self, name: &'a str, method: F) -> ClassBuilder<'a, T>
+        where F: Fn(MrubyType, Value) -> Value + 'static {
+
+        self.class_methods.push((name, Rc::new(method)));
+
+        self
+    }
+
+    /// Makes the `Class` inherit from the mruby `Class` named `name`, like `def_subclass_for`.
+    #[inline]
+    pub fn superclass(mut self, name: &'a str) -> ClassBuilder<'a, T> {
+        self.superclass = Some(name);
+
+        self
+    }
+
+    /// Requires `U` before defining the `Class`, unless its `Class` is already defined.
+    #[inline]
+    pub fn requires<U: MrubyFile + Any>(mut self) -> ClassBuilder<'a, T> {
+        self.requires.push(|mruby| {
+            if mruby.class_name_for::<U>().is_err() {
+                U::require(mruby.clone());
+            }
+        });
+
+        self
+    }
+
+    /// Includes the mruby `Module` named `name` in the `Class` once defined.
+    #[inline]
+    pub fn include(mut self, name: &'a str) -> ClassBuilder<'a, T> {
+        self.modules.push(name);
+
+        self
+    }
+
+    /// Defines the constant `name` on the `Class` once defined, like `Class::def_const`. `value`
+    /// is called right away.
+    #[inline]
+    pub fn constant<F>(mut self, name: &'a str, value: F) -> ClassBuilder<'a, T>
+        where F: FnOnce(&MrubyType) -> Value {
+
+        let value = value(&self.mruby);
+
+        self.consts.push((name, value));
+
+        self
+    }
+
+    /// Sets the class variable `name` once the `Class` is defined, like `Class::set_class_var`.
+    /// `value` is called right away.
+    #[inline]
+    pub fn class_var<F>(mut self, name: &'a str, value: F) -> ClassBuilder<'a, T>
+        where F: FnOnce(&MrubyType) -> Value {
+
+        let value = value(&self.mruby);
+
+        self.class_vars.push((name, value));
+
+        self
+    }
+
+    /// Makes the `Class` `Comparable` once defined, like `def_comparable_for`.
+    #[inline]
+    pub fn comparable(mut self) -> ClassBuilder<'a, T> where T: Ord {
+        self.hooks.push(|mruby| mruby.def_comparable_for::<T>());
+
+        self
+    }
+
+    /// Defines `==`, `eql?` and `hash` once the `Class` is defined, like `def_eq_for`.
+    #[inline]
+    pub fn equatable(mut self) -> ClassBuilder<'a, T> where T: PartialEq + Hash {
+        self.hooks.push(|mruby| mruby.def_eq_for::<T>());
+
+        self
+    }
+
+    /// Defines `inspect` once the `Class` is defined, like `def_display_for`.
+    #[inline]
+    pub fn display(mut self) -> ClassBuilder<'a, T> where T: fmt::Debug {
+        self.hooks.push(|mruby| mruby.def_display_for::<T>());
+
+        self
+    }
+
+    /// Defines `to_s` once the `Class` is defined, like `def_to_s_for`.
+    #[inline]
+    pub fn to_s(mut self) -> ClassBuilder<'a, T> where T: fmt::Display {
+        self.hooks.push(|mruby| mruby.def_to_s_for::<T>());
+
+        self
+    }
+
+    /// Defines the `Class`, unless it is reopened, and all the methods added to the builder and
+    /// returns the `Class`.
+    pub fn define(self) -> Class {
+        for require in &self.requires {
+            require(&self.mruby);
+        }
+
+        let class = match self.superclass {
+            _ if self.open => class_for::<T>(&self.mruby),
+            Some(name)     => match self.mruby.get_class(name) {
+                Ok(superclass) => self.mruby.def_subclass_for::<T>(self.name, &superclass),
+                Err(_)         => panic!("Superclass {} not found.", name)
+            },
+            None           => self.mruby.def_class_for::<T>(self.name)
+        };
+        let key = TypeId::of::<T>();
+
+        let (mrb, syms, class_syms) = {
+            let mut borrow = self.mruby.borrow_mut();
+            let borrow = &mut *borrow;
+
+            let syms = self.methods.iter().map(|&(name, _)| {
+                borrow.intern_cached(name)
+            }).collect::<Vec<_>>();
+            let class_syms = self.class_methods.iter().map(|&(name, _)| {
+                borrow.intern_cached(name)
+            }).collect::<Vec<_>>();
+
+            let methods = borrow.methods.get_mut(&key).unwrap();
+
+            for (&sym, (_, method)) in syms.iter().zip(self.methods.iter()) {
+                if let BuilderMethod::Closure(method) = method {
+                    methods.insert(sym, method.clone());
+                }
+            }
+
+            let class_methods = borrow.class_methods.get_mut(&key).unwrap();
+
+            for (&sym, (_, method)) in class_syms.iter().zip(self.class_methods.iter()) {
+                class_methods.insert(sym, method.clone());
+            }
+
+            (borrow.mrb, syms, class_syms)
+        };
+
+        unsafe {
+            for (&sym, (_, method)) in syms.iter().zip(self.methods.iter()) {
+                match *method {
+                    BuilderMethod::Closure(_) => {
+                        mrb_define_method_id(mrb, class.class, sym, call_method::<T>, 1 << 12);
+                    },
+                    BuilderMethod::Fn(method) => define_method_fn(mrb, class.class, sym, method)
+                }
+            }
+
+            let singleton = mrb_ext_get_class(mrb_singleton_class(mrb,
+                                                                  mrb_ext_class_value(class.class)));
+
+            for &sym in &class_syms {
+                mrb_define_method_id(mrb, singleton, sym, call_class_method::<T>, 1 << 12);
+            }
+        }
+
+        for &(name, visibility) in &self.visibilities {
+            class.set_visibility(name, visibility);
+        }
+
+        for (name, value) in self.consts {
+            class.def_const(name, value);
+        }
+
+        for (name, value) in self.class_vars {
+            class.set_class_var(name, value);
+        }
+
+        for name in &self.modules {
+            match self.mruby.get_module(name) {
+                Ok(module) => class.include(module),
+                Err(_)     => panic!("Module not found.")
+            }
+        }
+
+        if self.methods.iter().any(|&(name, _)| name == "coerce") {
+            def_coercions(&self.mruby);
+        }
+
+        for hook in &self.hooks {
+            hook(&self.mruby);
+        }
+
+        class
+    }
+}
+
 /// A `struct` that wraps around an mruby `Module`.
 ///
 /// # Examples
@@ -2425,7 +9856,9 @@ impl Module {
     #[doc(hidden)]
     pub fn new(mruby: MrubyType, module: *const MrClass) -> Module {
         let name = unsafe {
-            let name = mrb_class_name(mruby.borrow().mrb, module);
+            let mrb = mruby.borrow().mrb;
+
+            let name = mrb_class_name(mrb, module);
 
             CStr::from_ptr(name).to_str().unwrap()
         };
@@ -2469,7 +9902,9 @@ impl Module {
     /// ```
     pub fn include(&self, module: Module) {
         unsafe {
-            mrb_include_module(self.mruby.borrow().mrb, self.module, module.module);
+            let mrb = self.mruby.borrow().mrb;
+
+            mrb_include_module(mrb, self.module, module.module);
         }
     }
 
@@ -9,8 +9,9 @@ use std::any::Any;
 use std::cell::RefCell;
 use std::ffi::CStr;
 use std::mem;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::rc::Rc;
+use std::slice;
 
 use super::MrubyError;
 
@@ -19,6 +20,7 @@ pub enum MrContext {}
 
 pub enum MrClass {}
 pub enum MrData {}
+pub enum MrProc {}
 
 pub type MrFunc = extern "C" fn(*const MrState, MrValue) -> MrValue;
 
@@ -67,6 +69,16 @@ impl MrValue {
         mrb_str_new(mrb, value.as_ptr(), value.len())
     }
 
+    #[inline]
+    pub unsafe fn bytes(mrb: *const MrState, value: &[u8]) -> MrValue {
+        mrb_str_new(mrb, value.as_ptr(), value.len())
+    }
+
+    #[inline]
+    pub unsafe fn string_static(mrb: *const MrState, value: &'static str) -> MrValue {
+        mrb_ext_str_new_static_frozen(mrb, value.as_ptr(), value.len())
+    }
+
     #[inline]
     pub unsafe fn symbol(mrb: *const MrState, value: &str) -> MrValue {
         mrb_ext_sym_new(mrb, value.as_ptr(), value.len())
@@ -75,7 +87,12 @@ impl MrValue {
     #[inline]
     pub unsafe fn obj<T: Any>(mrb: *const MrState, class: *const MrClass,
                               obj: T, typ: &MrDataType) -> MrValue {
-        let rc = Rc::new(RefCell::new(obj));
+        MrValue::rc(mrb, class, Rc::new(RefCell::new(obj)), typ)
+    }
+
+    #[inline]
+    pub unsafe fn rc<T: Any>(mrb: *const MrState, class: *const MrClass,
+                             rc: Rc<RefCell<T>>, typ: &MrDataType) -> MrValue {
         let ptr: *const u8 = mem::transmute(rc);
         let data = mrb_data_object_alloc(mrb, class, ptr, typ as *const MrDataType);
 
@@ -133,12 +150,29 @@ impl MrValue {
             MrType::MRB_TT_STRING => {
                 let s = mrb_str_to_cstr(mrb, *self) as *const i8;
 
-                Ok(CStr::from_ptr(s).to_str().unwrap().clone())
+                Ok(CStr::from_ptr(s).to_str().unwrap())
             },
             MrType::MRB_TT_SYMBOL => {
                 let s = mrb_ext_sym2name(mrb, *self) as *const i8;
 
-                Ok(CStr::from_ptr(s).to_str().unwrap().clone())
+                Ok(CStr::from_ptr(s).to_str().unwrap())
+            },
+            _ => Err(MrubyError::Cast("String".to_owned()))
+        }
+    }
+
+    #[inline]
+    pub unsafe fn to_bytes<'a>(&self) -> Result<&'a [u8], MrubyError> {
+        match self.typ {
+            MrType::MRB_TT_STRING => {
+                let ptr = mrb_ext_str_ptr(*self);
+                let len = mrb_ext_str_len(*self);
+
+                if len == 0 {
+                    Ok(&[])
+                } else {
+                    Ok(slice::from_raw_parts(ptr, len))
+                }
             },
             _ => Err(MrubyError::Cast("String".to_owned()))
         }
@@ -244,6 +278,27 @@ extern "C" {
     pub fn mrb_open() -> *const MrState;
     pub fn mrb_close(mrb: *const MrState);
 
+    pub fn mrb_ext_open() -> *const MrState;
+    pub fn mrb_ext_close(mrb: *const MrState);
+    pub fn mrb_ext_memory_used(mrb: *const MrState) -> usize;
+    pub fn mrb_ext_set_memory_limit(mrb: *const MrState, limit: usize);
+    pub fn mrb_ext_gc_enable(mrb: *const MrState);
+    pub fn mrb_ext_gc_disable(mrb: *const MrState);
+    pub fn mrb_ext_set_data_mark(mrb: *const MrState, typ: *const MrDataType,
+                                 mark: extern "C" fn(*const MrState, *const u8));
+    pub fn mrb_ext_gc_mark_value(mrb: *const MrState, value: MrValue);
+    pub fn mrb_ext_shared_get(mrb: *const MrState, obj: *const c_void,
+                              wrapper: *mut MrValue) -> bool;
+    pub fn mrb_ext_shared_set(mrb: *const MrState, obj: *const c_void, wrapper: MrValue);
+    pub fn mrb_ext_shared_remove(mrb: *const MrState, obj: *const c_void);
+    pub fn mrb_ext_set_finalizing(mrb: *const MrState, finalizing: bool);
+    #[cfg(feature = "instruction-limit")]
+    pub fn mrb_ext_set_instruction_limit(mrb: *const MrState, count: u64);
+    #[cfg(feature = "instruction-limit")]
+    pub fn mrb_ext_reset_instruction_counter(mrb: *const MrState);
+    #[cfg(feature = "instruction-limit")]
+    pub fn mrb_ext_instruction_limit_exceeded(mrb: *const MrState) -> bool;
+
     #[inline]
     pub fn mrb_ext_get_ud(mrb: *const MrState) -> *const u8;
     #[inline]
@@ -254,15 +309,23 @@ extern "C" {
 
     pub fn mrbc_filename(mrb: *const MrState, context: *const MrContext,
                          filename: *const c_char) -> *const c_char;
+    pub fn mrb_ext_set_capture_errors(context: *const MrContext, capture: bool);
 
     pub fn mrb_load_nstring_cxt(mrb: *const MrState, code: *const u8, len: i32,
                                 context: *const MrContext) -> MrValue;
+    pub fn mrb_ext_load_nstring_cxt(mrb: *const MrState, code: *const u8, len: i32,
+                                    context: *const MrContext,
+                                    warn: Option<extern "C" fn(*const MrState, *const c_char)>)
+                                    -> MrValue;
     pub fn mrb_load_irep_cxt(mrb: *const MrState, code: *const u8,
                              context: *const MrContext) -> MrValue;
 
     pub fn mrb_class_defined(mrb: *const MrState, name: *const c_char) -> bool;
     pub fn mrb_ext_class_defined_under(mrb: *const MrState, outer: *const MrClass,
                                        name: *const c_char) -> bool;
+    pub fn mrb_ext_is_a_named(mrb: *const MrState, value: MrValue, name: *const c_char) -> bool;
+    pub fn mrb_obj_is_kind_of(mrb: *const MrState, value: MrValue, class: *const MrClass) -> bool;
+    pub fn mrb_obj_equal(mrb: *const MrState, value: MrValue, other: MrValue) -> bool;
 
     pub fn mrb_class_get(mrb: *const MrState, name: *const c_char) -> *const MrClass;
     pub fn mrb_module_get(mrb: *const MrState, name: *const c_char) -> *const MrClass;
@@ -283,6 +346,9 @@ extern "C" {
 
     pub fn mrb_define_const(mrb: *const MrState, class: *const MrClass, name: *const c_char,
                             value: MrValue);
+    pub fn mrb_const_get(mrb: *const MrState, scope: MrValue, sym: u32) -> MrValue;
+    pub fn mrb_const_set(mrb: *const MrState, scope: MrValue, sym: u32, value: MrValue);
+    pub fn mrb_const_remove(mrb: *const MrState, scope: MrValue, sym: u32);
     pub fn mrb_define_module_function(mrb: *const MrState, module: *const MrClass,
                                       name: *const c_char, fun: MrFunc, aspec: u32);
 
@@ -292,21 +358,59 @@ extern "C" {
 
     pub fn mrb_define_method(mrb: *const MrState, class: *const MrClass, name: *const c_char,
                              fun: MrFunc, aspec: u32);
+    pub fn mrb_define_method_id(mrb: *const MrState, class: *const MrClass, sym: u32, fun: MrFunc,
+                                aspec: u32);
     pub fn mrb_define_class_method(mrb: *const MrState, class: *const MrClass, name: *const c_char,
                                    fun: MrFunc, aspec: u32);
+    pub fn mrb_singleton_class(mrb: *const MrState, value: MrValue) -> MrValue;
+    pub fn mrb_define_method_raw(mrb: *const MrState, class: *const MrClass, sym: u32,
+                                 proc_: *const MrProc);
+    pub fn mrb_undef_method(mrb: *const MrState, class: *const MrClass, name: *const c_char);
+    pub fn mrb_undef_class_method(mrb: *const MrState, class: *const MrClass,
+                                  name: *const c_char);
+
+    pub fn mrb_proc_new_cfunc_with_env(mrb: *const MrState, fun: MrFunc, argc: i32,
+                                       argv: *const MrValue) -> *const MrProc;
+    pub fn mrb_proc_cfunc_env_get(mrb: *const MrState, idx: i32) -> MrValue;
 
     pub fn mrb_protect(mrb: *const MrState, fun: MrFunc, data: MrValue, state: *const bool) -> MrValue;
 
+    pub fn mrb_obj_id(value: MrValue) -> i32;
+
+    pub fn mrb_full_gc(mrb: *const MrState);
+    pub fn mrb_gc_arena_save(mrb: *const MrState) -> i32;
+    pub fn mrb_gc_arena_restore(mrb: *const MrState, idx: i32);
+    pub fn mrb_gc_protect(mrb: *const MrState, value: MrValue);
+    pub fn mrb_gc_register(mrb: *const MrState, value: MrValue);
+    pub fn mrb_gc_unregister(mrb: *const MrState, value: MrValue);
+
     #[inline]
     pub fn mrb_ext_class(mrb: *const MrState, value: MrValue) -> *const MrClass;
 
     pub fn mrb_get_args(mrb: *const MrState, format: *const c_char, ...);
     pub fn mrb_ext_get_mid(mrb: *const MrState) -> u32;
+    pub fn mrb_ext_get_target_class(mrb: *const MrState) -> *const MrClass;
+    pub fn mrb_ext_named_caller(mrb: *const MrState, caller: *mut MrValue) -> bool;
 
     pub fn mrb_intern(mrb: *const MrState, string: *const c_char, len: usize) -> u32;
+    pub fn mrb_sym2name(mrb: *const MrState, sym: u32) -> *const c_char;
+    pub fn mrb_gv_get(mrb: *const MrState, sym: u32) -> MrValue;
+    pub fn mrb_gv_set(mrb: *const MrState, sym: u32, value: MrValue);
+    pub fn mrb_gv_remove(mrb: *const MrState, sym: u32);
+    pub fn mrb_sym2name_len(mrb: *const MrState, sym: u32, len: *mut i32) -> *const c_char;
 
     pub fn mrb_funcall_argv(mrb: *const MrState, object: MrValue, sym: u32, argc: i32,
                             argv: *const MrValue) -> MrValue;
+    pub fn mrb_ext_keep_previous_method(mrb: *const MrState, class: *const MrClass, sym: u32);
+    pub fn mrb_ext_call_super(mrb: *const MrState, object: MrValue, sym: u32, argc: i32,
+                              argv: *const MrValue) -> MrValue;
+    pub fn mrb_ext_method_proc(mrb: *const MrState, object: MrValue, sym: u32) -> MrValue;
+    pub fn mrb_ext_instance_method_proc(mrb: *const MrState, class: MrValue, sym: u32) -> MrValue;
+    pub fn mrb_ext_define_method_proc(mrb: *const MrState, target: MrValue, singleton: bool,
+                                      sym: u32, proc_: MrValue);
+    pub fn mrb_ext_fiber_terminate_raised(mrb: *const MrState, fiber: MrValue);
+    pub fn mrb_ext_check_syntax(mrb: *const MrState, code: *const u8, len: usize,
+                                state: *mut i32) -> MrValue;
 
     #[inline]
     pub fn mrb_iv_defined(mrb: *const MrState, object: MrValue, sym: u32) -> bool;
@@ -315,6 +419,10 @@ extern "C" {
     #[inline]
     pub fn mrb_iv_set(mrb: *const MrState, object: MrValue, sym: u32, value: MrValue);
 
+    pub fn mrb_cv_defined(mrb: *const MrState, module: MrValue, sym: u32) -> bool;
+    pub fn mrb_cv_get(mrb: *const MrState, module: MrValue, sym: u32) -> MrValue;
+    pub fn mrb_mod_cv_set(mrb: *const MrState, class: *const MrClass, sym: u32, value: MrValue);
+
     #[inline]
     pub fn mrb_ext_fixnum_to_cint(value: MrValue) -> i32;
     #[inline]
@@ -332,8 +440,19 @@ extern "C" {
     pub fn mrb_ext_cdouble_to_float(mrb: *const MrState, value: f64) -> MrValue;
     #[inline]
     pub fn mrb_str_new(mrb: *const MrState, value: *const u8, len: usize) -> MrValue;
+    pub fn mrb_str_concat(mrb: *const MrState, string: MrValue, other: MrValue);
+    pub fn mrb_str_cat(mrb: *const MrState, string: MrValue, value: *const u8,
+                       len: usize) -> MrValue;
+    #[inline]
+    pub fn mrb_ext_str_new_static_frozen(mrb: *const MrState, value: *const u8,
+                                         len: usize) -> MrValue;
+    #[inline]
+    pub fn mrb_ext_str_ptr(value: MrValue) -> *const u8;
+    #[inline]
+    pub fn mrb_ext_str_len(value: MrValue) -> usize;
     #[inline]
     pub fn mrb_ext_sym2name(mrb: *const MrState, value: MrValue) -> *const u8;
+    pub fn mrb_ext_sym_id(value: MrValue) -> u32;
     #[inline]
     pub fn mrb_ext_sym_new(mrb: *const MrState, value: *const u8, len: usize) -> MrValue;
     #[inline]
@@ -365,15 +484,45 @@ extern "C" {
     pub fn mrb_ary_ref(mrb: *const MrState, array: MrValue, i: i32) -> MrValue;
     #[inline]
     pub fn mrb_ary_set(mrb: *const MrState, array: MrValue, i: i32, value: MrValue);
+    pub fn mrb_ary_new(mrb: *const MrState) -> MrValue;
+    pub fn mrb_ary_new_from_values(mrb: *const MrState, size: i32, values: *const MrValue)
+        -> MrValue;
+    pub fn mrb_ary_push(mrb: *const MrState, array: MrValue, value: MrValue);
+    pub fn mrb_ary_concat(mrb: *const MrState, array: MrValue, other: MrValue);
     #[inline]
     pub fn mrb_ext_ary_len(mrb: *const MrState, array: MrValue) -> i32;
 
+    pub fn mrb_hash_new(mrb: *const MrState) -> MrValue;
+    pub fn mrb_hash_set(mrb: *const MrState, hash: MrValue, key: MrValue, value: MrValue);
+    pub fn mrb_hash_get(mrb: *const MrState, hash: MrValue, key: MrValue) -> MrValue;
+    pub fn mrb_hash_fetch(mrb: *const MrState, hash: MrValue, key: MrValue,
+                          default: MrValue) -> MrValue;
+    pub fn mrb_hash_delete_key(mrb: *const MrState, hash: MrValue, key: MrValue) -> MrValue;
+    pub fn mrb_hash_keys(mrb: *const MrState, hash: MrValue) -> MrValue;
+    pub fn mrb_ext_hash_values(mrb: *const MrState, hash: MrValue) -> MrValue;
+    #[inline]
+    pub fn mrb_ext_hash_size(hash: MrValue) -> usize;
+
+    pub fn mrb_range_new(mrb: *const MrState, start: MrValue, end: MrValue,
+                         exclusive: bool) -> MrValue;
+    pub fn mrb_ext_range_beg(range: MrValue) -> MrValue;
+    pub fn mrb_ext_range_end(range: MrValue) -> MrValue;
+    pub fn mrb_ext_range_excl(range: MrValue) -> bool;
+
     #[inline]
     pub fn mrb_ext_raise(mrb: *const MrState, eclass: *const c_char, msg: *const c_char);
+    pub fn mrb_exc_new(mrb: *const MrState, class: *const MrClass, msg: *const u8,
+                       len: usize) -> MrValue;
+    pub fn mrb_exc_raise(mrb: *const MrState, exc: MrValue) -> !;
     #[inline]
     pub fn mrb_ext_raise_current(mrb: *const MrState);
+    pub fn mrb_ext_exc_restore(mrb: *const MrState, exc: MrValue);
+    pub fn mrb_ext_raise_nomem(mrb: *const MrState);
     #[inline]
     pub fn mrb_ext_exc_str(mrb: *const MrState, exc: MrValue) -> MrValue;
+    #[inline]
+    pub fn mrb_exc_backtrace(mrb: *const MrState, exc: MrValue) -> MrValue;
+    pub fn mrb_get_backtrace(mrb: *const MrState) -> MrValue;
 
     #[inline]
     pub fn mrb_ext_get_class(class: MrValue) -> *const MrClass;
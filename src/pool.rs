@@ -0,0 +1,297 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::os::raw::c_char;
+
+use super::mruby::*;
+use super::mruby_ffi::{mrb_gv_remove, mrb_intern};
+
+/// An `enum` choosing how a `MrubyPool` cleans an interpreter up before reusing it. Top-level
+/// local variables are always forgotten.
+///
+/// *Note:* Only what scripts define at the top level is tracked, i.e. global variables,
+/// constants and methods of `Object` and instance variables of the top-level `self`. Classes
+/// which existed after setup and are reopened by a script keep their changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolReset {
+    /// Removes everything defined at the top level since setup.
+    Clear,
+    /// Drops the interpreter if anything was defined at the top level since setup, so that a new
+    /// one gets set up on the next checkout.
+    Rebuild
+}
+
+/// A `struct` that keeps set-up interpreters around to run one script after the other, e.g. one
+/// per request, without paying for `Mruby::new` and the setup every time.
+///
+/// Interpreters are not `Send`, so a `MrubyPool` lives on a single thread. Servers keep one
+/// per worker thread, e.g. in a `thread_local!`.
+///
+/// # Examples
+///
+/// ```
+/// # use mrusty::Mruby;
+/// # use mrusty::MrubyImpl;
+/// # use mrusty::MrubyPool;
+/// let pool = MrubyPool::new(|mruby| {
+///     mruby.run("def greet(name); \"hello #{name}\"; end").unwrap();
+/// });
+///
+/// let greeting = pool.with(|mruby| {
+///     mruby.run("$greeted = true; greet 'pool'").unwrap().to_str().unwrap().to_owned()
+/// });
+///
+/// assert_eq!(greeting, "hello pool");
+///
+/// let greeted = pool.with(|mruby| mruby.run("$greeted.nil?").unwrap().to_bool().unwrap());
+///
+/// assert!(greeted);
+/// ```
+pub struct MrubyPool {
+    setup: Box<Fn(&MrubyType)>,
+    reset: PoolReset,
+    idle:  RefCell<Vec<Pooled>>,
+    built: Cell<usize>
+}
+
+struct Pooled {
+    mruby:     MrubyType,
+    top_level: TopLevel
+}
+
+// Names of everything defined at the top level.
+#[derive(PartialEq)]
+struct TopLevel {
+    globals:   HashSet<String>,
+    constants: HashSet<String>,
+    methods:   HashSet<String>,
+    ivars:     HashSet<String>
+}
+
+impl MrubyPool {
+    /// Creates an empty `MrubyPool` which runs `setup` on every interpreter it creates and
+    /// cleans interpreters up with `PoolReset::Clear`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::MrubyPool;
+    /// struct Cont;
+    ///
+    /// let pool = MrubyPool::new(|mruby| {
+    ///     mruby.def_class_for::<Cont>("Container");
+    /// });
+    /// ```
+    pub fn new<F: Fn(&MrubyType) + 'static>(setup: F) -> MrubyPool {
+        MrubyPool {
+            setup: Box::new(setup),
+            reset: PoolReset::Clear,
+            idle:  RefCell::new(vec![]),
+            built: Cell::new(0)
+        }
+    }
+
+    /// Sets how interpreters are cleaned up before they are reused.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::MrubyPool;
+    /// # use mrusty::PoolReset;
+    /// let pool = MrubyPool::new(|_| {}).reset(PoolReset::Rebuild);
+    ///
+    /// pool.with(|mruby| mruby.run("$dirty = true").unwrap());
+    /// pool.with(|mruby| mruby.run("1 + 1").unwrap());
+    ///
+    /// assert_eq!(pool.built(), 2);
+    /// ```
+    pub fn reset(mut self, reset: PoolReset) -> MrubyPool {
+        self.reset = reset;
+
+        self
+    }
+
+    /// Checks an interpreter out of the pool, setting a new one up if none is idle, and runs `f`
+    /// on it. The interpreter goes back to the pool once it is cleaned up, unless `f` panicked.
+    ///
+    /// *Note:* `Value`s created by `f` are only valid while it runs, since the interpreter
+    /// restores its GC arena afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::MrubyPool;
+    /// let pool = MrubyPool::new(|mruby| {
+    ///     mruby.run("LIMIT = 10").unwrap();
+    /// });
+    ///
+    /// for i in 0..3 {
+    ///     let result = pool.with(|mruby| {
+    ///         mruby.run(&format!("LIMIT - {}", i)).unwrap().to_i32().unwrap()
+    ///     });
+    ///
+    ///     assert_eq!(result, 10 - i);
+    /// }
+    ///
+    /// assert_eq!(pool.built(), 1);
+    /// ```
+    pub fn with<R, F: FnOnce(&MrubyType) -> R>(&self, f: F) -> R {
+        let pooled = self.idle.borrow_mut().pop();
+        let pooled = match pooled {
+            Some(pooled) => pooled,
+            None         => self.build()
+        };
+
+        let arena = pooled.mruby.gc_arena_save();
+
+        let result = f(&pooled.mruby);
+        let reuse = self.clean(&pooled);
+
+        pooled.mruby.gc_arena_restore(arena);
+
+        if reuse {
+            self.idle.borrow_mut().push(pooled);
+        }
+
+        result
+    }
+
+    /// Returns the number of idle interpreters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::MrubyPool;
+    /// let pool = MrubyPool::new(|_| {});
+    ///
+    /// assert_eq!(pool.idle(), 0);
+    ///
+    /// pool.with(|_| pool.with(|_| {}));
+    ///
+    /// assert_eq!(pool.idle(), 2);
+    /// ```
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Returns the number of interpreters set up by the pool so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mrusty::Mruby;
+    /// # use mrusty::MrubyImpl;
+    /// # use mrusty::MrubyPool;
+    /// let pool = MrubyPool::new(|_| {});
+    ///
+    /// pool.with(|_| {});
+    /// pool.with(|_| {});
+    ///
+    /// assert_eq!(pool.built(), 1);
+    /// ```
+    pub fn built(&self) -> usize {
+        self.built.get()
+    }
+
+    fn build(&self) -> Pooled {
+        let mruby = Mruby::new();
+
+        (self.setup)(&mruby);
+
+        mruby.reset_locals();
+
+        self.built.set(self.built.get() + 1);
+
+        let top_level = TopLevel::read(&mruby);
+
+        Pooled {
+            mruby:     mruby,
+            top_level: top_level
+        }
+    }
+
+    // Returns whether pooled can be reused.
+    fn clean(&self, pooled: &Pooled) -> bool {
+        pooled.mruby.reset_locals();
+
+        let top_level = TopLevel::read(&pooled.mruby);
+
+        if top_level == pooled.top_level {
+            return true;
+        }
+
+        match self.reset {
+            PoolReset::Clear   => {
+                pooled.top_level.clear(&pooled.mruby, &top_level);
+
+                true
+            },
+            PoolReset::Rebuild => false
+        }
+    }
+}
+
+impl TopLevel {
+    fn read(mruby: &MrubyType) -> TopLevel {
+        let names = mruby.run("
+          [
+            global_variables, Object.constants, Object.instance_methods(false),
+            instance_variables
+          ].map { |names| names.map { |name| name.to_s } }
+        ").unwrap().to_vec().unwrap();
+
+        let mut names = names.into_iter().map(|names| {
+            names.to_vec().unwrap().iter().map(|name| {
+                name.to_str().unwrap().to_owned()
+            }).collect::<HashSet<_>>()
+        });
+
+        TopLevel {
+            globals:   names.next().unwrap(),
+            constants: names.next().unwrap(),
+            methods:   names.next().unwrap(),
+            ivars:     names.next().unwrap()
+        }
+    }
+
+    // Removes what current defines on top of self.
+    fn clear(&self, mruby: &MrubyType, current: &TopLevel) {
+        let object = mruby.get_class("Object").unwrap().to_value();
+        let top_self = mruby.run("self").unwrap();
+
+        for name in current.globals.difference(&self.globals) {
+            unsafe {
+                let mrb = mruby.borrow().mrb;
+                let sym = mrb_intern(mrb, name.as_ptr() as *const c_char, name.len());
+
+                mrb_gv_remove(mrb, sym);
+            }
+        }
+
+        for name in current.constants.difference(&self.constants) {
+            object.call("remove_const", vec![mruby.symbol(name)]).unwrap();
+        }
+
+        for name in current.methods.difference(&self.methods) {
+            object.call("remove_method", vec![mruby.symbol(name)]).unwrap();
+        }
+
+        for name in current.ivars.difference(&self.ivars) {
+            top_self.call("remove_instance_variable", vec![mruby.symbol(name)]).unwrap();
+        }
+    }
+}
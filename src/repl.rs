@@ -63,7 +63,8 @@ impl Repl {
 
     /// Starts a `Repl`.
     ///
-    /// Use `'\'` for multiline statements.
+    /// Statements that are not complete yet, like a `def` without its `end`, continue on the next
+    /// line. Use `'\'` to continue any other line.
     ///
     /// # Examples
     ///
@@ -117,11 +118,15 @@ impl Repl {
                 command = command + &input;
             }
 
+            if self.mruby.check_syntax(&command) == SyntaxResult::Incomplete {
+                command = command + "\n";
+
+                continue
+            }
+
             match self.mruby.run(&command) {
                 Ok(value) => {
-                    let result = value.call("to_s", vec![]).unwrap().to_str().unwrap();
-
-                    println!("{}", result);
+                    println!("{}", value.inspect());
                 },
                 Err(message) => {
                     println!("{}", message);
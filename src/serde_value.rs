@@ -0,0 +1,701 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+                SeqAccess, VariantAccess, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+use super::*;
+
+/// Error of a conversion together with the path of the value it happened at, innermost segment
+/// last.
+#[derive(Debug)]
+pub struct SerdeError {
+    path:    Vec<String>,
+    message: String
+}
+
+impl SerdeError {
+    fn at(mut self, segment: String) -> SerdeError {
+        self.path.insert(0, segment);
+
+        self
+    }
+
+    fn key(self, key: &str) -> SerdeError {
+        self.at(format!(".{}", key))
+    }
+
+    fn index(self, index: usize) -> SerdeError {
+        self.at(format!("[{}]", index))
+    }
+}
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            let path = self.path.concat();
+
+            write!(f, "{}: {}", path.trim_start_matches('.'), self.message)
+        }
+    }
+}
+
+impl Error for SerdeError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(message: T) -> SerdeError {
+        SerdeError { path: vec![], message: message.to_string() }
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(message: T) -> SerdeError {
+        SerdeError { path: vec![], message: message.to_string() }
+    }
+}
+
+impl From<SerdeError> for MrubyError {
+    fn from(error: SerdeError) -> MrubyError {
+        MrubyError::Serde(error.to_string())
+    }
+}
+
+pub fn to_value<T: Serialize + ?Sized>(mruby: &MrubyType, value: &T) -> Result<Value, MrubyError> {
+    Ok(value.serialize(ValueSerializer { mruby })?)
+}
+
+pub fn from_value<T: de::DeserializeOwned>(value: &Value) -> Result<T, MrubyError> {
+    Ok(T::deserialize(ValueDeserializer { value: value.clone() })?)
+}
+
+struct ValueSerializer<'a> {
+    mruby: &'a MrubyType
+}
+
+impl<'a> ValueSerializer<'a> {
+    fn fixnum<T: TryInto<i32> + fmt::Display + Copy>(&self, value: T) -> Result<Value, SerdeError> {
+        match value.try_into() {
+            Ok(value) => Ok(self.mruby.fixnum(value)),
+            Err(_)    => Err(ser::Error::custom(format!("{} does not fit in a Fixnum", value)))
+        }
+    }
+
+    fn tagged(&self, variant: &str, value: Value) -> Value {
+        let mrb = self.mruby.borrow().mrb;
+
+        unsafe {
+            let hash = Value::new(self.mruby.clone(), mrb_hash_new(mrb));
+
+            mrb_hash_set(mrb, hash.value, self.mruby.symbol(variant).value, value.value);
+
+            hash
+        }
+    }
+}
+
+impl<'a> Serializer for ValueSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    type SerializeSeq = SeqSerializer<'a>;
+    type SerializeTuple = SeqSerializer<'a>;
+    type SerializeTupleStruct = SeqSerializer<'a>;
+    type SerializeTupleVariant = SeqSerializer<'a>;
+    type SerializeMap = MapSerializer<'a>;
+    type SerializeStruct = MapSerializer<'a>;
+    type SerializeStructVariant = MapSerializer<'a>;
+
+    fn serialize_bool(self, value: bool) -> Result<Value, SerdeError> {
+        Ok(self.mruby.bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value, SerdeError> {
+        self.fixnum(value)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value, SerdeError> {
+        Ok(self.mruby.float(value as f64))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value, SerdeError> {
+        Ok(self.mruby.float(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value, SerdeError> {
+        Ok(self.mruby.string(&value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value, SerdeError> {
+        Ok(self.mruby.string(value))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value, SerdeError> {
+        Ok(self.mruby.value(value))
+    }
+
+    fn serialize_none(self) -> Result<Value, SerdeError> {
+        Ok(self.mruby.nil())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, SerdeError> {
+        Ok(self.mruby.nil())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, SerdeError> {
+        Ok(self.mruby.nil())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32,
+                              variant: &'static str) -> Result<Value, SerdeError> {
+        Ok(self.mruby.symbol(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                       value: &T) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        variant: &'static str,
+                                                        value: &T) -> Result<Value, SerdeError> {
+        let value = value.serialize(ValueSerializer { mruby: self.mruby })
+                         .map_err(|error| error.key(variant))?;
+
+        Ok(self.tagged(variant, value))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer<'a>, SerdeError> {
+        Ok(SeqSerializer {
+            mruby:   self.mruby,
+            values:  Vec::with_capacity(len.unwrap_or(0)),
+            variant: None
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'a>, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str,
+                              len: usize) -> Result<SeqSerializer<'a>, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                               len: usize) -> Result<SeqSerializer<'a>, SerdeError> {
+        Ok(SeqSerializer {
+            mruby:   self.mruby,
+            values:  Vec::with_capacity(len),
+            variant: Some(variant)
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer<'a>, SerdeError> {
+        let hash = unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            Value::new(self.mruby.clone(), mrb_hash_new(mrb))
+        };
+
+        Ok(MapSerializer { mruby: self.mruby, hash, key: None, variant: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str,
+                        len: usize) -> Result<MapSerializer<'a>, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                                len: usize) -> Result<MapSerializer<'a>, SerdeError> {
+        let mut map = self.serialize_map(Some(len))?;
+
+        map.variant = Some(variant);
+
+        Ok(map)
+    }
+}
+
+struct SeqSerializer<'a> {
+    mruby:   &'a MrubyType,
+    values:  Vec<Value>,
+    variant: Option<&'static str>
+}
+
+impl<'a> SeqSerializer<'a> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let index = self.values.len();
+        let value = value.serialize(ValueSerializer { mruby: self.mruby })
+                         .map_err(|error| error.index(index))?;
+
+        self.values.push(value);
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, SerdeError> {
+        let array = self.mruby.array(self.values);
+
+        match self.variant {
+            Some(variant) => Ok(ValueSerializer { mruby: self.mruby }.tagged(variant, array)),
+            None          => Ok(array)
+        }
+    }
+}
+
+impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for SeqSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SeqSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for SeqSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let variant = self.variant.unwrap();
+
+        self.push(value).map_err(|error| error.key(variant))
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+struct MapSerializer<'a> {
+    mruby:   &'a MrubyType,
+    hash:    Value,
+    key:     Option<Value>,
+    variant: Option<&'static str>
+}
+
+impl<'a> MapSerializer<'a> {
+    fn insert(&self, key: Value, value: Value) {
+        unsafe {
+            let mrb = self.mruby.borrow().mrb;
+
+            mrb_hash_set(mrb, self.hash.value, key.value, value.value);
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &'static str,
+                                    value: &T) -> Result<(), SerdeError> {
+        let value = value.serialize(ValueSerializer { mruby: self.mruby })
+                         .map_err(|error| error.key(key))?;
+
+        self.insert(self.mruby.symbol(key), value);
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, SerdeError> {
+        match self.variant {
+            Some(variant) => Ok(ValueSerializer { mruby: self.mruby }.tagged(variant, self.hash)),
+            None          => Ok(self.hash)
+        }
+    }
+}
+
+impl<'a> ser::SerializeMap for MapSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.key = Some(key.serialize(ValueSerializer { mruby: self.mruby })?);
+
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self.key.take().unwrap();
+        let value = value.serialize(ValueSerializer { mruby: self.mruby }).map_err(|error| {
+            match key.to_str() {
+                Ok(key) => error.key(key),
+                Err(_)  => error
+            }
+        })?;
+
+        self.insert(key, value);
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for MapSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str,
+                                              value: &T) -> Result<(), SerdeError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for MapSerializer<'a> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str,
+                                              value: &T) -> Result<(), SerdeError> {
+        let variant = self.variant.unwrap();
+
+        self.field(key, value).map_err(|error| error.key(variant))
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+struct ValueDeserializer {
+    value: Value
+}
+
+impl ValueDeserializer {
+    fn type_name(&self) -> String {
+        self.value.class().to_str().to_owned()
+    }
+
+    fn invalid_type<V>(&self, expected: &dyn de::Expected) -> Result<V, SerdeError> {
+        Err(de::Error::custom(format!("invalid type: {}, expected {}", self.type_name(),
+                                      expected)))
+    }
+
+    fn is_nil(&self) -> bool {
+        self.value.value.typ == MrType::MRB_TT_FALSE && self.value.value.value as u32 == 0
+    }
+
+    fn entries(&self) -> Vec<(Value, Value)> {
+        let mruby = &self.value.mruby;
+        let mrb = mruby.borrow().mrb;
+
+        unsafe {
+            let keys = Value::new(mruby.clone(), mrb_hash_keys(mrb, self.value.value));
+
+            keys.to_vec().unwrap().into_iter().map(|key| {
+                let value = Value::new(mruby.clone(), mrb_hash_get(mrb, self.value.value,
+                                                                   key.value));
+
+                (key, value)
+            }).collect()
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.value.value.typ {
+            MrType::MRB_TT_FALSE if self.is_nil() => visitor.visit_unit(),
+            MrType::MRB_TT_FALSE  => visitor.visit_bool(false),
+            MrType::MRB_TT_TRUE   => visitor.visit_bool(true),
+            MrType::MRB_TT_FIXNUM => visitor.visit_i32(self.value.to_i32().unwrap()),
+            MrType::MRB_TT_FLOAT  => visitor.visit_f64(self.value.to_f64().unwrap()),
+            MrType::MRB_TT_STRING |
+            MrType::MRB_TT_SYMBOL => visitor.visit_str(self.value.to_str().unwrap()),
+            MrType::MRB_TT_ARRAY  => {
+                let values = self.value.to_vec().unwrap();
+
+                visitor.visit_seq(SeqDeserializer { values: values.into_iter(), index: 0 })
+            },
+            MrType::MRB_TT_HASH   => {
+                let entries = self.entries();
+
+                visitor.visit_map(MapDeserializer { entries: entries.into_iter(), key: None })
+            },
+            _                     => self.invalid_type(&visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if self.is_nil() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                                   visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value, SerdeError> {
+        match self.value.value.typ {
+            MrType::MRB_TT_STRING |
+            MrType::MRB_TT_SYMBOL => {
+                let variant = self.value.to_str().unwrap().to_owned();
+
+                visitor.visit_enum(EnumDeserializer { variant, value: None })
+            },
+            MrType::MRB_TT_HASH   => {
+                let mut entries = self.entries();
+
+                if entries.len() != 1 {
+                    return Err(de::Error::custom("expected a Hash with a single variant key"));
+                }
+
+                let (key, value) = entries.pop().unwrap();
+
+                let variant = match key.to_str() {
+                    Ok(variant) => variant.to_owned(),
+                    Err(_)      => {
+                        return ValueDeserializer { value: key }.invalid_type(&"a variant name");
+                    }
+                };
+
+                visitor.visit_enum(EnumDeserializer { variant, value: Some(value) })
+            },
+            _                     => self.invalid_type(&visitor)
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqDeserializer {
+    values: ::std::vec::IntoIter<Value>,
+    index:  usize
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self,
+                                                  seed: T) -> Result<Option<T::Value>, SerdeError> {
+        match self.values.next() {
+            Some(value) => {
+                let index = self.index;
+
+                self.index += 1;
+
+                seed.deserialize(ValueDeserializer { value })
+                    .map(Some)
+                    .map_err(|error| error.index(index))
+            },
+            None        => Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct MapDeserializer {
+    entries: ::std::vec::IntoIter<(Value, Value)>,
+    key:     Option<(String, Value)>
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self,
+                                              seed: K) -> Result<Option<K::Value>, SerdeError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                let name = match key.to_str() {
+                    Ok(key) => key.to_owned(),
+                    Err(_)  => key.call0("inspect").ok()
+                                  .and_then(|key| key.to_str().ok().map(|key| key.to_owned()))
+                                  .unwrap_or_default()
+                };
+
+                let result = seed.deserialize(ValueDeserializer { value: key })
+                                 .map_err(|error| error.key(&name));
+
+                self.key = Some((name, value));
+
+                result.map(Some)
+            },
+            None               => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self,
+                                                seed: V) -> Result<V::Value, SerdeError> {
+        let (name, value) = self.key.take().unwrap();
+
+        seed.deserialize(ValueDeserializer { value }).map_err(|error| error.key(&name))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value:   Option<Value>
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = SerdeError;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self,
+                                             seed: V) -> Result<(V::Value, VariantDeserializer),
+                                                                SerdeError> {
+        let variant = self.variant;
+        let name: de::value::StringDeserializer<SerdeError> = variant.clone().into_deserializer();
+
+        let result = seed.deserialize(name).map_err(|error| error.key(&variant))?;
+
+        Ok((result, VariantDeserializer { variant, value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    variant: String,
+    value:   Option<Value>
+}
+
+impl VariantDeserializer {
+    fn value(self) -> Result<(String, ValueDeserializer), SerdeError> {
+        match self.value {
+            Some(value) => Ok((self.variant, ValueDeserializer { value })),
+            None        => {
+                let error: SerdeError = de::Error::custom("expected a Hash with the variant's data");
+
+                Err(error.key(&self.variant))
+            }
+        }
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        let variant = self.variant;
+
+        match self.value {
+            Some(value) => {
+                let value = ValueDeserializer { value };
+
+                if value.is_nil() {
+                    Ok(())
+                } else {
+                    value.invalid_type(&"nil").map_err(|error| error.key(&variant))
+                }
+            },
+            None        => Ok(())
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self,
+                                                     seed: T) -> Result<T::Value, SerdeError> {
+        let (variant, value) = self.value()?;
+
+        seed.deserialize(value).map_err(|error| error.key(&variant))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize,
+                                      visitor: V) -> Result<V::Value, SerdeError> {
+        let (variant, value) = self.value()?;
+
+        value.deserialize_any(visitor).map_err(|error| error.key(&variant))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str],
+                                       visitor: V) -> Result<V::Value, SerdeError> {
+        let (variant, value) = self.value()?;
+
+        value.deserialize_any(visitor).map_err(|error| error.key(&variant))
+    }
+}
@@ -221,6 +221,23 @@ fn mrusty_class_mruby_values() {
     assert_eq!(result.to_bool().unwrap(), true);
 }
 
+#[test]
+fn mrusty_initialize_block_empty() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container", {
+        def!("initialize", |; &_block| {
+            Cont
+        });
+    });
+
+    Cont::require(mruby.clone());
+
+    mruby.run("Container.new {}").unwrap();
+}
+
 #[test]
 fn mrusty_initialize_block_only_values() {
     let mruby = Mruby::new();
@@ -424,6 +441,23 @@ fn mrusty_class_block_mruby_values() {
     assert_eq!(result.to_i32().unwrap(), 3);
 }
 
+#[test]
+fn mrusty_initialize_args_empty() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container", {
+        def!("initialize", |; _args| {
+            Cont
+        });
+    });
+
+    Cont::require(mruby.clone());
+
+    mruby.run("Container.new(1, 2)").unwrap();
+}
+
 #[test]
 fn mrusty_initialize_args_only_values() {
     let mruby = Mruby::new();
@@ -627,6 +661,23 @@ fn mrusty_class_args_mruby_values() {
     assert_eq!(result.to_i32().unwrap(), 3);
 }
 
+#[test]
+fn mrusty_initialize_args_block_empty() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container", {
+        def!("initialize", |; _args, &_block| {
+            Cont
+        });
+    });
+
+    Cont::require(mruby.clone());
+
+    mruby.run("Container.new(1) {}").unwrap();
+}
+
 #[test]
 fn mrusty_initialize_args_block_only_values() {
     let mruby = Mruby::new();
@@ -1303,3 +1354,1146 @@ fn mruby_class_args_block_mruby_values() {
 
     assert_eq!(result.to_i32().unwrap(), 3);
 }
+
+#[test]
+fn mruby_reopen_primitive_enumerable() {
+    let mruby = Mruby::new();
+
+    mruby_class!(mruby, "Fixnum", {
+        def!("double", |mruby, slf: i32| {
+            mruby.fixnum(slf * 2)
+        });
+
+        def!("plus", |mruby, slf: i32, other: i32| {
+            mruby.fixnum(slf + other)
+        });
+    });
+
+    let result = mruby.run("
+        [
+          [1, 2, 3].map(&:double),
+          [1, 2, 3].select { |n| n.double > 2 },
+          [1, 2, 3].inject { |a, n| a.plus n }
+        ]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_vec().unwrap()[2].to_i32().unwrap(), 6);
+    assert_eq!(result[1].to_vec().unwrap().len(), 2);
+    assert_eq!(result[2].to_i32().unwrap(), 6);
+
+    let array = mruby.run("[1, 2, 3]").unwrap();
+    let result = array.call("inject", vec![mruby.symbol("plus")]).unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 6);
+}
+
+#[test]
+fn mruby_reopen_inherited() {
+    let mruby = Mruby::new();
+
+    mruby_class!(mruby, "Object", {
+        def!("half", |mruby, slf: f64| {
+            mruby.float(slf / 2.0)
+        });
+    });
+
+    let result = mruby.run("[1.0, 3.0].map(&:half).inject(:+)").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 2.0);
+}
+
+#[test]
+fn mruby_reopen_slf_type_error() {
+    let mruby = Mruby::new();
+
+    mruby_class!(mruby, "Object", {
+        def!("half", |mruby, slf: f64| {
+            mruby.float(slf / 2.0)
+        });
+    });
+
+    let result = mruby.run("
+        begin
+          [1.0, 'a'].map(&:half)
+        rescue TypeError => e
+          e.message
+        end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "String cannot be converted to f64 in #half; declare \
+                                          `slf: Value` to accept any receiver");
+
+    let result = mruby.string("a").call("half", vec![]);
+
+    match result {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("TypeError: String cannot be converted to f64"));
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn def_data_class_to_h() {
+    let mruby = Mruby::new();
+
+    struct Point {
+        x: f64,
+        y: f64
+    }
+
+    def_data_class!(Point { x: f64, y: f64 }, "Point");
+
+    Point::require(mruby.clone());
+
+    let result = mruby.run("Point.new(1.0, 2.0).to_h == { x: 1.0, y: 2.0 }").unwrap();
+
+    assert_eq!(result.to_bool().unwrap(), true);
+}
+
+#[test]
+fn def_data_class_eq() {
+    let mruby = Mruby::new();
+
+    struct Point {
+        x: f64,
+        y: f64
+    }
+
+    def_data_class!(Point { x: f64, y: f64 }, "Point");
+
+    Point::require(mruby.clone());
+
+    let result = mruby.run("
+        [
+          Point.new(1.0, 2.0) == Point.new(1.0, 2.0),
+          Point.new(1.0, 2.0) == Point.new(2.0, 1.0),
+          Point.new(1.0, 2.0) == [1.0, 2.0]
+        ]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_bool().unwrap(), true);
+    assert_eq!(result[1].to_bool().unwrap(), false);
+    assert_eq!(result[2].to_bool().unwrap(), false);
+}
+
+#[test]
+fn def_data_class_accessors() {
+    let mruby = Mruby::new();
+
+    struct Cell {
+        row: i32,
+        value: f64,
+        visible: bool
+    }
+
+    def_data_class!(Cell { row: i32, value: f64, visible: bool }, "Cell");
+
+    Cell::require(mruby.clone());
+
+    let result = mruby.run("
+        cell = Cell.new(1, 2.5, false)
+        cell.row = 3
+        cell.visible = true
+
+        [cell.row, cell.value, cell.inspect]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_i32().unwrap(), 3);
+    assert_eq!(result[1].to_f64().unwrap(), 2.5);
+    assert_eq!(result[2].to_str().unwrap(), "#<Cell row=3, value=2.5, visible=true>");
+}
+
+#[test]
+fn def_data_class_obj_args() {
+    let mruby = Mruby::new();
+
+    struct Point {
+        x: f64,
+        y: f64
+    }
+
+    def_data_class!(Point { x: f64, y: f64 }, "Point", {
+        def!("dot", |mruby, slf: (&Point), other: (&Point)| {
+            mruby.float(slf.x * other.x + slf.y * other.y)
+        });
+    });
+
+    Point::require(mruby.clone());
+
+    let result = mruby.run("Point.new(1.0, 2.0).dot Point.new(3.0, 4.0)").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 11.0);
+
+    let point = mruby.run("Point.new(1.0, 2.0)").unwrap().to_obj::<Point>().unwrap();
+
+    assert_eq!(point.borrow().y, 2.0);
+}
+
+#[test]
+fn mrfn_typed_vecs() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("sum", mrfn!(|mruby, _slf: Value, ints: (Vec<i32>),
+                                                         floats: (Vec<f64>)| {
+        mruby.float(ints.iter().sum::<i32>() as f64 + floats.iter().sum::<f64>())
+    }));
+    mruby.def_class_method_for::<Cont, _>("join", mrfn!(|mruby, _slf: Value, strs: (Vec<String>)| {
+        mruby.string(&strs.join("-"))
+    }));
+
+    let result = mruby.run("Container.sum [1, 2], [0.5]").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 3.5);
+
+    let result = mruby.run("Container.join ['a', 'b', 'c']").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "a-b-c");
+
+    let result = mruby.run("
+        begin
+          Container.sum [1, 'two'], []
+        rescue RustPanic => e
+          e.message
+        end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "expected Array<Fixnum>");
+}
+
+#[test]
+fn mrfn_all_argument_kinds() {
+    let mruby = Mruby::new();
+
+    struct Cont {
+        value: i32
+    }
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+        slf.init(Cont { value: v })
+    }));
+    mruby.def_class_method_for::<Cont, _>("all", mrfn!(|mruby, _slf: Value, b: bool, i: i32,
+                                                         f: f64, s: (&str), a: (Vec<Value>),
+                                                         c: Class, v: Value, o: (&Cont);
+                                                         &blk| {
+        let result = vec![
+            mruby.bool(b),
+            mruby.fixnum(i),
+            mruby.float(f),
+            mruby.string(s),
+            mruby.fixnum(a.len() as i32),
+            mruby.string(c.to_str()),
+            v,
+            mruby.fixnum(o.value),
+            blk.call("call", vec![]).unwrap()
+        ];
+
+        mruby.array(result)
+    }));
+
+    let result = mruby.run("
+        Container.all(true, 1, 2.5, 'str', [1, 2], String, :sym, Container.new(3)) { 6 }
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_bool().unwrap(), true);
+    assert_eq!(result[1].to_i32().unwrap(), 1);
+    assert_eq!(result[2].to_f64().unwrap(), 2.5);
+    assert_eq!(result[3].to_str().unwrap(), "str");
+    assert_eq!(result[4].to_i32().unwrap(), 2);
+    assert_eq!(result[5].to_str().unwrap(), "String");
+    assert_eq!(result[6].to_str().unwrap(), "sym");
+    assert_eq!(result[7].to_i32().unwrap(), 3);
+    assert_eq!(result[8].to_i32().unwrap(), 6);
+}
+
+#[test]
+fn mrfn_rest_args_empty() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("rest", mrfn!(|mruby, _slf: Value; args| {
+        mruby.fixnum(args.len() as i32)
+    }));
+    mruby.def_class_method_for::<Cont, _>("rest_blk", mrfn!(|mruby, _slf: Value; args, &blk| {
+        mruby.array(vec![mruby.fixnum(args.len() as i32), blk])
+    }));
+    mruby.def_class_method_for::<Cont, _>("typed_rest", mrfn!(|mruby, _slf: Value, s: (&str);
+                                                                args| {
+        mruby.string(&format!("{}{}", s, args.len()))
+    }));
+    mruby.def_class_method_for::<Cont, _>("typed_rest_blk", mrfn!(|mruby, _slf: Value, b: bool;
+                                                                    args, &blk| {
+        mruby.array(vec![mruby.bool(b), mruby.fixnum(args.len() as i32), blk])
+    }));
+
+    let result = mruby.run("
+        [
+          Container.rest,
+          Container.rest(1, 2),
+          Container.rest_blk,
+          Container.rest_blk(1) { 2 }.last.call,
+          Container.typed_rest('a'),
+          Container.typed_rest('a', 1),
+          Container.typed_rest_blk(true),
+          Container.typed_rest_blk(false, 1, 2) { 3 }.last.call
+        ]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_i32().unwrap(), 0);
+    assert_eq!(result[1].to_i32().unwrap(), 2);
+    assert_eq!(result[2].to_vec().unwrap()[0].to_i32().unwrap(), 0);
+    assert_eq!(result[2].to_vec().unwrap()[1], mruby.nil());
+    assert_eq!(result[3].to_i32().unwrap(), 2);
+    assert_eq!(result[4].to_str().unwrap(), "a0");
+    assert_eq!(result[5].to_str().unwrap(), "a1");
+    assert_eq!(result[6].to_vec().unwrap()[1].to_i32().unwrap(), 0);
+    assert_eq!(result[7].to_i32().unwrap(), 3);
+}
+
+#[test]
+fn mrfn_gc_arena() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use mruby_ffi::mrb_gc_arena_save;
+
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    let max_arena = Rc::new(Cell::new(0));
+    let max = max_arena.clone();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("heavy", move |mruby, _slf| {
+        let arena = unsafe { mrb_gc_arena_save(mruby.borrow().mrb) };
+
+        max.set(::std::cmp::max(max.get(), arena));
+
+        let parts = (0..10).map(|i| mruby.string(&i.to_string())).collect();
+
+        mruby.array(parts).call("join", vec![]).unwrap()
+    });
+
+    let result = mruby.run("
+        result = nil
+        100_000.times { result = Container.heavy }
+        result
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "0123456789");
+    assert!(max_arena.get() < 64);
+}
+
+#[test]
+fn mrfn_gc_register() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    let stored = Rc::new(RefCell::new(None));
+    let store = stored.clone();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("store", move |mruby, _slf| {
+        let value = mruby.string("stored");
+
+        value.gc_register();
+        *store.borrow_mut() = Some(value);
+
+        mruby.nil()
+    });
+
+    mruby.run("
+        Container.store
+        100.times { 'garbage' * 100 }
+        GC.start
+    ").unwrap();
+
+    let value = stored.borrow_mut().take().unwrap();
+
+    assert_eq!(value.to_str().unwrap(), "stored");
+
+    value.gc_unregister();
+}
+
+#[test]
+fn mrfn_reentrant_mut_borrow() {
+    let mruby = Mruby::new();
+
+    struct Cont {
+        value: i32
+    }
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Cont { value: 1 })
+    }));
+    mruby.def_method_for::<Cont, _>("again", mrfn!(|_mruby, slf: (&mut Cont), blk: Value| {
+        slf.value += 1;
+
+        blk.call("call", vec![]).unwrap()
+    }));
+    mruby.def_method_for::<Cont, _>("take", mrfn!(|mruby, _slf: Value, other: (&mut Cont)| {
+        mruby.fixnum(other.value)
+    }));
+
+    let result = mruby.run("
+        c = Container.new
+        c.again(-> { begin; c.again(-> { 1 }); rescue => e; e.message; end })
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(),
+               "Rust borrow conflict: reentrant borrow of Cont in #again");
+
+    let result = mruby.run("
+        c = Container.new
+        c.again(-> { begin; Container.new.take(c); rescue => e; e.class; end })
+    ").unwrap();
+
+    assert_eq!(result.to_class().unwrap().to_str(), "ReentrantBorrowError");
+
+    let result = mruby.run("c.take(c)").unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 2);
+}
+
+#[test]
+fn mrfn_reentrant_borrow() {
+    let mruby = Mruby::new();
+
+    struct Cont {
+        value: i32
+    }
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Cont { value: 1 })
+    }));
+    mruby.def_method_for::<Cont, _>("update", mrfn!(|_mruby, slf: (&mut Cont); &blk| {
+        slf.value += 1;
+
+        blk.call("call", vec![]).unwrap()
+    }));
+    mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
+        mruby.fixnum(slf.value)
+    }));
+    mruby.def_method_for::<Cont, _>("peek", mrfn!(|mruby, _slf: Value, other: (&Cont)| {
+        mruby.fixnum(other.value)
+    }));
+
+    let result = mruby.run("
+        c = Container.new
+        c.update { begin; c.value; rescue ReentrantBorrowError => e; e.message; end }
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(),
+               "Rust borrow conflict: reentrant borrow of Cont in #value");
+
+    let result = mruby.run("
+        c.update { begin; Container.new.peek(c); rescue RuntimeError => e; e.message; end }
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(),
+               "Rust borrow conflict: reentrant borrow of Cont in #peek");
+
+    let c = mruby.run("c").unwrap();
+
+    {
+        let obj = c.to_obj::<Cont>().unwrap();
+        let _borrow = obj.borrow_mut();
+
+        match c.call("value", vec![]) {
+            Err(MrubyError::Runtime { message: err, .. }) => {
+                assert_eq!(err, "ReentrantBorrowError: Rust borrow conflict: reentrant borrow of \
+                                 Cont in #value");
+            },
+            _ => assert!(false)
+        }
+    }
+
+    assert_eq!(c.call("value", vec![]).unwrap().to_i32().unwrap(), 3);
+}
+
+#[test]
+fn mrfn_f32() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("scale", mrfn!(|mruby, _slf: Value, x: f32, k: f32| {
+        mruby.float32(x * k)
+    }));
+
+    let result = mruby.run("Container.scale(0.1, 3) + 1.5").unwrap();
+
+    assert_eq!(result.to_f64().unwrap() as f32, 0.1f32 * 3.0 + 1.5);
+
+    let result = mruby.run("Container.scale(0.1, 1)").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 0.1f32 as f64);
+    assert!((result.to_f64().unwrap() - 0.1).abs() < 1e-8);
+
+    let result = mruby.run("Container.scale(16777217.0, 1)").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 16777216.0);
+}
+
+#[test]
+fn mrfn_range() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("span", mrfn!(|mruby, _slf: Value, range: Range| {
+        let (start, end, exclusive) = range;
+        let len = end.to_i32().unwrap() - start.to_i32().unwrap();
+
+        mruby.fixnum(if exclusive { len } else { len + 1 })
+    }));
+
+    let result = mruby.run("[Container.span(3..10), Container.span(1...5)]").unwrap();
+
+    assert_eq!(result.to_rust::<Vec<i32>>().unwrap(), vec![8, 4]);
+
+    let result = mruby.run("
+      begin
+        Container.span(3)
+      rescue RustPanic => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "expected Range");
+}
+
+#[test]
+fn mrfn_bytes() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("xor", mrfn!(|mruby, _slf: Value, data: (Vec<u8>),
+                                                         key: i32| {
+        let data = data.iter().map(|byte| byte ^ key as u8).collect::<Vec<u8>>();
+
+        mruby.bytes(&data)
+    }));
+
+    let result = mruby.run("Container.xor(\"\\x00a\\x00\\xff\", 0)").unwrap();
+
+    assert_eq!(result.to_bytes().unwrap(), b"\x00a\x00\xff");
+
+    let result = mruby.run("Container.xor(Container.xor(\"\\x00\\x01\\x00\", 255), 255)").unwrap();
+
+    assert_eq!(result.to_bytes().unwrap(), b"\x00\x01\x00");
+
+    let result = mruby.run("
+      begin
+        Container.xor(1, 0)
+      rescue TypeError => e
+        e.class
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_class().unwrap().to_str(), "TypeError");
+}
+
+#[test]
+fn mrfn_symbol() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("mode", mrfn!(|mruby, _slf: Value, mode: (Symbol),
+                                                          times: i32| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+
+        mruby.string(&mode.repeat(times as usize))
+    }));
+
+    let result = mruby.run("Container.mode :ok, 2").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "okok");
+
+    let result = mruby.run("Container.mode 'read', 1").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "read");
+
+    let result = mruby.run("Container.mode :'read write', 1").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "read write");
+    assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+    let result = mruby.run("
+      begin
+        Container.mode 1, 1
+      rescue TypeError => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "1 is not a symbol");
+    assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn mrfn_without_mruby() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("slf", mrfn!(|slf: Value| {
+        slf
+    }));
+    mruby.def_method_for::<Cont, _>("first", mrfn!(|_slf: Value, a: Value, _b: i32| {
+        a
+    }));
+    mruby.def_method_for::<Cont, _>("apply", mrfn!(|_slf: Value, a: Value; &block| {
+        block.call("call", vec![a]).unwrap()
+    }));
+    mruby.def_method_for::<Cont, _>("last", mrfn!(|_slf: Value; args| {
+        args.last().unwrap().clone()
+    }));
+    mruby.def_method_for::<Cont, _>("apply_last", mrfn!(|_slf: Value; args, &block| {
+        block.call("call", vec![args.last().unwrap().clone()]).unwrap()
+    }));
+
+    let result = mruby.run("
+      c = Container.new
+      [c.slf == c, c.first(1, 2), c.apply(1) { |a| a + 2 }, c.last(1, 2, 3),
+       c.apply_last(1, 2) { |a| a * 2 }]
+    ").unwrap();
+
+    assert_eq!(result.to_vec().unwrap().iter().map(|v| v.to_ruby_string())
+                     .collect::<Vec<_>>(), vec!["true", "1", "3", "3", "4"]);
+}
+
+#[test]
+fn mrfn_tuple_return() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("pair", mrfn!(|mruby, _slf: Value, a: i32| -> (Value, Value) {
+        (mruby.fixnum(a), mruby.string("two"))
+    }));
+    mruby.def_method_for::<Cont, _>("triple", mrfn!(|_slf: Value; args| -> (Value, Value, Value) {
+        (args[2].clone(), args[1].clone(), args[0].clone())
+    }));
+    mruby.def_method_for::<Cont, _>("single", mrfn!(|mruby, _slf: Value| -> Value {
+        mruby.fixnum(1)
+    }));
+
+    let result = mruby.run("
+      c = Container.new
+      a, b = c.pair 1
+      x, y, z = c.triple 1, 2, 3
+      [a, b, x, y, z, c.single]
+    ").unwrap();
+
+    assert_eq!(result.to_vec().unwrap().iter().map(|v| v.to_ruby_string())
+                     .collect::<Vec<_>>(), vec!["1", "\"two\"", "3", "2", "1", "1"]);
+}
+
+#[test]
+fn mrfn_hash() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("open", mrfn!(|mruby, _slf: Value, name: (&str),
+                                                          options: Hash| {
+        let mut keys = options.keys().cloned().collect::<Vec<_>>();
+
+        keys.sort();
+
+        let width = options.get("width").map(|width| width.to_i32().unwrap()).unwrap_or(0);
+
+        mruby.string(&format!("{} {} {}", name, keys.join(","), width))
+    }));
+
+    let result = mruby.run("Container.open 'a', width: 640, 'title' => 't'").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "a title,width 640");
+
+    let result = mruby.run("Container.open 'b', {}").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "b  0");
+
+    let result = mruby.run("
+      begin
+        Container.open 'c', [1]
+      rescue RustPanic => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "expected Hash");
+
+    let result = mruby.run("
+      begin
+        Container.open 'd', { 1 => 2 }
+      rescue RustPanic => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "expected Hash with String or Symbol keys");
+}
+
+#[test]
+fn mrusty_class_visibility() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container", {
+        def!("initialize", || {
+            Cont
+        });
+
+        private!();
+
+        def!("secret", |mruby, _slf: Value| {
+            mruby.fixnum(1)
+        });
+
+        protected!();
+
+        def!("shared", |mruby, _slf: Value| {
+            mruby.fixnum(2)
+        });
+
+        public!();
+
+        def!("open", |mruby, slf: Value, other: Value| {
+            let secret = slf.call("reveal", vec![]).unwrap().to_i32().unwrap();
+            let shared = other.call("shared_of", vec![other.clone()]).unwrap().to_i32().unwrap();
+
+            mruby.fixnum(secret + shared)
+        });
+    });
+
+    Cont::require(mruby.clone());
+
+    mruby.run("
+      class Container
+        def reveal
+          secret
+        end
+
+        def shared_of(other)
+          other.shared
+        end
+      end
+    ").unwrap();
+
+    let result = mruby.run("Container.new.open(Container.new)").unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 3);
+    assert!(mruby.run("Container.new.secret").is_err());
+    assert!(mruby.run("Container.new.shared").is_err());
+    assert_eq!(mruby.run("Container.new.send(:shared)").unwrap().to_i32().unwrap(), 2);
+}
+
+#[test]
+fn mruby_class_visibility() {
+    let mruby = Mruby::new();
+
+    mruby_class!(mruby, "Container", {
+        private!();
+
+        def!("secret", |mruby, _slf: Value| {
+            mruby.fixnum(1)
+        });
+
+        def_self!("make", |slf: Value| {
+            slf.call("new", vec![]).unwrap()
+        });
+
+        public!();
+
+        def!("open", |mruby, _slf: Value| {
+            mruby.fixnum(2)
+        });
+    });
+
+    mruby.run("
+      class Container
+        def reveal
+          secret
+        end
+      end
+    ").unwrap();
+
+    assert_eq!(mruby.run("Container.make.reveal").unwrap().to_i32().unwrap(), 1);
+    assert_eq!(mruby.run("Container.new.open").unwrap().to_i32().unwrap(), 2);
+    assert!(mruby.run("Container.new.secret").is_err());
+}
+
+#[test]
+fn mrusty_class_operators() {
+    let mruby = Mruby::new();
+
+    #[derive(Clone, PartialEq)]
+    struct Vector2 {
+        x: f64,
+        y: f64
+    };
+
+    mrusty_class!(Vector2, {
+        def!("initialize", |x: f64, y: f64| {
+            Vector2 { x: x, y: y }
+        });
+
+        def!("+", |mruby, slf: (&Vector2), other: (&Vector2)| {
+            mruby.obj(Vector2 { x: slf.x + other.x, y: slf.y + other.y })
+        });
+
+        def!("*", |mruby, slf: (&Vector2), k: f64| {
+            mruby.obj(Vector2 { x: slf.x * k, y: slf.y * k })
+        });
+
+        def!("-@", |mruby, slf: (&Vector2)| {
+            mruby.obj(Vector2 { x: -slf.x, y: -slf.y })
+        });
+
+        def!("==", |mruby, slf: (&Vector2), other: Value| {
+            match other.to_obj::<Vector2>() {
+                Ok(other) => mruby.bool(*slf == *other.borrow()),
+                Err(_)    => mruby.bool(false)
+            }
+        });
+
+        def!("[]", |mruby, slf: (&Vector2), i: i32| {
+            match i {
+                0 => mruby.float(slf.x),
+                1 => mruby.float(slf.y),
+                _ => mruby.nil()
+            }
+        });
+
+        def!("[]=", |slf: (&mut Vector2), i: i32, v: f64| {
+            match i {
+                0 => slf.x = v,
+                _ => slf.y = v
+            }
+        });
+
+        def!("coerce", |mruby, slf: Value, k: Value| {
+            mruby.array(vec![slf, k])
+        });
+    });
+
+    Vector2::require(mruby.clone());
+
+    let result = mruby.run("
+      v = Vector2.new(1.0, 2.0)
+      w = -(v + Vector2.new(1.0, 1.0)) * 2
+
+      [w[0], w[1], 2 * v == v * 2, 1.5 * v == v * 1.5, v == 1, 3 + 4, 1.5 * 2]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_f64().unwrap(), -4.0);
+    assert_eq!(result[1].to_f64().unwrap(), -6.0);
+    assert_eq!(result[2].to_bool().unwrap(), true);
+    assert_eq!(result[3].to_bool().unwrap(), true);
+    assert_eq!(result[4].to_bool().unwrap(), false);
+    assert_eq!(result[5].to_i32().unwrap(), 7);
+    assert_eq!(result[6].to_f64().unwrap(), 3.0);
+
+    let result = mruby.run("
+      v = Vector2.new(1.0, 2.0)
+
+      [(v[0] = 5.0), v.send(:[]=, 1, 6.0), v[0], v[1]]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_f64().unwrap(), 5.0);
+    assert_eq!(result[1].to_f64().unwrap(), 6.0);
+    assert_eq!(result[2].to_f64().unwrap(), 5.0);
+    assert_eq!(result[3].to_f64().unwrap(), 6.0);
+}
+
+#[test]
+fn mruby_module_functions() {
+    let mruby = Mruby::new();
+
+    let module = mruby_module!(mruby, "MathUtils", {
+        def_self!("clamp", |mruby, _slf: Value, v: f64, lo: f64, hi: f64| {
+            mruby.float(v.max(lo).min(hi))
+        });
+
+        def_self!("name_of", |slf: Value| {
+            slf.call("to_s", vec![]).unwrap()
+        });
+    });
+
+    assert_eq!(module.to_str(), "MathUtils");
+
+    let result = mruby.run("[MathUtils.clamp(-1.0, 0.0, 1.0), MathUtils.name_of]").unwrap();
+    let result = result.to_vec().unwrap();
+
+    assert_eq!(result[0].to_f64().unwrap(), 0.0);
+    assert_eq!(result[1].to_str().unwrap(), "MathUtils");
+}
+
+#[test]
+fn mrusty_class_include() {
+    let mruby = Mruby::new();
+
+    struct Cont {
+        value: i32
+    }
+
+    mruby.run("
+      module Doubling
+        def double
+          value * 2
+        end
+      end
+    ").unwrap();
+
+    mrusty_class!(Cont, "Container", {
+        def!("initialize", |v: i32| {
+            Cont { value: v }
+        });
+
+        def!("value", |mruby, slf: (&Cont)| {
+            mruby.fixnum(slf.value)
+        });
+
+        include!("Doubling");
+    });
+
+    Cont::require(mruby.clone());
+
+    let class = mruby_class!(mruby, "Pair", {
+        private!();
+
+        include!("Doubling");
+
+        def!("value", |mruby, _slf: Value| {
+            mruby.fixnum(2)
+        });
+    });
+
+    assert_eq!(class.to_str(), "Pair");
+
+    let result = mruby.run("[Container.new(3).double, Pair.new.double, Pair.include?(Doubling)]");
+    let result = result.unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_i32().unwrap(), 6);
+    assert_eq!(result[1].to_i32().unwrap(), 4);
+    assert_eq!(result[2].to_bool().unwrap(), true);
+}
+
+#[test]
+fn mrusty_class_superclass() {
+    let mruby = Mruby::new();
+
+    struct Character {
+        health: i32
+    }
+
+    struct Enemy {
+        damage: i32
+    }
+
+    mrusty_class!(Character, "Character", {
+        def!("initialize", |health: i32| {
+            Character { health: health }
+        });
+
+        def!("health", |mruby, slf: (&Character)| {
+            mruby.fixnum(slf.health)
+        });
+    });
+
+    mrusty_class!(Enemy, "Enemy" < "Character", {
+        requires!(Character);
+
+        def!("initialize", |damage: i32| {
+            Enemy { damage: damage }
+        });
+
+        def!("damage", |mruby, slf: (&Enemy)| {
+            mruby.fixnum(slf.damage)
+        });
+
+        include!("Comparable");
+    });
+
+    Enemy::require(mruby.clone());
+
+    mruby.run("
+      class Character
+        def describe
+          \"#{self.class} (#{damage})\"
+        end
+      end
+    ").unwrap();
+
+    let result = mruby.run("
+      enemy = Enemy.new(3)
+
+      [enemy.is_a?(Character), enemy.describe, Character.new(5).health,
+       Enemy.ancestors.include?(Comparable)]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_bool().unwrap(), true);
+    assert_eq!(result[1].to_str().unwrap(), "Enemy (3)");
+    assert_eq!(result[2].to_i32().unwrap(), 5);
+    assert_eq!(result[3].to_bool().unwrap(), true);
+}
+
+#[test]
+#[should_panic(expected="Superclass Missing not found.")]
+fn mrusty_class_superclass_missing() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container" < "Missing", {
+        def!("initialize", || {
+            Cont
+        });
+    });
+
+    Cont::require(mruby.clone());
+}
+
+#[test]
+fn mrusty_class_consts_and_class_vars() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mrusty_class!(Cont, "Container", {
+        const!("MAX_SPEED", 300);
+        const!("NAME", |mruby| mruby.string("container"));
+        cvar!("@@instances", 0);
+
+        def!("initialize", || {
+            Cont
+        });
+
+        def!("register", |mruby, slf: Value| {
+            let class = slf.class();
+            let instances = class.get_class_var("@@instances").unwrap().to_i32().unwrap();
+
+            class.set_class_var("@@instances", mruby.fixnum(instances + 1));
+
+            slf
+        });
+    });
+
+    Cont::require(mruby.clone());
+
+    let result = mruby.run("
+      class Container
+        def self.instances
+          @@instances
+        end
+      end
+
+      Container.new.register
+      Container.new.register
+
+      [Container::MAX_SPEED, Container::NAME, Container.instances, Object.const_defined?(:NAME)]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_i32().unwrap(), 300);
+    assert_eq!(result[1].to_str().unwrap(), "container");
+    assert_eq!(result[2].to_i32().unwrap(), 2);
+    assert_eq!(result[3].to_bool().unwrap(), false);
+}
+
+#[test]
+fn mrusty_class_def_struct() {
+    let mruby = Mruby::new();
+
+    struct Player {
+        name: String,
+        level: i32,
+        ratio: f64
+    }
+
+    mrusty_class!(Player, {
+        def_struct!(name, level, ratio);
+
+        def!("to_s", |mruby, slf: (&Player)| {
+            mruby.string(&format!("{} {} {}", slf.name, slf.level, slf.ratio))
+        });
+    });
+
+    Player::require(mruby.clone());
+
+    let result = mruby.run("Player.new('hero', 3, 0.5).to_s").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "hero 3 0.5");
+
+    let result = mruby.run("
+      [
+        begin
+          Player.new('hero', 3)
+        rescue ArgumentError => e
+          e.message
+        end,
+        begin
+          Player.new('hero', 'three', 0.5)
+        rescue ArgumentError => e
+          e.message
+        end
+      ]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_str().unwrap(), "wrong number of arguments (2 for 3)");
+    assert_eq!(result[1].to_str().unwrap(), "expected Fixnum for level");
+}
+
+#[test]
+fn mruby_class_consts_and_class_vars() {
+    let mruby = Mruby::new();
+
+    let class = mruby_class!(mruby, "Container", {
+        private!();
+
+        const!("LIMIT", 2.5);
+        cvar!("@@names", |mruby| mruby.array(vec![]));
+    });
+
+    mruby.run("
+      class Container
+        def add(name)
+          @@names << name
+        end
+      end
+
+      Container.new.add 'a'
+      Container.new.add 'b'
+    ").unwrap();
+
+    assert_eq!(mruby.run("Container::LIMIT").unwrap().to_f64().unwrap(), 2.5);
+    assert_eq!(class.get_class_var("@@names").unwrap().to_vec().unwrap().len(), 2);
+}
@@ -429,21 +429,19 @@ fn protect() {
 
 #[test]
 pub fn args() {
-    use std::mem::uninitialized;
-
     unsafe {
         let mrb = mrb_open();
         let context = mrbc_context_new(mrb);
 
         extern "C" fn add(mrb: *const MrState, _slf: MrValue) -> MrValue {
             unsafe {
-                let a = uninitialized::<MrValue>();
-                let b = uninitialized::<MrValue>();
+                let mut a = MrValue::nil();
+                let mut b = MrValue::nil();
 
                 let sig_str = CString::new("oo").unwrap();
 
-                mrb_get_args(mrb, sig_str.as_ptr(), &a as *const MrValue,
-                             &b as *const MrValue);
+                mrb_get_args(mrb, sig_str.as_ptr(), &mut a as *mut MrValue,
+                             &mut b as *mut MrValue);
 
                 let args = &[b];
 
@@ -478,8 +476,8 @@ pub fn args() {
 #[test]
 pub fn str_args() {
     use std::ffi::CStr;
-    use std::mem::uninitialized;
     use std::os::raw::c_char;
+    use std::ptr;
 
     unsafe {
         let mrb = mrb_open();
@@ -487,13 +485,13 @@ pub fn str_args() {
 
         extern "C" fn add(mrb: *const MrState, _slf: MrValue) -> MrValue {
             unsafe {
-                let a = uninitialized::<*const c_char>();
-                let b = uninitialized::<*const c_char>();
+                let mut a = ptr::null::<c_char>();
+                let mut b = ptr::null::<c_char>();
 
                 let sig_str = CString::new("zz").unwrap();
 
-                mrb_get_args(mrb, sig_str.as_ptr(), &a as *const *const c_char,
-                             &b as *const *const c_char);
+                mrb_get_args(mrb, sig_str.as_ptr(), &mut a as *mut *const c_char,
+                             &mut b as *mut *const c_char);
 
                 let a = CStr::from_ptr(a).to_str().unwrap();
                 let b = CStr::from_ptr(b).to_str().unwrap();
@@ -530,19 +528,17 @@ pub fn str_args() {
 
 #[test]
 pub fn array_args() {
-    use std::mem::uninitialized;
-
     unsafe {
         let mrb = mrb_open();
         let context = mrbc_context_new(mrb);
 
         extern "C" fn add(mrb: *const MrState, _slf: MrValue) -> MrValue {
             unsafe {
-                let array = uninitialized::<MrValue>();
+                let mut array = MrValue::nil();
 
                 let a_str = CString::new("A").unwrap();
 
-                mrb_get_args(mrb, a_str.as_ptr(), &array as *const MrValue);
+                mrb_get_args(mrb, a_str.as_ptr(), &mut array as *mut MrValue);
 
                 let vec = array.to_vec(mrb).unwrap();
 
@@ -0,0 +1,104 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[macro_use]
+extern crate mrusty;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use mrusty::{Mruby, MrubyImpl};
+
+struct Counting;
+
+// Counted per thread so that tests running in parallel do not see each other's allocations.
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+// Calling a Rust method allocates the receiver's class name for error reporting, so arguments
+// are checked to cost nothing on top of a method without any.
+#[test]
+fn alloc_mrfn() {
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_class_method_for::<Cont, _>("none", mrfn!(|mruby, _slf: Value| {
+        mruby.nil()
+    }));
+    mruby.def_class_method_for::<Cont, _>("add", mrfn!(|mruby, _slf: Value, a: i32, b: f64| {
+        mruby.float(a as f64 + b)
+    }));
+
+    let none = mruby.intern("none");
+    let add = mruby.intern("add");
+    let class = mruby.get_class("Container").unwrap().to_value();
+
+    let result = class.call_sym(add, vec![mruby.fixnum(1), mruby.float(0.5)]).unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 1.5);
+
+    class.call_sym(none, vec![]).unwrap();
+
+    let before = allocations();
+
+    for _ in 0..1000 {
+        class.call_sym(none, vec![]).unwrap();
+    }
+
+    let baseline = allocations() - before;
+
+    let mut args: Vec<_> = (0..1000).map(|_| vec![mruby.fixnum(1), mruby.float(0.5)]).collect();
+
+    let before = allocations();
+
+    for args in args.drain(..) {
+        class.call_sym(add, args).unwrap();
+    }
+
+    assert_eq!(allocations() - before, baseline);
+}
+
+#[test]
+fn alloc_call0_call1() {
+    let mruby = Mruby::new();
+
+    let one = mruby.fixnum(1);
+
+    assert_eq!(one.call0("succ").unwrap().to_i32().unwrap(), 2);
+    assert_eq!(one.call1("+", mruby.fixnum(2)).unwrap().to_i32().unwrap(), 3);
+
+    let before = allocations();
+
+    for _ in 0..1000 {
+        one.call0("succ").unwrap();
+        one.call1("+", mruby.fixnum(2)).unwrap();
+    }
+
+    assert_eq!(allocations(), before);
+}
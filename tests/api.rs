@@ -8,9 +8,18 @@
 #[macro_use]
 extern crate mrusty;
 
-use std::path::Path;
-
-use mrusty::{Mruby, MrubyFile, MrubyImpl};
+use std::error::Error;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+
+use mrusty::{BacktraceEntry, MrInt, Mruby, MrubyError, MrubyFile, MrubyImpl, SandboxConfig};
+use mrusty::MrubyType;
+use mrusty::{FiberResult, MrubyPool, PoolReset};
+use mrusty::SyntaxResult;
+use mrusty::ValueEql;
+use mrusty::{FromValue, Marker, MrubyAny, MrubyMark, ToValue, Value, ValueSnapshot};
 
 mod example;
 
@@ -126,6 +135,101 @@ fn api_require_file() {
     ").unwrap();
 }
 
+#[test]
+fn api_require_loader() {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    struct Cont {
+        value: i32
+    }
+
+    impl MrubyFile for Cont {
+        fn require(mruby: MrubyType) {
+            mruby.def_class_for::<Cont>("Container");
+            mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+                slf.init(Cont { value: v })
+            }));
+            mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
+                mruby.fixnum(slf.value)
+            }));
+        }
+    }
+
+    let dir = env::temp_dir().join("mrusty_require_loader");
+
+    fs::create_dir_all(dir.join("lib")).unwrap();
+
+    File::create(dir.join("main.rb")).unwrap().write_all(b"
+        require_relative 'lib/helper'
+        require 'container'
+
+        $main_loads = ($main_loads || 0) + 1
+
+        def make
+          Container.new(helper)
+        end
+    ").unwrap();
+    File::create(dir.join("lib/helper.rb")).unwrap().write_all(b"
+        require_relative 'value'
+
+        def helper
+          VALUE * 2
+        end
+    ").unwrap();
+    File::create(dir.join("lib/value.rb")).unwrap().write_all(b"VALUE = 21").unwrap();
+
+    let mut features: HashMap<String, fn(MrubyType)> = HashMap::new();
+    features.insert("container".to_owned(), Cont::require);
+
+    let mruby = Mruby::new();
+
+    mruby.def_require_loader(features, vec![PathBuf::from("/nonexistent"), dir.clone()]);
+
+    assert_eq!(mruby.run("require 'main'").unwrap(), mruby.bool(true));
+    assert_eq!(mruby.run("require 'main'").unwrap(), mruby.bool(false));
+    assert_eq!(mruby.run("require 'container'").unwrap(), mruby.bool(false));
+    assert_eq!(mruby.run("$main_loads").unwrap(), mruby.fixnum(1));
+    assert_eq!(mruby.run("make.value").unwrap(), mruby.fixnum(42));
+
+    let dir = dir.canonicalize().unwrap();
+    let features = mruby.run("$LOADED_FEATURES").unwrap().to_vec().unwrap();
+    let features: Vec<_> = features.iter().map(|f| f.to_str().unwrap().to_owned()).collect();
+
+    assert_eq!(features, vec![
+        dir.join("main.rb").to_str().unwrap().to_owned(),
+        dir.join("lib/helper.rb").to_str().unwrap().to_owned(),
+        dir.join("lib/value.rb").to_str().unwrap().to_owned(),
+        "container".to_owned()
+    ]);
+
+    let result = mruby.run("
+        begin
+          require 'missing'
+        rescue LoadError => e
+          e.message
+        end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "cannot load such file -- missing");
+
+    let result = mruby.run("
+        begin
+          require_relative 'value'
+        rescue LoadError => e
+          e.message
+        end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "cannot infer basepath");
+
+    let result = mruby.execute(&dir.join("lib/helper.rb")).unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "helper");
+}
+
 #[test]
 fn api_dup() {
     static mut DROPPED: bool = false;
@@ -171,6 +275,373 @@ fn api_dup() {
     }
 }
 
+#[test]
+fn api_each() {
+    let mruby = Mruby::new();
+
+    Vector::require(mruby.clone());
+
+    mruby.def_each_for::<Vector, _, _>(|mruby, vector| {
+        vec![
+            mruby.float(vector.x as f64),
+            mruby.float(vector.y as f64),
+            mruby.float(vector.z as f64)
+        ]
+    });
+
+    let result = mruby.run("
+        vector = Vector.new(1.0, 2.0, 3.0)
+        sum = 0.0
+
+        vector.each { |c| sum += c }
+
+        [
+          sum,
+          vector.first,
+          vector.find { |c| c > 1.0 },
+          vector.each { |c| break c * 10 },
+          vector.each.next,
+          vector.each.equal?(vector),
+          vector.each { }.equal?(vector)
+        ]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_f64().unwrap(), 6.0);
+    assert_eq!(result[1].to_f64().unwrap(), 1.0);
+    assert_eq!(result[2].to_f64().unwrap(), 2.0);
+    assert_eq!(result[3].to_f64().unwrap(), 10.0);
+    assert_eq!(result[4].to_f64().unwrap(), 1.0);
+    assert_eq!(result[5].to_bool().unwrap(), false);
+    assert_eq!(result[6].to_bool().unwrap(), true);
+
+    match mruby.run("Vector.new(1.0, 2.0, 3.0).__each__") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("NoMethodError: private"));
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_each_raise() {
+    let mruby = Mruby::new();
+
+    Vector::require(mruby.clone());
+
+    mruby.def_each_for::<Vector, _, _>(|mruby, vector| {
+        vec![mruby.float(vector.x as f64)]
+    });
+
+    let result = mruby.run("
+        begin
+          Vector.new(1.0, 2.0, 3.0).each { |c| fail 'in block' }
+        rescue => e
+          e.message
+        end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "in block");
+}
+
+#[test]
+fn api_call_super() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+        class Base
+          def items(n)
+            [:base] * n
+          end
+        end
+
+        class Middle < Base; end
+
+        class Leaf < Middle
+          def items(n)
+            super + [:leaf]
+          end
+        end
+    ").unwrap();
+
+    mruby_class!(mruby, "Middle", {
+        def!("items", |mruby, slf: Value, n: i32| {
+            let items = slf.call_super("items", vec![mruby.fixnum(n)]).unwrap();
+
+            items.call("+", vec![mruby.run("[:middle]").unwrap()]).unwrap()
+        });
+    });
+
+    let result = mruby.run("[Middle.new.items(1), Leaf.new.items(2)].inspect").unwrap();
+
+    assert_eq!(result.to_str().unwrap(),
+               "[[:base, :middle], [:base, :base, :middle, :leaf]]");
+
+    let result = mruby.run("Base.new").unwrap().call_super("items", vec![mruby.fixnum(1)]);
+
+    match result {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "NoMethodError: super: no superclass method 'items'");
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_call_super_replaced() {
+    let mruby = Mruby::new();
+
+    mruby_class!(mruby, "Fixnum", {
+        def!("to_s", |mruby, slf: Value; args| {
+            let string = slf.call_super("to_s", args).unwrap();
+
+            mruby.string(&format!("#{}", string.to_str().unwrap()))
+        });
+    });
+
+    let result = mruby.run("[1.to_s, 255.to_s(16), -3.to_s]").unwrap().to_vec().unwrap();
+    let result: Vec<_> = result.iter().map(|string| string.to_str().unwrap()).collect();
+
+    assert_eq!(result, vec!["#1", "#ff", "#-3"]);
+
+    let mruby = Mruby::new();
+
+    mruby.run("
+        class Point
+          def initialize(x, y)
+            @x, @y = x, y
+          end
+
+          def to_s
+            \"(#{@x}, #{@y})\"
+          end
+        end
+    ").unwrap();
+
+    let point = mruby.def_class("Point");
+
+    mruby.def_method(point.clone(), "to_s", |mruby, slf| {
+        let string = slf.call_super("to_s", vec![]).unwrap();
+
+        mruby.string(&format!("Point{}", string.to_str().unwrap()))
+    });
+
+    assert_eq!(mruby.run("Point.new(1, 2).to_s").unwrap().to_str().unwrap(), "Point(1, 2)");
+
+    mruby.def_method(point, "to_s", |mruby, slf| {
+        let string = slf.call_super("to_s", vec![]).unwrap();
+
+        mruby.string(&format!("P{}", string.to_str().unwrap()))
+    });
+
+    assert_eq!(mruby.run("Point.new(1, 2).to_s").unwrap().to_str().unwrap(), "P(1, 2)");
+}
+
+#[test]
+fn api_const() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+        module Config
+          API_KEY = 'x'
+        end
+    ").unwrap();
+
+    let config = mruby.get_module("Config").unwrap().to_value();
+
+    mruby.const_set(config.clone(), "API_KEY", mruby.string("y"));
+
+    assert_eq!(mruby.const_get(config.clone(), "API_KEY").unwrap().to_str().unwrap(), "y");
+    assert_eq!(mruby.run("Config::API_KEY").unwrap().to_str().unwrap(), "y");
+
+    match mruby.const_get(config, "MISSING") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "NameError: uninitialized constant Config::MISSING");
+        },
+        _ => assert!(false)
+    }
+
+    match mruby.top_const_get("Missing") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "NameError: uninitialized constant Missing");
+        },
+        _ => assert!(false)
+    }
+
+    assert!(mruby.const_get(mruby.fixnum(1), "API_KEY").is_err());
+}
+
+#[test]
+fn api_backtrace() {
+    let mruby = Mruby::new();
+
+    mruby.run_with_filename("
+        def inner
+          fail 'deep'
+        end
+
+        def middle
+          inner
+        end
+    ", "inner.rb").unwrap();
+
+    let result = mruby.run_with_filename("
+        def outer
+          middle
+        end
+
+        outer
+    ", "outer.rb");
+
+    let entry = |file: &str, line, method: Option<&str>| {
+        BacktraceEntry {
+            file: file.to_owned(),
+            line: line,
+            method: method.map(|method| method.to_owned())
+        }
+    };
+
+    match result {
+        Err(MrubyError::Runtime { backtrace, .. }) => {
+            assert_eq!(backtrace, vec![
+                entry("inner.rb", 3, Some("Object.inner")),
+                entry("inner.rb", 7, Some("Object.middle")),
+                entry("outer.rb", 3, Some("Object.outer")),
+                entry("outer.rb", 6, None)
+            ]);
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_backtrace_value() {
+    let mruby = Mruby::new();
+
+    mruby.run_with_filename("
+        class Deep
+          def inner
+            fail 'deep'
+          end
+
+          def outer
+            inner
+          end
+        end
+    ", "deep.rb").unwrap();
+
+    let deep = mruby.run("Deep.new").unwrap();
+    let exc = deep.protected_call("outer", vec![]).unwrap_err();
+
+    assert_eq!(exc.backtrace(), vec![
+        BacktraceEntry {
+            file: "deep.rb".to_owned(),
+            line: 4,
+            method: Some("Deep.inner".to_owned())
+        },
+        BacktraceEntry {
+            file: "deep.rb".to_owned(),
+            line: 8,
+            method: Some("Deep.outer".to_owned())
+        }
+    ]);
+    assert!(deep.backtrace().is_empty());
+}
+
+#[test]
+fn api_error_boxed() {
+    fn run(mruby: &mrusty::MrubyType) -> Result<(), Box<Error + Send + Sync>> {
+        try!(mruby.run("fail 'first line\nsecond line'"));
+
+        Ok(())
+    }
+
+    let mruby = Mruby::new();
+
+    let err = run(&mruby).err().unwrap();
+
+    assert_eq!(format!("{}", err), "Runtime error: RuntimeError: first line\\nsecond line");
+
+    match err.downcast::<MrubyError>() {
+        Ok(err) => assert!(err.backtrace().is_some()),
+        Err(_)  => assert!(false)
+    }
+}
+
+#[test]
+fn api_error_source() {
+    let mruby = Mruby::new();
+
+    let err = mruby.execute(Path::new("tests/missing.rb")).err().unwrap();
+
+    assert!(err.backtrace().is_none());
+
+    let source = err.source().unwrap();
+
+    assert_eq!(source.downcast_ref::<std::io::Error>().unwrap().kind(),
+               std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn api_raise_nested_call() {
+    struct Cont;
+
+    let mruby = Mruby::new();
+    let data = Rc::new(());
+
+    mruby.def_class_for::<Cont>("Container");
+
+    let captured = data.clone();
+
+    mruby.def_method_for::<Cont, _>("nested", move |mruby, slf| {
+        let slf = slf.to_obj::<Cont>().unwrap();
+        let _slf = slf.borrow_mut();
+        let _data = captured.clone();
+
+        mruby.run("fail 'nested'").unwrap()
+    });
+
+    let obj = mruby.obj(Cont);
+
+    let mruby_count = Rc::strong_count(&mruby);
+    let data_count = Rc::strong_count(&data);
+
+    assert!(obj.call("nested", vec![]).is_err());
+
+    assert_eq!(Rc::strong_count(&mruby), mruby_count);
+    assert_eq!(Rc::strong_count(&data), data_count);
+    assert!(obj.to_obj::<Cont>().unwrap().try_borrow_mut().is_ok());
+}
+
+#[test]
+fn api_raise_args() {
+    struct Cont;
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("add", mrfn!(|mruby, _slf: (&mut Cont), a: i32, b: i32| {
+        mruby.fixnum(a + b)
+    }));
+
+    let obj = mruby.obj(Cont);
+
+    let mruby_count = Rc::strong_count(&mruby);
+
+    match obj.call("add", vec![mruby.fixnum(1)]) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "ArgumentError: wrong number of arguments");
+        },
+        _ => assert!(false)
+    }
+
+    assert_eq!(Rc::strong_count(&mruby), mruby_count);
+    assert!(obj.to_obj::<Cont>().unwrap().try_borrow_mut().is_ok());
+
+    let result = obj.call("add", vec![mruby.fixnum(1), mruby.fixnum(2)]).unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 3);
+}
+
 #[test]
 fn api_execute_binary() {
     let mruby = Mruby::new();
@@ -184,26 +655,3298 @@ fn api_execute_binary() {
 }
 
 #[test]
-fn api_mruby_class() {
+fn api_load_gem() {
     let mruby = Mruby::new();
 
     Scalar::require(mruby.clone());
 
-    mruby_class!(mruby, "Container", {
-        def!("initialize", |mruby, slf: Value, s: (&Scalar)| {
-            slf.set_var("value", mruby.float(s.value as f64));
+    mruby.load_gem("compiled", include_gem!("compiled.mrb")).unwrap();
 
-            slf
-        });
+    let result = mruby.run("require 'compiled'").unwrap();
 
-        def!("value", |slf: Value| {
-            slf.get_var("value").unwrap()
-        });
+    assert_eq!(result.to_bool().unwrap(), false);
+
+    match mruby.load_gem("broken", b"not bytecode") {
+        Err(MrubyError::Filetype) => (),
+        _                         => panic!("expected Filetype error")
+    }
+}
+
+#[test]
+fn api_rescue() {
+    let mruby = Mruby::new();
+
+    let result = mruby.rescue(|| mruby.run("1 + 2"), |_| "unreachable");
+
+    assert_eq!(result.unwrap().to_i32().unwrap(), 3);
+
+    let result = mruby.rescue(|| {
+        let value = try!(mruby.run("[1, 2]"));
+
+        value.call("fetch", vec![mruby.fixnum(5)])
+    }, |exc| exc.class().to_str().to_owned());
+
+    assert_eq!(result.unwrap_err(), "IndexError");
+
+    let result = mruby.rescue(|| unsafe {
+        Ok(mruby.nil().call_unchecked("raise", vec![mruby.string("unchecked")]))
+    }, |exc| {
+        exc.call("message", vec![]).unwrap().to_str().unwrap().to_owned()
     });
 
-    let result = mruby.run("Container.new(Scalar.new 3.0).value").unwrap();
+    assert_eq!(result.unwrap_err(), "unchecked");
 
-    assert_eq!(result.to_f64().unwrap(), 3.0);
+    let result = mruby.rescue(|| mruby.fixnum(1).to_str().map(|_| mruby.nil()), |exc| {
+        exc.class().to_str().to_owned()
+    });
+
+    assert_eq!(result.unwrap_err(), "StandardError");
+
+    let result = mruby.rescue(|| {
+        let result = mruby.run("raise ArgumentError, 'collected?'");
+
+        mruby.gc_run();
+
+        result
+    }, |exc| exc.call("message", vec![]).unwrap().to_str().unwrap().to_owned());
+
+    assert_eq!(result.unwrap_err(), "collected?");
+
+    let result = mruby.rescue(|| mruby.fixnum(1).to_str().map(|_| mruby.nil()), |exc| {
+        exc.call("message", vec![]).unwrap().to_str().unwrap().to_owned()
+    });
+
+    assert_eq!(result.unwrap_err(), "Cast error: expected String");
+}
+
+#[test]
+fn api_rescue_from() {
+    let mruby = Mruby::new();
+
+    let result = mruby.rescue_from("StandardError", "1 + 2", |exc| exc);
+
+    assert_eq!(result.unwrap().to_i32().unwrap(), 3);
+
+    let result = mruby.rescue_from("StandardError", "[1, 2].fetch 5", |exc| {
+        mruby.string(exc.class().to_str())
+    });
+
+    assert_eq!(result.unwrap().to_str().unwrap(), "IndexError");
+
+    let result = mruby.rescue_from("TypeError", "raise ArgumentError, 'bad'", |exc| exc);
+
+    assert_eq!(result.unwrap_err().to_string(), "Runtime error: ArgumentError: bad");
+
+    let result = mruby.rescue_from("Missing", "raise 'oops'", |exc| exc);
+
+    assert_eq!(result.unwrap_err().to_string(), "Runtime error: RuntimeError: oops");
+}
+
+#[test]
+fn api_exception_class() {
+    let mruby = Mruby::new();
+
+    let game_error = mruby.def_exception_class("GameError", None);
+    let save_error = mruby.def_exception_class("SaveError", Some(&game_error));
+
+    let class = mruby.def_class("Game");
+    mruby.def_method(class, "save", mrfn!(|mruby, _slf: Value, fatal: bool| {
+        let class = mruby.get_class(if fatal { "SaveError" } else { "GameError" }).unwrap();
+
+        mruby.raise_exception(&class, "cannot save")
+    }));
+
+    let result = mruby.run("
+      [SaveError.ancestors.take(3), GameError.new('bad').message,
+       begin
+         Game.new.save true
+       rescue GameError => e
+         [e.class, e.message]
+       end]
+    ").unwrap();
+
+    assert_eq!(result.inspect(),
+               "[[SaveError, GameError, StandardError], \"bad\", [SaveError, \"cannot save\"]]");
+
+    let result = mruby.rescue_from("GameError", "Game.new.save false", |exc| exc);
+
+    assert_eq!(result.unwrap().class().to_str(), "GameError");
+
+    let result = mruby.rescue_from("SaveError", "Game.new.save false", |exc| exc);
+
+    assert_eq!(result.unwrap_err().to_string(), "Runtime error: GameError: cannot save");
+    assert_eq!(save_error.to_str(), "SaveError");
+}
+
+#[test]
+fn api_ensure() {
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mruby = Mruby::new();
+
+    let cleaned = Cell::new(0);
+
+    let result = mruby.ensure(|| mruby.run("1"), || cleaned.set(cleaned.get() + 1));
+
+    assert_eq!(result.unwrap().to_i32().unwrap(), 1);
+
+    let result = mruby.ensure(|| mruby.run("raise 'oops'"), || cleaned.set(cleaned.get() + 1));
+
+    assert_eq!(result.unwrap_err().to_string(), "Runtime error: RuntimeError: oops");
+
+    let result = mruby.ensure(|| mruby.execute(Path::new("missing.rb")),
+                              || cleaned.set(cleaned.get() + 1));
+
+    match result {
+        Err(MrubyError::Io(_)) => (),
+        _                      => panic!("expected Io error")
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        mruby.ensure(|| panic!("body"), || cleaned.set(cleaned.get() + 1))
+    }));
+
+    assert!(result.is_err());
+    assert_eq!(cleaned.get(), 4);
+
+    assert_eq!(mruby.run("2").unwrap().to_i32().unwrap(), 2);
+}
+
+#[test]
+fn api_object_id() {
+    use std::collections::HashMap;
+
+    let mruby = Mruby::new();
+
+    let values = mruby.run("a = 'a'; [a, 'a', a, :sym, :sym]").unwrap().to_vec().unwrap();
+
+    let mut counts = HashMap::new();
+
+    for value in &values {
+        *counts.entry(value.object_id()).or_insert(0) += 1;
+    }
+
+    assert_eq!(counts[&values[0].object_id()], 2);
+    assert_eq!(counts[&values[1].object_id()], 1);
+    assert_eq!(counts[&values[3].object_id()], 2);
+
+    let result = mruby.run("$a = 'a'; $a.object_id").unwrap();
+    let a = mruby.run("$a").unwrap();
+
+    assert_eq!(result.to_i32().unwrap() as u32 as usize, a.object_id());
+}
+
+#[test]
+fn api_string_static() {
+    let mruby = Mruby::new();
+
+    let name = mruby.string_static("static");
+
+    assert_eq!(name.to_str().unwrap(), "static");
+    assert_eq!(name, mruby.string("static"));
+
+    mruby.run("$names = []").unwrap();
+    mruby.run("$names").unwrap().call("push", vec![name]).unwrap();
+
+    let result = mruby.run("
+      name = $names.first
+
+      errors = [['<<', 'x'], ['upcase!'], ['replace', 'x']].map do |method, *args|
+        begin
+          name.send(method, *args)
+          nil
+        rescue RuntimeError => e
+          e.message
+        end
+      end
+
+      copy = name.dup
+      copy << '!'
+
+      errors + [copy, name]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_str().unwrap(), "can't modify frozen string");
+    assert_eq!(result[1].to_str().unwrap(), "can't modify frozen string");
+    assert_eq!(result[2].to_str().unwrap(), "can't modify frozen string");
+    assert_eq!(result[3].to_str().unwrap(), "static!");
+    assert_eq!(result[4].to_str().unwrap(), "static");
+}
+
+#[test]
+fn api_value_eq_hash() {
+    use std::collections::HashMap;
+
+    let mruby = Mruby::new();
+    let other = Mruby::new();
+
+    assert_eq!(mruby.fixnum(1), mruby.float(1.0));
+    assert!(ValueEql(mruby.fixnum(1)) != ValueEql(mruby.float(1.0)));
+    assert!(mruby.string("a") != other.string("a"));
+    assert!(ValueEql(mruby.string("a")) != ValueEql(other.string("a")));
+
+    let keys = mruby.run("['a', :b, 1, [1, 2], 'a', [1, 2]]").unwrap().to_vec().unwrap();
+
+    let mut counts = HashMap::new();
+
+    for key in keys {
+        *counts.entry(ValueEql(key)).or_insert(0) += 1;
+    }
+
+    assert_eq!(counts.len(), 4);
+    assert_eq!(counts[&ValueEql(mruby.string("a"))], 2);
+    assert_eq!(counts[&ValueEql(mruby.run("[1, 2]").unwrap())], 2);
+    assert_eq!(counts[&ValueEql(mruby.symbol("b"))], 1);
+}
+
+#[test]
+fn api_def_method_fn() {
+    struct Closure;
+    struct Func;
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Closure>("Closure");
+    mruby.def_class_for::<Func>("Func");
+
+    mruby.def_method_for::<Closure, _>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Closure)
+    }));
+    mruby.def_method_for::<Closure, _>("add", mrfn!(|mruby, _slf: (&Closure), a: i32, b: i32| {
+        mruby.fixnum(a + b)
+    }));
+    mruby.def_method_for::<Closure, _>("fail", mrfn!(|_mruby, _slf: (&Closure)| {
+        panic!("failed")
+    }));
+
+    mruby.def_method_fn::<Func>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Func)
+    }));
+    mruby.def_method_fn::<Func>("add", mrfn!(|mruby, _slf: (&Func), a: i32, b: i32| {
+        mruby.fixnum(a + b)
+    }));
+    mruby.def_method_fn::<Func>("fail", mrfn!(|_mruby, _slf: (&Func)| {
+        panic!("failed")
+    }));
+
+    for class in &["Closure", "Func"] {
+        let run = |code: &str| mruby.run(&code.replace("C", class));
+
+        assert_eq!(run("C.new.add 1, 2").unwrap().to_i32().unwrap(), 3);
+        assert_eq!(run("C.new.add 1").err().unwrap().to_string(),
+                   "Runtime error: ArgumentError: wrong number of arguments");
+        assert_eq!(run("C.new.fail").err().unwrap().to_string(),
+                   "Runtime error: RustPanic: failed");
+        assert_eq!(run("C.new.add 1, 'a'").err().unwrap().to_string(),
+                   "Runtime error: TypeError: no implicit conversion of String into Integer");
+    }
+}
+
+#[test]
+fn api_class_builder() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Single {
+        value: i32
+    }
+
+    struct Built {
+        value: i32
+    }
+
+    let mruby = Mruby::new();
+    let calls = Rc::new(Cell::new(0));
+
+    mruby.def_class_for::<Single>("Single");
+    mruby.def_method_for::<Single, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+        slf.init(Single { value: v })
+    }));
+    mruby.def_method_for::<Single, _>("value", mrfn!(|mruby, slf: (&Single)| {
+        mruby.fixnum(slf.value)
+    }));
+    mruby.def_class_method_for::<Single, _>("double", mrfn!(|mruby, _slf: Value, v: i32| {
+        mruby.fixnum(v * 2)
+    }));
+
+    let counter = calls.clone();
+
+    mruby.class_builder::<Built>("Built")
+         .method_fn("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+             slf.init(Built { value: v })
+         }))
+         .method("value", move |mruby, slf| {
+             counter.set(counter.get() + 1);
+
+             mruby.fixnum(slf.to_obj::<Built>().unwrap().borrow().value)
+         })
+         .class_method("double", mrfn!(|mruby, _slf: Value, v: i32| {
+             mruby.fixnum(v * 2)
+         }))
+         .define();
+
+    for class in &["Single", "Built"] {
+        let run = |code: &str| mruby.run(&code.replace("C", class));
+
+        assert_eq!(run("C.new(3).value").unwrap().to_i32().unwrap(), 3);
+        assert_eq!(run("C.double 4").unwrap().to_i32().unwrap(), 8);
+        assert_eq!(run("C.new(3).dup.value").unwrap().to_i32().unwrap(), 3);
+        assert_eq!(run("C.new").err().unwrap().to_string(),
+                   "Runtime error: ArgumentError: wrong number of arguments");
+        assert!(run("C.respond_to?(:value)").unwrap() == mruby.bool(false));
+    }
+
+    assert_eq!(calls.get(), 2);
+
+    let value = mruby.obj(Built { value: 5 });
+
+    assert_eq!(value.call("value", vec![]).unwrap().to_i32().unwrap(), 5);
+}
+
+#[test]
+fn api_open_class_for() {
+    struct Cont {
+        value: i32
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_module("Doubling");
+    mruby.run("
+      module Doubling
+        def double
+          value * 2
+        end
+      end
+    ").unwrap();
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+        slf.init(Cont { value: v })
+    }));
+
+    let class = mruby.open_class_for::<Cont>()
+                     .method("value", mrfn!(|mruby, slf: (&Cont)| {
+                         mruby.fixnum(slf.value)
+                     }))
+                     .class_method("zero", mrfn!(|mruby, _slf: Value| {
+                         mruby.obj(Cont { value: 0 })
+                     }))
+                     .include("Doubling")
+                     .define();
+
+    assert_eq!(class.to_str(), "Container");
+
+    let result = mruby.run("[Container.new(3).double, Container.zero.value]").unwrap();
+
+    assert_eq!(result.to_vec().unwrap().iter().map(|v| v.to_i32().unwrap())
+                     .collect::<Vec<_>>(), vec![6, 0]);
+}
+
+#[test]
+fn api_def_attr_for() {
+    struct Player {
+        name: String,
+        hp: i32,
+        alive: bool
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Player>("Player");
+    mruby.def_attr_reader_for::<Player>(&[
+        ("name", |mruby, player| mruby.string(&player.name)),
+        ("hp", |mruby, player| mruby.fixnum(player.hp)),
+        ("alive?", |mruby, player| mruby.bool(player.alive))
+    ]);
+    mruby.def_attr_writer_for::<Player>(&[
+        ("name", |player, value| player.name = value.to_str().unwrap().to_owned()),
+        ("hp", |player, value| {
+            player.hp = value.to_i32().unwrap();
+            player.alive = player.hp > 0;
+        })
+    ]);
+
+    let player = mruby.obj(Player { name: "a".to_owned(), hp: 3, alive: true });
+
+    mruby.get_class("Object").unwrap().def_const("PLAYER", player);
+
+    let result = mruby.run("
+        PLAYER.name = 'b'
+        PLAYER.hp -= 3
+
+        [PLAYER.name, PLAYER.hp, PLAYER.alive?, (PLAYER.hp = 7)]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_str().unwrap(), "b");
+    assert_eq!(result[1].to_i32().unwrap(), 0);
+    assert_eq!(result[2].to_bool().unwrap(), false);
+    assert_eq!(result[3].to_i32().unwrap(), 7);
+
+    assert_eq!(mruby.run("PLAYER.send(:hp=)").err().unwrap().to_string(),
+               "Runtime error: ArgumentError: wrong number of arguments");
+
+    let obj = mruby.run("PLAYER").unwrap().to_obj::<Player>().unwrap();
+    let borrow = obj.borrow_mut();
+
+    assert!(mruby.run("PLAYER.hp = 1").err().unwrap().to_string()
+                 .contains("ReentrantBorrowError"));
+    assert!(mruby.run("PLAYER.hp").err().unwrap().to_string().contains("ReentrantBorrowError"));
+
+    drop(borrow);
+
+    assert_eq!(mruby.run("PLAYER.hp").unwrap().to_i32().unwrap(), 7);
+}
+
+#[test]
+fn api_value_conversions() {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    let mruby = Mruby::new();
+
+    let mut first = HashMap::new();
+
+    first.insert("a".to_owned(), 1);
+    first.insert("b".to_owned(), 2);
+
+    let nested = vec![Some(first), None, Some(HashMap::new())];
+    let value = mruby.value(&nested);
+
+    assert_eq!(value.call("size", vec![]).unwrap().to_i32().unwrap(), 3);
+    assert_eq!(value.to_rust::<Vec<Option<HashMap<String, i32>>>>().unwrap(), nested);
+
+    let value = mruby.run("[{ a: 1.5 }, nil]").unwrap();
+
+    assert_eq!(value.to_rust::<Vec<Option<HashMap<String, f64>>>>().unwrap()[0].as_ref()
+                    .unwrap()["a"], 1.5);
+
+    assert_eq!(mruby.value(-1i8).to_rust::<i8>().unwrap(), -1);
+    assert_eq!(mruby.value(200u8).to_rust::<u8>().unwrap(), 200);
+    assert_eq!(mruby.value(7i32).to_rust::<u64>().unwrap(), 7);
+    assert_eq!(mruby.value(2.5f32).to_rust::<f32>().unwrap(), 2.5);
+    assert_eq!(mruby.value("hi").to_rust::<String>().unwrap(), "hi");
+    assert_eq!(mruby.value(()), mruby.nil());
+
+    match mruby.fixnum(-1).to_rust::<u32>() {
+        Err(MrubyError::OutOfRange(typ)) => assert_eq!(typ, "u32"),
+        _                                => panic!("expected OutOfRange error")
+    }
+
+    match mruby.fixnum(300).to_rust::<i8>() {
+        Err(MrubyError::OutOfRange(typ)) => assert_eq!(typ, "i8"),
+        _                                => panic!("expected OutOfRange error")
+    }
+
+    match mruby.run("[1, 'a']").unwrap().to_rust::<Vec<i32>>() {
+        Err(MrubyError::Cast(expected)) => assert_eq!(expected, "Fixnum"),
+        _                               => panic!("expected Cast error")
+    }
+
+    assert!(mruby.run("[1]").unwrap().to_rust::<HashMap<String, i32>>().is_err());
+
+    struct Cont {
+        value: i32
+    }
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("value", mrfn!(|mruby, slf: (&Cont)| {
+        mruby.fixnum(slf.value)
+    }));
+
+    let cont = Rc::new(RefCell::new(Cont { value: 1 }));
+    let value = cont.to_value(&mruby);
+
+    cont.borrow_mut().value = 2;
+
+    assert_eq!(value.call("value", vec![]).unwrap().to_i32().unwrap(), 2);
+    assert!(Rc::ptr_eq(&value.to_rust::<Rc<RefCell<Cont>>>().unwrap(), &cont));
+
+    struct Point(i32, i32);
+
+    impl ToValue for Point {
+        fn to_value(&self, mruby: &mrusty::MrubyType) -> mrusty::Value {
+            mruby.value(vec![self.0, self.1])
+        }
+    }
+
+    impl FromValue for Point {
+        fn from_value(value: &mrusty::Value) -> Result<Point, MrubyError> {
+            let coords = value.to_rust::<Vec<i32>>()?;
+
+            Ok(Point(coords[0], coords[1]))
+        }
+    }
+
+    let points = mruby.value(vec![Point(1, 2), Point(3, 4)]);
+    let points = points.call("reverse", vec![]).unwrap().to_rust::<Vec<Point>>().unwrap();
+
+    assert_eq!((points[0].0, points[1].1), (3, 2));
+}
+
+#[test]
+fn api_memory_limit() {
+    let mruby = Mruby::new();
+
+    mruby.set_memory_limit(mruby.memory_used() + 4 * 1024 * 1024);
+
+    mruby.run("
+      def fill
+        strings = []
+        loop { strings << 'a' * 1024 }
+      end
+    ").unwrap();
+
+    match mruby.run("fill") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "NoMemoryError: failed to allocate memory");
+        },
+        _ => panic!("expected NoMemoryError")
+    }
+
+    let result = mruby.run("
+      begin
+        fill
+      rescue NoMemoryError
+        true
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_bool().unwrap(), true);
+
+    mruby.run("GC.start").unwrap();
+
+    assert!(mruby.memory_used() < 4 * 1024 * 1024);
+    assert_eq!(mruby.run("('a' * 1024).size").unwrap().to_i32().unwrap(), 1024);
+
+    mruby.set_memory_limit(0);
+
+    assert_eq!(mruby.run("('a' * 8_000_000).size").unwrap().to_i32().unwrap(), 8_000_000);
+}
+
+#[test]
+fn api_json() {
+    let mruby = Mruby::new();
+
+    let value = mruby.run(r#"
+      {
+        'name' => "caf\u00e9 \u{1F600} \"quoted\"\n",
+        kind: :cup,
+        sizes: [1, -2, 0.5, 1.0e20, []],
+        nested: { 'deep' => [{ 'empty' => {} }, nil, true, false] }
+      }
+    "#).unwrap();
+
+    let json = value.to_json().unwrap();
+
+    assert_eq!(json, "{\"name\":\"café 😀 \\\"quoted\\\"\\n\",\"kind\":\"cup\",\
+                      \"sizes\":[1,-2,0.5,1e20,[]],\
+                      \"nested\":{\"deep\":[{\"empty\":{}},null,true,false]}}");
+
+    let parsed = mruby.json_parse(&json).unwrap();
+
+    let expected = mruby.run(r#"
+      {
+        'name' => "caf\u00e9 \u{1F600} \"quoted\"\n",
+        'kind' => 'cup',
+        'sizes' => [1, -2, 0.5, 1.0e20, []],
+        'nested' => { 'deep' => [{ 'empty' => {} }, nil, true, false] }
+      }
+    "#).unwrap();
+
+    assert_eq!(parsed, expected);
+    assert_eq!(parsed.to_json().unwrap(), json);
+
+    let parsed = mruby.json_parse(" [\"\\u00e9\\ud83d\\ude00\\/\", 3000000000, 2.0, -0.25e1] ").unwrap();
+
+    assert_eq!(parsed.to_vec().unwrap(), vec![mruby.string("é😀/"), mruby.float(3000000000.0),
+                                              mruby.float(2.0), mruby.float(-2.5)]);
+
+    let expect = |json: &str, message: &str| {
+        match mruby.json_parse(json) {
+            Err(MrubyError::Json(error)) => assert_eq!(error, message),
+            result                       => panic!("unexpected {:?}", result)
+        }
+    };
+
+    expect("[1, 2", "unexpected end of input");
+    expect("{\"a\" 1}", "unexpected '1' at offset 5");
+    expect("[1] x", "unexpected 'x' at offset 4");
+    expect("nul", "unexpected end of input");
+    expect(&"[".repeat(200), "nesting of 101 is too deep");
+
+    let expect = |code: &str, message: &str| {
+        match mruby.run(code).unwrap().to_json() {
+            Err(MrubyError::Json(error)) => assert_eq!(error, message),
+            result                       => panic!("unexpected {:?}", result)
+        }
+    };
+
+    expect("{ a: [1, proc {}] }", "Proc cannot be converted to JSON");
+    expect("{ 1 => 2 }", "Fixnum keys cannot be converted to JSON");
+    expect("[1.0 / 0]", "inf cannot be converted to JSON");
+    expect("a = []; a << a", "nesting of 101 is too deep");
+}
+
+#[test]
+fn api_to_json_string() {
+    let mruby = Mruby::new();
+
+    let value = mruby.run("{ 'a' => [1, 2.5, nil, :b] }").unwrap();
+
+    assert_eq!(value.to_json_string().unwrap(), value.to_json().unwrap());
+
+    match mruby.run("[proc {}]").unwrap().to_json_string() {
+        Err(MrubyError::Json(error)) => assert_eq!(error, "Proc cannot be converted to JSON"),
+        result                       => panic!("unexpected {:?}", result)
+    }
+}
+
+#[test]
+fn api_from_json() {
+    let mruby = Mruby::new();
+
+    let json = r#"{"a":[1,2.5,null,"b"],"c":{}}"#;
+
+    assert_eq!(mruby.from_json(json).unwrap(), mruby.json_parse(json).unwrap());
+
+    match mruby.from_json("[1, 2") {
+        Err(MrubyError::Json(error)) => assert_eq!(error, "unexpected end of input"),
+        result                       => panic!("unexpected {:?}", result)
+    }
+}
+
+#[test]
+#[cfg(feature = "instruction-limit")]
+fn api_instruction_limit() {
+    let mruby = Mruby::new();
+
+    mruby.set_instruction_limit(100_000);
+
+    assert_eq!(mruby.run("(1..100).reduce(:+)").unwrap().to_i32().unwrap(), 5050);
+
+    let expect_limit = |script: &str| {
+        match mruby.run(script) {
+            Err(MrubyError::Runtime { message: err, .. }) => {
+                assert_eq!(err, "RuntimeError: instruction limit exceeded");
+            },
+            _ => panic!("expected the instruction limit to be exceeded")
+        }
+    };
+
+    expect_limit("loop {}");
+    expect_limit("1");
+
+    mruby.reset_instruction_counter();
+
+    expect_limit("
+      begin
+        loop {}
+      rescue
+        loop {}
+      end
+    ");
+
+    mruby.reset_instruction_counter();
+
+    assert_eq!(mruby.run("(1..100).reduce(:+)").unwrap().to_i32().unwrap(), 5050);
+
+    mruby.set_instruction_limit(0);
+
+    assert_eq!(mruby.run("i = 0; i += 1 while i < 200_000; i").unwrap().to_i32().unwrap(),
+               200_000);
+}
+
+#[test]
+fn api_check_syntax() {
+    let mruby = Mruby::new();
+
+    for code in &["1 + 2", "def one\n  1\nend", "s = <<EOS\nheredoc\nEOS\n", "[1,\n 2]"] {
+        assert_eq!(mruby.check_syntax(code), SyntaxResult::Complete);
+    }
+
+    for code in &["class Container", "def one\n  1", "[1, 2].", "'open", "s = <<EOS\nheredoc",
+                  "if true", "[1,", "foo(1,"] {
+        assert_eq!(mruby.check_syntax(code), SyntaxResult::Incomplete, "{}", code);
+    }
+
+    match mruby.check_syntax("1 +\n)") {
+        SyntaxResult::Error(message) => assert!(message.starts_with("line 2:"), "{}", message),
+        result                       => panic!("expected an error instead of {:?}", result)
+    }
+
+    mruby.check_syntax("checked = 1");
+
+    assert!(mruby.run("checked").is_err());
+}
+
+#[test]
+fn api_print_handler() {
+    use std::cell::RefCell;
+
+    let mruby = Mruby::new();
+    let lines = Rc::new(RefCell::new(vec![]));
+
+    let printed = lines.clone();
+
+    mruby.set_print_handler(move |s| printed.borrow_mut().push(s.to_owned()));
+    mruby.run("print 'a', 1; puts 2, 3").unwrap();
+
+    assert_eq!(*lines.borrow(), vec!["a", "1", "2", "\n", "3", "\n"]);
+
+    {
+        let capture = mruby.redirect_stdout_to_string();
+
+        mruby.run("p({ a: 1 }); puts; $stdout.__printstr__ 'raw'").unwrap();
+
+        assert_eq!(capture.output(), "{:a=>1}\n\nraw");
+    }
+
+    lines.borrow_mut().clear();
+    mruby.run("print 'b'").unwrap();
+
+    assert_eq!(*lines.borrow(), vec!["b"]);
+}
+
+#[test]
+fn api_error_handler() {
+    use std::cell::RefCell;
+
+    let mruby = Mruby::new();
+    let errors = Rc::new(RefCell::new(vec![]));
+
+    let reported = errors.clone();
+
+    mruby.set_error_handler(move |s| reported.borrow_mut().push(s.to_owned()));
+
+    match mruby.run("1 +\n\n") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("SyntaxError: "));
+        },
+        _ => assert!(false)
+    }
+
+    assert_eq!(errors.borrow().len(), 1);
+    assert!(errors.borrow()[0].starts_with("line "));
+    assert!(errors.borrow()[0].contains("syntax error"));
+
+    assert!(mruby.run("raise 'not a parse error'").is_err());
+    assert_eq!(mruby.run("1 + 1").unwrap().to_i32().unwrap(), 2);
+    assert_eq!(errors.borrow().len(), 1);
+
+    mruby.silence_errors();
+
+    assert!(mruby.run("def").is_err());
+    assert_eq!(errors.borrow().len(), 1);
+}
+
+#[test]
+fn api_warning_handler() {
+    use std::cell::RefCell;
+
+    let mruby = Mruby::new();
+    let warnings = Rc::new(RefCell::new(vec![]));
+
+    let reported = warnings.clone();
+    let mut count = 0;
+
+    mruby.set_warning_handler(move |s| {
+        count += 1;
+
+        reported.borrow_mut().push(format!("{} {}", count, s));
+    });
+
+    mruby.run("def f(*a); a; end").unwrap();
+
+    assert!(warnings.borrow().is_empty());
+
+    assert_eq!(mruby.run("f *[1]").unwrap().to_vec().unwrap().len(), 1);
+    assert_eq!(mruby.run("begin; 1; else; 2; end").unwrap().to_i32().unwrap(), 2);
+
+    assert_eq!(*warnings.borrow(), vec![
+        "1 line 1:3: '*' interpreted as argument prefix",
+        "2 line 1:23: else without rescue is useless"
+    ]);
+
+    mruby.filename("script.rb");
+    mruby.run("f *[2]").unwrap();
+
+    assert_eq!(warnings.borrow()[2], "3 script.rb:1:3: '*' interpreted as argument prefix");
+
+    match mruby.run("f *[") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("SyntaxError: "));
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_warning_handler_reentrant() {
+    use std::cell::RefCell;
+
+    let mruby = Mruby::new();
+    let warnings = Rc::new(RefCell::new(vec![]));
+
+    let reported = warnings.clone();
+    let inner = Rc::downgrade(&mruby);
+
+    mruby.set_warning_handler(move |s| {
+        reported.borrow_mut().push(s.to_owned());
+
+        let inner = inner.upgrade().unwrap();
+
+        assert_eq!(inner.run("f *[3]").unwrap().to_vec().unwrap().len(), 1);
+    });
+
+    mruby.run("def f(*a); a; end").unwrap();
+
+    assert_eq!(mruby.run("f *[1]").unwrap().to_vec().unwrap().len(), 1);
+    assert_eq!(*warnings.borrow(), vec!["line 1:3: '*' interpreted as argument prefix"]);
+}
+
+#[test]
+#[cfg(feature = "instruction-limit")]
+fn api_run_with_timeout() {
+    let mruby = Mruby::new();
+
+    match mruby.run_with_timeout("begin; loop {}; rescue; loop {}; end", 100_000) {
+        Err(MrubyError::Timeout) => (),
+        result                   => panic!("expected a timeout instead of {:?}", result)
+    }
+
+    match mruby.run_with_timeout("raise 'instruction limit exceeded'", 100_000) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "RuntimeError: instruction limit exceeded");
+        },
+        result => panic!("expected a RuntimeError instead of {:?}", result)
+    }
+
+    assert_eq!(mruby.run_with_timeout("(1..100).reduce(:+)", 100_000).unwrap().to_i32().unwrap(),
+               5050);
+    assert_eq!(mruby.run("i = 0; i += 1 while i < 200_000; i").unwrap().to_i32().unwrap(),
+               200_000);
+}
+
+#[test]
+fn api_send() {
+    let mruby = Mruby::new();
+
+    let strategies = mruby.run("[:upcase, :*]").unwrap().to_vec().unwrap();
+    let word = mruby.string("hi");
+
+    let result = mruby.send(&word, strategies[0].clone(), vec![]).unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "HI");
+
+    let result = mruby.send(&word, strategies[1].clone(), vec![mruby.fixnum(2)]).unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "hihi");
+
+    match mruby.send(&word, mruby.string("upcase"), vec![]) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "TypeError: \"upcase\" is not a symbol");
+        },
+        result => panic!("expected a TypeError instead of {:?}", result)
+    }
+
+    match mruby.send(&word, mruby.symbol("missing"), vec![]) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("NoMethodError: "));
+        },
+        result => panic!("expected a NoMethodError instead of {:?}", result)
+    }
+}
+
+#[test]
+fn api_inspect() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      class Odd
+        def inspect
+          42
+        end
+      end
+    ").unwrap();
+
+    assert_eq!(mruby.run("{ a: 1.5 }").unwrap().inspect(), "{:a=>1.5}");
+    assert_eq!(mruby.run("Odd.new").unwrap().inspect(), "<inspect raised: Odd>");
+    assert_eq!(mruby.nil().to_ruby_string(), "nil");
+}
+
+#[test]
+fn api_fmt() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      class Shy
+        def to_s
+          raise 'no'
+        end
+
+        def inspect
+          'shy'
+        end
+      end
+    ").unwrap();
+
+    let shy = mruby.run("Shy.new").unwrap();
+
+    assert_eq!(format!("{:?}", mruby.string("a")), "\"a\"");
+    assert_eq!(format!("{}", mruby.symbol("a")), "a");
+    assert_eq!(format!("{}", shy), "shy");
+    assert_eq!(format!("{:?}", Some(shy)), "Some(shy)");
+}
+
+#[test]
+fn api_run_sandboxed() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      class IO
+        def read
+          'secret'
+        end
+      end
+
+      class File < IO
+        def self.read(path)
+          'secret'
+        end
+      end
+
+      $file = File
+    ").unwrap();
+
+    let result = mruby.run_sandboxed("File");
+
+    match result {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("NameError: "));
+        },
+        _ => assert!(false)
+    }
+
+    let result = mruby.run_sandboxed("[$file.respond_to?(:read), $file.new.respond_to?(:read)]");
+
+    assert_eq!(result.unwrap().to_vec().unwrap(), vec![mruby.bool(false), mruby.bool(false)]);
+
+    assert_eq!(mruby.sandbox_config(), None);
+    assert_eq!(mruby.run("File.read('path') + $file.new.read").unwrap().to_str().unwrap(),
+               "secretsecret");
+    assert_eq!(mruby.run("respond_to?(:require)").unwrap(), mruby.bool(true));
+
+    mruby.sandbox(&SandboxConfig::new().allow("IO")).unwrap();
+
+    assert_eq!(mruby.run_sandboxed("1 + 1").unwrap(), mruby.fixnum(2));
+    assert_eq!(mruby.sandbox_config(), Some(SandboxConfig::new().allow("IO")));
+    assert_eq!(mruby.run("IO.new.read").unwrap().to_str().unwrap(), "secret");
+    assert!(mruby.run("File").is_err());
+}
+
+#[test]
+fn api_sandbox_allow() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      module Process
+        def self.pid
+          1
+        end
+      end
+    ").unwrap();
+
+    mruby.sandbox(&SandboxConfig::new().allow_require().allow("Process")).unwrap();
+
+    assert_eq!(mruby.run("Process.pid").unwrap(), mruby.fixnum(1));
+
+    let result = mruby.run("require 'missing'");
+
+    match result {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "RuntimeError: cannot load missing.rb or missing.mrb")
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_sandbox_relax() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      class IO
+        def read
+          'secret'
+        end
+      end
+
+      class File < IO
+        def self.read(path)
+          'secret'
+        end
+      end
+    ").unwrap();
+
+    mruby.sandbox(&SandboxConfig::new().allow("File")).unwrap();
+
+    assert_eq!(mruby.run("File.read('path')").unwrap().to_str().unwrap(), "secret");
+    assert_eq!(mruby.run("File.new.respond_to?(:read)").unwrap(), mruby.bool(false));
+
+    mruby.sandbox(&SandboxConfig::new().allow_require()).unwrap();
+
+    assert_eq!(mruby.sandbox_config(), Some(SandboxConfig::new()));
+    assert!(mruby.run("File").is_err());
+    assert!(mruby.run("require 'file'").is_err());
+
+    mruby.relax_sandbox(&SandboxConfig::new().allow("IO").allow("File"));
+
+    assert_eq!(mruby.run("File.read('path') + File.new.read").unwrap().to_str().unwrap(),
+               "secretsecret");
+    assert!(mruby.run("require 'file'").is_err());
+}
+
+#[test]
+fn api_sandbox_error() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      module Process
+        def self.instance_methods(*)
+          raise 'not listed'
+        end
+      end
+    ").unwrap();
+
+    match mruby.sandbox(&SandboxConfig::new()) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "RuntimeError: not listed");
+        },
+        _ => assert!(false)
+    }
+}
+
+#[test]
+fn api_new_sandboxed() {
+    struct Meters(f64);
+
+    let sandboxed = Mruby::new_sandboxed(&SandboxConfig::new());
+    let mruby = Mruby::new();
+
+    let removed = vec![
+        "1.instance_eval { self }",
+        "1.instance_exec { self }",
+        "String.class_eval { self }",
+        "String.module_eval { self }",
+        "puts 'out'",
+        "p 1",
+        "require 'math'"
+    ];
+
+    for script in removed {
+        match sandboxed.run(script) {
+            Err(MrubyError::Runtime { message: err, .. }) => {
+                assert!(err.starts_with("NoMethodError: "), "{}", err);
+            },
+            _ => assert!(false, "{} is not removed", script)
+        }
+    }
+
+    for script in &["GC.start", "ObjectSpace.count_objects"] {
+        match sandboxed.run(script) {
+            Err(MrubyError::Runtime { message: err, .. }) => {
+                assert!(err.starts_with("NameError: "), "{}", err);
+            },
+            _ => assert!(false, "{} is not removed", script)
+        }
+    }
+
+    mruby.set_print_handler(|_| {});
+    mruby.def_file::<Vector>("math");
+
+    for script in &["1.instance_eval { self }", "1.instance_exec { self }",
+                    "String.class_eval { self }", "String.module_eval { self }", "puts 'out'",
+                    "p 1", "require 'math'", "GC.start", "ObjectSpace.count_objects"] {
+        assert!(mruby.run(script).is_ok(), "{} fails", script);
+    }
+
+    Scalar::require(sandboxed.clone());
+    Vector::require(sandboxed.clone());
+
+    sandboxed.def_class_for::<Meters>("Meters");
+    sandboxed.def_method_for::<Meters, _>("initialize", mrfn!(|_mruby, slf: Value, v: f64| {
+        slf.init(Meters(v))
+    }));
+    sandboxed.def_method_for::<Meters, _>("coerce", mrfn!(|mruby, slf: (&Meters), other: f64| {
+        mruby.array(vec![mruby.float(other), mruby.float(slf.0)])
+    }));
+
+    let result = sandboxed.run("
+        scalar = Scalar.new 2.0
+        scalar.value = 3.0
+
+        [scalar * Vector.new(1.0, 2.0, 3.0), 1.5 + Meters.new(2.0)]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(*result[0].to_obj::<Vector>().unwrap().borrow(), Vector::new(3.0, 6.0, 9.0));
+    assert_eq!(result[1].to_f64().unwrap(), 3.5);
+
+    assert_eq!(sandboxed.sandbox_config(), Some(SandboxConfig::new()));
+    assert_eq!(mruby.sandbox_config(), None);
+
+    sandboxed.relax_sandbox(&SandboxConfig::new().allow_eval());
+
+    assert_eq!(sandboxed.sandbox_config(), Some(SandboxConfig::new().allow_eval()));
+    assert_eq!(sandboxed.run("1.instance_eval { self + 1 }").unwrap(), sandboxed.fixnum(2));
+    assert_eq!(sandboxed.run("String.class_eval { to_s }").unwrap().to_str().unwrap(), "String");
+    assert!(sandboxed.run("GC.start").is_err());
+
+    sandboxed.gc_run();
+    sandboxed.relax_sandbox(&SandboxConfig::new().allow_gc_control().allow("IO"));
+
+    assert_eq!(sandboxed.run("GC.start; ObjectSpace.class.to_s").unwrap().to_str().unwrap(),
+               "Module");
+    assert!(sandboxed.run("puts 'out'").is_err());
+    assert_eq!(sandboxed.sandbox_config(),
+               Some(SandboxConfig::new().allow_eval().allow_gc_control().allow("IO")));
+}
+
+#[test]
+fn api_gc() {
+    let mruby = Mruby::new();
+
+    mruby.gc_disable();
+
+    let used = mruby.memory_used();
+
+    mruby.run("10_000.times { 'garbage' * 10 }").unwrap();
+
+    let garbage = mruby.memory_used();
+
+    assert!(garbage > used);
+
+    mruby.gc_enable();
+    mruby.gc_run();
+
+    assert!(mruby.memory_used() < garbage);
+
+    let arena = mruby.gc_arena_save();
+    let kept = mruby.string("kept");
+
+    kept.gc_register();
+    mruby.gc_arena_restore(arena);
+    mruby.gc_run();
+
+    assert_eq!(kept.to_str().unwrap(), "kept");
+
+    kept.gc_unregister();
+}
+
+#[test]
+fn api_protected_call() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      class Picky
+        def pick(value)
+          raise ArgumentError, 'too picky' unless value == 1
+
+          value
+        end
+      end
+    ").unwrap();
+
+    let picky = mruby.run("Picky.new").unwrap();
+
+    assert_eq!(picky.protected_call("pick", vec![mruby.fixnum(1)]).unwrap(), mruby.fixnum(1));
+
+    let exc = picky.protected_call("pick", vec![mruby.fixnum(2)]).unwrap_err();
+
+    assert_eq!(exc.class().to_str(), "ArgumentError");
+    assert_eq!(exc.call("message", vec![]).unwrap().to_str().unwrap(), "too picky");
+    assert_eq!(picky.protected_call("pick", vec![mruby.fixnum(1)]).unwrap(), mruby.fixnum(1));
+}
+
+#[test]
+fn api_method_missing() {
+    use std::collections::HashMap;
+
+    struct Doc {
+        fields: HashMap<String, String>
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Doc>("Doc");
+    mruby.def_method_for::<Doc, _>("initialize", mrfn!(|_mruby, slf: Value| {
+        slf.init(Doc { fields: HashMap::new() })
+    }));
+    mruby.def_method_for::<Doc, _>("size", mrfn!(|mruby, slf: (&Doc)| {
+        mruby.fixnum(slf.fields.len() as i32)
+    }));
+    mruby.def_method_missing_for::<Doc, _, _>(|mruby, slf, name, args| {
+        let doc = slf.to_obj::<Doc>().unwrap();
+
+        if name.ends_with('=') && args.len() == 1 {
+            let value = args[0].to_str().unwrap().to_owned();
+
+            doc.borrow_mut().fields.insert(name.trim_end_matches('=').to_owned(), value);
+
+            return args[0].clone();
+        }
+
+        let value = doc.borrow().fields.get(name).cloned();
+
+        match value {
+            Some(ref value) if args.is_empty() => mruby.string(value),
+            _                                  => mruby.unhandled()
+        }
+    }, |_mruby, slf, name| {
+        let doc = slf.to_obj::<Doc>().unwrap();
+        let doc = doc.borrow();
+
+        name.ends_with('=') || doc.fields.contains_key(name)
+    });
+
+    let result = mruby.run("
+        doc = Doc.new
+        doc.title = 'Dune'
+
+        [doc.title, doc.size, doc.respond_to?(:title), doc.respond_to?('title'),
+         doc.respond_to?(:author=), doc.respond_to?(:author), doc.respond_to?(:size)]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_str().unwrap(), "Dune");
+    assert_eq!(result[1], mruby.fixnum(1));
+    assert_eq!(result[2..].to_vec(), vec![mruby.bool(true), mruby.bool(true), mruby.bool(true),
+                                          mruby.bool(false), mruby.bool(true)]);
+
+    match mruby.run("Doc.new.author") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("NoMethodError: undefined method 'author'"), "{}", err)
+        },
+        _ => assert!(false)
+    }
+
+    let result = mruby.run("
+        doc = Doc.new
+        doc.title = 'Dune'
+
+        begin
+          doc.title 1
+        rescue NoMethodError => e
+          e.name
+        end
+    ").unwrap();
+
+    assert_eq!(result, mruby.symbol("title"));
+}
+
+#[test]
+fn api_mark() {
+    struct Callbacks {
+        procs: Vec<Value>
+    }
+
+    impl MrubyMark for Callbacks {
+        fn mark(&self, marker: &Marker) {
+            for callback in &self.procs {
+                marker.mark(callback);
+            }
+        }
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Callbacks>("Callbacks");
+    mruby.def_mark_for::<Callbacks>();
+    mruby.def_method_for::<Callbacks, _>("register", mrfn!(|mruby, slf: (&mut Callbacks);
+                                                            &block| {
+        slf.procs.push(block);
+
+        mruby.nil()
+    }));
+    mruby.def_method_fn::<Callbacks>("register_fn", mrfn!(|mruby, slf: (&mut Callbacks);
+                                                          &block| {
+        slf.procs.push(block);
+
+        mruby.nil()
+    }));
+
+    let callbacks = mruby.obj(Callbacks { procs: vec![] });
+
+    mruby.const_set(mruby.get_class("Object").unwrap().to_value(), "CALLBACKS",
+                    callbacks.clone());
+
+    mruby.run("
+      100.times do |i|
+        name = \"callback #{i}\"
+
+        if i % 2 == 0
+          CALLBACKS.register { name }
+        else
+          CALLBACKS.register_fn { name }
+        end
+      end
+    ").unwrap();
+
+    callbacks.to_obj::<Callbacks>().unwrap().borrow_mut().procs
+             .push(mruby.run("proc { 'pushed from Rust' }").unwrap());
+
+    for _ in 0..10 {
+        mruby.run("1_000.times { 'garbage' * 10 }").unwrap();
+        mruby.gc_run();
+    }
+
+    let obj = callbacks.to_obj::<Callbacks>().unwrap();
+    let obj = obj.borrow();
+
+    assert_eq!(obj.procs.len(), 101);
+
+    for (i, callback) in obj.procs.iter().take(100).enumerate() {
+        assert_eq!(callback.call("call", vec![]).unwrap().to_str().unwrap(),
+                   format!("callback {}", i));
+    }
+
+    assert_eq!(obj.procs[100].call("call", vec![]).unwrap().to_str().unwrap(),
+               "pushed from Rust");
+}
+
+#[test]
+fn api_mark_sandboxed() {
+    struct Callbacks {
+        procs: Vec<Value>
+    }
+
+    impl MrubyMark for Callbacks {
+        fn mark(&self, marker: &Marker) {
+            for callback in &self.procs {
+                marker.mark(callback);
+            }
+        }
+    }
+
+    let mruby = Mruby::new_sandboxed(&SandboxConfig::new());
+
+    mruby.def_class_for::<Callbacks>("Callbacks");
+    mruby.def_mark_for::<Callbacks>();
+    mruby.def_method_for::<Callbacks, _>("register", mrfn!(|mruby, slf: (&mut Callbacks);
+                                                            &block| {
+        slf.procs.push(block);
+
+        mruby.nil()
+    }));
+
+    let callbacks = mruby.obj(Callbacks { procs: vec![] });
+
+    mruby.const_set(mruby.get_class("Object").unwrap().to_value(), "CALLBACKS",
+                    callbacks.clone());
+
+    mruby.run("10.times { |i| name = \"callback #{i}\"; CALLBACKS.register { name } }").unwrap();
+
+    for _ in 0..10 {
+        mruby.run("1_000.times { 'garbage' * 10 }").unwrap();
+        mruby.gc_run();
+    }
+
+    let obj = callbacks.to_obj::<Callbacks>().unwrap();
+    let obj = obj.borrow();
+
+    for (i, callback) in obj.procs.iter().enumerate() {
+        assert_eq!(callback.call("call", vec![]).unwrap().to_str().unwrap(),
+                   format!("callback {}", i));
+    }
+}
+
+#[test]
+fn api_mark_gc_locked() {
+    struct Empty;
+
+    impl MrubyMark for Empty {
+        fn mark(&self, _marker: &Marker) {}
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Empty>("Empty");
+    mruby.def_mark_for::<Empty>();
+
+    let result = mruby.run("
+      GC.generational_mode = true
+      GC.step_ratio = 200
+
+      [GC.generational_mode, GC.step_ratio > 200]
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_bool().unwrap(), false);
+    assert_eq!(result[1].to_bool().unwrap(), true);
+}
+
+#[test]
+fn api_finalizer() {
+    use std::cell::RefCell;
+
+    struct Handle {
+        id: i32
+    }
+
+    let finalized = Rc::new(RefCell::new(vec![]));
+    let ids = finalized.clone();
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Handle>("Handle");
+    mruby.def_finalizer_for::<Handle, _>(move |handle| ids.borrow_mut().push(handle.id));
+
+    let kept = mruby.obj(Handle { id: -1 });
+    let shared = Rc::new(RefCell::new(Handle { id: -2 }));
+
+    let arena = mruby.gc_arena_save();
+
+    for id in 0..100 {
+        mruby.obj(Handle { id: id });
+    }
+
+    mruby.obj_shared(shared.clone());
+
+    mruby.gc_arena_restore(arena);
+    mruby.gc_run();
+
+    let mut ids = finalized.borrow().clone();
+    ids.sort();
+
+    assert_eq!(ids, (-2..100).filter(|&id| id != -1).collect::<Vec<_>>());
+    assert_eq!(shared.borrow().id, -2);
+    assert_eq!(kept.to_obj::<Handle>().unwrap().borrow().id, -1);
+
+    drop(kept);
+    drop(mruby);
+
+    assert_eq!(finalized.borrow().len(), 102);
+    assert_eq!(*finalized.borrow().last().unwrap(), -1);
+}
+
+#[test]
+#[should_panic(expected = "cannot define a finalizer for api::Unregistered")]
+fn api_finalizer_unregistered() {
+    let mruby = Mruby::new();
+
+    mruby.def_finalizer_for::<Unregistered, _>(|_| {});
+}
+
+#[test]
+fn api_funcall_argv() {
+    let mruby = Mruby::new();
+
+    let push = mruby.intern("push");
+    let array = mruby.run("[]").unwrap();
+
+    for i in 0..100 {
+        mruby.funcall_argv(&array, push, &[mruby.fixnum(i)]).unwrap();
+    }
+
+    let args: Vec<Value> = (0..20).map(|i| mruby.fixnum(i)).collect();
+
+    mruby.funcall_argv(&array, push, &args).unwrap();
+
+    assert_eq!(array.call("size", vec![]).unwrap(), mruby.fixnum(120));
+    assert!(mruby.funcall_argv(&array, mruby.intern("missing"), &[]).is_err());
+}
+
+#[test]
+fn api_intern_borrowed() {
+    let mruby = Mruby::new();
+
+    let plus = mruby.intern("+");
+    let _borrow = mruby.borrow();
+
+    assert_eq!(mruby.intern("+"), plus);
+    assert_eq!(mruby.intern("not_cached"), mruby.intern("not_cached"));
+}
+
+#[test]
+fn api_lookup_symbol() {
+    let mruby = Mruby::new();
+
+    for name in &["serialize", "read write", "+", "héllo", ""] {
+        let id = mruby.intern(name);
+
+        assert_eq!(mruby.lookup_symbol(id), *name);
+    }
+
+    let id = mruby.intern("ok");
+    let _borrow = mruby.borrow();
+
+    assert_eq!(mruby.lookup_symbol(id), "ok");
+}
+
+#[test]
+fn api_toplevel_method() {
+    let mruby = Mruby::new();
+
+    mruby.def_toplevel_method("double", mrfn!(|mruby, _slf: Value, v: i32| {
+        mruby.fixnum(v * 2)
+    }));
+    mruby.def_toplevel_method("triple", mrfn!(|mruby, _slf: Value, v: i32| {
+        mruby.fixnum(v * 3)
+    }));
+
+    let result = mruby.run("
+      class Cont
+        def value
+          double 2
+        end
+      end
+
+      [double(3), Cont.new.value, triple(3)]
+    ").unwrap();
+
+    assert_eq!(result.to_vec().unwrap().iter().map(|v| v.to_i32().unwrap())
+                     .collect::<Vec<_>>(), vec![6, 4, 9]);
+}
+
+#[test]
+fn api_method_arity() {
+    let mruby = Mruby::new();
+
+    mruby.def_class("Container");
+    mruby.def_method(mruby.get_class("Container").unwrap(), "rust",
+                     mrfn!(|mruby, _slf: Value, _a: i32| {
+        mruby.nil()
+    }));
+
+    let cont = mruby.run("
+      class Container
+        def none; end
+        def two(a, b); end
+        def opt(a, b = 1); end
+        def rest(a, *b); end
+        def post(*a, b); end
+      end
+
+      Container.new
+    ").unwrap();
+
+    let arities = ["none", "two", "opt", "rest", "post", "rust"].iter().map(|name| {
+        cont.method_arity(name).unwrap()
+    }).collect::<Vec<_>>();
+
+    assert_eq!(arities, vec![0, 2, -2, -2, -2, -1]);
+    assert!(cont.method_arity("missing").is_err());
+}
+
+#[test]
+fn api_shallow_clone() {
+    struct Cont {
+        value: i32
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Cont>("Container");
+
+    let original = mruby.run("[1, [2]]").unwrap();
+    let copy = original.shallow_clone().unwrap();
+
+    copy.call("push", vec![mruby.fixnum(3)]).unwrap();
+    copy.array_get(1).unwrap().call("push", vec![mruby.fixnum(4)]).unwrap();
+
+    assert_eq!(original.inspect(), "[1, [2, 4]]");
+    assert_eq!(copy.inspect(), "[1, [2, 4], 3]");
+
+    let original = mruby.obj(Cont { value: 1 });
+    let copy = original.shallow_clone().unwrap();
+
+    copy.to_obj::<Cont>().unwrap().borrow_mut().value = 2;
+
+    assert_eq!(original.to_obj::<Cont>().unwrap().borrow().value, 2);
+    assert_eq!(original.object_id(), copy.object_id());
+
+    for value in &[mruby.fixnum(1), mruby.float(1.5), mruby.symbol("sym"), mruby.bool(true),
+                   mruby.nil()] {
+        assert!(value.shallow_clone().unwrap() == *value);
+    }
+}
+
+#[test]
+fn api_current_backtrace() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mruby = Mruby::new();
+    let frames = Rc::new(RefCell::new(vec![]));
+
+    let captured = frames.clone();
+
+    let class = mruby.def_class("Container");
+    mruby.def_method(class, "trace", move |mruby, slf| {
+        *captured.borrow_mut() = mruby.current_backtrace();
+
+        slf
+    });
+
+    mruby.run_with_filename("
+      class Deep
+        def inner
+          Container.new.trace
+        end
+      end
+
+      def outer
+        Deep.new.inner
+      end
+
+      outer
+    ", "deep.rb").unwrap();
+
+    let frames = frames.borrow();
+
+    assert_eq!(frames.iter().map(|frame| frame.line).collect::<Vec<_>>(), vec![4, 9, 12]);
+    assert_eq!(frames[0].file, "deep.rb");
+    assert_eq!(frames[0].method, Some("Deep.inner".to_owned()));
+    assert_eq!(frames[1].method, Some("Object.outer".to_owned()));
+    assert!(mruby.current_backtrace().is_empty());
+}
+
+#[test]
+fn api_map() {
+    let mruby = Mruby::new();
+
+    let result = mruby.run("{ width: 640, 'title' => 'mrusty', nested: { on: true } }").unwrap();
+    let map = result.to_map().unwrap();
+
+    assert_eq!(map.len(), 3);
+    assert_eq!(map["width"], mruby.fixnum(640));
+    assert_eq!(map["title"], mruby.string("mrusty"));
+    assert_eq!(map["nested"].to_map().unwrap()["on"], mruby.bool(true));
+
+    let hash = mruby.map(&map);
+
+    assert_eq!(hash.call("[]", vec![mruby.string("width")]).unwrap(), mruby.fixnum(640));
+    assert_eq!(hash.call("key?", vec![mruby.symbol("width")]).unwrap(), mruby.bool(false));
+
+    let ordered = result.to_btree_map().unwrap();
+
+    assert_eq!(ordered.keys().collect::<Vec<_>>(), vec!["nested", "title", "width"]);
+
+    let hash = mruby.map(&ordered);
+
+    assert_eq!(hash.call("keys", vec![]).unwrap().to_rust::<Vec<String>>().unwrap(),
+               vec!["nested", "title", "width"]);
+
+    let expect = |code: &str, message: &str| {
+        match mruby.run(code).unwrap().to_map() {
+            Err(error) => assert_eq!(error.to_string(), message),
+            result     => panic!("unexpected {:?}", result)
+        }
+    };
+
+    expect("{ 1 => 2 }", "Cast error: expected String or Symbol key instead of 1");
+    expect("{ [:a] => 2 }", "Cast error: expected String or Symbol key instead of [:a]");
+    expect("[]", "Cast error: expected Hash");
+}
+
+#[test]
+fn api_range() {
+    let mruby = Mruby::new();
+
+    let range = mruby.range(mruby.fixnum(1), mruby.fixnum(3), false);
+
+    assert_eq!(range.call("to_a", vec![]).unwrap().to_rust::<Vec<i32>>().unwrap(), vec![1, 2, 3]);
+
+    let (start, end, exclusive) = mruby.run("1.5...2").unwrap().to_range().unwrap();
+
+    assert_eq!(start.to_f64().unwrap(), 1.5);
+    assert_eq!(end.to_i32().unwrap(), 2);
+    assert_eq!(exclusive, true);
+
+    assert_eq!(mruby.run("3...10").unwrap().to_i32_range().unwrap(), 3..10);
+    assert_eq!(mruby.run("1..5").unwrap().to_rust::<RangeInclusive<i32>>().unwrap(), 1..=5);
+
+    assert_eq!(mruby.run("1..5").unwrap().to_i32_range().err().unwrap().to_string(),
+               "Cast error: expected exclusive Range (a...b)");
+    assert_eq!(mruby.run("1...5").unwrap().to_rust::<RangeInclusive<i32>>().err().unwrap()
+                   .to_string(),
+               "Cast error: expected inclusive Range (a..b)");
+    assert_eq!(mruby.run("[1, 5]").unwrap().to_range().err().unwrap().to_string(),
+               "Cast error: expected Range");
+
+    let top = mruby.run("def sum(range); range.reduce(:+); end; self").unwrap();
+
+    assert_eq!(top.call("sum", vec![mruby.value(0..4)]).unwrap().to_i32().unwrap(), 6);
+    assert_eq!(top.call("sum", vec![mruby.value(0..=4)]).unwrap().to_i32().unwrap(), 10);
+
+    // Endless ranges are newer than the bundled mruby.
+    match mruby.run("1..") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("SyntaxError"));
+        },
+        result => panic!("unexpected {:?}", result)
+    }
+}
+
+#[test]
+fn api_array_building() {
+    let mruby = Mruby::new();
+
+    let array = mruby.array_new();
+
+    for i in 0..10_000 {
+        mruby.array_push(&array, mruby.fixnum(i));
+    }
+
+    mruby.array_concat(&array, &mruby.run("['a', :b]").unwrap());
+    mruby.array_concat(&array, &mruby.array_new());
+
+    let top = mruby.run("def describe(a); [a.size, a[9_999], a.last]; end; self").unwrap();
+    let description = top.call("describe", vec![array.clone()]).unwrap().to_vec().unwrap();
+
+    assert_eq!(description, vec![mruby.fixnum(10_002), mruby.fixnum(9_999), mruby.symbol("b")]);
+
+    let nested = mruby.array_new();
+
+    mruby.array_push(&nested, array);
+    mruby.array_concat(&nested, &nested.clone());
+
+    assert_eq!(nested.to_vec().unwrap().len(), 2);
+}
+
+#[test]
+#[should_panic(expected = "array_push expects an Array")]
+fn api_array_push_non_array() {
+    let mruby = Mruby::new();
+
+    mruby.array_push(&mruby.string("a"), mruby.nil());
+}
+
+#[test]
+fn api_wide_integers() {
+    let mruby = Mruby::new();
+
+    let max = MrInt::max_value() as i64;
+    let min = MrInt::min_value() as i64;
+
+    assert_eq!(mruby.fixnum_i64(max), mruby.fixnum(MrInt::max_value()));
+    assert_eq!(mruby.fixnum_i64(min), mruby.fixnum(MrInt::min_value()));
+    assert_eq!(mruby.fixnum_i64(max + 1), mruby.float((max + 1) as f64));
+    assert_eq!(mruby.fixnum_i64(min - 1), mruby.float((min - 1) as f64));
+    assert_eq!(mruby.fixnum_u64(max as u64 + 1), mruby.float((max + 1) as f64));
+
+    for &value in &[max - 1, max, max + 1, min - 1, min, min + 1] {
+        assert_eq!(mruby.fixnum_i64(value).to_i64().unwrap(), value);
+    }
+
+    // mruby turns overflowing Fixnum arithmetic into Floats.
+    assert_eq!(mruby.run("2147483647 + 1").unwrap().to_i64().unwrap(), max + 1);
+    assert_eq!(mruby.run("2147483647 + 1").unwrap().to_u32().unwrap(), 1 << 31);
+    assert_eq!(mruby.run("-2147483648 - 1").unwrap().to_i64().unwrap(), min - 1);
+
+    assert_eq!(mruby.fixnum_u64(u32::max_value() as u64).to_u32().unwrap(), u32::max_value());
+    assert_eq!(mruby.fixnum_u64(1 << 60).to_u64().unwrap(), 1 << 60);
+    assert_eq!(mruby.value(i64::min_value()).to_i64().unwrap(), i64::min_value());
+
+    let out_of_range = |result: Result<u64, MrubyError>, expected: &str| {
+        match result {
+            Err(MrubyError::OutOfRange(typ)) => assert_eq!(typ, expected),
+            result                           => panic!("unexpected {:?}", result)
+        }
+    };
+
+    out_of_range(mruby.fixnum_u64(u32::max_value() as u64 + 1).to_u32().map(|v| v as u64),
+                 "u32");
+    out_of_range(mruby.fixnum(-1).to_u64(), "u64");
+    out_of_range(mruby.float(-1.0).to_u64(), "u64");
+    out_of_range(mruby.float(18446744073709551616.0).to_u64(), "u64");
+    out_of_range(mruby.float(9223372036854775808.0).to_i64().map(|v| v as u64), "i64");
+    out_of_range(mruby.run("1.0 / 0").unwrap().to_i64().map(|v| v as u64), "i64");
+
+    match mruby.float(2.5).to_i64() {
+        Err(MrubyError::Cast(expected)) => assert_eq!(expected, "integral Float"),
+        result                          => panic!("unexpected {:?}", result)
+    }
+
+    assert_eq!(mruby.float(2.5).to_i64_lossy().unwrap(), 2);
+    assert_eq!(mruby.float(-1e30).to_i64_lossy().unwrap(), i64::min_value());
+    assert_eq!(mruby.fixnum(-7).to_i64_lossy().unwrap(), -7);
+    assert!(mruby.string("7").to_i64_lossy().is_err());
+    assert!(mruby.float(2.0).to_i32().is_err());
+}
+
+#[test]
+fn api_array_access() {
+    let mruby = Mruby::new();
+
+    let array = mruby.run("[:a, 'b', 3]").unwrap();
+
+    assert_eq!(array.array_len().unwrap(), 3);
+    assert_eq!(array.array_get(1).unwrap(), mruby.string("b"));
+    assert_eq!(array.array_get(-3).unwrap(), mruby.symbol("a"));
+    assert_eq!(array.array_get(-4).unwrap(), mruby.nil());
+    assert_eq!(array.array_get(1 << 40).unwrap(), mruby.nil());
+
+    array.array_set(0, mruby.fixnum(0)).unwrap();
+    array.array_set(-2, mruby.fixnum(1)).unwrap();
+    array.array_set(5, mruby.fixnum(5)).unwrap();
+
+    assert_eq!(array.to_rust::<Vec<Option<i32>>>().unwrap(),
+               vec![Some(0), Some(1), Some(3), None, None, Some(5)]);
+
+    match array.array_set(-7, mruby.nil()) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("IndexError"));
+        },
+        result => panic!("unexpected {:?}", result)
+    }
+
+    match array.array_set(1 << 40, mruby.nil()) {
+        Err(MrubyError::OutOfRange(typ)) => assert_eq!(typ, "MrInt"),
+        result                           => panic!("unexpected {:?}", result)
+    }
+
+    let string = mruby.string("abc");
+
+    assert!(string.array_len().is_err());
+    assert!(string.array_get(0).is_err());
+    assert!(string.array_set(0, mruby.nil()).is_err());
+}
+
+#[test]
+fn api_string_building() {
+    let mruby = Mruby::new();
+
+    let s = mruby.string("");
+
+    for i in 0..1_000 {
+        mruby.string_concat(&s, &mruby.string("ab")).unwrap();
+        mruby.string_append_bytes(&s, &[b'0' + (i % 10) as u8]).unwrap();
+    }
+
+    assert_eq!(s.string_len().unwrap(), 3_000);
+    assert_eq!(&s.to_str().unwrap()[..9], "ab0ab1ab2");
+
+    let s = mruby.string("n=");
+
+    mruby.string_concat(&s, &mruby.fixnum(2)).unwrap();
+    mruby.string_concat(&s, &mruby.symbol("x")).unwrap();
+    mruby.string_append_bytes(&s, &[]).unwrap();
+
+    assert_eq!(s.to_str().unwrap(), "n=2x");
+
+    let frozen = mruby.string_static("frozen");
+
+    match mruby.string_append_bytes(&frozen, b"!") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("RuntimeError"));
+        },
+        result => panic!("unexpected {:?}", result)
+    }
+
+    assert!(mruby.string_concat(&frozen, &mruby.string("!")).is_err());
+    assert_eq!(frozen.to_str().unwrap(), "frozen");
+
+    assert_eq!(mruby.string_concat(&mruby.fixnum(1), &s).err().unwrap().to_string(),
+               "Cast error: expected String");
+    assert!(mruby.string_append_bytes(&mruby.symbol("a"), b"b").is_err());
+    assert!(mruby.symbol("a").string_len().is_err());
+}
+
+#[test]
+fn api_path() {
+    let mruby = Mruby::new();
+
+    let path = Path::new("assets/naïve dir/main.rb");
+    let value = mruby.string_from_path(path).unwrap();
+
+    assert_eq!(value.to_str().unwrap(), "assets/naïve dir/main.rb");
+    assert_eq!(value.to_path_buf().unwrap(), path);
+    assert_eq!(mruby.value(path.to_path_buf()).to_rust::<PathBuf>().unwrap(), path);
+
+    let joined = mruby.run("'assets' + '/' + 'main.rb'").unwrap();
+
+    assert_eq!(joined.to_rust::<PathBuf>().unwrap(), Path::new("assets/main.rb"));
+
+    assert_eq!(mruby.symbol("main.rb").to_path_buf().err().unwrap().to_string(),
+               "Cast error: expected String");
+    assert_eq!(mruby.bytes(b"a\0b").to_path_buf().err().unwrap().to_string(),
+               "Cast error: expected String without NUL bytes");
+}
+
+#[cfg(unix)]
+#[test]
+fn api_path_non_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mruby = Mruby::new();
+
+    let path = Path::new(OsStr::from_bytes(b"caf\xe9/\xff.rb"));
+    let value = mruby.string_from_path(path).unwrap();
+
+    assert_eq!(value.to_bytes().unwrap(), b"caf\xe9/\xff.rb");
+    assert_eq!(value.call("bytesize", vec![]).unwrap().to_i32().unwrap(), 9);
+    assert_eq!(value.to_path_buf().unwrap(), path);
+
+    let top = mruby.run("def dir(path); path[0, path.index('/')]; end; self").unwrap();
+    let dir = top.call("dir", vec![mruby.value(path)]).unwrap();
+
+    assert_eq!(dir.to_rust::<PathBuf>().unwrap(), Path::new(OsStr::from_bytes(b"caf\xe9")));
+}
+
+#[test]
+fn api_time() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let mruby = Mruby::new();
+
+    let times = vec![
+        UNIX_EPOCH,
+        UNIX_EPOCH + Duration::new(1_466_000_000, 123_456_000),
+        UNIX_EPOCH + Duration::new(4_102_444_800, 999_999_000),
+        UNIX_EPOCH - Duration::new(0, 1_000),
+        UNIX_EPOCH - Duration::new(86_400, 654_321_000)
+    ];
+
+    for time in times {
+        let value = mruby.time(time).unwrap();
+
+        assert_eq!(value.to_system_time().unwrap(), time);
+        assert_eq!(value.to_rust::<SystemTime>().unwrap(), time);
+    }
+
+    let time = mruby.time(UNIX_EPOCH - Duration::new(1, 250_000_000)).unwrap();
+
+    assert_eq!(time.call("to_i", vec![]).unwrap().to_i32().unwrap(), -2);
+    assert_eq!(time.call("usec", vec![]).unwrap().to_i32().unwrap(), 750_000);
+
+    let truncated = mruby.time(UNIX_EPOCH + Duration::new(3, 1_999)).unwrap();
+
+    assert_eq!(truncated.to_system_time().unwrap(), UNIX_EPOCH + Duration::new(3, 1_000));
+
+    let now = SystemTime::now();
+    let value = mruby.run("Time.now").unwrap().to_system_time().unwrap();
+
+    assert!(value.duration_since(now).unwrap_or_default() < Duration::from_secs(60));
+
+    assert_eq!(mruby.fixnum(1).to_system_time().err().unwrap().to_string(),
+               "Cast error: expected Time");
+
+    mruby.run("Object.send(:remove_const, :Time)").unwrap();
+
+    assert!(mruby.time(UNIX_EPOCH).err().unwrap().to_string().contains("mruby-time gem"));
+}
+
+#[test]
+fn api_duration() {
+    use std::time::Duration;
+
+    let mruby = Mruby::new();
+
+    let durations = vec![
+        Duration::new(0, 0),
+        Duration::new(0, 1_000),
+        Duration::new(1, 999_999_000),
+        Duration::new(86_400 * 365 * 100, 123_456_000)
+    ];
+
+    for duration in durations {
+        assert_eq!(mruby.duration(duration).to_duration().unwrap(), duration);
+        assert_eq!(mruby.value(duration).to_rust::<Duration>().unwrap(), duration);
+    }
+
+    assert_eq!(mruby.duration(Duration::from_millis(1500)), mruby.float(1.5));
+    assert_eq!(mruby.fixnum(3).to_duration().unwrap(), Duration::from_secs(3));
+    assert_eq!(mruby.run("0.1 + 0.2").unwrap().to_duration().unwrap(),
+               Duration::from_micros(300_000));
+
+    assert_eq!(mruby.float(-0.5).to_duration().err().unwrap().to_string(),
+               MrubyError::OutOfRange("Duration".to_owned()).to_string());
+    assert_eq!(mruby.string("1").to_duration().err().unwrap().to_string(),
+               "Cast error: expected Float or Fixnum");
+}
+
+#[test]
+fn api_enumerator() {
+    let mruby = Mruby::new();
+
+    let top = mruby.run("
+      def first(enum, n)
+        enum.take(n)
+      end
+
+      def pull(enum)
+        [enum.next, enum.each_with_index.to_a, enum.each.to_a]
+      end
+
+      def drain(enum)
+        enum.next
+      rescue StopIteration => e
+        e.class
+      end
+
+      def store(enum)
+        $stored = enum
+      end
+
+      def stored_next
+        $stored.next
+      end
+
+      self
+    ").unwrap();
+
+    let infinite = mruby.enumerator(1..);
+
+    assert_eq!(top.call("first", vec![infinite.clone(), mruby.fixnum(3)]).unwrap()
+                   .to_rust::<Vec<i32>>().unwrap(),
+               vec![1, 2, 3]);
+    assert_eq!(top.call("first", vec![infinite, mruby.fixnum(2)]).unwrap()
+                   .to_rust::<Vec<i32>>().unwrap(),
+               vec![4, 5]);
+
+    let letters = mruby.enumerator("abc".chars().map(|c| c.to_string()));
+
+    let pulled = top.call("pull", vec![letters.clone()]).unwrap().to_vec().unwrap();
+
+    assert_eq!(pulled[0].to_str().unwrap(), "a");
+    assert_eq!(pulled[1].call("inspect", vec![]).unwrap().to_str().unwrap(),
+               r#"[["b", 0], ["c", 1]]"#);
+    assert!(pulled[2].to_vec().unwrap().is_empty());
+
+    assert_eq!(top.call("drain", vec![letters]).unwrap().to_class().unwrap().to_str(),
+               "StopIteration");
+
+    let stored = top.clone();
+    let reentrant = mruby.enumerator((0..).map(move |_| {
+        stored.call("stored_next", vec![]).err().unwrap().to_string()
+    }));
+
+    top.call("store", vec![reentrant]).unwrap();
+
+    let error = top.call("stored_next", vec![]).unwrap();
+
+    assert!(error.to_str().unwrap().contains("RustEnumerator#next called while producing"));
+    assert!(mruby.run("RustEnumerator.new").is_err());
+}
+
+#[test]
+fn api_hash_access() {
+    let mruby = Mruby::new();
+
+    let hash = mruby.run("h = Hash.new(0); h['a'] = 1; h").unwrap();
+
+    assert_eq!(mruby.hash_len(&hash), 1);
+    assert_eq!(mruby.hash_get(&hash, &mruby.string("a")).to_i32().unwrap(), 1);
+    assert_eq!(mruby.hash_get(&hash, &mruby.string("b")).call("nil?", vec![]).unwrap()
+                                                         .to_bool().unwrap(), true);
+
+    mruby.hash_set(&hash, mruby.symbol("b"), mruby.string("two"));
+    mruby.hash_set(&hash, mruby.fixnum(3), mruby.nil());
+
+    assert_eq!(mruby.hash_len(&hash), 3);
+    assert_eq!(mruby.hash_get(&hash, &mruby.symbol("b")).to_str().unwrap(), "two");
+
+    assert_eq!(mruby.hash_delete(&hash, &mruby.string("a")).to_i32().unwrap(), 1);
+    assert_eq!(mruby.hash_delete(&hash, &mruby.string("a")).call("nil?", vec![]).unwrap()
+                                                           .to_bool().unwrap(), true);
+
+    assert_eq!(mruby.hash_len(&hash), 2);
+    assert_eq!(mruby.hash_len(&mruby.run("{}").unwrap()), 0);
+    assert_eq!(hash.call("keys", vec![]).unwrap().call("size", vec![]).unwrap()
+                   .to_i32().unwrap(), 2);
+}
+
+#[test]
+fn api_hash_keys_values() {
+    let mruby = Mruby::new();
+
+    let hash = mruby.run("{ 'c' => 3 }").unwrap();
+
+    mruby.hash_set(&hash, mruby.string("a"), mruby.fixnum(1));
+    mruby.hash_set(&hash, mruby.symbol("b"), mruby.fixnum(2));
+    mruby.hash_set(&hash, mruby.string("c"), mruby.fixnum(4));
+    mruby.hash_delete(&hash, &mruby.string("a"));
+    mruby.hash_set(&hash, mruby.string("a"), mruby.fixnum(5));
+
+    assert_eq!(mruby.hash_keys(&hash).unwrap(),
+               vec![mruby.string("c"), mruby.symbol("b"), mruby.string("a")]);
+    assert_eq!(mruby.hash_values(&hash).unwrap(),
+               vec![mruby.fixnum(4), mruby.fixnum(2), mruby.fixnum(5)]);
+
+    assert_eq!(mruby.hash_keys(&mruby.run("{}").unwrap()).unwrap(), vec![]);
+    assert_eq!(mruby.hash_values(&mruby.run("{}").unwrap()).unwrap(), vec![]);
+
+    match mruby.hash_values(&mruby.fixnum(1)) {
+        Err(MrubyError::Cast(expected)) => assert_eq!(expected, "Hash"),
+        result                          => panic!("unexpected {:?}", result)
+    }
+}
+
+#[test]
+#[should_panic(expected = "hash_len expects a Hash")]
+fn api_hash_len_non_hash() {
+    let mruby = Mruby::new();
+
+    mruby.hash_len(&mruby.array(vec![]));
+}
+
+#[test]
+fn api_comparable_and_equatable() {
+    let mruby = Mruby::new();
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Rank {
+        value: i32
+    }
+
+    mrusty_class!(Rank, "Rank", {
+        def!("initialize", |v: i32| {
+            Rank { value: v }
+        });
+
+        def!("value", |mruby, slf: (&Rank)| {
+            mruby.fixnum(slf.value)
+        });
+
+        comparable!();
+        equatable!();
+    });
+
+    Rank::require(mruby.clone());
+
+    let result = mruby.run("[3, 1, 2].map { |v| Rank.new(v) }.sort.map(&:value)").unwrap();
+
+    assert_eq!(result.to_vec().unwrap().iter().map(|v| v.to_i32().unwrap()).collect::<Vec<_>>(),
+               vec![1, 2, 3]);
+
+    let result = mruby.run("
+      Rank.new(1) < Rank.new(2) && Rank.new(2).between?(Rank.new(1), Rank.new(3))
+    ").unwrap();
+
+    assert_eq!(result.to_bool().unwrap(), true);
+    assert_eq!(mruby.run("Rank.new(1) <=> 1").unwrap().call("nil?", vec![]).unwrap()
+                    .to_bool().unwrap(), true);
+    assert_eq!(mruby.run("Rank.new(1) == Rank.new(1)").unwrap().to_bool().unwrap(), true);
+    assert_eq!(mruby.run("Rank.new(1) == 1").unwrap().to_bool().unwrap(), false);
+    assert_eq!(mruby.run("Rank.new(1).eql?(Rank.new(2))").unwrap().to_bool().unwrap(), false);
+    assert_eq!(mruby.run("{ Rank.new(1) => :one }[Rank.new(1)]").unwrap().to_str().unwrap(),
+               "one");
+}
+
+#[test]
+fn api_display() {
+    use std::fmt;
+
+    let mruby = Mruby::new();
+
+    #[derive(Debug)]
+    struct Label {
+        text: String
+    }
+
+    impl fmt::Display for Label {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "label {}", self.text)
+        }
+    }
+
+    mrusty_class!(Label, "Label", {
+        def!("initialize", |text: (&str)| {
+            Label { text: text.to_owned() }
+        });
+
+        display!(to_s);
+    });
+
+    Label::require(mruby.clone());
+
+    mruby.def_method_for::<Label, _>("inspect_while_mutating", |_mruby, slf| {
+        let label = slf.to_obj::<Label>().unwrap();
+        let _label = label.borrow_mut();
+
+        slf.call("inspect", vec![]).unwrap()
+    });
+
+    let result = mruby.run("
+      $printed = ''
+
+      def __printstr__(string)
+        $printed << string
+      end
+
+      label = Label.new('a')
+      p label
+
+      [$printed, [label].inspect, \"#{label}\", label.inspect_while_mutating]
+    ").unwrap().to_vec().unwrap();
+
+    assert!(result[0].to_str().unwrap().contains("Label { text: \"a\" }"));
+    assert_eq!(result[1].to_str().unwrap(), "[Label { text: \"a\" }]");
+    assert_eq!(result[2].to_str().unwrap(), "label a");
+    assert_eq!(result[3].to_str().unwrap(), "#<Label>");
+}
+
+struct Wrapper<T> {
+    value: T
+}
+
+mrusty_class!(Wrapper<i32>, "IntWrapper", {
+    def!("initialize", |v: i32| {
+        Wrapper { value: v }
+    });
+
+    def!("value", |mruby, slf: (&Wrapper<i32>)| {
+        mruby.fixnum(slf.value)
+    });
+});
+
+mrusty_class!(Wrapper<String>, "StringWrapper", {
+    def!("initialize", |v: (&str)| {
+        Wrapper { value: v.to_owned() }
+    });
+
+    def!("value", |mruby, slf: (&Wrapper<String>)| {
+        mruby.string(&slf.value)
+    });
+});
+
+#[test]
+fn api_generic_class() {
+    let mruby = Mruby::new();
+
+    Wrapper::<i32>::require(mruby.clone());
+    Wrapper::<String>::require(mruby.clone());
+
+    let result = mruby.run("[IntWrapper.new(1).value, StringWrapper.new('a').value]").unwrap();
+
+    assert_eq!(result.to_vec().unwrap()[0].to_i32().unwrap(), 1);
+    assert_eq!(result.to_vec().unwrap()[1].to_str().unwrap(), "a");
+
+    let int = mruby.run("IntWrapper.new(2)").unwrap();
+
+    assert_eq!(int.to_obj::<Wrapper<i32>>().unwrap().borrow().value, 2);
+    assert!(int.to_obj::<Wrapper<String>>().is_err());
+}
+
+struct Boxed<T> {
+    value: T
+}
+
+trait Suffix {
+    fn suffix() -> &'static str;
+}
+
+impl Suffix for u8 {
+    fn suffix() -> &'static str { "Byte" }
+}
+
+impl Suffix for String {
+    fn suffix() -> &'static str { "String" }
+}
+
+mrusty_class!(<T: Suffix + FromValue + ToValue + Clone + 'static> Boxed<T>,
+              format!("Boxed{}", T::suffix()), {
+    def!("initialize", |v: T| {
+        Boxed { value: v }
+    });
+
+    def!("value", |mruby, slf: (&Boxed<T>)| {
+        mruby.value(slf.value.clone())
+    });
+});
+
+#[test]
+fn api_generic_class_impl() {
+    let mruby = Mruby::new();
+
+    Boxed::<u8>::require(mruby.clone());
+    Boxed::<String>::require(mruby.clone());
+
+    let result = mruby.run("[BoxedByte.new(7).value, BoxedString.new('b').value]").unwrap();
+
+    assert_eq!(result.to_vec().unwrap()[0].to_i32().unwrap(), 7);
+    assert_eq!(result.to_vec().unwrap()[1].to_str().unwrap(), "b");
+
+    match mruby.run("BoxedByte.new(300)") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert!(err.starts_with("ArgumentError"));
+        },
+        result => panic!("expected an ArgumentError, got {:?}", result)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Difficulty {
+    Easy,
+    Normal,
+    VeryHard
+}
+
+mruby_enum!(Difficulty, { Easy, Normal, VeryHard });
+
+#[test]
+fn api_enum() {
+    let mruby = Mruby::new();
+
+    Difficulty::require(mruby.clone());
+
+    struct Game;
+
+    mruby.def_class_for::<Game>("Game");
+    mruby.def_class_method_for::<Game, _>("name", mrfn!(|mruby, _slf: Value, d: Difficulty| {
+        mruby.string(&format!("{:?}", d))
+    }));
+
+    let variants = [Difficulty::Easy, Difficulty::Normal, Difficulty::VeryHard];
+    let names = [("EASY", "easy"), ("NORMAL", "normal"), ("VERY_HARD", "very_hard")];
+
+    for (&variant, &(constant, symbol)) in variants.iter().zip(names.iter()) {
+        let value = variant.to_value(&mruby);
+
+        assert_eq!(value, mruby.run(&format!("Difficulty::{}", constant)).unwrap());
+        assert_eq!(value, mruby.run(&format!("Difficulty.from_sym(:{})", symbol)).unwrap());
+        assert_eq!(value.to_rust::<Difficulty>().unwrap(), variant);
+
+        let from_constant = mruby.run(&format!("Game.name Difficulty::{}", constant)).unwrap();
+        let from_symbol = mruby.run(&format!("Game.name :{}", symbol)).unwrap();
+
+        assert_eq!(from_constant.to_str().unwrap(), format!("{:?}", variant));
+        assert_eq!(from_symbol.to_str().unwrap(), format!("{:?}", variant));
+    }
+
+    match mruby.run("Game.name :impossible") {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "ArgumentError: expected Difficulty (:easy, :normal, :very_hard) \
+                             instead of :impossible");
+        },
+        result => panic!("unexpected {:?}", result)
+    }
+
+    assert!(mruby.run("Game.name 3").is_err());
+    assert!(mruby.run("Difficulty.from_sym(:impossible)").is_err());
+}
+
+#[test]
+fn api_operators() {
+    let mruby = Mruby::new();
+
+    #[derive(Debug, PartialEq)]
+    struct Num(i32);
+
+    mruby.def_class_for::<Num>("Num");
+    mruby.def_method_for::<Num, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+        slf.init(Num(v))
+    }));
+    mruby.def_operators_for::<Num>(vec![
+        ("+", Box::new(|mruby, num, other| {
+            let other = other.to_obj::<Num>().unwrap();
+            let sum = num.0 + other.borrow().0;
+
+            mruby.obj(Num(sum))
+        })),
+        ("*", Box::new(|mruby, num, k| mruby.obj(Num(num.0 * k.to_i32().unwrap())))),
+        ("-@", Box::new(|mruby, num, _| mruby.obj(Num(-num.0)))),
+        ("==", Box::new(|mruby, num, other| {
+            let eq = other.to_obj::<Num>().map(|other| *num == *other.borrow()).unwrap_or(false);
+
+            mruby.bool(eq)
+        }))
+    ]);
+    mruby.def_operator_for::<Num, _>("[]", |mruby, num, i| {
+        mruby.fixnum((num.0 >> i.to_i32().unwrap()) & 1)
+    });
+
+    let result = mruby.run("n = Num.new(2); -(n + n * 3) == Num.new(-8)").unwrap();
+
+    assert_eq!(result.to_bool().unwrap(), true);
+    assert_eq!(mruby.run("Num.new(5)[2]").unwrap().to_i32().unwrap(), 1);
+    assert_eq!(mruby.run("Num.new(1) != Num.new(2)").unwrap().to_bool().unwrap(), true);
+    assert!(mruby.run("Num.new(1) + 1").is_err());
+}
+
+#[test]
+fn api_obj_shared() {
+    use std::cell::RefCell;
+
+    let mruby = Mruby::new();
+
+    struct Entity {
+        hp: i32
+    }
+
+    mruby.def_class_for::<Entity>("Entity");
+    mruby.def_method_for::<Entity, _>("hit", mrfn!(|mruby, slf: (&mut Entity)| {
+        slf.hp -= 1;
+
+        mruby.fixnum(slf.hp)
+    }));
+
+    let entity = Rc::new(RefCell::new(Entity { hp: 10 }));
+
+    let first = mruby.obj_shared(entity.clone());
+    let second = mruby.obj_shared(entity.clone());
+    let third = mruby.value(entity.clone());
+
+    assert!(first.call("equal?", vec![second.clone()]).unwrap().to_bool().unwrap());
+    assert!(first.call("equal?", vec![third]).unwrap().to_bool().unwrap());
+    assert!(!first.call("equal?", vec![mruby.obj_shared(Rc::new(RefCell::new(Entity { hp: 10 })))])
+                  .unwrap().to_bool().unwrap());
+
+    assert_eq!(second.call("hit", vec![]).unwrap().to_i32().unwrap(), 9);
+    assert_eq!(entity.borrow().hp, 9);
+
+    entity.borrow_mut().hp = 5;
+
+    assert_eq!(first.call("hit", vec![]).unwrap().to_i32().unwrap(), 4);
+    assert_eq!(Rc::strong_count(&entity), 2);
+
+    let other = Rc::new(RefCell::new(Entity { hp: 3 }));
+    let arena = mruby.gc_arena_save();
+
+    mruby.obj_shared(other.clone());
+
+    assert_eq!(Rc::strong_count(&other), 2);
+
+    mruby.gc_arena_restore(arena);
+    mruby.gc_run();
+
+    assert_eq!(Rc::strong_count(&other), 1);
+
+    let shared = mruby.obj_shared(other.clone());
+
+    assert_eq!(shared.call("hit", vec![]).unwrap().to_i32().unwrap(), 2);
+    assert_eq!(other.borrow().hp, 2);
+}
+
+#[test]
+fn api_reentrant_run() {
+    let mruby = Mruby::new();
+
+    struct Level {
+        depth: i32
+    }
+
+    mruby.def_class_for::<Level>("Level");
+    mruby.def_class_method_for::<Level, _>("make", mrfn!(|mruby, _slf: Value, depth: i32| {
+        mruby.obj(Level { depth: depth })
+    }));
+    mruby.def_method_for::<Level, _>("descend", mrfn!(|mruby, slf: (&Level)| {
+        let depth = slf.depth;
+
+        // Defining methods and interning while methods are running needs mutable access.
+        mruby.def_method_for::<Level, _>(&format!("visited_{}", depth), mrfn!(|mruby, _slf: Value| {
+            mruby.bool(true)
+        }));
+        mruby.intern(&format!("level_{}", depth));
+
+        let trail = if depth == 0 {
+            mruby.run("GC.start; $trail << 0; Level.make(9).visited_0").unwrap();
+
+            "bottom".to_owned()
+        } else {
+            let below = mruby.run(&format!("GC.start; $trail << {}; Level.make({}).descend",
+                                           depth, depth - 1)).unwrap();
+
+            format!("{} < {}", depth, below.to_str().unwrap())
+        };
+
+        let level = mruby.obj(Level { depth: depth });
+
+        assert_eq!(level.to_obj::<Level>().unwrap().borrow().depth, depth);
+
+        mruby.string(&trail)
+    }));
+
+    mruby.def_toplevel_method("hop", mrfn!(|mruby, _slf: Value, depth: i32| {
+        let result = mruby.run(&format!("Level.make({}).descend", depth)).unwrap();
+
+        result.call("+", vec![mruby.string("!")]).unwrap()
+    }));
+
+    let result = mruby.run("$trail = []; hop 2").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "2 < 1 < bottom!");
+    assert_eq!(mruby.run("$trail").unwrap().to_rust::<Vec<i32>>().unwrap(), vec![2, 1, 0]);
+    assert!(mruby.run("Level.make(0).visited_2").unwrap().to_bool().unwrap());
+
+    // Hash lookups call back into Rust while hash_get runs.
+    mruby.def_method_for::<Level, _>("hash", mrfn!(|mruby, slf: (&Level)| {
+        mruby.def_method_for::<Level, _>("hashed?", mrfn!(|mruby, _slf: Value| {
+            mruby.bool(true)
+        }));
+
+        mruby.fixnum(slf.depth)
+    }));
+    mruby.def_method_for::<Level, _>("eql?", mrfn!(|mruby, slf: (&Level), other: (&Level)| {
+        mruby.bool(slf.depth == other.depth)
+    }));
+
+    let hash = mruby.run("{ Level.make(4) => :four }").unwrap();
+    let key = mruby.obj(Level { depth: 4 });
+
+    assert_eq!(mruby.hash_get(&hash, &key), mruby.symbol("four"));
+    assert!(key.call("hashed?", vec![]).unwrap().to_bool().unwrap());
+}
+
+#[test]
+fn api_close() {
+    let mruby = Mruby::new();
+
+    mruby.run("$kept = 'global'").unwrap();
+
+    assert_eq!(mruby.close(), Ok(()));
+
+    let mruby = Mruby::new();
+
+    struct Cont;
+
+    mruby.def_class_for::<Cont>("Container");
+
+    let leaked = mruby.run("[1, 2]").unwrap();
+    let registered = mruby.obj(Cont);
+    let other = mruby.clone();
+
+    registered.gc_register();
+    mruby.string("temporary").gc_register();
+
+    let err = mruby.close().unwrap_err();
+
+    assert_eq!(err.handles, 3);
+    assert_eq!(err.registered.get("Container"), Some(&1));
+    assert_eq!(err.registered.get("String"), Some(&1));
+    assert_eq!(format!("{}", err),
+               "Close error: 3 handles still alive, registered values: 1 Container, 1 String");
+
+    match leaked.to_vec() {
+        Err(MrubyError::Closed) => (),
+        _                       => panic!("leaked Value still usable")
+    }
+
+    match leaked.call("size", vec![]) {
+        Err(MrubyError::Closed) => (),
+        _                       => panic!("leaked Value still callable")
+    }
+
+    match registered.to_obj::<Cont>() {
+        Err(MrubyError::Closed) => (),
+        _                       => panic!("leaked object still usable")
+    }
+
+    match other.run("1 + 1") {
+        Err(MrubyError::Closed) => (),
+        _                       => panic!("closed mruby still runs scripts")
+    }
+}
+
+#[test]
+fn api_userdata() {
+    let mruby = Mruby::new();
+
+    struct Counter {
+        count: i32
+    }
+
+    struct Log(Vec<&'static str>);
+
+    struct Cont;
+
+    mruby.set_userdata(Counter { count: 0 });
+    mruby.set_userdata(Log(vec![]));
+
+    mruby.def_class_for::<Cont>("Container");
+    mruby.def_method_for::<Cont, _>("tick", mrfn!(|mruby, _slf: Value| {
+        mruby.userdata::<Counter>().unwrap().borrow_mut().count += 1;
+        mruby.userdata::<Log>().unwrap().borrow_mut().0.push("method");
+
+        mruby.nil()
+    }));
+    mruby.def_class_method_for::<Cont, _>("tick", mrfn!(|mruby, _slf: Value, by: i32| {
+        mruby.userdata::<Counter>().unwrap().borrow_mut().count += by;
+        mruby.userdata::<Log>().unwrap().borrow_mut().0.push("class method");
+
+        mruby.nil()
+    }));
+    mruby.def_toplevel_method("tick", mrfn!(|mruby, _slf: Value| {
+        let counter = mruby.userdata::<Counter>().unwrap();
+        let mut counter = counter.borrow_mut();
+
+        counter.count *= 10;
+
+        mruby.fixnum(counter.count)
+    }));
+
+    let result = mruby.run("Container.new.tick; Container.tick 2; tick").unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 30);
+    assert_eq!(mruby.userdata::<Counter>().unwrap().borrow().count, 30);
+    assert_eq!(mruby.userdata::<Log>().unwrap().borrow().0, vec!["method", "class method"]);
+    assert!(mruby.userdata::<Cont>().is_none());
+
+    mruby.set_userdata(Counter { count: -1 });
+
+    assert_eq!(mruby.run("tick").unwrap().to_i32().unwrap(), -10);
+}
+
+#[test]
+fn api_fiber() {
+    let mruby = Mruby::new();
+
+    let script = mruby.run("
+      proc do |name|
+        step = Fiber.yield \"#{name} walks\"
+        step = Fiber.yield \"#{name} jumps #{step}\"
+        Fiber.yield \"#{name} lands #{step}\"
+
+        :done
+      end
+    ").unwrap();
+
+    let fiber = mruby.fiber(script);
+    let mut yielded = vec![];
+
+    for frame in 0..3 {
+        let arg = if frame == 0 { mruby.string("entity") } else { mruby.fixnum(frame) };
+
+        match fiber.resume(vec![arg]).unwrap() {
+            FiberResult::Yielded(value) => yielded.push(value.to_str().unwrap().to_owned()),
+            FiberResult::Finished(_)    => panic!("fiber finished early")
+        }
+
+        assert!(fiber.alive());
+    }
+
+    assert_eq!(yielded, vec!["entity walks", "entity jumps 1", "entity lands 2"]);
+    assert_eq!(fiber.resume(vec![]).unwrap(), FiberResult::Finished(mruby.symbol("done")));
+    assert!(!fiber.alive());
+
+    match fiber.resume(vec![]) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "FiberError: resuming dead fiber");
+        },
+        _ => panic!("dead fiber resumed")
+    }
+
+    let failing = mruby.fiber(mruby.run("proc { Fiber.yield 1; raise 'broken' }").unwrap());
+
+    assert_eq!(failing.resume(vec![]).unwrap(), FiberResult::Yielded(mruby.fixnum(1)));
+    assert!(failing.resume(vec![]).is_err());
+    assert!(!failing.alive());
+
+    match failing.resume(vec![]) {
+        Err(MrubyError::Runtime { message: err, .. }) => {
+            assert_eq!(err, "FiberError: resuming dead fiber");
+        },
+        _ => panic!("failed fiber resumed")
+    }
+
+    assert_eq!(mruby.run("1 + 1").unwrap().to_i32().unwrap(), 2);
+}
+
+fn pool_setup(mruby: &mrusty::MrubyType) {
+    mruby.def_class_for::<Unregistered>("Setup");
+
+    mruby.run("
+      50.times do |i|
+        Object.const_set(\"Model#{i}\", Class.new do
+          10.times { |j| define_method(\"field#{j}\") { i * j } }
+        end)
+      end
+
+      def helper(value)
+        value * 2
+      end
+
+      $config = { limit: 10 }
+    ").unwrap();
+}
+
+#[test]
+fn api_pool_clear() {
+    let pool = MrubyPool::new(pool_setup);
+
+    let result = pool.with(|mruby| {
+        mruby.run("
+          local = 1
+          $request = 'first'
+          $config[:limit] = 20
+          @user = 'admin'
+          CACHE = []
+
+          def leaked; end
+
+          [Model3.new.field4, helper(local)]
+        ").unwrap().to_vec().unwrap().iter().map(|value| value.to_i32().unwrap()).collect::<Vec<_>>()
+    });
+
+    assert_eq!(result, vec![12, 2]);
+
+    let result = pool.with(|mruby| {
+        mruby.run("
+          [
+            (local rescue nil), $request, instance_variable_defined?(:@user),
+            Object.const_defined?(:CACHE),
+            respond_to?(:leaked), Object.const_defined?(:Model49), helper(2), $config[:limit]
+          ].inspect
+        ").unwrap().to_str().unwrap().to_owned()
+    });
+
+    // Globals defined by setup are kept as they are.
+    assert_eq!(result, "[nil, nil, false, false, false, true, 4, 20]");
+    assert_eq!(pool.built(), 1);
+    assert_eq!(pool.idle(), 1);
+    assert!(pool.with(|mruby| mruby.is_defined("Setup")));
+}
+
+#[test]
+fn api_pool_rebuild() {
+    let pool = MrubyPool::new(pool_setup).reset(PoolReset::Rebuild);
+
+    pool.with(|mruby| mruby.run("local = Model1.new.field1").unwrap());
+    pool.with(|mruby| mruby.run("$config[:limit] = 20").unwrap());
+
+    assert_eq!(pool.built(), 1);
+
+    pool.with(|mruby| mruby.run("DIRTY = true").unwrap());
+
+    assert_eq!(pool.built(), 1);
+    assert_eq!(pool.idle(), 0);
+
+    let result = pool.with(|mruby| {
+        mruby.run("[Object.const_defined?(:DIRTY), $config[:limit]].inspect").unwrap().to_str().unwrap().to_owned()
+    });
+
+    assert_eq!(result, "[false, 10]");
+    assert_eq!(pool.built(), 2);
+}
+
+#[test]
+fn api_pool_reentrant() {
+    let pool = MrubyPool::new(pool_setup);
+
+    let result = pool.with(|outer| {
+        outer.run("$outer = 1").unwrap();
+
+        let inner = pool.with(|inner| inner.run("$outer").unwrap() == inner.nil());
+
+        (inner, outer.run("$outer").unwrap().to_i32().unwrap())
+    });
+
+    assert_eq!(result, (true, 1));
+    assert_eq!(pool.built(), 2);
+    assert_eq!(pool.idle(), 2);
+}
+
+#[test]
+fn api_pool_reuse() {
+    use std::rc::Rc;
+
+    let script = "(0...50).map { |i| Object.const_get(\"Model#{i}\").new.field9 }.reduce(:+)";
+
+    let pool = MrubyPool::new(pool_setup);
+
+    let first = pool.with(|mruby| mruby.clone());
+
+    for i in 0..10 {
+        let result = pool.with(|mruby| {
+            assert!(Rc::ptr_eq(mruby, &first));
+
+            let stale = mruby.run("$run.nil?").unwrap().to_bool().unwrap();
+
+            mruby.run(&format!("$run = {}", i)).unwrap();
+
+            (stale, mruby.run(script).unwrap().to_i32().unwrap())
+        });
+
+        assert_eq!(result, (true, 11_025));
+    }
+
+    assert_eq!(pool.built(), 1);
+    assert_eq!(pool.idle(), 1);
+}
+
+// Moves mruby to another thread, which mrusty has to refuse.
+struct Smuggled(MrubyType, Value);
+
+unsafe impl Send for Smuggled {}
+
+fn on_other_thread<T, F>(mruby: &MrubyType, value: Value, f: F) -> Option<Result<T, MrubyError>>
+    where T: Send + 'static,
+          F: FnOnce(&MrubyType, &Value) -> Result<T, MrubyError> + Send + 'static {
+
+    let smuggled = Smuggled(mruby.clone(), value);
+
+    thread::spawn(move || {
+        let smuggled = smuggled;
+
+        f(&smuggled.0, &smuggled.1)
+    }).join().ok()
+}
+
+fn assert_wrong_thread<T>(result: Option<Result<T, MrubyError>>) {
+    match result {
+        Some(Err(MrubyError::WrongThread)) => (),
+        Some(Err(err)) => panic!("unexpected error: {}", err),
+        _ => panic!("cross-thread use was not refused")
+    }
+}
+
+#[test]
+fn api_wrong_thread() {
+    let mruby = Mruby::new();
+    let value = mruby.run("[1, 2]").unwrap();
+
+    assert_wrong_thread(on_other_thread(&mruby, value.clone(), |mruby, _| {
+        mruby.run("1 + 1").map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, value.clone(), |_, value| {
+        value.call("size", vec![]).map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, value.clone(), |_, value| {
+        value.to_vec().map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, mruby.string("a"), |_, value| {
+        value.to_str().map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, value.clone(), |_, value| {
+        value.array_get(0).map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, mruby.run("{ a: 1 }").unwrap(), |mruby, value| {
+        mruby.hash_keys(value).map(|_| ())
+    }));
+    assert_wrong_thread(on_other_thread(&mruby, value.clone(), |_, value| {
+        value.method_arity("size").map(|_| ())
+    }));
+
+    // Methods that cannot return an error panic instead.
+    assert!(on_other_thread(&mruby, value.clone(), |mruby, value| {
+        mruby.array_push(value, mruby.fixnum(3));
+
+        Ok(())
+    }).is_none());
+
+    assert_eq!(value.call("size", vec![]).unwrap().to_i32().unwrap(), 2);
+    assert_eq!(format!("{}", MrubyError::WrongThread),
+               "Thread error: mruby used from a thread other than the one that created it");
+}
+
+#[test]
+fn api_snapshot() {
+    let mruby = Mruby::new();
+    let result = mruby.run("
+      { 'name' => 'mrusty', tags: [:ruby, :'read write'], ratio: 0.5, count: 3, none: nil,
+        ok: true }
+    ").unwrap();
+
+    let snapshot = result.to_snapshot().unwrap();
+
+    let restored = thread::spawn(move || {
+        let mruby = Mruby::new();
+
+        let check = mruby.run("
+          lambda do |result|
+            result['name'] == 'mrusty' && result[:tags] == [:ruby, :'read write'] &&
+              result[:ratio] == 0.5 && result[:count] == 3 && result.key?(:none) &&
+              result[:none].nil? && result[:ok] == true && result.keys.size == 6
+          end
+        ").unwrap();
+
+        check.call("call", vec![snapshot.to_value(&mruby)]).unwrap().to_bool().unwrap()
+    }).join().unwrap();
+
+    assert!(restored);
+
+    let nested = mruby.run("a = []; 200.times { a = [a] }; a").unwrap();
+
+    assert!(mruby.run("Object.new").unwrap().to_snapshot().is_err());
+    assert!(mruby.run("[1, -> {}]").unwrap().to_snapshot().is_err());
+    assert!(nested.to_snapshot().is_err());
+}
+
+trait Plugin: MrubyAny {
+    fn name(&self) -> String;
+
+    fn run(&mut self, input: &str) -> String;
+}
+
+struct Upcase {
+    runs: i32
+}
+
+impl Plugin for Upcase {
+    fn name(&self) -> String {
+        "upcase".to_owned()
+    }
+
+    fn run(&mut self, input: &str) -> String {
+        self.runs += 1;
+
+        input.to_uppercase()
+    }
+}
+
+struct Repeat {
+    times: usize
+}
+
+impl Plugin for Repeat {
+    fn name(&self) -> String {
+        format!("repeat {}", self.times)
+    }
+
+    fn run(&mut self, input: &str) -> String {
+        input.repeat(self.times)
+    }
+}
+
+#[test]
+fn api_trait_object_class() {
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Box<Plugin>>("Plugin");
+    mruby.def_downcast_for::<Plugin>();
+    mruby.def_method_for::<Box<Plugin>, _>("name", mrfn!(|mruby, slf: (&dyn Plugin)| {
+        mruby.string(&slf.name())
+    }));
+    mruby.def_method_for::<Box<Plugin>, _>("run", mrfn!(|mruby, slf: (&mut dyn Plugin),
+                                                          input: (&str)| {
+        mruby.string(&slf.run(input))
+    }));
+    mruby.def_method_for::<Box<Plugin>, _>("same_name?", mrfn!(|mruby, slf: (&dyn Plugin),
+                                                                 other: (&dyn Plugin)| {
+        mruby.bool(slf.name() == other.name())
+    }));
+    mruby.def_method_for::<Box<Plugin>, _>("runs", mrfn!(|mruby, slf: Value| {
+        match slf.to_obj_downcast::<Upcase>() {
+            Ok(upcase) => mruby.fixnum(upcase.borrow().runs),
+            Err(err)   => panic!("{}", err)
+        }
+    }));
+    mruby.def_method_for::<Box<Plugin>, _>("times=", mrfn!(|mruby, slf: Value, times: i32| {
+        slf.to_obj_downcast::<Repeat>().unwrap().borrow_mut().times = times as usize;
+
+        mruby.nil()
+    }));
+
+    let upcase: Box<Plugin> = Box::new(Upcase { runs: 0 });
+    let repeat: Box<Plugin> = Box::new(Repeat { times: 2 });
+
+    let plugins = mruby.array(vec![mruby.obj(upcase), mruby.obj(repeat)]);
+
+    mruby.const_set(mruby.get_class("Object").unwrap().to_value(), "PLUGINS", plugins);
+
+    let result = mruby.run("
+      upcase, repeat = PLUGINS
+
+      repeat.times = 3
+
+      [
+        PLUGINS.map { |plugin| plugin.run(plugin.name) },
+        upcase.runs,
+        upcase.same_name?(repeat),
+        upcase.same_name?(upcase)
+      ]
+    ").unwrap().to_vec().unwrap();
+
+    let outputs = result[0].to_vec().unwrap();
+
+    assert_eq!(outputs[0].to_str().unwrap(), "UPCASE");
+    assert_eq!(outputs[1].to_str().unwrap(), "repeat 3repeat 3repeat 3");
+    assert_eq!(result[1].to_i32().unwrap(), 1);
+    assert_eq!(result[2].to_bool().unwrap(), false);
+    assert_eq!(result[3].to_bool().unwrap(), true);
+
+    let result = mruby.run("
+      begin
+        PLUGINS[1].runs
+      rescue RustPanic => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(),
+               "Cast error: expected api::Upcase, found api::Repeat");
+
+    let upcase = mruby.run("PLUGINS[0]").unwrap();
+
+    assert_eq!(upcase.to_obj_downcast::<Upcase>().unwrap().borrow().runs, 1);
+    assert!(mruby.fixnum(1).to_obj_downcast::<Upcase>().is_err());
+}
+
+#[test]
+fn api_obj_registry() {
+    use std::cell::RefCell;
+
+    struct Child {
+        name: String
+    }
+
+    struct Parent {
+        child: Rc<RefCell<Child>>
+    }
+
+    let mruby = Mruby::new();
+
+    mruby.def_class_for::<Child>("Child");
+    mruby.def_method_for::<Child, _>("name", mrfn!(|mruby, slf: (&Child)| {
+        mruby.string(&slf.name)
+    }));
+
+    mruby.def_class_for::<Parent>("Parent");
+    mruby.def_method_for::<Parent, _>("child", mrfn!(|mruby, slf: (&Parent)| {
+        slf.child.clone().to_value(&mruby)
+    }));
+
+    let child = Rc::new(RefCell::new(Child { name: "first".to_owned() }));
+    let parent = mruby.obj(Parent { child: child.clone() });
+
+    mruby.const_set(mruby.get_class("Object").unwrap().to_value(), "PARENT", parent);
+
+    // Run in a method so that no register of the top-level scope keeps the child alive.
+    let result = mruby.run("
+      def count_children
+        counts = { PARENT.child => 1 }
+        counts[PARENT.child] += 1
+
+        [PARENT.child.equal?(PARENT.child), counts[PARENT.child], counts.size]
+      end
+
+      count_children
+    ").unwrap().to_vec().unwrap();
+
+    assert_eq!(result[0].to_bool().unwrap(), true);
+    assert_eq!(result[1].to_i32().unwrap(), 2);
+    assert_eq!(result[2].to_i32().unwrap(), 1);
+
+    for _ in 0..3 {
+        mruby.run("1_000.times { 'garbage' * 10 }").unwrap();
+        mruby.gc_run();
+    }
+
+    assert!(mruby.find_obj(&child).is_none());
+    assert_eq!(Rc::strong_count(&child), 2);
+
+    let result = mruby.run("PARENT.child.name").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "first");
+    assert!(mruby.find_obj(&child).is_some());
+
+    let value = mruby.obj(Child { name: "second".to_owned() });
+    let obj = value.to_obj::<Child>().unwrap();
+    let wrapped = obj.clone().to_value(&mruby);
+
+    assert!(wrapped.call("equal?", vec![value.clone()]).unwrap().to_bool().unwrap());
+    assert!(mruby.find_obj(&obj).unwrap().call("equal?", vec![value]).unwrap()
+                 .to_bool().unwrap());
+}
+
+struct Unregistered;
+
+#[test]
+fn api_unregistered() {
+    let mruby = Mruby::new();
+
+    let result = mruby.try_def_method_for::<Unregistered, _>("value", |mruby, _slf| mruby.nil());
+
+    assert_eq!(result.unwrap_err().to_string(),
+               "Unregistered error: cannot define method 'value' for api::Unregistered; define it \
+                with def_class_for or require it first");
+
+    let result = mruby.try_def_class_method_for::<Unregistered, _>("build", |mruby, _slf| {
+        mruby.nil()
+    });
+
+    assert_eq!(result.unwrap_err().to_string(),
+               "Unregistered error: cannot define class method 'build' for api::Unregistered; \
+                define it with def_class_for or require it first");
+
+    assert_eq!(mruby.fixnum(1).to_obj::<Unregistered>().err().unwrap().to_string(),
+               "Unregistered error: cannot convert a Value for api::Unregistered; define it with \
+                def_class_for or require it first");
+
+    mruby.def_class_for::<Unregistered>("Unregistered");
+
+    assert!(mruby.try_def_method_for::<Unregistered, _>("value", |mruby, _slf| mruby.nil())
+                 .is_ok());
+    assert!(mruby.run("Unregistered.new.value").unwrap().call("nil?", vec![]).unwrap()
+                 .to_bool().unwrap());
+}
+
+#[test]
+#[should_panic(expected = "cannot define method 'value' for api::Unregistered")]
+fn api_unregistered_panic() {
+    let mruby = Mruby::new();
+
+    mruby.def_method_for::<Unregistered, _>("value", |mruby, _slf| mruby.nil());
+}
+
+#[test]
+#[should_panic(expected = "cannot create an object for api::Unregistered")]
+fn api_unregistered_obj() {
+    let mruby = Mruby::new();
+
+    mruby.obj(Unregistered);
+}
+
+#[test]
+#[should_panic(expected = "unknown operator `plus`")]
+fn api_operator_unknown() {
+    let mruby = Mruby::new();
+
+    struct Num;
+
+    mruby.def_class_for::<Num>("Num");
+    mruby.def_operator_for::<Num, _>("plus", |mruby, _num, _other| mruby.nil());
+}
+
+#[test]
+fn api_visibility() {
+    let mruby = Mruby::new();
+
+    struct Account {
+        balance: i32
+    }
+
+    mruby.def_class_for::<Account>("Account");
+    mruby.def_method_for::<Account, _>("initialize", mrfn!(|_mruby, slf: Value, v: i32| {
+        slf.init(Account { balance: v })
+    }));
+    mruby.def_private_method_for::<Account, _>("secret", mrfn!(|mruby, slf: (&Account)| {
+        mruby.fixnum(slf.balance * 2)
+    }));
+    mruby.def_protected_method_for::<Account, _>("balance", mrfn!(|mruby, slf: (&Account)| {
+        mruby.fixnum(slf.balance)
+    }));
+
+    mruby.run("
+      class Account
+        def doubled
+          secret + self.secret
+        end
+
+        def others_secret(other)
+          other.secret
+        end
+
+        def richer_than?(other)
+          balance > other.balance
+        end
+      end
+
+      class Auditor
+        def peek(account)
+          account.balance
+        end
+      end
+    ").unwrap();
+
+    assert_eq!(mruby.run("Account.new(2).doubled").unwrap().to_i32().unwrap(), 8);
+    assert_eq!(mruby.run("Account.new(3).richer_than?(Account.new(2))").unwrap()
+                    .to_bool().unwrap(), true);
+    assert_eq!(mruby.run("Account.new(2).send(:secret)").unwrap().to_i32().unwrap(), 4);
+    assert_eq!(mruby.run("Account.new(2).send(:balance)").unwrap().to_i32().unwrap(), 2);
+
+    let account = mruby.run("Account.new(5)").unwrap();
+
+    assert_eq!(account.call("secret", vec![]).unwrap().to_i32().unwrap(), 10);
+
+    for script in &["Account.new(1).secret", "Account.new(1).others_secret(Account.new(2))",
+                    "Account.new(1).balance", "Auditor.new.peek(Account.new(1))"] {
+        match mruby.run(script) {
+            Err(MrubyError::Runtime { message: err, .. }) => {
+                assert!(err.starts_with("NoMethodError: "), "{}", err);
+                assert!(err.contains("method 'secret' called for Account") ||
+                        err.contains("method 'balance' called for Account"), "{}", err);
+            },
+            result => panic!("unexpected {:?}", result)
+        }
+    }
+
+    let result = mruby.run("
+      begin
+        Account.new(1).secret
+      rescue NoMethodError => e
+        e.message
+      end
+    ").unwrap();
+
+    assert_eq!(result.to_str().unwrap(), "private method 'secret' called for Account");
+}
+
+#[test]
+fn api_mruby_class() {
+    let mruby = Mruby::new();
+
+    Scalar::require(mruby.clone());
+
+    mruby_class!(mruby, "Container", {
+        def!("initialize", |mruby, slf: Value, s: (&Scalar)| {
+            slf.set_var("value", mruby.float(s.value as f64));
+
+            slf
+        });
+
+        def!("value", |slf: Value| {
+            slf.get_var("value").unwrap()
+        });
+    });
+
+    let result = mruby.run("Container.new(Scalar.new 3.0).value").unwrap();
+
+    assert_eq!(result.to_f64().unwrap(), 3.0);
+}
+
+#[test]
+fn api_mruby_class_reopen() {
+    let mruby = Mruby::new();
+
+    assert!(!mruby.is_class_defined("Container"));
+
+    mruby_class!(mruby, "Container", {
+        def!("first", |mruby, _slf: Value| {
+            mruby.fixnum(1)
+        });
+    });
+
+    assert!(mruby.is_class_defined("Container"));
+
+    mruby_class!(mruby, "Container", {
+        def!("second", |mruby, _slf: Value| {
+            mruby.fixnum(2)
+        });
+    });
+
+    let result = mruby.run("c = Container.new; c.first + c.second").unwrap();
+
+    assert_eq!(result.to_i32().unwrap(), 3);
+}
+
+#[test]
+fn api_require_twice() {
+    let mruby = Mruby::new();
+
+    Scalar::require(mruby.clone());
+
+    let scalar = mruby.run("Scalar.new 2.0").unwrap();
+
+    Scalar::require(mruby.clone());
+
+    mruby.def_method_for::<Scalar, _>("double", mrfn!(|mruby, slf: (&Scalar)| {
+        mruby.float(slf.value as f64 * 2.0)
+    }));
+
+    let class = mruby.def_class_for::<Scalar>("Other");
+
+    assert_eq!(class.to_str(), "Scalar");
+    assert!(!mruby.is_defined("Other"));
+
+    mruby.run("GC.start").unwrap();
+
+    assert_eq!(scalar.to_obj::<Scalar>().unwrap().borrow().value, 2.0);
+    assert_eq!(scalar.call("value", vec![]).unwrap().to_f64().unwrap(), 2.0);
+    assert_eq!(scalar.call("double", vec![]).unwrap().to_f64().unwrap(), 4.0);
+    assert_eq!(mruby.run("Scalar.new(3.0).double").unwrap().to_f64().unwrap(), 6.0);
 }
 
 describe!(Scalar, "
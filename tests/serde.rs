@@ -0,0 +1,143 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#![cfg(feature = "serde")]
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate mrusty;
+
+use std::collections::HashMap;
+
+use mrusty::{Mruby, MrubyError, MrubyImpl};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Window {
+    width: i32,
+    height: i32,
+    title: String,
+    fullscreen: bool
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Line(i32, i32),
+    Rect { w: i32, h: i32 }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    window: Window,
+    scale: f64,
+    icon: Option<String>,
+    shapes: Vec<Shape>,
+    keys: HashMap<String, u8>
+}
+
+fn config() -> Config {
+    let mut keys = HashMap::new();
+
+    keys.insert("jump".to_owned(), 32);
+
+    Config {
+        window: Window {
+            width: 640,
+            height: 480,
+            title: "mrusty".to_owned(),
+            fullscreen: false
+        },
+        scale: 1.5,
+        icon: None,
+        shapes: vec![Shape::Empty, Shape::Circle(0.5), Shape::Line(1, 2),
+                     Shape::Rect { w: 3, h: 4 }],
+        keys: keys
+    }
+}
+
+#[test]
+fn serde_round_trip() {
+    let mruby = Mruby::new();
+
+    let value = mruby.to_value_serde(&config()).unwrap();
+
+    assert_eq!(mruby.from_value_serde::<Config>(&value).unwrap(), config());
+}
+
+#[test]
+fn serde_script() {
+    let mruby = Mruby::new();
+
+    mruby.run("
+      def resize(config)
+        config[:window][:width] *= 2
+        config[:icon] = 'icon.png'
+        config[:shapes] << { 'Rect' => { 'w' => 5, h: 6 } } << :Empty
+        config
+      end
+
+      def describe(config)
+        config[:shapes].map { |shape| shape.is_a?(Hash) ? shape.keys.first : shape }
+      end
+    ").unwrap();
+
+    let value = mruby.to_value_serde(&config()).unwrap();
+    let top = mruby.run("self").unwrap();
+
+    let shapes = top.call("describe", vec![value.clone()]).unwrap();
+
+    assert_eq!(shapes.to_rust::<Vec<String>>().unwrap(),
+               vec!["Empty", "Circle", "Line", "Rect"]);
+
+    let resized = top.call("resize", vec![value]).unwrap();
+    let resized = mruby.from_value_serde::<Config>(&resized).unwrap();
+
+    assert_eq!(resized.window.width, 1280);
+    assert_eq!(resized.icon, Some("icon.png".to_owned()));
+    assert_eq!(&resized.shapes[4..], &[Shape::Rect { w: 5, h: 6 }, Shape::Empty]);
+}
+
+#[test]
+fn serde_errors() {
+    let mruby = Mruby::new();
+
+    let expect = |code: &str, message: &str| {
+        let value = mruby.run(code).unwrap();
+
+        match mruby.from_value_serde::<Config>(&value) {
+            Err(MrubyError::Serde(error)) => assert_eq!(error, message),
+            result                        => panic!("unexpected {:?}", result)
+        }
+    };
+
+    let valid = "
+      {
+        window: { width: 1, height: 2, title: 'a', fullscreen: true },
+        scale: 1.0,
+        icon: nil,
+        shapes: [],
+        keys: {}
+      }
+    ";
+
+    expect(&valid.replace("width: 1", "width: 'wide'"),
+           "window.width: invalid type: string \"wide\", expected i32");
+    expect(&valid.replace("width: 1", "width: 1, depth: 3"),
+           "window.depth: unknown field `depth`, expected one of `width`, `height`, `title`, \
+            `fullscreen`");
+    expect(&valid.replace("shapes: []", "shapes: [:Empty, { Circle: 'round' }]"),
+           "shapes[1].Circle: invalid type: string \"round\", expected f64");
+    expect(&valid.replace("keys: {}", "keys: { jump: 300 }"),
+           "keys.jump: invalid value: integer `300`, expected u8");
+    expect(&valid.replace("scale: 1.0,", ""), "missing field `scale`");
+
+    assert_eq!(mruby.to_value_serde(&vec![1u64 << 40]).err().unwrap().to_string(),
+               "Serde error: [0]: 1099511627776 does not fit in a Fixnum");
+}
@@ -0,0 +1,54 @@
+// mrusty. mruby safe bindings for Rust
+// Copyright (C) 2016  Dragoș Tiselice
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+extern crate mrusty;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mrusty::{Mruby, MrubyImpl};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Kept as a single test since the allocation counter is shared by every test thread.
+#[test]
+fn symbols_no_alloc() {
+    let mruby = Mruby::new();
+
+    let succ = mruby.intern("succ");
+    let one = mruby.fixnum(1);
+
+    assert_eq!(one.call_sym(succ, vec![]).unwrap().to_i32().unwrap(), 2);
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+
+    for _ in 0..1000 {
+        assert_eq!(mruby.intern("succ"), succ);
+        assert_eq!(mruby.lookup_symbol(succ), "succ");
+
+        one.call_sym(succ, vec![]).unwrap();
+    }
+
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), before);
+}